@@ -0,0 +1,2 @@
+drop index if exists user_emails_one_primary_per_user_idx;
+alter table user_emails drop column if exists is_primary;
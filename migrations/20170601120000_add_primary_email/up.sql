@@ -0,0 +1,22 @@
+-- Every user can have one primary email address which is used whenever we
+-- need to attribute something to the user (e.g. commits created via the web
+-- interface).
+alter table user_emails
+    add column is_primary bool
+        not null
+        default false;
+
+-- Existing users get a primary address right away, so that they can edit
+-- their baskets. Addresses don't have a creation date, so the alphabetically
+-- first address of each user is chosen. It can be changed in the settings.
+update user_emails
+    set is_primary = true
+    where email in (
+        select distinct on (user_id) email
+            from user_emails
+            order by user_id, email
+    );
+
+create unique index user_emails_one_primary_per_user_idx
+    on user_emails (user_id)
+    where is_primary;
@@ -0,0 +1 @@
+-- The original free text kinds can't be restored.
@@ -0,0 +1,5 @@
+-- Basket kinds used to be free text. Now they have to be one of the kinds
+-- registered in `src/model/kind.rs`; everything else becomes "generic".
+update baskets
+    set kind = 'generic'
+    where kind not in ('generic', 'notes', 'bookmarks', 'recipes', 'tasks');
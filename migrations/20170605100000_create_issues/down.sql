@@ -0,0 +1,11 @@
+drop index if exists issue_comments_issue_id_idx;
+drop table if exists issue_comments;
+
+drop index if exists issue_assignees_user_id_idx;
+drop table if exists issue_assignees;
+
+drop index if exists issue_labels_label_idx;
+drop table if exists issue_labels;
+
+drop index if exists issues_unique_number_per_basket_idx;
+drop table if exists issues;
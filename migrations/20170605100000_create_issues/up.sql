@@ -0,0 +1,94 @@
+create table issues (
+    id bigserial
+        primary key,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    -- Issues are numbered per basket, starting at 1.
+    number int
+        not null
+        check (number > 0),
+
+    author_id bigint
+        references users(id)
+            on delete set null
+            on update cascade,
+
+    title sl_string
+        not null,
+    body ml_string
+        not null,
+
+    is_open bool
+        not null
+        default true,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create unique index issues_unique_number_per_basket_idx on issues (basket_id, number);
+
+
+create table issue_labels (
+    issue_id bigint
+        not null
+        references issues(id)
+            on delete cascade
+            on update cascade,
+    label sl_string
+        not null,
+
+    primary key (issue_id, label)
+);
+
+create index issue_labels_label_idx on issue_labels (label);
+
+
+create table issue_assignees (
+    issue_id bigint
+        not null
+        references issues(id)
+            on delete cascade
+            on update cascade,
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    primary key (issue_id, user_id)
+);
+
+create index issue_assignees_user_id_idx on issue_assignees (user_id);
+
+
+create table issue_comments (
+    id bigserial
+        primary key,
+
+    issue_id bigint
+        not null
+        references issues(id)
+            on delete cascade
+            on update cascade,
+
+    author_id bigint
+        references users(id)
+            on delete set null
+            on update cascade,
+
+    body ml_string
+        not null,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index issue_comments_issue_id_idx on issue_comments (issue_id);
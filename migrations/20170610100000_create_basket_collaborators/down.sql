@@ -0,0 +1,2 @@
+drop index if exists basket_collaborators_user_id_idx;
+drop table if exists basket_collaborators;
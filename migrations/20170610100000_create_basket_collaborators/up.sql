@@ -0,0 +1,33 @@
+-- Users other than the owner which have access to a basket. A row is created
+-- when the user is invited and becomes effective once the invitation is
+-- accepted.
+create table basket_collaborators (
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    -- One of the roles defined in `src/model/collaborator.rs`.
+    role sl_string
+        not null
+        check (role in ('read', 'write', 'admin')),
+
+    accepted bool
+        not null
+        default false,
+
+    created_at timestamptz
+        not null
+        default now(),
+
+    primary key (basket_id, user_id)
+);
+
+create index basket_collaborators_user_id_idx on basket_collaborators (user_id);
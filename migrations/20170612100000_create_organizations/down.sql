@@ -0,0 +1,12 @@
+drop index if exists team_members_user_id_idx;
+drop table if exists team_members;
+
+drop index if exists teams_unique_lower_name_per_org_idx;
+drop table if exists teams;
+
+drop index if exists organization_members_user_id_idx;
+drop table if exists organization_members;
+
+-- The rows in `users` belonging to organizations stay, but become regular
+-- users without password.
+drop table if exists organizations;
@@ -0,0 +1,77 @@
+-- Organizations are stored as rows in `users` (without password), so that
+-- they share the username namespace and can own baskets like users. This
+-- table marks such rows as organization and holds organization specific
+-- settings.
+create table organizations (
+    user_id bigint
+        primary key
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    members_can_create_baskets bool
+        not null
+        default true
+);
+
+
+create table organization_members (
+    organization_id bigint
+        not null
+        references organizations(user_id)
+            on delete cascade
+            on update cascade,
+
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    -- One of the roles defined in `src/model/organization.rs`.
+    role sl_string
+        not null
+        check (role in ('owner', 'member')),
+
+    primary key (organization_id, user_id)
+);
+
+create index organization_members_user_id_idx on organization_members (user_id);
+
+
+create table teams (
+    id bigserial
+        primary key,
+
+    organization_id bigint
+        not null
+        references organizations(user_id)
+            on delete cascade
+            on update cascade,
+
+    name sl_string
+        not null,
+
+    description ml_string
+);
+
+create unique index teams_unique_lower_name_per_org_idx on teams (organization_id, lower(name));
+
+
+create table team_members (
+    team_id bigint
+        not null
+        references teams(id)
+            on delete cascade
+            on update cascade,
+
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    primary key (team_id, user_id)
+);
+
+create index team_members_user_id_idx on team_members (user_id);
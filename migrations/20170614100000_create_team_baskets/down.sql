@@ -0,0 +1,2 @@
+drop index if exists team_baskets_basket_id_idx;
+drop table if exists team_baskets;
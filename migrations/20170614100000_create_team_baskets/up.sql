@@ -0,0 +1,24 @@
+-- Grants a team of an organization access to one of the organization's
+-- baskets. All members of the team get the given role in the basket.
+create table team_baskets (
+    team_id bigint
+        not null
+        references teams(id)
+            on delete cascade
+            on update cascade,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    -- One of the roles defined in `src/model/collaborator.rs`.
+    role sl_string
+        not null
+        check (role in ('read', 'write', 'admin')),
+
+    primary key (team_id, basket_id)
+);
+
+create index team_baskets_basket_id_idx on team_baskets (basket_id);
@@ -0,0 +1,15 @@
+-- Internal baskets become private.
+alter table baskets
+    add column public bool
+        not null
+        default false;
+
+update baskets
+    set public = true
+    where visibility = 'public';
+
+alter table baskets
+    alter column public drop default;
+
+alter table baskets
+    drop column visibility;
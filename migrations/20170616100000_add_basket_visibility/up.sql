@@ -0,0 +1,18 @@
+-- Replaces the boolean `public` by one of the visibility levels defined in
+-- `src/model/basket.rs`. "internal" baskets are visible to all logged in
+-- users.
+alter table baskets
+    add column visibility sl_string
+        not null
+        default 'private'
+        check (visibility in ('public', 'internal', 'private'));
+
+update baskets
+    set visibility = 'public'
+    where public;
+
+alter table baskets
+    alter column visibility drop default;
+
+alter table baskets
+    drop column public;
@@ -0,0 +1,6 @@
+-- Archived baskets are read-only: they can be viewed, but not changed until
+-- they are unarchived again.
+alter table baskets
+    add column archived bool
+        not null
+        default false;
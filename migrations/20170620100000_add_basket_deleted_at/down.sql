@@ -0,0 +1,5 @@
+-- Baskets which were deleted but not purged yet become visible again.
+drop index if exists baskets_deleted_at_idx;
+
+alter table baskets
+    drop column if exists deleted_at;
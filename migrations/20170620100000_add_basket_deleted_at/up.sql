@@ -0,0 +1,7 @@
+-- Deleted baskets are only marked as deleted first, so that they can be
+-- restored for a while. They keep their name until they are purged.
+alter table baskets
+    add column deleted_at timestamptz;
+
+create index baskets_deleted_at_idx on baskets (deleted_at)
+    where deleted_at is not null;
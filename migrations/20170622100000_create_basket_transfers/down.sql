@@ -0,0 +1,4 @@
+drop table if exists basket_redirects;
+
+drop index if exists basket_transfers_to_user_id_idx;
+drop table if exists basket_transfers;
@@ -0,0 +1,44 @@
+-- A pending transfer of a basket to a new owner. The transfer is executed
+-- once the new owner accepts it.
+create table basket_transfers (
+    basket_id bigint
+        primary key
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    to_user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index basket_transfers_to_user_id_idx on basket_transfers (to_user_id);
+
+
+-- The former locations of transferred baskets. Requests to the old URL are
+-- redirected to the basket's current location, unless there is a basket with
+-- that name again.
+create table basket_redirects (
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    name sl_string
+        not null,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    primary key (user_id, name)
+);
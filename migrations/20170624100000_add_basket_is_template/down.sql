@@ -0,0 +1,2 @@
+alter table baskets
+    drop column if exists is_template;
@@ -0,0 +1,5 @@
+-- Templates are offered on `/new` as starting point for new baskets.
+alter table baskets
+    add column is_template bool
+        not null
+        default false;
@@ -0,0 +1,2 @@
+drop index if exists stars_basket_id_idx;
+drop table if exists stars;
@@ -0,0 +1,22 @@
+-- Users can star baskets to bookmark them and to show appreciation.
+create table stars (
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    created_at timestamptz
+        not null
+        default now(),
+
+    primary key (user_id, basket_id)
+);
+
+create index stars_basket_id_idx on stars (basket_id);
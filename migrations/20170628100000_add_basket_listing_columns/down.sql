@@ -0,0 +1,9 @@
+drop index if exists baskets_user_id_star_count_idx;
+drop index if exists baskets_user_id_updated_at_idx;
+drop index if exists baskets_user_id_created_at_idx;
+drop index if exists baskets_user_id_name_idx;
+
+alter table baskets
+    drop column if exists star_count,
+    drop column if exists updated_at,
+    drop column if exists created_at;
@@ -0,0 +1,22 @@
+-- Columns to sort basket listings by. `updated_at` is set whenever the
+-- content or the settings of a basket change. `star_count` mirrors the number
+-- of rows in `stars`, so that listings can be sorted by it cheaply.
+alter table baskets
+    add column created_at timestamptz
+        not null
+        default now(),
+    add column updated_at timestamptz
+        not null
+        default now(),
+    add column star_count integer
+        not null
+        default 0;
+
+update baskets
+    set star_count = (select count(*) from stars where stars.basket_id = baskets.id);
+
+-- Indexes for the keyset pagination of the baskets of one owner.
+create index baskets_user_id_name_idx on baskets (user_id, name, id);
+create index baskets_user_id_created_at_idx on baskets (user_id, created_at, id);
+create index baskets_user_id_updated_at_idx on baskets (user_id, updated_at, id);
+create index baskets_user_id_star_count_idx on baskets (user_id, star_count, id);
@@ -0,0 +1,11 @@
+drop index if exists basket_contents_search_idx;
+drop table if exists basket_contents;
+
+drop index if exists users_search_idx;
+drop index if exists baskets_search_idx;
+
+drop function if exists search_matches(tsvector, tsquery);
+drop function if exists search_query(text);
+drop function if exists content_document(text);
+drop function if exists user_document(text, text, text);
+drop function if exists basket_document(text, text);
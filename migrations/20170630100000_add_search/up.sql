@@ -0,0 +1,56 @@
+-- Full-text search over baskets, users and the content of baskets.
+--
+-- `infer_schema!` can't handle `tsvector` columns, so the documents are
+-- computed by the immutable functions below and indexed as expressions. The
+-- application uses the same functions, so that the indexes are used. All
+-- documents use the 'simple' configuration: names and content can be in any
+-- language, so we don't stem.
+
+create function basket_document(name text, description text) returns tsvector as $$
+    select to_tsvector('simple', name || ' ' || coalesce(description, ''))
+$$ language sql immutable;
+
+create function user_document(username text, name text, bio text) returns tsvector as $$
+    select to_tsvector('simple', username || ' ' || coalesce(name, '') || ' ' || coalesce(bio, ''))
+$$ language sql immutable;
+
+create function content_document(body text) returns tsvector as $$
+    select to_tsvector('simple', body)
+$$ language sql immutable;
+
+create function search_query(query text) returns tsquery as $$
+    select plainto_tsquery('simple', query)
+$$ language sql immutable;
+
+-- `@@` as function, since diesel has no operator for it. The function is
+-- inlined by the planner, so the indexes are still used.
+create function search_matches(document tsvector, query tsquery) returns bool as $$
+    select document @@ query
+$$ language sql immutable;
+
+create index baskets_search_idx on baskets
+    using gin (basket_document(name, description));
+create index users_search_idx on users
+    using gin (user_document(username, name, bio));
+
+
+-- The text files of all baskets, copied from their repositories so that they
+-- can be searched. Updated whenever a basket's content changes.
+create table basket_contents (
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    path text
+        not null,
+
+    body text
+        not null,
+
+    primary key (basket_id, path)
+);
+
+create index basket_contents_search_idx on basket_contents
+    using gin (content_document(body));
@@ -0,0 +1 @@
+drop index if exists stars_created_at_idx;
@@ -0,0 +1,3 @@
+-- The explore page counts the stars of the last days to find trending
+-- baskets.
+create index stars_created_at_idx on stars (created_at);
@@ -0,0 +1,2 @@
+drop index if exists events_basket_id_created_at_idx;
+drop table if exists events;
@@ -0,0 +1,30 @@
+-- Things which happened in baskets. They are shown in the activity feed.
+create table events (
+    id bigserial
+        primary key,
+
+    -- The user who did it.
+    actor_id bigint
+        references users(id)
+            on delete set null
+            on update cascade,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    -- One of 'created', 'forked', 'starred', 'pushed' and 'settings_changed'.
+    kind text
+        not null,
+
+    -- Depends on the kind, e.g. the commit message for 'pushed'.
+    details text,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index events_basket_id_created_at_idx on events (basket_id, created_at);
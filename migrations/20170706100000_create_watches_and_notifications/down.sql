@@ -0,0 +1,4 @@
+drop index if exists notifications_user_id_read_at_idx;
+drop table if exists notifications;
+drop index if exists watches_basket_id_idx;
+drop table if exists watches;
@@ -0,0 +1,55 @@
+-- How closely users follow baskets. Without a row, owners and collaborators
+-- are notified as if they chose 'participating' and everybody else isn't
+-- notified at all.
+create table watches (
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    level text
+        not null
+        check (level in ('participating', 'all', 'ignore')),
+
+    primary key (user_id, basket_id)
+);
+
+create index watches_basket_id_idx on watches (basket_id);
+
+
+-- The notifications inbox: events users are notified about.
+create table notifications (
+    id bigserial
+        primary key,
+
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    event_id bigint
+        not null
+        references events(id)
+            on delete cascade
+            on update cascade,
+
+    -- Why the user is notified: 'owner', 'collaborator' or 'watching'.
+    reason text
+        not null,
+
+    read_at timestamptz,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index notifications_user_id_read_at_idx on notifications (user_id, read_at);
@@ -0,0 +1,5 @@
+drop index if exists notifications_email_pending_idx;
+alter table notifications
+    drop column if exists emailed_at,
+    drop column if exists email_delivery;
+drop table if exists email_preferences;
@@ -0,0 +1,29 @@
+-- The event kinds users want to be emailed about, and how.
+create table email_preferences (
+    user_id bigint
+        not null
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    -- One of the values of `events.kind`.
+    kind text
+        not null,
+
+    delivery text
+        not null
+        check (delivery in ('immediate', 'daily')),
+
+    primary key (user_id, kind)
+);
+
+-- How the notification is emailed, copied from the preferences when it is
+-- created, and when that happened. Unread notifications are emailed only
+-- once; read ones aren't emailed at all.
+alter table notifications
+    add column email_delivery text
+        check (email_delivery in ('immediate', 'daily')),
+    add column emailed_at timestamptz;
+
+create index notifications_email_pending_idx on notifications (user_id)
+    where email_delivery is not null and emailed_at is null;
@@ -0,0 +1,80 @@
+-- URLs which are sent a request whenever certain events happen in a basket.
+create table webhooks (
+    id bigserial
+        primary key,
+
+    basket_id bigint
+        not null
+        references baskets(id)
+            on delete cascade
+            on update cascade,
+
+    url text
+        not null,
+
+    -- The key of the HMAC signature of every request.
+    secret text
+        not null,
+
+    -- The values of `events.kind` the hook is triggered by.
+    kinds text[]
+        not null,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index webhooks_basket_id_idx on webhooks (basket_id);
+
+-- One request of a webhook, including its retries.
+create table webhook_deliveries (
+    id bigserial
+        primary key,
+
+    webhook_id bigint
+        not null
+        references webhooks(id)
+            on delete cascade
+            on update cascade,
+
+    -- `null` for test deliveries and once the event was deleted.
+    event_id bigint
+        references events(id)
+            on delete set null
+            on update cascade,
+
+    -- The kind of the event or 'ping' for test deliveries.
+    kind text
+        not null,
+
+    request_body text
+        not null,
+
+    status text
+        not null
+        default 'pending'
+        check (status in ('pending', 'succeeded', 'failed')),
+
+    attempts integer
+        not null
+        default 0,
+
+    -- When the next attempt is due; `null` once the delivery isn't pending.
+    next_attempt_at timestamptz
+        default now(),
+
+    -- The outcome of the last attempt: either the response or the error
+    -- which prevented getting one.
+    response_status integer,
+    response_body text,
+    error text,
+
+    created_at timestamptz
+        not null
+        default now()
+);
+
+create index webhook_deliveries_webhook_id_idx on webhook_deliveries (webhook_id, created_at);
+create index webhook_deliveries_due_idx on webhook_deliveries (next_attempt_at)
+    where status = 'pending';
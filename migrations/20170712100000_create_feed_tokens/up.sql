@@ -0,0 +1,18 @@
+-- Personal tokens which let feed readers see the private baskets the user can
+-- see, without logging in.
+create table feed_tokens (
+    user_id bigint
+        primary key
+        references users(id)
+            on delete cascade
+            on update cascade,
+
+    token text
+        not null
+        unique
+        check (char_length(token) = 40),
+
+    created_at timestamptz
+        not null
+        default now()
+);
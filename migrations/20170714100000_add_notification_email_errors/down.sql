@@ -0,0 +1,3 @@
+alter table notifications
+    drop column if exists email_error,
+    drop column if exists email_attempts;
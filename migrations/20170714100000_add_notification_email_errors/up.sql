@@ -0,0 +1,7 @@
+-- Failed attempts to email a notification and the error of the last one, so
+-- that failures are recorded and a notification isn't tried forever.
+alter table notifications
+    add column email_attempts integer
+        not null
+        default 0,
+    add column email_error text;
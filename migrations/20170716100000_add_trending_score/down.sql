@@ -0,0 +1 @@
+drop function if exists trending_score(bigint, timestamptz, timestamptz);
@@ -0,0 +1,9 @@
+-- The score the explore page ranks trending baskets by: two points for every
+-- star since `since` and one point if the basket changed since then. It's a
+-- function, so that the stars are counted by the database instead of being
+-- loaded into the application.
+create function trending_score(basket bigint, changed timestamptz, since timestamptz)
+    returns bigint as $$
+    select 2 * (select count(*) from stars where basket_id = basket and created_at > since)
+        + case when changed > since then 1 else 0 end
+$$ language sql stable;
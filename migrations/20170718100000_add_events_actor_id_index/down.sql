@@ -0,0 +1 @@
+drop index if exists events_actor_id_created_at_idx;
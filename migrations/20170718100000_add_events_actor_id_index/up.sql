@@ -0,0 +1,2 @@
+-- The feed of a user lists the events the user caused.
+create index events_actor_id_created_at_idx on events (actor_id, created_at);
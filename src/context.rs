@@ -1,9 +1,10 @@
 use rocket::request::FlashMessage;
+use serde::{Serialize, Serializer};
 
 use model::AuthUser;
 
 /// Serves as the main template context.
-#[derive(Clone, Eq, PartialEq, Serialize)]
+#[derive(Clone, Eq, PartialEq)]
 pub struct Context<T = ()> {
     /// Information about the user, if a login session exists.
     pub auth_user: Option<AuthUser>,
@@ -37,6 +38,25 @@ impl Context<()> {
     }
 }
 
+impl<T: Serialize> Serialize for Context<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Context", 4)?;
+        s.serialize_field("auth_user", &self.auth_user)?;
+        s.serialize_field("flash", &self.flash)?;
+        s.serialize_field("content", &self.content)?;
+        // Shown in the header of every page.
+        s.serialize_field(
+            "unread_notifications",
+            &self.auth_user.as_ref().map(|u| u.unread_notifications()),
+        )?;
+        s.end()
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Serialize)]
 pub struct FlashContext {
     pub name: String,
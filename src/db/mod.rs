@@ -8,6 +8,7 @@ use std::env;
 pub mod schema;
 
 
+#[derive(Clone)]
 pub struct Db {
     pub pool: Pool<ConnectionManager<PgConnection>>,
 }
@@ -29,6 +30,29 @@ impl Db {
         Self { pool }
     }
 
+    /// Opens a database for tests. The pool has a single connection, in
+    /// which a transaction is started that is never committed, so tests
+    /// don't leave anything behind.
+    ///
+    /// Like the application, this needs `DATABASE_URL` (e.g. from `.env`)
+    /// pointing to a migrated database.
+    #[cfg(test)]
+    pub fn open_test_connection() -> Self {
+        use diesel::Connection;
+
+        dotenv().ok();
+
+        let config = r2d2::Config::builder().pool_size(1).build();
+        let database_url = env::var("DATABASE_URL")
+            .expect("DATABASE_URL must be set");
+        let manager = ConnectionManager::new(database_url);
+        let pool = Pool::new(config, manager).expect("Failed to create pool.");
+
+        let db = Self { pool };
+        db.conn().begin_test_transaction().unwrap();
+        db
+    }
+
     /// Returns a DB connection.
     pub fn conn(&self) -> PooledConnection<ConnectionManager<PgConnection>> {
         self.pool.get().unwrap()
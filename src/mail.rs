@@ -0,0 +1,179 @@
+//! Sending emails.
+//!
+//! Emails are rendered from the plain text Tera templates in
+//! `templates/email/` and handed to a `Transport`. Which transport is used
+//! is configured with the `MAIL_TRANSPORT` environment variable:
+//!
+//! - `maildir` (default): the emails are stored in the maildir given by
+//!   `MAIL_DIR` (`data/mail/` by default), where they can be read with any
+//!   mail client. This is meant for development and testing.
+//! - `sendmail`: the emails are piped to `sendmail -t -i`.
+
+use chrono::offset::utc::UTC;
+use rand;
+use serde::Serialize;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tera::Tera;
+
+
+/// Returns the URL under which the site is reachable, used for links in
+/// emails. Can be configured with `BASE_URL`.
+pub fn base_url() -> String {
+    env::var("BASE_URL").unwrap_or("http://localhost:8000".into())
+}
+
+/// An email ready to be sent.
+#[derive(Clone, Debug)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    /// Plain text.
+    pub body: String,
+}
+
+impl Mail {
+    /// Formats the email as RFC 5322 message with the given sender.
+    fn to_message(&self, from: &str) -> String {
+        format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+                Content-Type: text/plain; charset=utf-8\r\n\r\n{}",
+            from,
+            self.to,
+            self.subject,
+            UTC::now().to_rfc2822(),
+            self.body.replace("\r\n", "\n").replace('\n', "\r\n"),
+        )
+    }
+}
+
+/// Something which can deliver emails.
+pub trait Transport: Send + Sync {
+    /// Delivers a complete message to the recipients in its headers.
+    fn send(&self, message: &str) -> io::Result<()>;
+}
+
+/// Stores all emails in a maildir instead of sending them.
+pub struct MaildirTransport {
+    dir: PathBuf,
+}
+
+impl MaildirTransport {
+    /// Creates the maildir if it doesn't exist yet.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        for sub in &["tmp", "new", "cur"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        Ok(MaildirTransport { dir })
+    }
+}
+
+impl Transport for MaildirTransport {
+    fn send(&self, message: &str) -> io::Result<()> {
+        // Messages are written to `tmp` and then moved to `new`, so that
+        // readers never see incomplete messages.
+        let name = format!("{}.{:016x}.basgit", UTC::now().timestamp(), rand::random::<u64>());
+        let tmp = self.dir.join("tmp").join(&name);
+        fs::File::create(&tmp)?.write_all(message.as_bytes())?;
+        fs::rename(&tmp, self.dir.join("new").join(&name))
+    }
+}
+
+/// Sends emails with the local `sendmail` command.
+pub struct SendmailTransport;
+
+impl Transport for SendmailTransport {
+    fn send(&self, message: &str) -> io::Result<()> {
+        // `-t` takes the recipients from the message headers.
+        let mut child = Command::new("sendmail")
+            .args(&["-t", "-i"])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        child.stdin.take().unwrap().write_all(message.as_bytes())?;
+
+        let status = child.wait()?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other, format!("sendmail failed: {}", status)))
+        }
+    }
+}
+
+/// Renders and sends emails.
+pub struct Mailer {
+    transport: Box<Transport>,
+    templates: Tera,
+    /// The sender of all emails. Can be configured with `MAIL_FROM`.
+    from: String,
+}
+
+impl Mailer {
+    pub fn new(transport: Box<Transport>) -> Self {
+        let templates = Tera::new("templates/email/*.tera")
+            .expect("failed to load email templates");
+
+        Mailer {
+            transport,
+            templates,
+            from: env::var("MAIL_FROM").unwrap_or("BasGit <noreply@localhost>".into()),
+        }
+    }
+
+    /// Creates a mailer with the transport configured by the environment
+    /// (see the module documentation).
+    pub fn from_env() -> Self {
+        let transport: Box<Transport> = match env::var("MAIL_TRANSPORT") {
+            Ok(ref t) if t == "sendmail" => Box::new(SendmailTransport),
+            Ok(ref t) if t != "maildir" => panic!("unknown MAIL_TRANSPORT '{}'", t),
+            _ => {
+                let dir = env::var("MAIL_DIR").unwrap_or("data/mail/".into());
+                Box::new(MaildirTransport::new(dir).expect("failed to create maildir"))
+            }
+        };
+        Self::new(transport)
+    }
+
+    /// Renders the template `templates/email/<template>.txt.tera` and sends
+    /// the result.
+    pub fn send<T: Serialize>(
+        &self,
+        to: &str,
+        subject: &str,
+        template: &str,
+        context: &T,
+    ) -> Result<(), MailError> {
+        let body = self.templates
+            .value_render(&format!("{}.txt.tera", template), context)
+            .map_err(|e| MailError::Render(e.to_string()))?;
+        let mail = Mail {
+            to: to.into(),
+            subject: subject.into(),
+            body,
+        };
+
+        self.transport
+            .send(&mail.to_message(&self.from))
+            .map_err(MailError::Transport)
+    }
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Render(String),
+    Transport(io::Error),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MailError::Render(ref e) => write!(f, "failed to render email: {}", e),
+            MailError::Transport(ref e) => write!(f, "failed to send email: {}", e),
+        }
+    }
+}
@@ -3,10 +3,16 @@
 #![plugin(rocket_codegen)]
 
 extern crate chrono;
+extern crate crypto;
 #[macro_use] extern crate diesel;
 #[macro_use] extern crate diesel_codegen;
 extern crate dotenv;
+extern crate git2;
 extern crate hex;
+extern crate hyper;
+extern crate hyper_native_tls;
+#[macro_use] extern crate log;
+extern crate pulldown_cmark;
 extern crate pwhash;
 extern crate r2d2;
 extern crate r2d2_diesel;
@@ -16,19 +22,36 @@ extern crate rocket_contrib;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 #[macro_use] extern crate serde_json;
+extern crate tera;
+extern crate toml;
 
 
 pub mod context;
 pub mod db;
+pub mod mail;
 pub mod model;
 pub mod routes;
 
 fn main() {
-    use routes::{self, basket, index, login, new, user};
+    use routes::{
+        self, account, api, basket, bookmarks, explore, feed, files, index, issues, login, new,
+        notifications, organization, records, search, settings, user, wiki,
+    };
     use db::Db;
+    use routes::openapi::ApiSpec;
+    use model::explore::ExploreCache;
+    use mail::Mailer;
+
+    let db = Db::open_connection();
+    model::basket::spawn_purge_thread(db.clone());
+    model::search::spawn_index_thread(db.clone());
+    model::email_notification::spawn_mail_thread(Mailer::from_env(), db.clone());
+    model::webhook::spawn_delivery_thread(db.clone());
 
     rocket::ignite()
-        .manage(Db::open_connection())
+        .manage(db)
+        .manage(ApiSpec::build(&api::routes()))
+        .manage(ExploreCache::new())
         .mount("/", routes![
             // Routes for serving the index page
             index::with_login,
@@ -40,10 +63,37 @@ fn main() {
             login::validate_data,
             login::logout,
 
+            // Settings of the current user
+            account::index,
+            account::primary_email,
+            account::feed_token,
+
             // `/<user>` routes
             user::index,
             user::tabs,
 
+            // Atom feeds of users and baskets
+            feed::user,
+            feed::user_with_token,
+            feed::basket,
+            feed::basket_with_token,
+
+            // Discovering public baskets
+            explore::index,
+            explore::kind,
+            explore::kind_listing,
+
+            // The notifications inbox
+            notifications::index,
+            notifications::mark_read,
+            notifications::mark_all_read,
+            notifications::settings,
+            notifications::save_settings,
+
+            // Searching baskets, users and content
+            search::empty,
+            search::results,
+
             // `/new` for creating new baskets
             new::with_login,
             new::without_login,
@@ -53,8 +103,94 @@ fn main() {
             basket::index,
             basket::facade,
 
+            // Organizations, their members and teams
+            organization::new_form,
+            organization::new_form_without_login,
+            organization::create,
+            organization::set_member,
+            organization::remove_member,
+            organization::settings,
+            organization::create_team,
+            organization::delete_team,
+            organization::add_team_member,
+            organization::remove_team_member,
+            organization::grant_team,
+            organization::revoke_team,
+
+            // Settings and collaborators of a basket
+            settings::index,
+            settings::invite,
+            settings::remove,
+            settings::archive,
+            settings::template,
+            settings::watch,
+            settings::add_webhook,
+            settings::webhook,
+            settings::ping_webhook,
+            settings::redeliver,
+            settings::delete_webhook,
+            settings::transfer,
+            settings::cancel_transfer,
+            settings::delete,
+            settings::deleted,
+            settings::restore,
+            settings::invitations,
+            settings::respond,
+            settings::respond_transfer,
+
+            // Browsing and editing the files of a basket
+            files::list,
+            files::show,
+            files::new_file,
+            files::edit,
+            files::save,
+            files::delete,
+
+            // The wiki facade of notes baskets
+            wiki::index,
+            wiki::page,
+            wiki::edit,
+            wiki::history,
+            wiki::pages,
+            wiki::save,
+
+            // The issue tracker facade of tasks baskets
+            issues::list,
+            issues::filtered_list,
+            issues::new_issue,
+            issues::create,
+            issues::show,
+            issues::comment,
+            issues::edit,
+            issues::set_state,
+            issues::set_labels,
+            issues::set_assignees,
+
+            // The bookmarks facade of bookmarks baskets
+            bookmarks::list,
+            bookmarks::by_tag,
+            bookmarks::add,
+            bookmarks::delete,
+            bookmarks::export,
+            bookmarks::import_form,
+            bookmarks::import_raw,
+
+            // The records facade of recipes baskets
+            records::list,
+            records::show,
+            records::new_record,
+            records::edit,
+            records::create,
+            records::save,
+            records::delete,
+
             // Serving static files in `static/`
             routes::static_files,
         ])
+        .mount(api::PREFIX, api::routes())
+        .catch(errors![
+            // Redirects from former locations of transferred baskets
+            basket::not_found,
+        ])
         .launch();
 }
@@ -1,19 +1,45 @@
+use chrono::{DateTime, Duration};
+use chrono::offset::utc::UTC;
 use diesel::prelude::*;
 use diesel;
 use serde::{Serialize, Serializer};
+use std::env;
 use std::fmt;
 use std::ops::Deref;
+use std::time;
 
 use db::schema::baskets;
 use db::schema::users;
 
 use db::Db;
-use model::{basket, AuthUser, PubUser, User};
-use model::permissions::{has_permission, UserAction};
+use model::{self, basket, AuthUser, BasketKind, PubUser, User};
+use model::content::{Author, Change, Content, EditError};
+use model::event::{self, EventKind};
+use model::kind;
+use model::organization;
+use model::permissions::{has_permission, UserAction, Visibility};
+use model::search;
 use routes::new::NewBasketForm;
 use super::MAX_SL_LEN;
 
 
+/// The message shown when a change to an archived basket is refused.
+pub const ARCHIVED_MESSAGE: &str = "This basket is archived and therefore read-only. \
+    Administrators can unarchive it in the settings.";
+
+/// How often the purge thread looks for deleted baskets to purge.
+const PURGE_INTERVAL_SECS: u64 = 60 * 60;
+
+/// Returns for how long deleted baskets can be restored before they are
+/// purged. Can be configured in days with the `BASKET_RESTORE_DAYS`
+/// environment variable and defaults to 30 days.
+pub fn restore_window() -> Duration {
+    let days = env::var("BASKET_RESTORE_DAYS")
+        .ok()
+        .and_then(|days| days.parse().ok())
+        .unwrap_or(30);
+    Duration::days(days)
+}
 
 pub fn is_valid_name(s: &str) -> bool {
     use std::ascii::AsciiExt;
@@ -24,6 +50,39 @@ pub fn is_valid_name(s: &str) -> bool {
 }
 
 
+/// Who can see a basket, apart from the users having a role in it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum BasketVisibility {
+    /// Visible to everyone, including anonymous visitors.
+    #[serde(rename = "public")]
+    Public,
+    /// Visible to all logged in users.
+    #[serde(rename = "internal")]
+    Internal,
+    /// Only visible to users having a role in the basket.
+    #[serde(rename = "private")]
+    Private,
+}
+
+impl BasketVisibility {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "public" => Some(BasketVisibility::Public),
+            "internal" => Some(BasketVisibility::Internal),
+            "private" => Some(BasketVisibility::Private),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BasketVisibility::Public => "public",
+            BasketVisibility::Internal => "internal",
+            BasketVisibility::Private => "private",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Identifiable, Queryable, Associations)]
 #[table_name = "baskets"]
 #[belongs_to(User)]
@@ -32,14 +91,60 @@ pub struct BasketRecord {
     name: String,
     user_id: i64,
     description: Option<String>,
-    public: bool,
     kind: String,
     forked_from: Option<i64>,
+    visibility: String,
+    archived: bool,
+    /// Deleted baskets are kept for a while so that they can be restored.
+    deleted_at: Option<DateTime<UTC>>,
+    is_template: bool,
+    created_at: DateTime<UTC>,
+    /// Last change of the content or the settings.
+    updated_at: DateTime<UTC>,
+    /// Number of users who starred the basket, see `model::star`.
+    star_count: i32,
 }
 
 impl BasketRecord {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Returns the id of the user owning the basket.
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    /// Returns the visibility of the basket. Falls back to private for
+    /// unknown values.
+    pub fn visibility(&self) -> BasketVisibility {
+        BasketVisibility::from_str(&self.visibility).unwrap_or(BasketVisibility::Private)
+    }
+
     pub fn is_public(&self) -> bool {
-        self.public
+        self.visibility() == BasketVisibility::Public
+    }
+
+    /// Whether the basket is offered as template for new baskets.
+    pub fn is_template(&self) -> bool {
+        self.is_template
+    }
+
+    pub fn created_at(&self) -> DateTime<UTC> {
+        self.created_at
+    }
+
+    pub fn updated_at(&self) -> DateTime<UTC> {
+        self.updated_at
+    }
+
+    pub fn star_count(&self) -> i32 {
+        self.star_count
+    }
+
+    /// Whether the basket is archived and thus read-only.
+    pub fn is_archived(&self) -> bool {
+        self.archived
     }
 
     pub fn name(&self) -> &str {
@@ -53,6 +158,12 @@ impl BasketRecord {
     pub fn kind(&self) -> &str {
         &self.kind
     }
+
+    /// Returns the registered kind of this basket. Falls back to the generic
+    /// kind for unknown values.
+    pub fn kind_info(&self) -> &'static BasketKind {
+        BasketKind::from_id(&self.kind).unwrap_or(&kind::GENERIC)
+    }
 }
 
 #[derive(Clone, Debug, Insertable)]
@@ -61,11 +172,12 @@ pub struct NewBasket {
     name: String,
     user_id: i64,
     description: Option<String>,
-    public: bool,
     kind: String,
     forked_from: Option<i64>,
+    visibility: String,
 }
 
+#[derive(Clone)]
 pub struct Basket {
     record: BasketRecord,
     user: PubUser,
@@ -76,6 +188,31 @@ impl Basket {
         Self { record, user }
     }
 
+    /// Loads the owners of the given records with a single query. The order
+    /// of the records is kept.
+    pub fn with_owners(records: Vec<BasketRecord>, db: &Db) -> Vec<Self> {
+        if records.is_empty() {
+            return vec![];
+        }
+
+        let owner_ids = records.iter().map(|r| r.user_id).collect::<Vec<_>>();
+        let owners = users::table
+            .filter(users::id.eq_any(owner_ids))
+            .load::<User>(&*db.conn())
+            .unwrap()
+            .into_iter()
+            .map(PubUser::from_user)
+            .collect::<Vec<_>>();
+
+        records.into_iter()
+            .filter_map(|record| {
+                owners.iter()
+                    .find(|o| o.id() == record.user_id)
+                    .map(|owner| Self { record, user: owner.clone() })
+            })
+            .collect()
+    }
+
     pub fn create(
         new: NewBasketForm,
         auth_user: &AuthUser,
@@ -83,7 +220,10 @@ impl Basket {
     ) -> Result<Self, CreateError> {
         use diesel::result::{Error as DieselError, DatabaseErrorKind};
 
-        if !has_permission(Some(auth_user), UserAction::CreateBasket { owner: &new.owner }) {
+        let can_create = has_permission(Some(auth_user), UserAction::CreateBasket {
+            owner: &new.owner,
+        }, db);
+        if !can_create {
             return Err(CreateError::NoPermission { owner: new.owner });
         }
 
@@ -93,11 +233,32 @@ impl Basket {
         if !basket::is_valid_name(&new.name) {
             return Err(CreateError::NameInvalid);
         }
+        // If a template is given, its content and kind are used for the new
+        // basket.
+        let template = if new.template.is_empty() {
+            None
+        } else {
+            match Self::load_template(&new.template, auth_user, db) {
+                Some(template) => Some(template),
+                None => return Err(CreateError::TemplateInvalid),
+            }
+        };
+        let kind = match template {
+            Some(ref template) => template.kind_info(),
+            None => match BasketKind::from_id(&new.kind) {
+                Some(kind) => kind,
+                None => return Err(CreateError::KindInvalid { kind: new.kind }),
+            },
+        };
+        let visibility = match BasketVisibility::from_str(&new.visibility) {
+            Some(visibility) => visibility,
+            None => return Err(CreateError::VisibilityInvalid),
+        };
 
-        // TODO: in case we introduce organizations, this need to change.
-        // We can unwrap, because we checked above, whether the current user
-        // can create baskets for the given owner. It should have returned
-        // "false" if the owner doesn't even exist.
+        // Organizations are stored as users, so the owner is always a user
+        // row. We can unwrap, because we checked above, whether the current
+        // user can create baskets for the given owner. It should have
+        // returned "false" if the owner doesn't even exist.
         let user = PubUser::from_username(&new.owner, db).unwrap();
 
         let description = if new.description.trim().is_empty() {
@@ -110,9 +271,9 @@ impl Basket {
             name: new.name,
             user_id: user.id(),
             description: description,
-            public: new.is_public,
-            kind: new.kind,
+            kind: kind.id.into(),
             forked_from: None,
+            visibility: visibility.as_str().into(),
         };
 
         let inserted = diesel::insert(&new_basket)
@@ -123,10 +284,91 @@ impl Basket {
             return Err(CreateError::NameAlreadyUsed);
         }
 
-        Ok(Self {
+        let basket = Self {
             record: inserted.unwrap(),
             user,
-        })
+        };
+
+        // Fill the new basket with the content of the template or the
+        // initial content of its kind. Copying a template doesn't copy its
+        // history, and the new basket is not a fork of the template.
+        let author = auth_user.commit_author(db)
+            .unwrap_or_else(|| Author::noreply(auth_user.username()));
+        let result = match template {
+            Some(ref template) => {
+                Content::open(template)
+                    .and_then(|source| Content::create(&basket.record).map(|c| (source, c)))
+                    .map_err(EditError::from)
+                    .and_then(|(source, content)| {
+                        if source.head().is_some() {
+                            let message = format!(
+                                "Initial commit from template {}",
+                                template.url(),
+                            );
+                            content.copy_from(&source, &author, &message).map(|_| ())
+                        } else {
+                            Ok(())
+                        }
+                    })
+            }
+            None if !kind.template.is_empty() => {
+                let changes = kind.template.iter()
+                    .map(|&(path, content)| Change::Create {
+                        path: path.into(),
+                        content: content.into(),
+                    })
+                    .collect::<Vec<_>>();
+
+                Content::create(&basket.record)
+                    .map_err(EditError::from)
+                    .and_then(|content| {
+                        content.commit(&changes, &author, "Initial commit", None)
+                    })
+                    .map(|_| ())
+            }
+            None => Ok(()),
+        };
+        if let Err(error) = result {
+            // Don't leave a basket without its initial content behind. The
+            // name can be used again right away.
+            let _ = Content::remove(basket.record.id);
+            diesel::delete(baskets::table.find(basket.record.id))
+                .execute(&*db.conn())
+                .unwrap();
+            return Err(CreateError::ContentFailed { error });
+        }
+        search::index_content(&basket.record, db);
+
+        let details = template.map(|t| t.url());
+        event::record(EventKind::Created, auth_user, &basket.record, details, db);
+
+        Ok(basket)
+    }
+
+    /// Loads the template given by its URL `/owner/name`, if the user can see
+    /// it and it is marked as template.
+    fn load_template(url: &str, auth_user: &AuthUser, db: &Db) -> Option<Self> {
+        let mut parts = url.trim_left_matches('/').splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(owner), Some(name)) => {
+                Self::load(name, owner, Some(auth_user), db)
+                    .and_then(|b| if b.is_template() { Some(b) } else { None })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns all templates visible to the user, sorted by owner and name.
+    pub fn templates(auth_user: &AuthUser, db: &Db) -> Vec<Self> {
+        let records = Visibility::of(Some(&**auth_user), db)
+            .visible_baskets()
+            .filter(baskets::is_template.eq(true))
+            .load::<BasketRecord>(&*db.conn())
+            .unwrap();
+
+        let mut templates = Self::with_owners(records, db);
+        templates.sort_by(|a, b| (a.owner(), a.name()).cmp(&(b.owner(), b.name())));
+        templates
     }
 
     pub fn load(
@@ -135,36 +377,348 @@ impl Basket {
         auth_user: Option<&AuthUser>,
         db: &Db,
     ) -> Option<Self> {
-        baskets::table
+        let row = baskets::table
             .inner_join(users::table)
             .filter(baskets::name.eq(name))
             .filter(users::username.eq(owner))
-            .first(&*db.conn())
+            .filter(baskets::deleted_at.is_null())
+            .first::<(BasketRecord, User)>(&*db.conn())
             .optional()
-            .unwrap()
-            .and_then(|(record, user)| {
-                let user = PubUser::from_user(user);
-                let can_view = has_permission(auth_user, UserAction::ViewBasket {
-                    owner: &user,
-                    basket: &record,
-                });
-                if can_view {
-                    Some(Self { record, user })
-                } else {
-                    None
-                }
-            })
+            .unwrap();
+        row.and_then(|(record, user)| {
+            let user = PubUser::from_user(user);
+            let can_view = has_permission(auth_user, UserAction::ViewBasket {
+                owner: &user,
+                basket: &record,
+            }, db);
+            if can_view {
+                Some(Self { record, user })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Loads the basket with the given id, if the user can see it.
+    pub fn load_by_id(id: i64, auth_user: Option<&AuthUser>, db: &Db) -> Option<Self> {
+        // Checking the permission needs a connection of its own.
+        let row = baskets::table
+            .inner_join(users::table)
+            .filter(baskets::id.eq(id))
+            .filter(baskets::deleted_at.is_null())
+            .first::<(BasketRecord, User)>(&*db.conn())
+            .optional()
+            .unwrap();
+        row.and_then(|(record, user)| {
+            let user = PubUser::from_user(user);
+            let can_view = has_permission(auth_user, UserAction::ViewBasket {
+                owner: &user,
+                basket: &record,
+            }, db);
+            if can_view {
+                Some(Self { record, user })
+            } else {
+                None
+            }
+        })
     }
 
     pub fn owner(&self) -> &str {
         self.user.username()
     }
 
+    pub fn owner_user(&self) -> &PubUser {
+        &self.user
+    }
+
+    /// Returns whether the given user may change the content of this basket.
+    pub fn can_edit(&self, auth_user: Option<&AuthUser>, db: &Db) -> bool {
+        has_permission(auth_user, UserAction::EditBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db)
+    }
+
+    /// Returns whether the given user may change the settings of this basket.
+    pub fn can_manage(&self, auth_user: Option<&AuthUser>, db: &Db) -> bool {
+        has_permission(auth_user, UserAction::ManageBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db)
+    }
+
+    /// Changes the description and the visibility of the basket. `None` keeps
+    /// the current value; an empty description removes it.
+    pub fn update(
+        &mut self,
+        description: Option<String>,
+        visibility: Option<BasketVisibility>,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), SettingsError> {
+        if self.is_archived() {
+            return Err(SettingsError::Archived);
+        }
+        if !self.can_manage(Some(auth_user), db) {
+            return Err(SettingsError::NoPermission);
+        }
+
+        let description = match description {
+            Some(ref d) if d.trim().is_empty() => None,
+            Some(d) => Some(d.trim().to_string()),
+            None => self.record.description.clone(),
+        };
+        let visibility = visibility.unwrap_or(self.visibility());
+
+        let now = UTC::now();
+        diesel::update(baskets::table.find(self.record.id))
+            .set((
+                baskets::description.eq(description.clone()),
+                baskets::visibility.eq(visibility.as_str()),
+                baskets::updated_at.eq(now),
+            ))
+            .execute(&*db.conn())
+            .unwrap();
+        self.record.description = description;
+        self.record.visibility = visibility.as_str().into();
+        self.record.updated_at = now;
+
+        let details = "description and visibility".to_string();
+        event::record(EventKind::SettingsChanged, auth_user, &self.record, Some(details), db);
+        Ok(())
+    }
+
+    /// Records that the content of the basket changed just now.
+    pub fn touch(&self, db: &Db) {
+        diesel::update(baskets::table.find(self.record.id))
+            .set(baskets::updated_at.eq(UTC::now()))
+            .execute(&*db.conn())
+            .unwrap();
+    }
+
+    /// Marks the basket as template or removes the mark.
+    pub fn set_template(
+        &mut self,
+        is_template: bool,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), SettingsError> {
+        if self.is_archived() {
+            return Err(SettingsError::Archived);
+        }
+        if !self.can_manage(Some(auth_user), db) {
+            return Err(SettingsError::NoPermission);
+        }
+
+        let now = UTC::now();
+        diesel::update(baskets::table.find(self.record.id))
+            .set((baskets::is_template.eq(is_template), baskets::updated_at.eq(now)))
+            .execute(&*db.conn())
+            .unwrap();
+        self.record.is_template = is_template;
+        self.record.updated_at = now;
+
+        let details = if is_template { "offered as template" } else { "no template anymore" };
+        let details = Some(details.to_string());
+        event::record(EventKind::SettingsChanged, auth_user, &self.record, details, db);
+        Ok(())
+    }
+
+    /// Returns whether the given user may transfer this basket to another
+    /// owner.
+    pub fn can_transfer(&self, auth_user: Option<&AuthUser>, db: &Db) -> bool {
+        has_permission(auth_user, UserAction::TransferBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db)
+    }
+
+    /// Returns whether the given user may archive and unarchive this basket.
+    pub fn can_archive(&self, auth_user: Option<&AuthUser>, db: &Db) -> bool {
+        has_permission(auth_user, UserAction::ArchiveBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db)
+    }
+
+    /// Archives or unarchives the basket.
+    pub fn set_archived(
+        &mut self,
+        archived: bool,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), ArchiveError> {
+        let can_archive = has_permission(Some(auth_user), UserAction::ArchiveBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db);
+        if !can_archive {
+            return Err(ArchiveError::NoPermission);
+        }
+
+        let now = UTC::now();
+        diesel::update(baskets::table.find(self.record.id))
+            .set((baskets::archived.eq(archived), baskets::updated_at.eq(now)))
+            .execute(&*db.conn())
+            .unwrap();
+        self.record.archived = archived;
+        self.record.updated_at = now;
+
+        let details = if archived { "archived" } else { "unarchived" };
+        let details = Some(details.to_string());
+        event::record(EventKind::SettingsChanged, auth_user, &self.record, details, db);
+        Ok(())
+    }
+
+    /// Marks the basket as deleted. It can be restored by administrators
+    /// within the restore window (see `restore_window`); afterwards the purge
+    /// thread removes it including its content.
+    pub fn delete(self, auth_user: &AuthUser, db: &Db) -> Result<(), DeleteError> {
+        if self.is_archived() {
+            return Err(DeleteError::Archived);
+        }
+        let can_delete = has_permission(Some(auth_user), UserAction::DeleteBasket {
+            owner: &self.user,
+            basket: &self.record,
+        }, db);
+        if !can_delete {
+            return Err(DeleteError::NoPermission);
+        }
+
+        diesel::update(baskets::table.find(self.record.id))
+            .set(baskets::deleted_at.eq(Some(UTC::now())))
+            .execute(&*db.conn())
+            .unwrap();
+        Ok(())
+    }
+
+    /// Restores the deleted basket `owner/name`, if the restore window isn't
+    /// over yet. Everyone who could delete the basket can restore it.
+    pub fn restore(
+        owner: &str,
+        name: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<Self, RestoreError> {
+        let cutoff = UTC::now() - restore_window();
+        let (record, user) = baskets::table
+            .inner_join(users::table)
+            .filter(baskets::name.eq(name))
+            .filter(users::username.eq(owner))
+            .filter(baskets::deleted_at.gt(cutoff))
+            .first::<(BasketRecord, User)>(&*db.conn())
+            .optional()
+            .unwrap()
+            .ok_or(RestoreError::NotFound)?;
+        let mut basket = Self { record, user: PubUser::from_user(user) };
+
+        let can_restore = has_permission(Some(auth_user), UserAction::DeleteBasket {
+            owner: &basket.user,
+            basket: &basket.record,
+        }, db);
+        if !can_restore {
+            // Don't leak the existence of private baskets.
+            return Err(RestoreError::NotFound);
+        }
+
+        diesel::update(baskets::table.find(basket.record.id))
+            .set(baskets::deleted_at.eq(None::<DateTime<UTC>>))
+            .execute(&*db.conn())
+            .unwrap();
+        basket.record.deleted_at = None;
+        Ok(basket)
+    }
+
     pub fn url(&self) -> String {
         format!("/{}/{}", self.user.username(), self.record.name)
     }
 }
 
+/// A basket which was deleted, but can still be restored.
+pub struct DeletedBasket {
+    basket: Basket,
+    deleted_at: DateTime<UTC>,
+}
+
+impl DeletedBasket {
+    /// Returns all deleted baskets the user can restore: those owned by the
+    /// user and by the organizations the user owns. Sorted by the time of
+    /// deletion, most recent first.
+    pub fn list(auth_user: &AuthUser, db: &Db) -> Vec<Self> {
+        let mut owner_ids = organization::owned_organization_ids(auth_user, db);
+        owner_ids.push(auth_user.id());
+
+        let cutoff = UTC::now() - restore_window();
+        baskets::table
+            .inner_join(users::table)
+            .filter(baskets::user_id.eq_any(owner_ids))
+            .filter(baskets::deleted_at.gt(cutoff))
+            .order(baskets::deleted_at.desc())
+            .load::<(BasketRecord, User)>(&*db.conn())
+            .unwrap()
+            .into_iter()
+            .filter_map(|(record, user)| {
+                record.deleted_at.map(|deleted_at| DeletedBasket {
+                    basket: Basket::from_parts(record, PubUser::from_user(user)),
+                    deleted_at,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Serialize for DeletedBasket {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let purge_at = self.deleted_at + restore_window();
+        let mut s = serializer.serialize_struct("DeletedBasket", 3)?;
+        s.serialize_field("basket", &self.basket)?;
+        s.serialize_field("deleted_at", &self.deleted_at.format("%Y-%m-%d %H:%M").to_string())?;
+        s.serialize_field("purge_at", &purge_at.format("%Y-%m-%d").to_string())?;
+        s.end()
+    }
+}
+
+/// Removes all baskets whose restore window is over from the database and
+/// from disk. Returns the number of purged baskets. Baskets which can't be
+/// purged are logged and tried again next time.
+pub fn purge_deleted(db: &Db) -> QueryResult<usize> {
+    let cutoff = UTC::now() - restore_window();
+    let ids = baskets::table
+        .filter(baskets::deleted_at.lt(cutoff))
+        .select(baskets::id)
+        .load::<i64>(&*db.conn())?;
+
+    let mut purged = 0;
+    for id in ids {
+        // The content is removed first: if that fails, the row stays and we
+        // try again next time.
+        if let Err(e) = Content::remove(id) {
+            error!("failed to remove the content of deleted basket {}: {}", id, e);
+            continue;
+        }
+        match diesel::delete(baskets::table.find(id)).execute(&*db.conn()) {
+            Ok(_) => purged += 1,
+            Err(e) => error!("failed to purge deleted basket {}: {}", id, e),
+        }
+    }
+    Ok(purged)
+}
+
+/// Starts a thread which regularly purges deleted baskets whose restore
+/// window is over.
+pub fn spawn_purge_thread(db: Db) {
+    let interval = time::Duration::from_secs(PURGE_INTERVAL_SECS);
+    model::spawn_periodic("purge", interval, move || {
+        if let Err(e) = purge_deleted(&db) {
+            error!("failed to purge deleted baskets: {}", e);
+        }
+    });
+}
+
 impl Deref for Basket {
     type Target = BasketRecord;
     fn deref(&self) -> &Self::Target {
@@ -178,13 +732,19 @@ impl Serialize for Basket {
     {
         use serde::ser::SerializeStruct;
 
-        let mut s = serializer.serialize_struct("Basket", 6)?;
+        let mut s = serializer.serialize_struct("Basket", 12)?;
         // Skipping id: the id should never be sent to the user
         s.serialize_field("name", self.name())?;
         s.serialize_field("description", &self.description())?;
-        s.serialize_field("is_public", &self.is_public())?;
+        s.serialize_field("visibility", &self.visibility())?;
+        s.serialize_field("is_archived", &self.is_archived())?;
+        s.serialize_field("is_template", &self.is_template())?;
+        s.serialize_field("created_at", &self.created_at().to_rfc3339())?;
+        s.serialize_field("updated_at", &self.updated_at().to_rfc3339())?;
+        s.serialize_field("stars", &self.star_count())?;
         s.serialize_field("url", &self.url())?;
         s.serialize_field("kind", self.kind())?;
+        s.serialize_field("kind_name", self.kind_info().name)?;
         s.serialize_field("owner", self.owner())?;
         s.end()
     }
@@ -199,6 +759,17 @@ pub enum CreateError {
     NameEmpty,
     NameInvalid,
     NameAlreadyUsed,
+    /// The given basket kind is not registered.
+    KindInvalid {
+        kind: String,
+    },
+    VisibilityInvalid,
+    /// The template doesn't exist, isn't visible or isn't a template.
+    TemplateInvalid,
+    /// The initial content couldn't be written. Nothing was created.
+    ContentFailed {
+        error: EditError,
+    },
 }
 
 impl fmt::Display for CreateError {
@@ -223,9 +794,89 @@ impl fmt::Display for CreateError {
             }
             NameAlreadyUsed => {
                 "A repository with the given name already exists for the \
-                given owner"
+                given owner (deleted baskets keep their name until they are \
+                purged)"
                     .fmt(f)
             }
+            KindInvalid { ref kind } => {
+                write!(f, "'{}' is not a valid basket kind!", kind)
+            }
+            VisibilityInvalid => {
+                "The visibility has to be one of 'public', 'internal' or 'private'.".fmt(f)
+            }
+            TemplateInvalid => {
+                "The chosen template doesn't exist!".fmt(f)
+            }
+            ContentFailed { ref error } => {
+                write!(f, "The initial content of the basket couldn't be created: {}", error)
+            }
+        }
+    }
+}
+
+pub enum SettingsError {
+    NoPermission,
+    Archived,
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettingsError::NoPermission => {
+                "You don't have the permission to change the settings of this basket!".fmt(f)
+            }
+            SettingsError::Archived => {
+                ARCHIVED_MESSAGE.fmt(f)
+            }
+        }
+    }
+}
+
+pub enum ArchiveError {
+    NoPermission,
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArchiveError::NoPermission => {
+                "You don't have the permission to archive this basket!".fmt(f)
+            }
+        }
+    }
+}
+
+pub enum RestoreError {
+    /// There is no deleted basket with this name the user could restore, or
+    /// its restore window is over.
+    NotFound,
+}
+
+impl fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RestoreError::NotFound => {
+                "This basket can't be restored anymore.".fmt(f)
+            }
+        }
+    }
+}
+
+pub enum DeleteError {
+    NoPermission,
+    /// Archived baskets have to be unarchived before they can be deleted.
+    Archived,
+}
+
+impl fmt::Display for DeleteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DeleteError::NoPermission => {
+                "You don't have the permission to delete this basket!".fmt(f)
+            }
+            DeleteError::Archived => {
+                ARCHIVED_MESSAGE.fmt(f)
+            }
         }
     }
 }
@@ -0,0 +1,443 @@
+//! Bookmarks of "bookmarks" baskets.
+//!
+//! Every bookmark is stored as JSON file `bookmarks/<id>.json` in the
+//! basket's repository. The id is derived from the URL, so adding the same URL
+//! twice updates the existing bookmark.
+
+use chrono::offset::utc::UTC;
+use git2::{ObjectType, Oid};
+use serde_json;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use model::content::{Change, Content};
+use super::MAX_SL_LEN;
+
+
+/// Directory containing all bookmarks.
+const BOOKMARK_DIR: &str = "bookmarks/";
+
+/// Number of hex digits of the bookmark id.
+const ID_LEN: usize = 12;
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bookmark {
+    /// Not stored in the file, but derived from the path.
+    #[serde(skip_deserializing, default)]
+    pub id: String,
+    pub url: String,
+    pub title: String,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Seconds since the unix epoch.
+    pub added: i64,
+}
+
+impl Bookmark {
+    /// Creates a new bookmark, validating and normalizing the given values.
+    /// `tags` is a comma separated list.
+    pub fn new(url: &str, title: &str, notes: &str, tags: &str) -> Result<Self, BookmarkError> {
+        let url = url.trim();
+        if !is_valid_url(url) {
+            return Err(BookmarkError::UrlInvalid);
+        }
+
+        let title = title.trim();
+        Ok(Bookmark {
+            id: id_for(url),
+            url: url.into(),
+            title: if title.is_empty() { url.into() } else { title.into() },
+            notes: notes.trim().into(),
+            tags: parse_tags(tags),
+            added: UTC::now().timestamp(),
+        })
+    }
+
+    /// Returns the path of the file storing this bookmark.
+    pub fn path(&self) -> String {
+        path_for(&self.id)
+    }
+
+    /// Returns the change that stores this bookmark in the repository.
+    pub fn to_change(&self, exists: bool) -> Change {
+        let content = serde_json::to_string_pretty(self).unwrap() + "\n";
+        if exists {
+            Change::Update { path: self.path(), content }
+        } else {
+            Change::Create { path: self.path(), content }
+        }
+    }
+}
+
+/// Returns the id of the bookmark for the given URL: a prefix of the git hash
+/// of the URL.
+pub fn id_for(url: &str) -> String {
+    let hash = Oid::hash_object(ObjectType::Blob, url.as_bytes())
+        .expect("failed to hash URL")
+        .to_string();
+    hash[..ID_LEN].to_string()
+}
+
+fn path_for(id: &str) -> String {
+    format!("{}{}.json", BOOKMARK_DIR, id)
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN && id.chars().all(|c| c.is_digit(16))
+}
+
+fn is_valid_url(url: &str) -> bool {
+    let has_scheme = ["http://", "https://", "ftp://", "mailto:"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme));
+
+    has_scheme && url.len() > 8 && !url.chars().any(|c| c.is_whitespace())
+}
+
+/// Parses a comma separated list of tags. Tags are lowercased and
+/// deduplicated.
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    let mut out = tags.split(',')
+        .map(|t| t.trim().to_lowercase())
+        .filter(|t| !t.is_empty() && t.len() < MAX_SL_LEN)
+        .collect::<Vec<_>>();
+    out.sort();
+    out.dedup();
+    out
+}
+
+/// A tag together with the number of bookmarks using it.
+#[derive(Clone, Debug, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// Read access to all bookmarks of a basket.
+pub struct Bookmarks<'a> {
+    content: &'a Content,
+}
+
+impl<'a> Bookmarks<'a> {
+    pub fn new(content: &'a Content) -> Self {
+        Self { content }
+    }
+
+    /// Returns all bookmarks, newest first. Files which can't be parsed are
+    /// skipped.
+    pub fn all(&self) -> Vec<Bookmark> {
+        let mut out = self.content.files()
+            .into_iter()
+            .filter(|f| f.path.starts_with(BOOKMARK_DIR) && f.path.ends_with(".json"))
+            .filter_map(|f| {
+                let id = f.path[BOOKMARK_DIR.len()..f.path.len() - ".json".len()].to_string();
+                self.load(&id)
+            })
+            .collect::<Vec<_>>();
+
+        out.sort_by(|a, b| b.added.cmp(&a.added).then_with(|| a.title.cmp(&b.title)));
+        out
+    }
+
+    /// Returns all bookmarks with the given tag, newest first.
+    pub fn with_tag(&self, tag: &str) -> Vec<Bookmark> {
+        self.all()
+            .into_iter()
+            .filter(|b| b.tags.iter().any(|t| t == tag))
+            .collect()
+    }
+
+    /// Returns the bookmark with the given id.
+    pub fn get(&self, id: &str) -> Option<Bookmark> {
+        if is_valid_id(id) {
+            self.load(id)
+        } else {
+            None
+        }
+    }
+
+    fn load(&self, id: &str) -> Option<Bookmark> {
+        self.content.read_text(&path_for(id))
+            .and_then(|json| serde_json::from_str::<Bookmark>(&json).ok())
+            .map(|mut b| {
+                b.id = id.into();
+                b
+            })
+    }
+
+    /// Returns whether a bookmark with the given id exists.
+    pub fn exists(&self, id: &str) -> bool {
+        self.content.exists(&path_for(id))
+    }
+
+    /// Returns all tags with their number of uses, sorted alphabetically.
+    pub fn tags(&self) -> Vec<TagCount> {
+        let mut counts = BTreeMap::new();
+        for bookmark in self.all() {
+            for tag in bookmark.tags {
+                *counts.entry(tag).or_insert(0) += 1;
+            }
+        }
+
+        counts.into_iter()
+            .map(|(tag, count)| TagCount { tag, count })
+            .collect()
+    }
+}
+
+
+/// Exports the given bookmarks in the Netscape bookmark file format, which is
+/// understood by all common browsers.
+pub fn export_html(title: &str, bookmarks: &[Bookmark]) -> String {
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE NETSCAPE-Bookmark-file-1>\n");
+    out.push_str("<!-- This is an automatically generated file. -->\n");
+    out.push_str("<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n");
+    out.push_str(&format!("<TITLE>{}</TITLE>\n", escape(title)));
+    out.push_str(&format!("<H1>{}</H1>\n", escape(title)));
+    out.push_str("<DL><p>\n");
+
+    for b in bookmarks {
+        out.push_str(&format!(
+            "    <DT><A HREF=\"{}\" ADD_DATE=\"{}\" TAGS=\"{}\">{}</A>\n",
+            escape(&b.url),
+            b.added,
+            escape(&b.tags.join(",")),
+            escape(&b.title),
+        ));
+        if !b.notes.is_empty() {
+            out.push_str(&format!("    <DD>{}\n", escape(&b.notes)));
+        }
+    }
+
+    out.push_str("</DL><p>\n");
+    out
+}
+
+/// Parses a file in the Netscape bookmark file format. The names of the
+/// folders containing a bookmark are added to its tags, in addition to the
+/// tags from the `TAGS` attribute. Entries with invalid URLs are skipped.
+pub fn import_html(html: &str) -> Vec<Bookmark> {
+    let mut out: Vec<Bookmark> = Vec::new();
+
+    // Names of the currently open folders. A folder is opened by an `<H3>`
+    // and closed by the `</DL>` ending the following list.
+    let mut folders: Vec<String> = Vec::new();
+    let mut pending_folder: Option<String> = None;
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[1..end];
+        let after_tag = &rest[end + 1..];
+        let tag_name = tag.split_whitespace().next().unwrap_or("").to_uppercase();
+
+        match tag_name.as_str() {
+            "H3" => {
+                pending_folder = Some(decode(text_until_tag(after_tag).trim()));
+            }
+            "DL" => {
+                if let Some(folder) = pending_folder.take() {
+                    folders.push(folder);
+                } else {
+                    // Lists without heading (like the outermost one) still
+                    // need an entry, so that `</DL>` pops the right one.
+                    folders.push(String::new());
+                }
+            }
+            "/DL" => {
+                folders.pop();
+            }
+            "A" => {
+                let url = attribute(tag, "HREF").unwrap_or_default();
+                let title = decode(text_until_tag(after_tag).trim());
+                let tags = attribute(tag, "TAGS").unwrap_or_default();
+                let folder_tags = folders.iter()
+                    .filter(|f| !f.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                let all_tags = format!("{},{}", tags, folder_tags);
+                if let Ok(mut bookmark) = Bookmark::new(&url, &title, "", &all_tags) {
+                    if let Some(added) = attribute(tag, "ADD_DATE").and_then(|d| d.parse().ok()) {
+                        bookmark.added = added;
+                    }
+                    out.push(bookmark);
+                }
+            }
+            "DD" => {
+                // A description belongs to the bookmark right before it.
+                if let Some(last) = out.last_mut() {
+                    if last.notes.is_empty() {
+                        last.notes = decode(text_until_tag(after_tag).trim());
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        rest = after_tag;
+    }
+
+    // Later entries win if the same URL appears multiple times.
+    let mut by_id = BTreeMap::new();
+    for bookmark in out {
+        by_id.insert(bookmark.id.clone(), bookmark);
+    }
+    by_id.into_iter().map(|(_, b)| b).collect()
+}
+
+/// Returns the text up to the next tag.
+fn text_until_tag(s: &str) -> &str {
+    match s.find('<') {
+        Some(end) => &s[..end],
+        None => s,
+    }
+}
+
+/// Extracts the value of an attribute from the inside of a tag. Attribute
+/// names are case insensitive.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let upper = tag.to_uppercase();
+    let needle = format!("{}=\"", name.to_uppercase());
+
+    // We search in the uppercased string, but extract from the original one.
+    // This works since uppercasing doesn't change the byte length of ASCII
+    // and attribute names are ASCII.
+    upper.find(&needle).and_then(|pos| {
+        let start = pos + needle.len();
+        if upper.len() != tag.len() {
+            return None;
+        }
+        tag[start..].find('"').map(|len| decode(&tag[start..start + len]))
+    })
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn decode(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+
+pub enum BookmarkError {
+    UrlInvalid,
+}
+
+impl fmt::Display for BookmarkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BookmarkError::UrlInvalid => {
+                "The URL is invalid! It has to start with 'http://', 'https://', \
+                'ftp://' or 'mailto:'."
+                    .fmt(f)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{export_html, id_for, import_html, is_valid_id, parse_tags, Bookmark};
+
+    fn find<'a>(bookmarks: &'a [Bookmark], url: &str) -> &'a Bookmark {
+        bookmarks.iter().find(|b| b.url == url).expect("bookmark missing")
+    }
+
+    #[test]
+    fn new_validates_and_normalizes() {
+        let b = Bookmark::new(" https://example.com/ ", "  ", " notes ", "Web, web,a")
+            .ok()
+            .unwrap();
+        assert_eq!(b.url, "https://example.com/");
+        assert_eq!(b.title, "https://example.com/");
+        assert_eq!(b.notes, "notes");
+        assert_eq!(b.tags, vec!["a", "web"]);
+        assert_eq!(b.id, id_for("https://example.com/"));
+        assert!(is_valid_id(&b.id));
+
+        assert!(Bookmark::new("example.com", "", "", "").is_err());
+        assert!(Bookmark::new("javascript:alert(1)", "", "", "").is_err());
+        assert!(Bookmark::new("https://exa mple.com", "", "", "").is_err());
+    }
+
+    #[test]
+    fn tags() {
+        assert_eq!(parse_tags(" Rust, web ,rust,, "), vec!["rust", "web"]);
+        assert!(parse_tags(" , ").is_empty());
+    }
+
+    #[test]
+    fn import() {
+        let html = "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+            <TITLE>Bookmarks</TITLE>\n\
+            <DL><p>\n\
+            <DT><H3 ADD_DATE=\"1\">Dev</H3>\n\
+            <DL><p>\n\
+            <DT><a href=\"https://www.rust-lang.org/\" add_date=\"1500000000\" \
+                tags=\"Lang\">Rust &amp; friends</a>\n\
+            <DD>A &quot;systems&quot; language\n\
+            </DL><p>\n\
+            <DT><A HREF=\"javascript:alert(1)\">Bad</A>\n\
+            <DT><A HREF=\"https://example.com/\">Example</A>\n\
+            <DT><A HREF=\"https://example.com/\">Example again</A>\n\
+            </DL><p>\n";
+        let bookmarks = import_html(html);
+        assert_eq!(bookmarks.len(), 2);
+
+        let rust = find(&bookmarks, "https://www.rust-lang.org/");
+        assert_eq!(rust.title, "Rust & friends");
+        assert_eq!(rust.notes, "A \"systems\" language");
+        assert_eq!(rust.tags, vec!["dev", "lang"]);
+        assert_eq!(rust.added, 1500000000);
+
+        // Outside of the folder, and the later duplicate wins.
+        let example = find(&bookmarks, "https://example.com/");
+        assert_eq!(example.title, "Example again");
+        assert!(example.tags.is_empty());
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        let mut first = Bookmark::new(
+            "https://example.com/?a=1&b=\"2\"",
+            "<Tags> & \"quotes\"",
+            "Notes & <more>",
+            "x, y",
+        ).ok().unwrap();
+        first.added = 1234;
+        let second = Bookmark::new("mailto:someone@example.com", "Mail", "", "").ok().unwrap();
+
+        let html = export_html("My <bookmarks>", &[first.clone(), second.clone()]);
+        assert!(html.contains("<TITLE>My &lt;bookmarks&gt;</TITLE>"));
+
+        let imported = import_html(&html);
+        assert_eq!(imported.len(), 2);
+        for original in &[first, second] {
+            let b = find(&imported, &original.url);
+            assert_eq!(b.id, original.id);
+            assert_eq!(b.title, original.title);
+            assert_eq!(b.notes, original.notes);
+            assert_eq!(b.tags, original.tags);
+            assert_eq!(b.added, original.added);
+        }
+    }
+}
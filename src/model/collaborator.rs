@@ -0,0 +1,286 @@
+//! Users other than the owner who have access to a basket.
+//!
+//! The owner invites a user with a role. The invited user only gains access
+//! after accepting the invitation.
+
+use chrono::DateTime;
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use std::fmt;
+
+use db::Db;
+use db::schema::{basket_collaborators, baskets, users};
+use model::{AuthUser, Basket, BasketRecord, PubUser, User};
+use model::basket::ARCHIVED_MESSAGE;
+
+
+/// The access level of a collaborator. Every role includes the permissions of
+/// the roles before it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub enum Role {
+    /// Can see the basket, even if it is private.
+    #[serde(rename = "read")]
+    Read,
+    /// Can change the content of the basket and triage issues.
+    #[serde(rename = "write")]
+    Write,
+    /// Can change the settings of the basket, manage its collaborators and
+    /// delete it, just like the owner.
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl Role {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(Role::Read),
+            "write" => Some(Role::Write),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Role::Read => "read",
+            Role::Write => "write",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Associations)]
+#[table_name = "basket_collaborators"]
+#[belongs_to(User)]
+struct CollaboratorRecord {
+    basket_id: i64,
+    user_id: i64,
+    role: String,
+    accepted: bool,
+    created_at: DateTime<UTC>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "basket_collaborators"]
+struct NewCollaborator {
+    basket_id: i64,
+    user_id: i64,
+    role: String,
+}
+
+/// A user with access to a basket, or who was invited to it.
+#[derive(Clone, Serialize)]
+pub struct Collaborator {
+    pub user: PubUser,
+    pub role: Role,
+    /// Whether the invitation was accepted yet.
+    pub accepted: bool,
+}
+
+/// A pending invitation of a user to a basket.
+#[derive(Serialize)]
+pub struct Invitation {
+    pub basket: Basket,
+    pub role: Role,
+}
+
+/// Returns the role of the given user in the basket, if the user accepted an
+/// invitation. Doesn't take ownership into account.
+pub fn role_of(basket: &BasketRecord, user: &PubUser, db: &Db) -> Option<Role> {
+    basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .filter(basket_collaborators::user_id.eq(user.id()))
+        .filter(basket_collaborators::accepted.eq(true))
+        .select(basket_collaborators::role)
+        .first::<String>(&*db.conn())
+        .optional()
+        .unwrap()
+        .and_then(|role| Role::from_str(&role))
+}
+
+/// Returns all collaborators of the basket including pending invitations,
+/// sorted by username.
+pub fn list(basket: &BasketRecord, db: &Db) -> Vec<Collaborator> {
+    basket_collaborators::table
+        .inner_join(users::table)
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .order(users::username)
+        .load::<(CollaboratorRecord, User)>(&*db.conn())
+        .unwrap()
+        .into_iter()
+        .filter_map(|(record, user)| {
+            Role::from_str(&record.role).map(|role| Collaborator {
+                user: PubUser::from_user(user),
+                role,
+                accepted: record.accepted,
+            })
+        })
+        .collect()
+}
+
+/// Returns the ids of all baskets the user collaborates on.
+pub fn basket_ids_of(user: &PubUser, db: &Db) -> Vec<i64> {
+    basket_collaborators::table
+        .filter(basket_collaborators::user_id.eq(user.id()))
+        .filter(basket_collaborators::accepted.eq(true))
+        .select(basket_collaborators::basket_id)
+        .load(&*db.conn())
+        .unwrap()
+}
+
+/// Invites the user to the basket or changes the role of an existing
+/// collaborator. The caller has to check the permissions.
+pub fn invite(
+    basket: &Basket,
+    username: &str,
+    role: Role,
+    db: &Db,
+) -> Result<(), CollaboratorError> {
+    let user = match PubUser::from_username(username, db) {
+        Some(user) => user,
+        None => return Err(CollaboratorError::UserNotFound { username: username.into() }),
+    };
+    if user.id() == basket.owner_user().id() {
+        return Err(CollaboratorError::IsOwner);
+    }
+
+    let conn = db.conn();
+    let existing = basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .filter(basket_collaborators::user_id.eq(user.id()));
+    let updated = diesel::update(existing)
+        .set(basket_collaborators::role.eq(role.as_str()))
+        .execute(&*conn)
+        .unwrap();
+
+    if updated == 0 {
+        let new = NewCollaborator {
+            basket_id: basket.id(),
+            user_id: user.id(),
+            role: role.as_str().into(),
+        };
+        diesel::insert(&new)
+            .into(basket_collaborators::table)
+            .execute(&*conn)
+            .unwrap();
+    }
+
+    Ok(())
+}
+
+/// Removes the user from the collaborators, also cancelling a pending
+/// invitation. Returns whether the user was a collaborator.
+pub fn remove(basket: &BasketRecord, user: &PubUser, db: &Db) -> bool {
+    let existing = basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .filter(basket_collaborators::user_id.eq(user.id()));
+    diesel::delete(existing)
+        .execute(&*db.conn())
+        .unwrap() > 0
+}
+
+/// Returns all invitations the user hasn't responded to yet.
+pub fn pending_invitations(auth_user: &AuthUser, db: &Db) -> Vec<Invitation> {
+    let conn = db.conn();
+    let pending = basket_collaborators::table
+        .filter(basket_collaborators::user_id.eq(auth_user.id()))
+        .filter(basket_collaborators::accepted.eq(false))
+        .select((basket_collaborators::basket_id, basket_collaborators::role))
+        .load::<(i64, String)>(&*conn)
+        .unwrap();
+    if pending.is_empty() {
+        return vec![];
+    }
+
+    let ids = pending.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+    baskets::table
+        .inner_join(users::table)
+        .filter(baskets::id.eq_any(ids))
+        .filter(baskets::deleted_at.is_null())
+        .order((users::username, baskets::name))
+        .load::<(BasketRecord, User)>(&*conn)
+        .unwrap()
+        .into_iter()
+        .filter_map(|(record, owner)| {
+            pending.iter()
+                .find(|&&(id, _)| id == record.id())
+                .and_then(|&(_, ref role)| Role::from_str(role))
+                .map(|role| Invitation {
+                    basket: Basket::from_parts(record, PubUser::from_user(owner)),
+                    role,
+                })
+        })
+        .collect()
+}
+
+/// Accepts or declines the user's invitation to the basket `owner/name`.
+/// Returns `false` if there is no such invitation.
+pub fn respond(owner: &str, name: &str, auth_user: &AuthUser, accept: bool, db: &Db) -> bool {
+    let conn = db.conn();
+    let basket_id = baskets::table
+        .inner_join(users::table)
+        .filter(baskets::name.eq(name))
+        .filter(users::username.eq(owner))
+        .filter(baskets::deleted_at.is_null())
+        .select(baskets::id)
+        .first::<i64>(&*conn)
+        .optional()
+        .unwrap();
+    let basket_id = match basket_id {
+        Some(id) => id,
+        None => return false,
+    };
+
+    let invitation = basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket_id))
+        .filter(basket_collaborators::user_id.eq(auth_user.id()))
+        .filter(basket_collaborators::accepted.eq(false));
+
+    let changed = if accept {
+        diesel::update(invitation)
+            .set(basket_collaborators::accepted.eq(true))
+            .execute(&*conn)
+    } else {
+        diesel::delete(invitation).execute(&*conn)
+    };
+    changed.unwrap() > 0
+}
+
+
+pub enum CollaboratorError {
+    NoPermission,
+    /// The collaborators of archived baskets can't be changed.
+    Archived,
+    UserNotFound {
+        username: String,
+    },
+    /// The owner can't be added as collaborator.
+    IsOwner,
+    RoleInvalid,
+}
+
+impl fmt::Display for CollaboratorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CollaboratorError::*;
+
+        match *self {
+            NoPermission => {
+                "You don't have the permission to manage the collaborators of this basket!"
+                    .fmt(f)
+            }
+            Archived => {
+                ARCHIVED_MESSAGE.fmt(f)
+            }
+            UserNotFound { ref username } => {
+                write!(f, "There is no user named '{}'!", username)
+            }
+            IsOwner => {
+                "The owner of a basket can't be added as collaborator.".fmt(f)
+            }
+            RoleInvalid => {
+                "The role has to be one of 'read', 'write' or 'admin'.".fmt(f)
+            }
+        }
+    }
+}
@@ -0,0 +1,678 @@
+//! Access to the actual content of a basket.
+//!
+//! Every basket is backed by a bare git repository on disk. The repository is
+//! named after the basket's id (not its name), so that renaming a basket or
+//! transferring it to another owner doesn't require touching the file system.
+
+use git2::{self, ErrorCode, Index, IndexEntry, IndexTime, Oid, Repository, Signature, Tree};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use model::BasketRecord;
+
+
+/// The only branch we currently work with.
+pub const DEFAULT_BRANCH: &str = "master";
+
+/// File mode of a regular, non-executable file in git.
+const FILE_MODE: u32 = 0o100644;
+
+
+/// Returns the directory in which all basket repositories are stored. Can be
+/// configured with the `BASKET_DIR` environment variable.
+pub fn storage_dir() -> PathBuf {
+    env::var("BASKET_DIR").unwrap_or("data/baskets/".into()).into()
+}
+
+/// Checks whether the given string is a valid path of a file inside a
+/// basket. We only allow relative paths without any `.` or `..` components
+/// and forbid touching anything git related.
+pub fn is_valid_path(path: &str) -> bool {
+    let p = Path::new(path);
+
+    !path.is_empty()
+        && path.len() < super::MAX_SL_LEN * 2
+        && !path.ends_with('/')
+        && !path.contains('\\')
+        && p.components().all(|c| match c {
+            Component::Normal(s) => s != ".git",
+            _ => false,
+        })
+}
+
+/// The author of a change. Is used as author and committer of the resulting
+/// commit.
+#[derive(Clone, Debug)]
+pub struct Author {
+    pub name: String,
+    pub email: String,
+}
+
+impl Author {
+    /// An author for commits not created through the editor, used if the
+    /// user has no primary email address.
+    pub fn noreply(username: &str) -> Self {
+        Author {
+            name: username.into(),
+            email: format!("{}@users.noreply.basgit", username),
+        }
+    }
+}
+
+/// A single modification of the basket's content.
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// Creates a new file. Fails if the file already exists.
+    Create {
+        path: String,
+        content: String,
+    },
+    /// Replaces the content of an existing file.
+    Update {
+        path: String,
+        content: String,
+    },
+    /// Removes an existing file.
+    Delete {
+        path: String,
+    },
+    /// Moves an existing file to a new path, optionally changing its content
+    /// at the same time.
+    Rename {
+        from: String,
+        to: String,
+        content: Option<String>,
+    },
+}
+
+/// A file in the basket's repository.
+#[derive(Clone, Debug, Serialize)]
+pub struct FileEntry {
+    pub path: String,
+    pub size: usize,
+}
+
+/// A commit in the basket's history.
+#[derive(Clone, Debug, Serialize)]
+pub struct CommitInfo {
+    pub id: String,
+    pub short_id: String,
+    pub message: String,
+    pub author_name: String,
+    pub author_email: String,
+    /// Seconds since the unix epoch.
+    pub time: i64,
+}
+
+impl<'a> From<git2::Commit<'a>> for CommitInfo {
+    fn from(commit: git2::Commit<'a>) -> Self {
+        let id = commit.id().to_string();
+        let author = commit.author();
+        Self {
+            short_id: id[..7].to_string(),
+            id: id,
+            message: commit.message().unwrap_or("").trim().to_string(),
+            author_name: author.name().unwrap_or("").to_string(),
+            author_email: author.email().unwrap_or("").to_string(),
+            time: commit.time().seconds(),
+        }
+    }
+}
+
+/// The content of a basket, backed by a bare git repository.
+pub struct Content {
+    /// `None` if the repository wasn't created yet. The basket is empty
+    /// then.
+    repo: Option<Repository>,
+}
+
+impl Content {
+    /// Opens the repository of the given basket for reading. Nothing is
+    /// created on disk: if the repository doesn't exist, the basket is
+    /// treated as empty.
+    pub fn open(basket: &BasketRecord) -> Result<Self, git2::Error> {
+        let path = Self::repo_path(basket.id());
+        let repo = if path.exists() {
+            Some(Repository::open_bare(&path)?)
+        } else {
+            None
+        };
+
+        Ok(Self { repo })
+    }
+
+    /// Opens the repository of the given basket for writing. If it doesn't
+    /// exist yet, an empty repository is created.
+    pub fn create(basket: &BasketRecord) -> Result<Self, git2::Error> {
+        let path = Self::repo_path(basket.id());
+        let repo = if path.exists() {
+            Repository::open_bare(&path)?
+        } else {
+            Repository::init_bare(&path)?
+        };
+
+        Ok(Self { repo: Some(repo) })
+    }
+
+    /// Returns the repository, which only writing methods may rely on.
+    fn repo(&self) -> Result<&Repository, git2::Error> {
+        self.repo.as_ref().ok_or_else(|| {
+            git2::Error::from_str("the repository was opened for reading only")
+        })
+    }
+
+    /// Removes the repository of the basket with the given id from disk.
+    /// Does nothing if the repository doesn't exist.
+    pub fn remove(basket_id: i64) -> io::Result<()> {
+        let path = Self::repo_path(basket_id);
+        if path.exists() {
+            fs::remove_dir_all(path)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the path of the repository of the basket with the given id.
+    pub fn repo_path(basket_id: i64) -> PathBuf {
+        storage_dir().join(format!("{}.git", basket_id))
+    }
+
+    /// Returns the commit the default branch currently points to or `None` if
+    /// there are no commits yet.
+    pub fn head(&self) -> Option<Oid> {
+        self.repo.as_ref().and_then(|repo| repo.refname_to_id(&branch_ref()).ok())
+    }
+
+    fn head_tree(&self) -> Option<Tree> {
+        self.head()
+            .and_then(|oid| self.repo.as_ref().and_then(|repo| repo.find_commit(oid).ok()))
+            .and_then(|commit| commit.tree().ok())
+    }
+
+    /// Lists all files in the basket, sorted by path.
+    pub fn files(&self) -> Vec<FileEntry> {
+        let mut out = Vec::new();
+        if let (Some(repo), Some(tree)) = (self.repo.as_ref(), self.head_tree()) {
+            self.collect_files(repo, &tree, "", &mut out);
+        }
+        out.sort_by(|a, b| a.path.cmp(&b.path));
+        out
+    }
+
+    fn collect_files(
+        &self,
+        repo: &Repository,
+        tree: &Tree,
+        prefix: &str,
+        out: &mut Vec<FileEntry>,
+    ) {
+        for entry in tree.iter() {
+            let name = match entry.name() {
+                Some(name) => name,
+                None => continue,
+            };
+            let path = format!("{}{}", prefix, name);
+
+            match entry.kind() {
+                Some(git2::ObjectType::Tree) => {
+                    if let Ok(subtree) = repo.find_tree(entry.id()) {
+                        self.collect_files(repo, &subtree, &format!("{}/", path), out);
+                    }
+                }
+                Some(git2::ObjectType::Blob) => {
+                    let size = repo.find_blob(entry.id())
+                        .map(|b| b.content().len())
+                        .unwrap_or(0);
+                    out.push(FileEntry { path, size });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns whether a file with the given path exists at the current head.
+    pub fn exists(&self, path: &str) -> bool {
+        self.blob_id(path).is_some()
+    }
+
+    fn blob_id(&self, path: &str) -> Option<Oid> {
+        self.head_tree()
+            .and_then(|tree| tree.get_path(Path::new(path)).ok())
+            .map(|entry| entry.id())
+    }
+
+    /// Reads the raw content of the given file at the current head.
+    pub fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.blob_id(path)
+            .and_then(|oid| self.repo.as_ref().and_then(|repo| repo.find_blob(oid).ok()))
+            .map(|blob| blob.content().to_vec())
+    }
+
+    /// Reads the given file at the current head as UTF-8 text. Returns `None`
+    /// if the file doesn't exist or isn't valid UTF-8.
+    pub fn read_text(&self, path: &str) -> Option<String> {
+        self.read(path).and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+
+    /// Returns the commits of the default branch, newest first. If `path` is
+    /// given, only commits changing that file are returned.
+    pub fn history(&self, path: Option<&str>, limit: usize) -> Vec<CommitInfo> {
+        let (repo, head) = match (self.repo.as_ref(), self.head()) {
+            (Some(repo), Some(head)) => (repo, head),
+            _ => return vec![],
+        };
+
+        let mut walk = match repo.revwalk() {
+            Ok(walk) => walk,
+            Err(_) => return vec![],
+        };
+        walk.set_sorting(git2::SORT_TIME);
+        if walk.push(head).is_err() {
+            return vec![];
+        }
+
+        walk.filter_map(|oid| oid.ok())
+            .filter_map(|oid| repo.find_commit(oid).ok())
+            .filter(|commit| {
+                let path = match path {
+                    Some(path) => Path::new(path),
+                    None => return true,
+                };
+
+                // A commit touches the file if the blob at `path` differs
+                // from the blob in the (first) parent.
+                let blob_in = |c: &git2::Commit| {
+                    c.tree().ok()
+                        .and_then(|t| t.get_path(path).ok())
+                        .map(|e| e.id())
+                };
+                let before = commit.parent(0).ok().and_then(|p| blob_in(&p));
+                let after = blob_in(commit);
+                before != after
+            })
+            .take(limit)
+            .map(CommitInfo::from)
+            .collect()
+    }
+
+    /// Applies the given changes on top of the default branch and creates a
+    /// new commit. Returns the id of the new commit.
+    ///
+    /// `expected_head` is the head the user based their changes on. If the
+    /// branch was moved in the meantime (e.g. by another user editing the
+    /// basket), the changes are rejected with `EditError::Conflict` instead of
+    /// silently overwriting the other changes.
+    pub fn commit(
+        &self,
+        changes: &[Change],
+        author: &Author,
+        message: &str,
+        expected_head: Option<Oid>,
+    ) -> Result<Oid, EditError> {
+        if message.trim().is_empty() {
+            return Err(EditError::MessageEmpty);
+        }
+
+        let repo = self.repo()?;
+        let head = self.head();
+        if head != expected_head {
+            return Err(EditError::Conflict);
+        }
+
+        let parent = match head {
+            Some(oid) => Some(repo.find_commit(oid)?),
+            None => None,
+        };
+
+        // Build the new tree in an in-memory index, starting from the
+        // current head.
+        let mut index = Index::new()?;
+        if let Some(ref parent) = parent {
+            index.read_tree(&parent.tree()?)?;
+        }
+
+        for change in changes {
+            self.apply_change(&mut index, change)?;
+        }
+
+        let tree_id = index.write_tree_to(repo)?;
+        let tree = repo.find_tree(tree_id)?;
+
+        // Don't create empty commits.
+        if let Some(ref parent) = parent {
+            if parent.tree_id() == tree_id {
+                return Err(EditError::NothingChanged);
+            }
+        }
+
+        let sig = Signature::now(&author.name, &author.email)?;
+        let parents = parent.iter().collect::<Vec<_>>();
+
+        // libgit2 only updates the reference if its current value is the
+        // first parent. That catches the race between our check above and
+        // this point.
+        repo.commit(Some(&branch_ref()), &sig, &sig, message.trim(), &tree, &parents)
+            .map_err(|e| if e.code() == ErrorCode::Modified {
+                EditError::Conflict
+            } else {
+                e.into()
+            })
+    }
+
+    fn apply_change(&self, index: &mut Index, change: &Change) -> Result<(), EditError> {
+        match *change {
+            Change::Create { ref path, ref content } => {
+                check_path(path)?;
+                if index.get_path(Path::new(path), 0).is_some() {
+                    return Err(EditError::FileExists { path: path.clone() });
+                }
+                self.add_file(index, path, content)
+            }
+            Change::Update { ref path, ref content } => {
+                check_path(path)?;
+                if index.get_path(Path::new(path), 0).is_none() {
+                    return Err(EditError::FileNotFound { path: path.clone() });
+                }
+                self.add_file(index, path, content)
+            }
+            Change::Delete { ref path } => {
+                check_path(path)?;
+                if index.get_path(Path::new(path), 0).is_none() {
+                    return Err(EditError::FileNotFound { path: path.clone() });
+                }
+                index.remove_path(Path::new(path))?;
+                Ok(())
+            }
+            Change::Rename { ref from, ref to, ref content } => {
+                check_path(from)?;
+                check_path(to)?;
+
+                let old = match index.get_path(Path::new(from), 0) {
+                    Some(entry) => entry,
+                    None => return Err(EditError::FileNotFound { path: from.clone() }),
+                };
+                if from != to && index.get_path(Path::new(to), 0).is_some() {
+                    return Err(EditError::FileExists { path: to.clone() });
+                }
+
+                index.remove_path(Path::new(from))?;
+                match *content {
+                    Some(ref content) => self.add_file(index, to, content),
+                    None => {
+                        let entry = IndexEntry {
+                            path: to.as_bytes().to_vec(),
+                            .. old
+                        };
+                        index.add(&entry)?;
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    /// Creates the first commit of this (still empty) basket, containing all
+    /// files of `source` at its current head, but none of its history.
+    pub fn copy_from(
+        &self,
+        source: &Content,
+        author: &Author,
+        message: &str,
+    ) -> Result<Oid, EditError> {
+        let repo = self.repo()?;
+        if self.head().is_some() {
+            return Err(EditError::Conflict);
+        }
+
+        let mut index = Index::new()?;
+        for file in source.files() {
+            if let Some(content) = source.read(&file.path) {
+                self.add_file(&mut index, &file.path, &content)?;
+            }
+        }
+
+        let tree_id = index.write_tree_to(repo)?;
+        let tree = repo.find_tree(tree_id)?;
+        let sig = Signature::now(&author.name, &author.email)?;
+        repo.commit(Some(&branch_ref()), &sig, &sig, message, &tree, &[])
+            .map_err(EditError::from)
+    }
+
+    fn add_file<C>(&self, index: &mut Index, path: &str, content: &C) -> Result<(), EditError>
+        where C: AsRef<[u8]> + ?Sized
+    {
+        // Make sure no file is turned into a directory and vice versa.
+        if is_directory_conflict(index, path) {
+            return Err(EditError::PathInvalid { path: path.into() });
+        }
+
+        let content = content.as_ref();
+        let blob = self.repo()?.blob(content)?;
+        let entry = IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: FILE_MODE,
+            uid: 0,
+            gid: 0,
+            file_size: content.len() as u32,
+            id: blob,
+            flags: 0,
+            flags_extended: 0,
+            path: path.as_bytes().to_vec(),
+        };
+        index.add(&entry)?;
+        Ok(())
+    }
+}
+
+fn branch_ref() -> String {
+    format!("refs/heads/{}", DEFAULT_BRANCH)
+}
+
+fn check_path(path: &str) -> Result<(), EditError> {
+    if is_valid_path(path) {
+        Ok(())
+    } else {
+        Err(EditError::PathInvalid { path: path.into() })
+    }
+}
+
+/// Returns `true` if adding a file at `path` would require a file to be a
+/// directory or the other way around.
+fn is_directory_conflict(index: &Index, path: &str) -> bool {
+    let as_dir = format!("{}/", path);
+    index.iter().any(|entry| {
+        let existing = String::from_utf8_lossy(&entry.path);
+        existing.starts_with(&as_dir) || path.starts_with(&format!("{}/", existing))
+    })
+}
+
+
+pub enum EditError {
+    /// The branch was changed since the user started editing.
+    Conflict,
+    MessageEmpty,
+    NothingChanged,
+    PathInvalid {
+        path: String,
+    },
+    FileExists {
+        path: String,
+    },
+    FileNotFound {
+        path: String,
+    },
+    /// Something went wrong inside git. This is not the user's fault.
+    Git(git2::Error),
+}
+
+impl From<git2::Error> for EditError {
+    fn from(e: git2::Error) -> Self {
+        EditError::Git(e)
+    }
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::EditError::*;
+
+        match *self {
+            Conflict => {
+                "The basket was changed by someone else while you were editing. \
+                Please reload the page and apply your changes again."
+                    .fmt(f)
+            }
+            MessageEmpty => {
+                "The commit message can't be empty!".fmt(f)
+            }
+            NothingChanged => {
+                "Your changes don't change anything.".fmt(f)
+            }
+            PathInvalid { ref path } => {
+                write!(f, "'{}' is not a valid file path!", path)
+            }
+            FileExists { ref path } => {
+                write!(f, "A file with the path '{}' already exists!", path)
+            }
+            FileNotFound { ref path } => {
+                write!(f, "The file '{}' doesn't exist!", path)
+            }
+            Git(ref e) => {
+                write!(f, "Internal error while saving your changes: {}", e)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use git2::Repository;
+    use rand;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{Author, Change, Content, EditError};
+
+    /// A bare repository in the temporary directory, which is removed
+    /// afterwards.
+    struct TempRepo {
+        dir: PathBuf,
+    }
+
+    impl TempRepo {
+        fn new() -> Self {
+            let name = format!("basgit-test-repo-{:016x}.git", rand::random::<u64>());
+            let dir = env::temp_dir().join(name);
+            Repository::init_bare(&dir).unwrap();
+            TempRepo { dir }
+        }
+
+        fn content(&self) -> Content {
+            Content { repo: Some(Repository::open_bare(&self.dir).unwrap()) }
+        }
+    }
+
+    impl Drop for TempRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Passes if `$result` is the given error.
+    macro_rules! assert_err {
+        ($result:expr, $pattern:pat) => {
+            match $result {
+                Err($pattern) => {}
+                Err(e) => panic!("expected {}, got: {}", stringify!($pattern), e),
+                Ok(_) => panic!("expected {}", stringify!($pattern)),
+            }
+        }
+    }
+
+    fn ok<T>(result: Result<T, EditError>) -> T {
+        match result {
+            Ok(value) => value,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn author() -> Author {
+        Author::noreply("test-user")
+    }
+
+    fn create(path: &str, content: &str) -> Change {
+        Change::Create { path: path.into(), content: content.into() }
+    }
+
+    fn update(path: &str, content: &str) -> Change {
+        Change::Update { path: path.into(), content: content.into() }
+    }
+
+    #[test]
+    fn commits_need_the_current_head() {
+        let repo = TempRepo::new();
+        let content = repo.content();
+        assert_eq!(content.head(), None);
+
+        let first = ok(content.commit(&[create("a.txt", "a")], &author(), "Add a", None));
+        assert_eq!(content.head(), Some(first));
+        let changes = [update("a.txt", "b")];
+        let second = ok(content.commit(&changes, &author(), "Change a", Some(first)));
+
+        // Both changes were based on an outdated head.
+        assert_err!(
+            content.commit(&[create("b.txt", "b")], &author(), "Add b", Some(first)),
+            EditError::Conflict
+        );
+        assert_err!(
+            content.commit(&[create("b.txt", "b")], &author(), "Add b", None),
+            EditError::Conflict
+        );
+
+        assert_eq!(content.head(), Some(second));
+        assert_eq!(content.read_text("a.txt"), Some("b".to_string()));
+        assert!(!content.exists("b.txt"));
+        assert_eq!(content.history(None, 10).len(), 2);
+    }
+
+    #[test]
+    fn copies_have_no_history() {
+        let template_repo = TempRepo::new();
+        let template = template_repo.content();
+        let changes = [create("a.txt", "a"), create("dir/b.txt", "b")];
+        let first = ok(template.commit(&changes, &author(), "Add files", None));
+        ok(template.commit(&[update("a.txt", "c")], &author(), "Change a", Some(first)));
+
+        let copy_repo = TempRepo::new();
+        let copy = copy_repo.content();
+        let id = ok(copy.copy_from(&template, &author(), "Create from template"));
+
+        assert_eq!(copy.head(), Some(id));
+        let history = copy.history(None, 10);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].message, "Create from template");
+        let commit = copy.repo().unwrap().find_commit(id).unwrap();
+        assert_eq!(commit.parent_count(), 0);
+
+        let paths = |content: &Content| {
+            content.files().into_iter().map(|f| f.path).collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&copy), vec!["a.txt", "dir/b.txt"]);
+        assert_eq!(paths(&copy), paths(&template));
+        assert_eq!(copy.read_text("a.txt"), Some("c".to_string()));
+        assert_eq!(copy.read_text("dir/b.txt"), Some("b".to_string()));
+
+        // Only empty baskets can be created from a template.
+        assert_err!(
+            copy.copy_from(&template, &author(), "Create again"),
+            EditError::Conflict
+        );
+    }
+}
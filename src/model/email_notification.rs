@@ -0,0 +1,497 @@
+//! Emailing notifications, either right away or as daily digest.
+//!
+//! Users choose for each event kind whether and how they want to be emailed
+//! about it. The choice is copied to every notification when it's created
+//! (see `notification::notify`), and a background thread sends the pending
+//! notifications regularly. Failed attempts are recorded on the notification
+//! and retried a few times.
+
+use chrono::{DateTime, Duration};
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use std::collections::BTreeMap;
+use std::time;
+
+use db::Db;
+use db::schema::{email_preferences, events, notifications, user_emails, users};
+use mail::{self, Mailer};
+use model::{self, PubUser, User};
+use model::event::{Event, EventKind, EventRecord};
+
+
+/// How often the background thread looks for notifications to send.
+const MAIL_INTERVAL_SECS: u64 = 60;
+
+/// Number of failed attempts after which a notification isn't emailed.
+const MAX_MAIL_ATTEMPTS: i32 = 10;
+
+/// How notifications of a kind are emailed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum Delivery {
+    /// One email per notification, as soon as possible.
+    #[serde(rename = "immediate")]
+    Immediate,
+    /// One email per day listing all notifications of that day.
+    #[serde(rename = "daily")]
+    Daily,
+}
+
+impl Delivery {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "immediate" => Some(Delivery::Immediate),
+            "daily" => Some(Delivery::Daily),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Delivery::Immediate => "immediate",
+            Delivery::Daily => "daily",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "email_preferences"]
+struct PreferenceRecord {
+    user_id: i64,
+    kind: String,
+    delivery: String,
+}
+
+/// The choice of a user for one event kind.
+#[derive(Serialize)]
+pub struct Preference {
+    pub kind: &'static str,
+    pub title: &'static str,
+    /// `None` if the user doesn't want emails about this kind.
+    pub delivery: Option<Delivery>,
+}
+
+/// Returns the choices of the user for all event kinds.
+pub fn preferences(user: &PubUser, db: &Db) -> Vec<Preference> {
+    let records = email_preferences::table
+        .filter(email_preferences::user_id.eq(user.id()))
+        .load::<PreferenceRecord>(&*db.conn())
+        .unwrap();
+
+    EventKind::all()
+        .iter()
+        .map(|kind| Preference {
+            kind: kind.as_str(),
+            title: kind.title(),
+            delivery: records.iter()
+                .find(|r| r.kind == kind.as_str())
+                .and_then(|r| Delivery::from_str(&r.delivery)),
+        })
+        .collect()
+}
+
+/// Replaces all choices of the user. Event kinds which aren't listed aren't
+/// emailed anymore.
+pub fn set_preferences(user: &PubUser, choices: &[(EventKind, Delivery)], db: &Db) {
+    let records = choices.iter()
+        .map(|&(kind, delivery)| PreferenceRecord {
+            user_id: user.id(),
+            kind: kind.as_str().into(),
+            delivery: delivery.as_str().into(),
+        })
+        .collect::<Vec<_>>();
+
+    let conn = db.conn();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(email_preferences::table.filter(email_preferences::user_id.eq(user.id())))
+            .execute(&*conn)?;
+        if !records.is_empty() {
+            diesel::insert(&records)
+                .into(email_preferences::table)
+                .execute(&*conn)?;
+        }
+        Ok(())
+    }).unwrap();
+}
+
+/// Returns how each of the given users wants to be emailed about events of
+/// the kind. Users who don't want emails are left out.
+pub fn deliveries(
+    user_ids: &[i64],
+    kind: EventKind,
+    db: &Db,
+) -> QueryResult<Vec<(i64, Delivery)>> {
+    Ok(email_preferences::table
+        .filter(email_preferences::user_id.eq_any(user_ids.to_vec()))
+        .filter(email_preferences::kind.eq(kind.as_str()))
+        .load::<PreferenceRecord>(&*db.conn())?
+        .into_iter()
+        .filter_map(|r| Delivery::from_str(&r.delivery).map(|d| (r.user_id, d)))
+        .collect())
+}
+
+/// A notification which wasn't emailed yet.
+#[derive(Clone, Debug, Queryable)]
+struct PendingRecord {
+    id: i64,
+    user_id: i64,
+    event_id: i64,
+    email_delivery: Option<String>,
+    read_at: Option<DateTime<UTC>>,
+    created_at: DateTime<UTC>,
+}
+
+impl PendingRecord {
+    fn delivery(&self) -> Option<Delivery> {
+        self.email_delivery.as_ref().and_then(|d| Delivery::from_str(d))
+    }
+}
+
+/// Sends all pending emails: every notification to be emailed immediately
+/// and the digests of users whose oldest pending notification for the
+/// digest is at least a day old. Notifications which were read in the
+/// meantime aren't emailed anymore. Notifications whose email couldn't be
+/// sent stay pending and are tried again next time, up to
+/// `MAX_MAIL_ATTEMPTS` times.
+pub fn send_pending(mailer: &Mailer, db: &Db) {
+    let pending = notifications::table
+        .filter(notifications::email_delivery.is_not_null())
+        .filter(notifications::emailed_at.is_null())
+        .filter(notifications::email_attempts.lt(MAX_MAIL_ATTEMPTS))
+        .order(notifications::id)
+        .select((
+            notifications::id,
+            notifications::user_id,
+            notifications::event_id,
+            notifications::email_delivery,
+            notifications::read_at,
+            notifications::created_at,
+        ))
+        .load::<PendingRecord>(&*db.conn())
+        .unwrap();
+
+    let mut by_user = BTreeMap::new();
+    for record in pending {
+        by_user.entry(record.user_id).or_insert_with(Vec::new).push(record);
+    }
+
+    let digest_cutoff = UTC::now() - Duration::days(1);
+    for (user_id, records) in by_user {
+        let (immediate, mut daily): (Vec<_>, Vec<_>) = records.into_iter()
+            .partition(|r| r.delivery() == Some(Delivery::Immediate));
+        if !daily.iter().any(|r| r.created_at <= digest_cutoff) {
+            daily.clear();
+        }
+
+        let outcome = send_to_user(user_id, &immediate, &daily, mailer, db);
+        if !outcome.done.is_empty() {
+            diesel::update(notifications::table.filter(notifications::id.eq_any(outcome.done)))
+                .set(notifications::emailed_at.eq(Some(UTC::now())))
+                .execute(&*db.conn())
+                .unwrap();
+        }
+        for (ids, error) in outcome.failed {
+            error!("failed to email notifications {:?} to user {}: {}", ids, user_id, error);
+            diesel::update(notifications::table.filter(notifications::id.eq_any(ids)))
+                .set((
+                    notifications::email_attempts.eq(notifications::email_attempts + 1),
+                    notifications::email_error.eq(Some(error)),
+                ))
+                .execute(&*db.conn())
+                .unwrap();
+        }
+    }
+}
+
+/// What happened to the notifications of a user in `send_to_user`.
+#[derive(Default)]
+struct Outcome {
+    /// Notifications which were sent or don't need to be sent anymore.
+    done: Vec<i64>,
+    /// Notifications whose email couldn't be sent, with the error.
+    failed: Vec<(Vec<i64>, String)>,
+}
+
+/// Sends the given notifications to the user.
+fn send_to_user(
+    user_id: i64,
+    immediate: &[PendingRecord],
+    daily: &[PendingRecord],
+    mailer: &Mailer,
+    db: &Db,
+) -> Outcome {
+    let all_ids = immediate.iter().chain(daily).map(|r| r.id).collect::<Vec<_>>();
+
+    // Without a primary address, the user can't be emailed at all.
+    let address = user_emails::table
+        .filter(user_emails::user_id.eq(user_id))
+        .filter(user_emails::is_primary.eq(true))
+        .select(user_emails::email)
+        .first::<String>(&*db.conn())
+        .optional()
+        .unwrap();
+    let address = match address {
+        Some(address) => address,
+        None => return Outcome { done: all_ids, failed: vec![] },
+    };
+    let user = PubUser::from_user(users::table.find(user_id).first::<User>(&*db.conn()).unwrap());
+
+    let unread_event_ids = immediate.iter()
+        .chain(daily)
+        .filter(|r| r.read_at.is_none())
+        .map(|r| r.event_id)
+        .collect::<Vec<_>>();
+    let event_records = events::table
+        .filter(events::id.eq_any(unread_event_ids))
+        .load::<EventRecord>(&*db.conn())
+        .unwrap();
+    let events = Event::with_details(event_records, db);
+    let event_of = |record: &PendingRecord| {
+        if record.read_at.is_some() {
+            None
+        } else {
+            events.iter().find(|e| e.record().id() == record.event_id)
+        }
+    };
+
+    let base_url = mail::base_url();
+    let settings_url = format!("{}/notifications/settings", base_url);
+    let mut outcome = Outcome::default();
+
+    for record in immediate {
+        let event = match event_of(record) {
+            Some(event) => event,
+            None => {
+                outcome.done.push(record.id);
+                continue;
+            }
+        };
+
+        let subject = event.summary();
+        let context = json!({
+            "username": user.username(),
+            "summary": subject,
+            "details": event.record().details(),
+            "url": format!("{}{}", base_url, event.basket().url()),
+            "settings_url": settings_url,
+        });
+        match mailer.send(&address, &subject, "notification", &context) {
+            Ok(()) => outcome.done.push(record.id),
+            Err(e) => outcome.failed.push((vec![record.id], e.to_string())),
+        }
+    }
+
+    let digest = daily.iter()
+        .filter_map(|r| event_of(r))
+        .map(|event| json!({
+            "summary": event.summary(),
+            "details": event.record().details(),
+            "url": format!("{}{}", base_url, event.basket().url()),
+        }))
+        .collect::<Vec<_>>();
+    let daily_ids = daily.iter().map(|r| r.id).collect::<Vec<_>>();
+    if digest.is_empty() {
+        outcome.done.extend(daily_ids);
+    } else {
+        let context = json!({
+            "username": user.username(),
+            "events": digest,
+            "settings_url": settings_url,
+        });
+        match mailer.send(&address, "Your daily BasGit digest", "digest", &context) {
+            Ok(()) => outcome.done.extend(daily_ids),
+            Err(e) => outcome.failed.push((daily_ids, e.to_string())),
+        }
+    }
+
+    outcome
+}
+
+/// Starts a thread which regularly sends the pending emails.
+pub fn spawn_mail_thread(mailer: Mailer, db: Db) {
+    let interval = time::Duration::from_secs(MAIL_INTERVAL_SECS);
+    model::spawn_periodic("mail", interval, move || send_pending(&mailer, &db));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration};
+    use chrono::offset::utc::UTC;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+    use diesel;
+    use rand;
+    use std::env;
+    use std::fs;
+    use std::io::{self, Read};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use db::Db;
+    use db::schema::notifications;
+    use mail::{Mailer, MaildirTransport, Transport};
+    use super::{send_pending, MAX_MAIL_ATTEMPTS};
+
+    /// A maildir in the temporary directory, which is removed afterwards.
+    struct Maildir {
+        dir: PathBuf,
+    }
+
+    impl Maildir {
+        fn new() -> Self {
+            let name = format!("basgit-test-mail-{:016x}", rand::random::<u64>());
+            Maildir { dir: env::temp_dir().join(name) }
+        }
+
+        fn mailer(&self) -> Mailer {
+            Mailer::new(Box::new(MaildirTransport::new(&self.dir).unwrap()))
+        }
+
+        /// The delivered emails to the test user.
+        fn mails(&self) -> Vec<String> {
+            fs::read_dir(self.dir.join("new"))
+                .unwrap()
+                .map(|entry| {
+                    let mut mail = String::new();
+                    fs::File::open(entry.unwrap().path())
+                        .unwrap()
+                        .read_to_string(&mut mail)
+                        .unwrap();
+                    mail
+                })
+                .filter(|mail| mail.contains("To: reader@example.com\r\n"))
+                .collect()
+        }
+    }
+
+    impl Drop for Maildir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// Refuses every email.
+    struct FailingTransport {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl Transport for FailingTransport {
+        fn send(&self, _: &str) -> io::Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err(io::Error::new(io::ErrorKind::Other, "relay refused"))
+        }
+    }
+
+    /// Creates a user with an address and two events the user is notified
+    /// about: 900301 (unread) and 900302 (read), both to be emailed with
+    /// the given delivery and created `age` ago.
+    fn setup(delivery: &str, age: &str) -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute(&format!("
+            insert into users (id, username) values
+                (900001, 'test-reader'),
+                (900002, 'test-actor');
+            insert into user_emails (email, user_id, is_primary) values
+                ('reader@example.com', 900001, true);
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'watched', 900002, 'generic', 'public');
+            insert into events (id, actor_id, basket_id, kind, details) values
+                (900201, 900002, 900101, 'pushed', 'Add the first recipe'),
+                (900202, 900002, 900101, 'pushed', 'Fix a typo');
+            insert into notifications
+                (id, user_id, event_id, reason, read_at, created_at, email_delivery)
+            values
+                (900301, 900001, 900201, 'watching', null, now() - interval '{age}', '{d}'),
+                (900302, 900001, 900202, 'watching', now(), now() - interval '{age}', '{d}');
+        ", d = delivery, age = age)).unwrap();
+        db
+    }
+
+    /// Returns `emailed_at`, `email_attempts` and `email_error`.
+    fn state(id: i64, db: &Db) -> (Option<DateTime<UTC>>, i32, Option<String>) {
+        notifications::table
+            .find(id)
+            .select((
+                notifications::emailed_at,
+                notifications::email_attempts,
+                notifications::email_error,
+            ))
+            .first(&*db.conn())
+            .unwrap()
+    }
+
+    #[test]
+    fn immediate_is_sent_once() {
+        let db = setup("immediate", "1 minute");
+        let maildir = Maildir::new();
+        let mailer = maildir.mailer();
+
+        send_pending(&mailer, &db);
+        let mails = maildir.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("Subject: test-actor pushed to test-actor/watched\r\n"));
+        assert!(mails[0].contains("Add the first recipe"));
+        assert!(state(900301, &db).0.is_some());
+
+        send_pending(&mailer, &db);
+        assert_eq!(maildir.mails().len(), 1);
+    }
+
+    #[test]
+    fn read_is_skipped() {
+        let db = setup("immediate", "1 minute");
+        let maildir = Maildir::new();
+
+        send_pending(&maildir.mailer(), &db);
+        assert!(maildir.mails().iter().all(|mail| !mail.contains("Fix a typo")));
+        // It's done with anyway, so it isn't looked at again.
+        let (emailed_at, attempts, _) = state(900302, &db);
+        assert!(emailed_at.is_some());
+        assert_eq!(attempts, 0);
+    }
+
+    #[test]
+    fn digest_waits_for_a_day() {
+        let db = setup("daily", "2 hours");
+        let maildir = Maildir::new();
+        let mailer = maildir.mailer();
+
+        send_pending(&mailer, &db);
+        assert!(maildir.mails().is_empty());
+        assert_eq!(state(900301, &db).0, None);
+
+        diesel::update(notifications::table.filter(notifications::user_id.eq(900001)))
+            .set(notifications::created_at.eq(UTC::now() - Duration::hours(25)))
+            .execute(&*db.conn())
+            .unwrap();
+        send_pending(&mailer, &db);
+        let mails = maildir.mails();
+        assert_eq!(mails.len(), 1);
+        assert!(mails[0].contains("Subject: Your daily BasGit digest\r\n"));
+        assert!(mails[0].contains("Add the first recipe"));
+        assert!(!mails[0].contains("Fix a typo"));
+        assert!(state(900301, &db).0.is_some());
+        assert!(state(900302, &db).0.is_some());
+    }
+
+    #[test]
+    fn failures_are_recorded_and_retried_a_few_times() {
+        let db = setup("immediate", "1 minute");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mailer = Mailer::new(Box::new(FailingTransport { attempts: attempts.clone() }));
+
+        send_pending(&mailer, &db);
+        let (emailed_at, recorded, error) = state(900301, &db);
+        assert_eq!(emailed_at, None);
+        assert_eq!(recorded, 1);
+        assert!(error.unwrap().contains("relay refused"));
+
+        for _ in 0..MAX_MAIL_ATTEMPTS + 2 {
+            send_pending(&mailer, &db);
+        }
+        assert_eq!(state(900301, &db).1, MAX_MAIL_ATTEMPTS);
+        assert_eq!(attempts.load(Ordering::SeqCst), MAX_MAIL_ATTEMPTS as usize);
+    }
+}
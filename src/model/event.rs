@@ -0,0 +1,296 @@
+//! Events: things which happened in baskets, shown in the activity feed.
+
+use chrono::DateTime;
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use serde::{Serialize, Serializer};
+
+use db::Db;
+use db::schema::{baskets, events, users};
+use model::{AuthUser, Basket, BasketRecord, PubUser, User};
+use model::collaborator;
+use model::notification;
+use model::organization;
+use model::permissions::Visibility;
+use model::watch;
+use model::webhook;
+
+
+/// Number of events shown in the activity feed.
+const FEED_SIZE: i64 = 50;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventKind {
+    /// A new basket was created. The details name the template, if any.
+    Created,
+    /// A basket was created as fork. The details name the forked basket.
+    /// Nothing records this yet, as baskets can't be forked so far.
+    Forked,
+    Starred,
+    /// Content was committed. The details are the commit message.
+    Pushed,
+    /// The settings changed. The details name the changed setting.
+    SettingsChanged,
+}
+
+impl EventKind {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "created" => Some(EventKind::Created),
+            "forked" => Some(EventKind::Forked),
+            "starred" => Some(EventKind::Starred),
+            "pushed" => Some(EventKind::Pushed),
+            "settings_changed" => Some(EventKind::SettingsChanged),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            EventKind::Created => "created",
+            EventKind::Forked => "forked",
+            EventKind::Starred => "starred",
+            EventKind::Pushed => "pushed",
+            EventKind::SettingsChanged => "settings_changed",
+        }
+    }
+
+    /// A short description for users, e.g. in settings.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            EventKind::Created => "New baskets",
+            EventKind::Forked => "Forks",
+            EventKind::Starred => "Stars",
+            EventKind::Pushed => "Changes of the content",
+            EventKind::SettingsChanged => "Changes of the settings",
+        }
+    }
+
+    /// The kinds users can choose from, e.g. for webhooks and emails.
+    /// `Forked` is left out until baskets can be forked.
+    pub fn all() -> &'static [EventKind] {
+        &[
+            EventKind::Created,
+            EventKind::Starred,
+            EventKind::Pushed,
+            EventKind::SettingsChanged,
+        ]
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+pub struct EventRecord {
+    id: i64,
+    actor_id: Option<i64>,
+    basket_id: i64,
+    kind: String,
+    details: Option<String>,
+    created_at: DateTime<UTC>,
+}
+
+impl EventRecord {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn actor_id(&self) -> Option<i64> {
+        self.actor_id
+    }
+
+    pub fn basket_id(&self) -> i64 {
+        self.basket_id
+    }
+
+    pub fn kind(&self) -> EventKind {
+        // The column only ever contains the values of `EventKind::as_str`.
+        EventKind::from_str(&self.kind).unwrap()
+    }
+
+    pub fn details(&self) -> Option<&str> {
+        self.details.as_ref().map(AsRef::as_ref)
+    }
+
+    pub fn created_at(&self) -> DateTime<UTC> {
+        self.created_at
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "events"]
+struct NewEvent {
+    actor_id: Option<i64>,
+    basket_id: i64,
+    kind: String,
+    details: Option<String>,
+}
+
+/// Records that `actor` did something in the basket, notifies everybody
+/// following the basket and triggers its webhooks.
+///
+/// This runs after the change itself succeeded, so failures are only logged.
+pub fn record(
+    kind: EventKind,
+    actor: &PubUser,
+    basket: &BasketRecord,
+    details: Option<String>,
+    db: &Db,
+) {
+    let new_event = NewEvent {
+        actor_id: Some(actor.id()),
+        basket_id: basket.id(),
+        kind: kind.as_str().into(),
+        details,
+    };
+
+    let event = match diesel::insert(&new_event)
+        .into(events::table)
+        .get_result::<EventRecord>(&*db.conn())
+    {
+        Ok(event) => event,
+        Err(e) => {
+            error!("failed to record {} event in basket {}: {}", kind.as_str(), basket.id(), e);
+            return;
+        }
+    };
+    if let Err(e) = notification::notify(&event, db) {
+        error!("failed to create notifications for event {}: {}", event.id(), e);
+    }
+    if let Err(e) = webhook::enqueue(&event, db) {
+        error!("failed to queue webhook deliveries for event {}: {}", event.id(), e);
+    }
+}
+
+/// An event together with its basket and actor.
+#[derive(Clone)]
+pub struct Event {
+    record: EventRecord,
+    /// `None` if the account of the actor was deleted.
+    actor: Option<PubUser>,
+    basket: Basket,
+}
+
+impl Event {
+    pub fn record(&self) -> &EventRecord {
+        &self.record
+    }
+
+    pub fn actor(&self) -> Option<&PubUser> {
+        self.actor.as_ref()
+    }
+
+    pub fn basket(&self) -> &Basket {
+        &self.basket
+    }
+
+    /// Describes the event in a short sentence without the details, e.g. in
+    /// email subjects.
+    pub fn summary(&self) -> String {
+        let actor = self.actor.as_ref().map(|a| a.username()).unwrap_or("Someone");
+        let basket = format!("{}/{}", self.basket.owner(), self.basket.name());
+        let action = match self.record.kind() {
+            EventKind::Created => "created",
+            EventKind::Forked => "forked",
+            EventKind::Starred => "starred",
+            EventKind::Pushed => "pushed to",
+            EventKind::SettingsChanged => "changed the settings of",
+        };
+        format!("{} {} {}", actor, action, basket)
+    }
+
+    /// Loads the baskets and actors of the given records with one query
+    /// each. Events of baskets which don't exist anymore are dropped; the
+    /// order is kept.
+    pub fn with_details(records: Vec<EventRecord>, db: &Db) -> Vec<Self> {
+        if records.is_empty() {
+            return vec![];
+        }
+
+        let basket_ids = records.iter().map(|r| r.basket_id).collect::<Vec<_>>();
+        let basket_records = baskets::table
+            .filter(baskets::id.eq_any(basket_ids))
+            .filter(baskets::deleted_at.is_null())
+            .load::<BasketRecord>(&*db.conn())
+            .unwrap();
+        let baskets = Basket::with_owners(basket_records, db);
+
+        let actor_ids = records.iter().filter_map(|r| r.actor_id).collect::<Vec<_>>();
+        let actors = users::table
+            .filter(users::id.eq_any(actor_ids))
+            .load::<User>(&*db.conn())
+            .unwrap()
+            .into_iter()
+            .map(PubUser::from_user)
+            .collect::<Vec<_>>();
+
+        records.into_iter()
+            .filter_map(|record| {
+                let actor = actors.iter().find(|a| Some(a.id()) == record.actor_id).cloned();
+                baskets.iter()
+                    .find(|b| b.id() == record.basket_id)
+                    .map(|basket| Event { record, actor, basket: basket.clone() })
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Event {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Event", 5)?;
+        s.serialize_field("kind", self.record.kind().as_str())?;
+        s.serialize_field("actor", &self.actor.as_ref().map(|a| a.username()))?;
+        s.serialize_field("basket", &self.basket)?;
+        s.serialize_field("details", &self.record.details())?;
+        s.serialize_field("created_at", &self.record.created_at.to_rfc3339())?;
+        s.end()
+    }
+}
+
+/// Returns the ids of the baskets whose events are shown in the user's
+/// feed: the baskets the user or one of the user's organizations owns, those
+/// the user collaborates on, directly or through a team, and those the user
+/// watches.
+pub fn feed_basket_ids(user: &PubUser, db: &Db) -> Vec<i64> {
+    let mut owner_ids = organization::organization_ids_of(user, db);
+    owner_ids.push(user.id());
+
+    let mut ids = baskets::table
+        .filter(baskets::user_id.eq_any(owner_ids))
+        .select(baskets::id)
+        .load::<i64>(&*db.conn())
+        .unwrap();
+    ids.extend(collaborator::basket_ids_of(user, db));
+    ids.extend(organization::team_basket_ids_of(user, db));
+    ids.extend(watch::watched_basket_ids(user, db));
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+/// Returns the most recent events of the baskets in the user's feed (see
+/// `feed_basket_ids`) which the user can see, newest first.
+pub fn feed(auth_user: &AuthUser, db: &Db) -> Vec<Event> {
+    let conn = db.conn();
+    let ids = Visibility::of(Some(&**auth_user), db)
+        .visible_baskets()
+        .filter(baskets::id.eq_any(feed_basket_ids(auth_user, db)))
+        .select(baskets::id)
+        .load::<i64>(&*conn)
+        .unwrap();
+    if ids.is_empty() {
+        return vec![];
+    }
+
+    let records = events::table
+        .filter(events::basket_id.eq_any(ids))
+        .order((events::created_at.desc(), events::id.desc()))
+        .limit(FEED_SIZE)
+        .load::<EventRecord>(&*conn)
+        .unwrap();
+
+    Event::with_details(records, db)
+}
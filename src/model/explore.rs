@@ -0,0 +1,152 @@
+//! Discovering public baskets: trending and new baskets and the number of
+//! baskets of each kind.
+//!
+//! Finding the trending baskets means counting all recent stars, so the
+//! overview is cached for a few minutes by `ExploreCache` instead of being
+//! computed on every request.
+
+use chrono::Duration;
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel::types::{BigInt, Timestamptz};
+use std::cmp;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{self, Instant};
+
+use db::Db;
+use db::schema::{baskets, stars};
+use model::{Basket, BasketKind, BasketRecord};
+use model::permissions::Visibility;
+
+
+/// Stars and changes within this many days make a basket trending.
+const TRENDING_DAYS: i64 = 7;
+
+/// Number of baskets in the trending and newest sections.
+const SECTION_SIZE: usize = 10;
+
+/// How long a computed overview is served before it's computed again.
+const CACHE_SECS: u64 = 5 * 60;
+
+// Defined in the `add_trending_score` migration: two points for each star
+// since `since` and one point if the basket changed since then.
+sql_function!(trending_score, trending_score_t,
+    (basket: BigInt, changed: Timestamptz, since: Timestamptz) -> BigInt);
+
+/// The number of public baskets of a kind.
+#[derive(Serialize)]
+pub struct KindCount {
+    pub kind: &'static BasketKind,
+    pub count: i64,
+}
+
+/// All sections of the explore page. Only public baskets are included, so
+/// the overview is the same for everyone and can be cached.
+#[derive(Serialize)]
+pub struct Overview {
+    /// The baskets with most recent stars and changes.
+    pub trending: Vec<Basket>,
+    pub newest: Vec<Basket>,
+    pub kinds: Vec<KindCount>,
+}
+
+impl Overview {
+    pub fn compute(db: &Db) -> Self {
+        Overview {
+            trending: trending(db),
+            newest: newest(db),
+            kinds: kind_counts(db),
+        }
+    }
+}
+
+/// Keeps the last computed overview, which is managed as state by Rocket.
+pub struct ExploreCache {
+    cached: Mutex<Option<(Instant, Arc<Overview>)>>,
+}
+
+impl ExploreCache {
+    pub fn new() -> Self {
+        ExploreCache {
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached overview or computes a new one if it's too old.
+    /// The lock isn't held while computing, so a failing query can't poison
+    /// it. Concurrent requests may compute the overview at the same time,
+    /// which only happens once every few minutes.
+    pub fn overview(&self, db: &Db) -> Arc<Overview> {
+        if let Some((computed_at, ref overview)) = *self.lock() {
+            if computed_at.elapsed() < time::Duration::from_secs(CACHE_SECS) {
+                return overview.clone();
+            }
+        }
+
+        let overview = Arc::new(Overview::compute(db));
+        *self.lock() = Some((Instant::now(), overview.clone()));
+        overview
+    }
+
+    fn lock(&self) -> MutexGuard<Option<(Instant, Arc<Overview>)>> {
+        // The value is replaced as a whole, so it's fine even if another
+        // thread panicked while holding the lock.
+        self.cached.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Ranks the public baskets which were starred or changed recently by their
+/// `trending_score`. Ties are broken by the total number of stars and the
+/// last change.
+fn trending(db: &Db) -> Vec<Basket> {
+    let cutoff = UTC::now() - Duration::days(TRENDING_DAYS);
+    let recently_starred = stars::table
+        .filter(stars::created_at.gt(cutoff))
+        .select(stars::basket_id);
+
+    let records = Visibility::of(None, db)
+        .visible_baskets()
+        .filter(baskets::archived.eq(false))
+        .filter(baskets::id.eq_any(recently_starred).or(baskets::updated_at.gt(cutoff)))
+        .order((
+            trending_score(baskets::id, baskets::updated_at, cutoff).desc(),
+            baskets::star_count.desc(),
+            baskets::updated_at.desc(),
+        ))
+        .limit(SECTION_SIZE as i64)
+        .load::<BasketRecord>(&*db.conn())
+        .unwrap();
+
+    Basket::with_owners(records, db)
+}
+
+fn newest(db: &Db) -> Vec<Basket> {
+    let records = Visibility::of(None, db)
+        .visible_baskets()
+        .order((baskets::created_at.desc(), baskets::id.desc()))
+        .limit(SECTION_SIZE as i64)
+        .load::<BasketRecord>(&*db.conn())
+        .unwrap();
+
+    Basket::with_owners(records, db)
+}
+
+/// Counts the public baskets of each kind. Kinds without any baskets are
+/// listed, too, most common kinds first.
+fn kind_counts(db: &Db) -> Vec<KindCount> {
+    let mut counts = BasketKind::all()
+        .iter()
+        .map(|&kind| {
+            let count = Visibility::of(None, db)
+                .visible_baskets()
+                .filter(baskets::kind.eq(kind.id))
+                .count()
+                .get_result(&*db.conn())
+                .unwrap();
+            KindCount { kind, count }
+        })
+        .collect::<Vec<_>>();
+
+    counts.sort_by_key(|c| cmp::Reverse(c.count));
+    counts
+}
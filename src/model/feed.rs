@@ -0,0 +1,351 @@
+//! Atom feeds of the activity of users and baskets.
+//!
+//! Feed readers can't log in, so feeds only contain public baskets by
+//! default. Users can create a personal token in their settings, which can be
+//! added to feed URLs (`?token=...`) to include everything the user can see.
+
+use chrono::{DateTime, TimeZone};
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use rand::Rng;
+use rand::os::OsRng;
+
+use db::Db;
+use db::schema::{baskets, events, feed_tokens, users};
+use mail;
+use model::{AuthUser, Basket, PubUser, User};
+use model::content::Content;
+use model::event::{Event, EventKind, EventRecord};
+use model::organization;
+use model::permissions::Visibility;
+
+
+/// Maximum number of entries of a feed.
+const FEED_SIZE: i64 = 30;
+
+/// Number of characters of a feed token.
+const TOKEN_LEN: usize = 40;
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "feed_tokens"]
+struct NewFeedToken {
+    user_id: i64,
+    token: String,
+}
+
+/// Returns the feed token of the user, if the user created one.
+pub fn token_of(user: &PubUser, db: &Db) -> Option<String> {
+    feed_tokens::table
+        .find(user.id())
+        .select(feed_tokens::token)
+        .first::<String>(&*db.conn())
+        .optional()
+        .unwrap()
+}
+
+/// Creates a new feed token for the user. An existing token is replaced, so
+/// that feed URLs containing it stop working. Returns the new token.
+pub fn reset_token(user: &PubUser, db: &Db) -> String {
+    // The token grants read access like a session, so it's generated the
+    // same way.
+    let mut rng = OsRng::new().expect("could not use system rng");
+    let new_token = NewFeedToken {
+        user_id: user.id(),
+        token: rng.gen_ascii_chars().take(TOKEN_LEN).collect(),
+    };
+
+    let conn = db.conn();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(feed_tokens::table.find(user.id()))
+            .execute(&*conn)?;
+        diesel::insert(&new_token)
+            .into(feed_tokens::table)
+            .execute(&*conn)?;
+        Ok(())
+    }).unwrap();
+
+    new_token.token
+}
+
+/// Returns the user the token belongs to, if any.
+pub fn token_owner(token: &str, db: &Db) -> Option<PubUser> {
+    if token.len() != TOKEN_LEN {
+        return None;
+    }
+
+    let conn = db.conn();
+    feed_tokens::table
+        .filter(feed_tokens::token.eq(token))
+        .select(feed_tokens::user_id)
+        .first::<i64>(&*conn)
+        .optional()
+        .unwrap()
+        .map(|user_id| {
+            let user = users::table.find(user_id).first::<User>(&*conn).unwrap();
+            PubUser::from_user(user)
+        })
+}
+
+/// An entry of a feed.
+struct Entry {
+    id: String,
+    title: String,
+    url: String,
+    author: Option<String>,
+    updated: DateTime<UTC>,
+    content: Option<String>,
+}
+
+impl Entry {
+    fn from_event(event: &Event) -> Self {
+        Entry {
+            id: format!("urn:basgit:event:{}", event.record().id()),
+            title: event.summary(),
+            url: format!("{}{}", mail::base_url(), event.basket().url()),
+            author: event.actor().map(|a| a.username().to_string()),
+            updated: event.record().created_at(),
+            content: event.record().details().map(Into::into),
+        }
+    }
+}
+
+/// Renders the entries as Atom document. `url` is the page the feed is
+/// about and `path` the (tokenless) path of the feed itself.
+fn render(title: &str, author: &str, url: &str, path: &str, entries: &[Entry]) -> String {
+    let base_url = mail::base_url();
+    let updated = entries.iter().map(|e| e.updated).max().unwrap_or(UTC::now());
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    out.push_str(&format!("  <id>{}{}</id>\n", escape(&base_url), escape(path)));
+    out.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    out.push_str(&format!("  <updated>{}</updated>\n", updated.to_rfc3339()));
+    out.push_str(&format!("  <author><name>{}</name></author>\n", escape(author)));
+    out.push_str(&format!("  <link rel=\"alternate\" href=\"{}\"/>\n", escape(url)));
+    out.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}{}\"/>\n",
+        escape(&base_url),
+        escape(path),
+    ));
+
+    for e in entries {
+        out.push_str("  <entry>\n");
+        out.push_str(&format!("    <id>{}</id>\n", escape(&e.id)));
+        out.push_str(&format!("    <title>{}</title>\n", escape(&e.title)));
+        out.push_str(&format!("    <updated>{}</updated>\n", e.updated.to_rfc3339()));
+        out.push_str(&format!("    <link href=\"{}\"/>\n", escape(&e.url)));
+        if let Some(ref author) = e.author {
+            out.push_str(&format!("    <author><name>{}</name></author>\n", escape(author)));
+        }
+        if let Some(ref content) = e.content {
+            out.push_str(&format!("    <content type=\"text\">{}</content>\n", escape(content)));
+        }
+        out.push_str("  </entry>\n");
+    }
+
+    out.push_str("</feed>\n");
+    out
+}
+
+/// Returns the feed of a user: what the user did in the baskets `viewer`
+/// can see. For organizations, which don't do anything themselves, it's the
+/// activity in their baskets instead.
+pub fn user_feed(user: &PubUser, viewer: Option<&AuthUser>, db: &Db) -> String {
+    let is_organization = organization::is_organization(user, db);
+
+    let mut visible = Visibility::of(viewer.map(|u| &**u), db).visible_basket_ids();
+    let mut query = events::table
+        .order((events::created_at.desc(), events::id.desc()))
+        .limit(FEED_SIZE)
+        .into_boxed();
+    if is_organization {
+        visible = visible.filter(baskets::user_id.eq(user.id()));
+    } else {
+        query = query.filter(events::actor_id.eq(user.id()));
+    }
+    let records = query
+        .filter(events::basket_id.eq_any(visible))
+        .load::<EventRecord>(&*db.conn())
+        .unwrap();
+
+    let entries = Event::with_details(records, db)
+        .iter()
+        .map(Entry::from_event)
+        .collect::<Vec<_>>();
+    let url = format!("{}/{}", mail::base_url(), user.username());
+    let path = format!("/{}.atom", user.username());
+    let title = format!("Activity of {} – BasGit", user.username());
+    render(&title, user.username(), &url, &path, &entries)
+}
+
+/// Returns the feed of a basket: its activity and its commits. Commits are
+/// listed on their own, so the events recording them are left out.
+pub fn basket_feed(basket: &Basket, db: &Db) -> String {
+    let records = events::table
+        .filter(events::basket_id.eq(basket.id()))
+        .filter(events::kind.ne(EventKind::Pushed.as_str()))
+        .order((events::created_at.desc(), events::id.desc()))
+        .limit(FEED_SIZE)
+        .load::<EventRecord>(&*db.conn())
+        .unwrap();
+    let mut entries = Event::with_details(records, db)
+        .iter()
+        .map(Entry::from_event)
+        .collect::<Vec<_>>();
+
+    let url = format!("{}{}", mail::base_url(), basket.url());
+    let commits = Content::open(basket)
+        .map(|content| content.history(None, FEED_SIZE as usize))
+        .unwrap_or_default();
+    entries.extend(commits.into_iter().map(|commit| {
+        let mut lines = commit.message.splitn(2, '\n');
+        let title = lines.next().unwrap_or("").to_string();
+        let body = lines.next().map(|s| s.trim()).unwrap_or("");
+        Entry {
+            id: format!("urn:basgit:commit:{}:{}", basket.id(), commit.id),
+            title: format!("{} {}", commit.short_id, title),
+            url: format!("{}/files", url),
+            author: Some(commit.author_name),
+            updated: UTC.timestamp(commit.time, 0),
+            content: if body.is_empty() { None } else { Some(body.to_string()) },
+        }
+    }));
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated));
+    entries.truncate(FEED_SIZE as usize);
+
+    let path = format!("{}.atom", basket.url());
+    let title = format!("{}/{} – BasGit", basket.owner(), basket.name());
+    render(&title, basket.owner(), &url, &path, &entries)
+}
+
+/// Escapes the text for XML. Control characters, which XML 1.0 doesn't
+/// allow at all, are dropped.
+fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|&c| is_xml_char(c))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn is_xml_char(c: char) -> bool {
+    match c {
+        '\t' | '\n' | '\r' => true,
+        '\u{0}'...'\u{1f}' | '\u{fffe}' | '\u{ffff}' => false,
+        _ => true,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono::offset::utc::UTC;
+    use diesel::connection::SimpleConnection;
+
+    use db::Db;
+    use model::{AuthUser, PubUser};
+    use super::{escape, render, reset_token, token_of, token_owner, user_feed, Entry};
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape("a & <b> \"c\"\u{1b}[0m\u{0}\u{ffff}\tok\r\n"),
+            "a &amp; &lt;b&gt; &quot;c&quot;[0m\tok\r\n",
+        );
+        assert_eq!(escape("Grüße ✓"), "Grüße ✓");
+    }
+
+    #[test]
+    fn atom_document() {
+        let entries = vec![
+            Entry {
+                id: "urn:basgit:event:1".into(),
+                title: "alice pushed to alice/<notes>".into(),
+                url: "http://localhost:8000/alice/notes".into(),
+                author: Some("alice".into()),
+                updated: UTC.ymd(2017, 7, 1).and_hms(12, 0, 0),
+                content: Some("Fix \u{8}typo".into()),
+            },
+            Entry {
+                id: "urn:basgit:event:2".into(),
+                title: "Someone starred alice/notes".into(),
+                url: "http://localhost:8000/alice/notes".into(),
+                author: None,
+                updated: UTC.ymd(2017, 7, 2).and_hms(8, 30, 0),
+                content: None,
+            },
+        ];
+        let feed = render("Feed & more", "alice", "http://x/alice", "/alice.atom", &entries);
+
+        assert!(feed.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n"));
+        assert!(feed.ends_with("</feed>\n"));
+        assert!(feed.contains("  <title>Feed &amp; more</title>\n"));
+        // The feed was updated with its newest entry.
+        assert!(feed.contains("  <updated>2017-07-02T08:30:00+00:00</updated>\n"));
+        assert!(feed.contains("/alice.atom\"/>\n"));
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert!(feed.contains("    <title>alice pushed to alice/&lt;notes&gt;</title>\n"));
+        assert!(feed.contains("    <content type=\"text\">Fix typo</content>\n"));
+        assert_eq!(feed.matches("<author>").count(), 2);
+        assert_eq!(feed.matches("<content").count(), 1);
+    }
+
+    fn setup() -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute("
+            insert into users (id, username) values
+                (900001, 'test-author'),
+                (900002, 'test-other');
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'public', 900001, 'generic', 'public'),
+                (900102, 'private', 900001, 'generic', 'private');
+            insert into events (id, actor_id, basket_id, kind) values
+                (900201, 900001, 900101, 'created'),
+                (900202, 900001, 900102, 'created'),
+                (900203, 900002, 900101, 'starred');
+        ").unwrap();
+        db
+    }
+
+    #[test]
+    fn tokens() {
+        let db = setup();
+        let author = PubUser::from_username("test-author", &db).unwrap();
+        assert_eq!(token_of(&author, &db), None);
+
+        let token = reset_token(&author, &db);
+        assert_eq!(token_of(&author, &db), Some(token.clone()));
+        assert_eq!(token_owner(&token, &db).map(|u| u.id()), Some(900001));
+        assert!(token_owner(&token[1..], &db).is_none());
+        assert!(token_owner("", &db).is_none());
+
+        // Resetting makes the old token useless.
+        let new_token = reset_token(&author, &db);
+        assert_ne!(new_token, token);
+        assert!(token_owner(&token, &db).is_none());
+        assert!(AuthUser::from_feed_token(&new_token, &db).is_some());
+    }
+
+    #[test]
+    fn user_feed_shows_visible_activity() {
+        let db = setup();
+        let author = PubUser::from_username("test-author", &db).unwrap();
+
+        let public = user_feed(&author, None, &db);
+        assert!(public.contains("<id>urn:basgit:event:900201</id>"));
+        assert!(!public.contains("urn:basgit:event:900202"));
+        // Only what the user did is listed.
+        assert!(!public.contains("urn:basgit:event:900203"));
+
+        let token = reset_token(&author, &db);
+        let viewer = AuthUser::from_feed_token(&token, &db).unwrap();
+        let private = user_feed(&author, Some(&viewer), &db);
+        assert!(private.contains("urn:basgit:event:900201"));
+        assert!(private.contains("urn:basgit:event:900202"));
+    }
+}
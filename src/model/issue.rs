@@ -0,0 +1,724 @@
+//! Issues of "tasks" baskets.
+//!
+//! Issues are stored in the database (not in the basket's repository) and are
+//! numbered per basket.
+
+use chrono::DateTime;
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt;
+
+use db::Db;
+use db::schema::{issues, issue_assignees, issue_comments, issue_labels, users};
+use model::{AuthUser, Basket, PubUser, User};
+use model::basket::ARCHIVED_MESSAGE;
+use model::permissions::{has_permission, UserAction};
+use super::{MAX_ML_LEN, MAX_SL_LEN};
+
+
+/// How often we try to allocate a new issue number before giving up. Only
+/// matters if many issues are opened in the same basket at the same time.
+const NUMBER_ALLOCATION_ATTEMPTS: usize = 5;
+
+
+#[derive(Clone, Debug, Identifiable, Queryable)]
+#[table_name = "issues"]
+pub struct IssueRecord {
+    id: i64,
+    basket_id: i64,
+    number: i32,
+    author_id: Option<i64>,
+    title: String,
+    body: String,
+    is_open: bool,
+    created_at: DateTime<UTC>,
+}
+
+impl IssueRecord {
+    pub fn number(&self) -> i32 {
+        self.number
+    }
+
+    pub fn author_id(&self) -> Option<i64> {
+        self.author_id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn body(&self) -> &str {
+        &self.body
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open
+    }
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "issues"]
+struct NewIssue {
+    basket_id: i64,
+    number: i32,
+    author_id: Option<i64>,
+    title: String,
+    body: String,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "issue_labels"]
+struct IssueLabel {
+    issue_id: i64,
+    label: String,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Associations)]
+#[table_name = "issue_assignees"]
+#[belongs_to(User)]
+struct IssueAssignee {
+    issue_id: i64,
+    user_id: i64,
+}
+
+#[derive(Clone, Debug, Queryable)]
+struct CommentRecord {
+    id: i64,
+    issue_id: i64,
+    author_id: Option<i64>,
+    body: String,
+    created_at: DateTime<UTC>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "issue_comments"]
+struct NewComment {
+    issue_id: i64,
+    author_id: Option<i64>,
+    body: String,
+}
+
+/// A comment on an issue.
+#[derive(Clone, Debug, Serialize)]
+pub struct Comment {
+    pub author: Option<String>,
+    pub body: String,
+    pub created_at: String,
+}
+
+/// Which issues to show in the issue list.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IssueState {
+    Open,
+    Closed,
+    All,
+}
+
+impl IssueState {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "open" => Some(IssueState::Open),
+            "closed" => Some(IssueState::Closed),
+            "all" => Some(IssueState::All),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            IssueState::Open => "open",
+            IssueState::Closed => "closed",
+            IssueState::All => "all",
+        }
+    }
+}
+
+/// Filter for the issue list.
+#[derive(Clone, Debug)]
+pub struct IssueFilter {
+    pub state: IssueState,
+    pub label: Option<String>,
+    /// Username of an assignee.
+    pub assignee: Option<String>,
+}
+
+impl Default for IssueFilter {
+    fn default() -> Self {
+        IssueFilter {
+            state: IssueState::Open,
+            label: None,
+            assignee: None,
+        }
+    }
+}
+
+/// An issue together with its labels, assignees and author.
+pub struct Issue {
+    record: IssueRecord,
+    labels: Vec<String>,
+    assignees: Vec<PubUser>,
+    author: Option<PubUser>,
+    basket_url: String,
+}
+
+impl Issue {
+    /// Opens a new issue in the given basket.
+    pub fn create(
+        basket: &Basket,
+        auth_user: &AuthUser,
+        title: &str,
+        body: &str,
+        labels: &str,
+        db: &Db,
+    ) -> Result<Self, IssueError> {
+        use diesel::expression::dsl::max;
+        use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+        check_not_archived(basket)?;
+        let can_create = has_permission(Some(auth_user), UserAction::CreateIssue {
+            owner: basket.owner_user(),
+            basket: basket,
+        }, db);
+        if !can_create {
+            return Err(IssueError::NoPermission);
+        }
+
+        let (title, body) = check_title_body(title, body)?;
+        let labels = parse_labels(labels)?;
+
+        let conn = db.conn();
+        for _ in 0..NUMBER_ALLOCATION_ATTEMPTS {
+            let result = conn.transaction::<_, DieselError, _>(|| {
+                let last = issues::table
+                    .filter(issues::basket_id.eq(basket.id()))
+                    .select(max(issues::number))
+                    .first::<Option<i32>>(&*conn)?;
+
+                let new_issue = NewIssue {
+                    basket_id: basket.id(),
+                    number: last.unwrap_or(0) + 1,
+                    author_id: Some(auth_user.id()),
+                    title: title.clone(),
+                    body: body.clone(),
+                };
+                let record = diesel::insert(&new_issue)
+                    .into(issues::table)
+                    .get_result::<IssueRecord>(&*conn)?;
+
+                let new_labels = labels.iter()
+                    .map(|label| IssueLabel { issue_id: record.id, label: label.clone() })
+                    .collect::<Vec<_>>();
+                if !new_labels.is_empty() {
+                    diesel::insert(&new_labels)
+                        .into(issue_labels::table)
+                        .execute(&*conn)?;
+                }
+
+                Ok(record)
+            });
+
+            match result {
+                Ok(record) => {
+                    return Ok(Issue {
+                        record,
+                        labels: labels,
+                        assignees: vec![],
+                        author: Some((**auth_user).clone()),
+                        basket_url: basket.url(),
+                    });
+                }
+                // Someone else took the number in the meantime: try again.
+                Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {}
+                Err(e) => {
+                    error!("failed to insert issue into basket {}: {}", basket.id(), e);
+                    return Err(IssueError::SaveFailed);
+                }
+            }
+        }
+
+        Err(IssueError::TryAgain)
+    }
+
+    /// Loads the issue with the given number from the given basket.
+    pub fn load(basket: &Basket, number: i32, db: &Db) -> Option<Self> {
+        issues::table
+            .filter(issues::basket_id.eq(basket.id()))
+            .filter(issues::number.eq(number))
+            .first::<IssueRecord>(&*db.conn())
+            .optional()
+            .unwrap()
+            .map(|record| {
+                Self::with_details(vec![record], basket, db).pop().unwrap()
+            })
+    }
+
+    /// Lists the issues of the given basket matching the filter, newest first.
+    pub fn list(basket: &Basket, filter: &IssueFilter, db: &Db) -> Vec<Self> {
+        let conn = db.conn();
+        let mut query = issues::table
+            .filter(issues::basket_id.eq(basket.id()))
+            .order(issues::number.desc())
+            .into_boxed();
+
+        match filter.state {
+            IssueState::Open => query = query.filter(issues::is_open.eq(true)),
+            IssueState::Closed => query = query.filter(issues::is_open.eq(false)),
+            IssueState::All => {}
+        }
+
+        // The ids are restricted to this basket, so that they don't grow with
+        // the whole site.
+        if let Some(ref label) = filter.label {
+            let ids = issue_labels::table
+                .inner_join(issues::table)
+                .filter(issues::basket_id.eq(basket.id()))
+                .filter(issue_labels::label.eq(label))
+                .select(issue_labels::issue_id)
+                .load::<i64>(&*conn)
+                .unwrap();
+            query = query.filter(issues::id.eq_any(ids));
+        }
+
+        if let Some(ref assignee) = filter.assignee {
+            let user = match PubUser::from_username(assignee, db) {
+                Some(user) => user,
+                None => return vec![],
+            };
+            let ids = issue_assignees::table
+                .inner_join(issues::table)
+                .filter(issues::basket_id.eq(basket.id()))
+                .filter(issue_assignees::user_id.eq(user.id()))
+                .select(issue_assignees::issue_id)
+                .load::<i64>(&*conn)
+                .unwrap();
+            query = query.filter(issues::id.eq_any(ids));
+        }
+
+        let records = query.load::<IssueRecord>(&*conn).unwrap();
+        Self::with_details(records, basket, db)
+    }
+
+    /// Returns all labels used in the given basket, sorted alphabetically.
+    pub fn labels_in(basket: &Basket, db: &Db) -> Vec<String> {
+        let conn = db.conn();
+        let mut labels = issue_labels::table
+            .inner_join(issues::table)
+            .filter(issues::basket_id.eq(basket.id()))
+            .select(issue_labels::label)
+            .load::<String>(&*conn)
+            .unwrap();
+        labels.sort();
+        labels.dedup();
+        labels
+    }
+
+    /// Loads labels, assignees and authors for the given issues.
+    fn with_details(records: Vec<IssueRecord>, basket: &Basket, db: &Db) -> Vec<Self> {
+        let conn = db.conn();
+        let ids = records.iter().map(|r| r.id).collect::<Vec<_>>();
+
+        let mut labels: HashMap<i64, Vec<String>> = HashMap::new();
+        let label_rows = issue_labels::table
+            .filter(issue_labels::issue_id.eq_any(ids.clone()))
+            .order(issue_labels::label)
+            .load::<IssueLabel>(&*conn)
+            .unwrap();
+        for row in label_rows {
+            labels.entry(row.issue_id).or_insert_with(Vec::new).push(row.label);
+        }
+
+        let mut assignees: HashMap<i64, Vec<PubUser>> = HashMap::new();
+        let assignee_rows = issue_assignees::table
+            .inner_join(users::table)
+            .filter(issue_assignees::issue_id.eq_any(ids))
+            .order(users::username)
+            .load::<(IssueAssignee, User)>(&*conn)
+            .unwrap();
+        for (row, user) in assignee_rows {
+            assignees.entry(row.issue_id)
+                .or_insert_with(Vec::new)
+                .push(PubUser::from_user(user));
+        }
+
+        let author_ids = records.iter().filter_map(|r| r.author_id).collect::<Vec<_>>();
+        let authors = load_users(author_ids, db);
+
+        records.into_iter()
+            .map(|record| Issue {
+                labels: labels.remove(&record.id).unwrap_or_default(),
+                assignees: assignees.remove(&record.id).unwrap_or_default(),
+                author: record.author_id.and_then(|id| authors.get(&id).cloned()),
+                basket_url: basket.url(),
+                record,
+            })
+            .collect()
+    }
+
+    pub fn url(&self) -> String {
+        format!("{}/issues/{}", self.basket_url, self.record.number)
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn assignees(&self) -> &[PubUser] {
+        &self.assignees
+    }
+
+    /// Returns all comments on this issue, oldest first.
+    pub fn comments(&self, db: &Db) -> Vec<Comment> {
+        let records = issue_comments::table
+            .filter(issue_comments::issue_id.eq(self.record.id))
+            .order(issue_comments::id)
+            .load::<CommentRecord>(&*db.conn())
+            .unwrap();
+
+        let author_ids = records.iter().filter_map(|r| r.author_id).collect::<Vec<_>>();
+        let authors = load_users(author_ids, db);
+
+        records.into_iter()
+            .map(|record| Comment {
+                author: record.author_id
+                    .and_then(|id| authors.get(&id))
+                    .map(|u| u.username().to_string()),
+                body: record.body,
+                created_at: record.created_at.format("%Y-%m-%d %H:%M").to_string(),
+            })
+            .collect()
+    }
+
+    /// Adds a comment written by the given user.
+    pub fn add_comment(
+        &self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        body: &str,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        check_not_archived(basket)?;
+        let can_comment = has_permission(Some(auth_user), UserAction::CommentOnIssue {
+            owner: basket.owner_user(),
+            basket: basket,
+        }, db);
+        if !can_comment {
+            return Err(IssueError::NoPermission);
+        }
+
+        let body = body.trim();
+        if body.is_empty() {
+            return Err(IssueError::CommentEmpty);
+        }
+        if body.len() >= MAX_ML_LEN {
+            return Err(IssueError::BodyTooLong);
+        }
+
+        let new_comment = NewComment {
+            issue_id: self.record.id,
+            author_id: Some(auth_user.id()),
+            body: body.into(),
+        };
+        diesel::insert(&new_comment)
+            .into(issue_comments::table)
+            .execute(&*db.conn())
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Changes title and body of this issue.
+    pub fn edit(
+        &mut self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        title: &str,
+        body: &str,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        self.check_edit_permission(basket, auth_user, db)?;
+        let (title, body) = check_title_body(title, body)?;
+
+        self.record = diesel::update(issues::table.find(self.record.id))
+            .set((issues::title.eq(title), issues::body.eq(body)))
+            .get_result(&*db.conn())
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Closes or reopens this issue.
+    pub fn set_open(
+        &mut self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        is_open: bool,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        self.check_edit_permission(basket, auth_user, db)?;
+
+        self.record = diesel::update(issues::table.find(self.record.id))
+            .set(issues::is_open.eq(is_open))
+            .get_result(&*db.conn())
+            .unwrap();
+
+        Ok(())
+    }
+
+    /// Replaces the labels of this issue. `labels` is a comma separated list.
+    pub fn set_labels(
+        &mut self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        labels: &str,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        check_triage_permission(basket, auth_user, db)?;
+        let labels = parse_labels(labels)?;
+
+        let conn = db.conn();
+        let issue_id = self.record.id;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(issue_labels::table.filter(issue_labels::issue_id.eq(issue_id)))
+                .execute(&*conn)?;
+
+            let new_labels = labels.iter()
+                .map(|label| IssueLabel { issue_id, label: label.clone() })
+                .collect::<Vec<_>>();
+            if !new_labels.is_empty() {
+                diesel::insert(&new_labels)
+                    .into(issue_labels::table)
+                    .execute(&*conn)?;
+            }
+            Ok(())
+        }).unwrap();
+
+        self.labels = labels;
+        Ok(())
+    }
+
+    /// Replaces the assignees of this issue. `usernames` is a comma separated
+    /// list. Only users who can work on the basket can be assigned.
+    pub fn set_assignees(
+        &mut self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        usernames: &str,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        check_triage_permission(basket, auth_user, db)?;
+
+        let mut assignees = Vec::new();
+        for username in usernames.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let user = PubUser::from_username(username, db)
+                .ok_or_else(|| IssueError::AssigneeInvalid { username: username.into() })?;
+
+            let can_assign = has_permission(Some(auth_user), UserAction::AssignIssue {
+                owner: basket.owner_user(),
+                basket: basket,
+                assignee: &user,
+            }, db);
+            if !can_assign {
+                return Err(IssueError::AssigneeInvalid { username: username.into() });
+            }
+
+            if !assignees.iter().any(|u: &PubUser| u.id() == user.id()) {
+                assignees.push(user);
+            }
+        }
+
+        let conn = db.conn();
+        let issue_id = self.record.id;
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            diesel::delete(
+                issue_assignees::table.filter(issue_assignees::issue_id.eq(issue_id))
+            ).execute(&*conn)?;
+
+            let rows = assignees.iter()
+                .map(|u| IssueAssignee { issue_id, user_id: u.id() })
+                .collect::<Vec<_>>();
+            if !rows.is_empty() {
+                diesel::insert(&rows)
+                    .into(issue_assignees::table)
+                    .execute(&*conn)?;
+            }
+            Ok(())
+        }).unwrap();
+
+        self.assignees = assignees;
+        Ok(())
+    }
+
+    fn check_edit_permission(
+        &self,
+        basket: &Basket,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), IssueError> {
+        check_not_archived(basket)?;
+        let can_edit = has_permission(Some(auth_user), UserAction::EditIssue {
+            owner: basket.owner_user(),
+            basket: basket,
+            issue: &self.record,
+        }, db);
+        if can_edit {
+            Ok(())
+        } else {
+            Err(IssueError::NoPermission)
+        }
+    }
+}
+
+impl Serialize for Issue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let assignees = self.assignees.iter().map(|u| u.username()).collect::<Vec<_>>();
+
+        let mut s = serializer.serialize_struct("Issue", 9)?;
+        // Skipping id: the number identifies the issue within the basket
+        s.serialize_field("number", &self.record.number)?;
+        s.serialize_field("title", self.record.title())?;
+        s.serialize_field("body", self.record.body())?;
+        s.serialize_field("is_open", &self.record.is_open)?;
+        s.serialize_field("author", &self.author.as_ref().map(|u| u.username()))?;
+        s.serialize_field("created_at", &self.record.created_at.format("%Y-%m-%d").to_string())?;
+        s.serialize_field("labels", &self.labels)?;
+        s.serialize_field("assignees", &assignees)?;
+        s.serialize_field("url", &self.url())?;
+        s.end()
+    }
+}
+
+fn check_triage_permission(
+    basket: &Basket,
+    auth_user: &AuthUser,
+    db: &Db,
+) -> Result<(), IssueError> {
+    check_not_archived(basket)?;
+    let can_triage = has_permission(Some(auth_user), UserAction::TriageIssues {
+        owner: basket.owner_user(),
+        basket: basket,
+    }, db);
+    if can_triage {
+        Ok(())
+    } else {
+        Err(IssueError::NoPermission)
+    }
+}
+
+fn check_not_archived(basket: &Basket) -> Result<(), IssueError> {
+    if basket.is_archived() {
+        Err(IssueError::Archived)
+    } else {
+        Ok(())
+    }
+}
+
+fn check_title_body(title: &str, body: &str) -> Result<(String, String), IssueError> {
+    let title = title.trim();
+    if title.is_empty() {
+        return Err(IssueError::TitleEmpty);
+    }
+    if title.len() >= MAX_SL_LEN {
+        return Err(IssueError::TitleTooLong);
+    }
+    if body.len() >= MAX_ML_LEN {
+        return Err(IssueError::BodyTooLong);
+    }
+
+    Ok((title.into(), body.trim().into()))
+}
+
+/// Parses a comma separated list of labels.
+fn parse_labels(labels: &str) -> Result<Vec<String>, IssueError> {
+    let mut out = Vec::new();
+    for label in labels.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if label.len() >= MAX_SL_LEN {
+            return Err(IssueError::LabelInvalid { label: label.into() });
+        }
+        if !out.iter().any(|l| l == label) {
+            out.push(label.to_string());
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+fn load_users(ids: Vec<i64>, db: &Db) -> HashMap<i64, PubUser> {
+    users::table
+        .filter(users::id.eq_any(ids))
+        .load::<User>(&*db.conn())
+        .unwrap()
+        .into_iter()
+        .map(PubUser::from_user)
+        .map(|u| (u.id(), u))
+        .collect()
+}
+
+
+pub enum IssueError {
+    /// The current user is not allowed to perform the action.
+    NoPermission,
+    /// The basket is archived and can't be changed.
+    Archived,
+    TitleEmpty,
+    TitleTooLong,
+    BodyTooLong,
+    CommentEmpty,
+    LabelInvalid {
+        label: String,
+    },
+    /// The user doesn't exist or can't be assigned to issues of this basket.
+    AssigneeInvalid {
+        username: String,
+    },
+    /// We couldn't allocate an issue number due to concurrent inserts.
+    TryAgain,
+    /// The database refused the issue for another reason, which was logged.
+    SaveFailed,
+}
+
+impl fmt::Display for IssueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::IssueError::*;
+
+        match *self {
+            NoPermission => {
+                "You don't have the permission to do that!".fmt(f)
+            }
+            Archived => {
+                ARCHIVED_MESSAGE.fmt(f)
+            }
+            TitleEmpty => {
+                "The title can't be empty!".fmt(f)
+            }
+            TitleTooLong => {
+                "The title is too long!".fmt(f)
+            }
+            BodyTooLong => {
+                "The text is too long!".fmt(f)
+            }
+            CommentEmpty => {
+                "The comment can't be empty!".fmt(f)
+            }
+            LabelInvalid { ref label } => {
+                write!(f, "'{}' is not a valid label!", label)
+            }
+            AssigneeInvalid { ref username } => {
+                write!(f, "'{}' can't be assigned to issues of this basket!", username)
+            }
+            TryAgain => {
+                "Too many issues are being created right now. Please try again."
+                    .fmt(f)
+            }
+            SaveFailed => {
+                "The issue couldn't be saved. Please try again later.".fmt(f)
+            }
+        }
+    }
+}
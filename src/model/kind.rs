@@ -0,0 +1,158 @@
+//! The registry of all basket kinds.
+//!
+//! The kind of a basket determines which facades are shown by default and
+//! with which content a new basket starts. The kind is stored as its `id` in
+//! the `baskets.kind` column.
+
+/// A facade is a specific view onto the content of a basket.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct Facade {
+    /// Used as last URL segment: `/<owner>/<basket>/<id>`.
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+pub const FILES: Facade = Facade { id: "files", name: "Files" };
+pub const WIKI: Facade = Facade { id: "wiki", name: "Wiki" };
+pub const PAGES: Facade = Facade { id: "pages", name: "Pages" };
+pub const BOOKMARKS_FACADE: Facade = Facade { id: "bookmarks", name: "Bookmarks" };
+pub const RECORDS: Facade = Facade { id: "records", name: "Records" };
+pub const ISSUES: Facade = Facade { id: "issues", name: "Issues" };
+pub const SETTINGS: Facade = Facade { id: "settings", name: "Settings" };
+
+/// A kind of basket.
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct BasketKind {
+    pub id: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+
+    /// Facades shown in the facade bar, in this order. The settings facade is
+    /// always shown and mustn't be listed here.
+    pub default_facades: &'static [Facade],
+
+    /// Files (path and content) committed when a basket of this kind is
+    /// created.
+    #[serde(skip_serializing)]
+    pub template: &'static [(&'static str, &'static str)],
+}
+
+/// The kind used if nothing more specific fits.
+pub static GENERIC: BasketKind = BasketKind {
+    id: "generic",
+    name: "Generic",
+    description: "A plain collection of files without any special structure.",
+    default_facades: &[FILES],
+    template: &[
+        ("README.md", "# README\n\nDescribe what this basket is about.\n"),
+    ],
+};
+
+pub static NOTES: BasketKind = BasketKind {
+    id: "notes",
+    name: "Notes",
+    description: "A wiki of Markdown pages linking to each other.",
+    default_facades: &[WIKI, PAGES, FILES],
+    template: &[
+        ("Home.md", "# Home\n\nThis is the start page of your notes.\n"),
+    ],
+};
+
+pub static BOOKMARKS: BasketKind = BasketKind {
+    id: "bookmarks",
+    name: "Bookmarks",
+    description: "A collection of links with notes and tags.",
+    default_facades: &[BOOKMARKS_FACADE, FILES],
+    template: &[
+        ("README.md", "# Bookmarks\n"),
+    ],
+};
+
+pub static RECIPES: BasketKind = BasketKind {
+    id: "recipes",
+    name: "Recipes",
+    description: "Structured records, like recipes, following a schema defined in \
+        the basket.",
+    default_facades: &[RECORDS, FILES],
+    template: &[
+        ("README.md", "# Recipes\n\nThe fields of all recipes are defined in `schema.toml`.\n"),
+        ("schema.toml", RECIPE_SCHEMA),
+    ],
+};
+
+/// The schema of new "recipes" baskets. See `model::record`.
+const RECIPE_SCHEMA: &str = r#"name = "Recipe"
+directory = "recipes"
+columns = ["title", "category", "time", "servings"]
+
+[[fields]]
+name = "title"
+label = "Title"
+type = "string"
+required = true
+
+[[fields]]
+name = "category"
+label = "Category"
+type = "choice"
+options = ["Starter", "Main course", "Dessert", "Snack", "Drink"]
+
+[[fields]]
+name = "time"
+label = "Time (minutes)"
+type = "integer"
+min = 0
+
+[[fields]]
+name = "servings"
+label = "Servings"
+type = "integer"
+min = 1
+
+[[fields]]
+name = "vegetarian"
+label = "Vegetarian"
+type = "boolean"
+
+[[fields]]
+name = "ingredients"
+label = "Ingredients (one per line)"
+type = "list"
+required = true
+
+[[fields]]
+name = "instructions"
+label = "Instructions"
+type = "text"
+required = true
+"#;
+
+pub static TASKS: BasketKind = BasketKind {
+    id: "tasks",
+    name: "Tasks",
+    description: "Track work with issues.",
+    default_facades: &[ISSUES, FILES],
+    template: &[
+        ("README.md", "# Tasks\n"),
+    ],
+};
+
+/// All known basket kinds in the order they are offered to the user.
+pub static ALL: &[&BasketKind] = &[&GENERIC, &NOTES, &BOOKMARKS, &RECIPES, &TASKS];
+
+impl BasketKind {
+    /// Returns the kind with the given id, if it exists.
+    pub fn from_id(id: &str) -> Option<&'static BasketKind> {
+        ALL.iter().cloned().find(|kind| kind.id == id)
+    }
+
+    /// Returns all known kinds.
+    pub fn all() -> &'static [&'static BasketKind] {
+        ALL
+    }
+
+    /// Returns whether this kind offers the facade with the given id.
+    pub fn has_facade(&self, id: &str) -> bool {
+        id == SETTINGS.id || self.default_facades.iter().any(|f| f.id == id)
+    }
+}
@@ -0,0 +1,547 @@
+//! Sorting, filtering and keyset pagination of basket listings.
+//!
+//! A page ends with a cursor describing the position of its last basket (see
+//! `Cursor`), which is passed as `after` to get the next page. The next page
+//! starts right after that position in the chosen order, so the database
+//! never has to skip rows like with offsets.
+
+use chrono::{DateTime, TimeZone, Timelike};
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use rocket::request::{FormItems, FromForm};
+use std::cmp;
+use std::fmt;
+
+use db::Db;
+use db::schema::baskets;
+use model::{Basket, BasketRecord, BasketVisibility};
+use model::permissions::BasketQuery;
+
+
+/// Number of baskets per page if nothing else is requested.
+pub const DEFAULT_PAGE_SIZE: i64 = 30;
+
+/// Maximum number of baskets per page.
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// The order of a basket listing. Ties are broken by the basket id.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BasketSort {
+    /// Alphabetically.
+    Name,
+    /// Newest first.
+    Created,
+    /// Most recently changed first.
+    Updated,
+    /// Most starred first.
+    Stars,
+}
+
+impl BasketSort {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "name" => Some(BasketSort::Name),
+            "created" => Some(BasketSort::Created),
+            "updated" => Some(BasketSort::Updated),
+            "stars" => Some(BasketSort::Stars),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BasketSort::Name => "name",
+            BasketSort::Created => "created",
+            BasketSort::Updated => "updated",
+            BasketSort::Stars => "stars",
+        }
+    }
+}
+
+/// The options of a basket listing: order, filters and the page.
+#[derive(Clone, Debug)]
+pub struct BasketListing {
+    pub sort: BasketSort,
+    /// Whether archived baskets come after all others.
+    pub archived_last: bool,
+    /// Only show baskets of this kind.
+    pub kind: Option<String>,
+    /// Only show baskets with this visibility.
+    pub visibility: Option<BasketVisibility>,
+    /// The cursor of the previous page, see `Cursor`.
+    pub after: Option<String>,
+    pub limit: i64,
+}
+
+impl Default for BasketListing {
+    fn default() -> Self {
+        BasketListing {
+            sort: BasketSort::Name,
+            archived_last: false,
+            kind: None,
+            visibility: None,
+            after: None,
+            limit: DEFAULT_PAGE_SIZE,
+        }
+    }
+}
+
+/// A page of a basket listing.
+#[derive(Serialize)]
+pub struct BasketPage {
+    pub baskets: Vec<Basket>,
+    /// The value of `after` for the next page, if there is one.
+    pub next: Option<String>,
+}
+
+/// The value a basket is sorted by.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SortValue {
+    Name(String),
+    /// The creation or update time.
+    Time(DateTime<UTC>),
+    Stars(i32),
+}
+
+/// The position of a basket in a listing: the value it's sorted by, whether
+/// it is archived and its id, which breaks ties.
+///
+/// The position is taken from the basket when the page is loaded. The next
+/// page thus doesn't depend on the basket anymore, which may have changed,
+/// been deleted or become invisible in the meantime. As text, it's
+/// `<id>.<archived>.<value>`, with times in microseconds since the epoch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Cursor {
+    pub id: i64,
+    pub archived: bool,
+    pub value: SortValue,
+}
+
+impl Cursor {
+    /// Returns the position of the basket in a listing with the given order.
+    pub fn of(record: &BasketRecord, sort: BasketSort) -> Self {
+        let value = match sort {
+            BasketSort::Name => SortValue::Name(record.name().to_string()),
+            BasketSort::Created => SortValue::Time(record.created_at()),
+            BasketSort::Updated => SortValue::Time(record.updated_at()),
+            BasketSort::Stars => SortValue::Stars(record.star_count()),
+        };
+        Cursor {
+            id: record.id(),
+            archived: record.is_archived(),
+            value,
+        }
+    }
+
+    /// Parses a cursor of a listing with the given order. Returns `None` if
+    /// it's invalid or belongs to another order.
+    pub fn parse(s: &str, sort: BasketSort) -> Option<Self> {
+        let mut parts = s.splitn(3, '.');
+        let id = match parts.next().and_then(|id| id.parse().ok()) {
+            Some(id) => id,
+            None => return None,
+        };
+        let archived = match parts.next() {
+            Some("0") => false,
+            Some("1") => true,
+            _ => return None,
+        };
+        let value = match parts.next() {
+            Some(value) => value,
+            None => return None,
+        };
+
+        let value = match sort {
+            BasketSort::Name => Some(SortValue::Name(value.to_string())),
+            BasketSort::Created | BasketSort::Updated => {
+                value.parse::<i64>().ok()
+                    .and_then(|micros| {
+                        if micros < 0 {
+                            return None;
+                        }
+                        let nanos = (micros % 1_000_000) as u32 * 1000;
+                        UTC.timestamp_opt(micros / 1_000_000, nanos).single()
+                    })
+                    .map(SortValue::Time)
+            }
+            BasketSort::Stars => value.parse().ok().map(SortValue::Stars),
+        };
+
+        value.map(|value| Cursor { id, archived, value })
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.", self.id, if self.archived { 1 } else { 0 })?;
+        match self.value {
+            SortValue::Name(ref name) => write!(f, "{}", name),
+            SortValue::Time(time) => {
+                let micros = time.timestamp() * 1_000_000 + i64::from(time.nanosecond() / 1000);
+                write!(f, "{}", micros)
+            }
+            SortValue::Stars(stars) => write!(f, "{}", stars),
+        }
+    }
+}
+
+/// Adds the keyset condition to `$query`: only rows after `$cursor` in the
+/// order given by `$column` and the id, both compared with `$cmp` (`gt` for
+/// ascending, `lt` for descending). Archived baskets come last if
+/// `$archived_last` is set.
+macro_rules! after {
+    ($query:expr, $column:expr, $cmp:ident, $value:expr, $cursor:expr, $archived_last:expr) => {{
+        let value = $value;
+        let key = $column.$cmp(value.clone())
+            .or($column.eq(value).and(baskets::id.$cmp($cursor.id)));
+        if $archived_last {
+            let archived = $cursor.archived;
+            $query.filter(
+                baskets::archived.gt(archived)
+                    .or(baskets::archived.eq(archived).and(key))
+            )
+        } else {
+            $query.filter(key)
+        }
+    }}
+}
+
+impl BasketListing {
+    /// Whether the listing shows the first page.
+    pub fn is_first_page(&self) -> bool {
+        self.after.is_none()
+    }
+
+    /// Applies a single query parameter. Unknown parameters and invalid
+    /// values are ignored. Returns whether the parameter was used.
+    pub fn apply_param(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            // `sort=archived` sorts by name with archived baskets last.
+            "sort" if value == "archived" => {
+                self.sort = BasketSort::Name;
+                self.archived_last = true;
+            }
+            "sort" => {
+                if let Some(sort) = BasketSort::from_str(value) {
+                    self.sort = sort;
+                }
+            }
+            "archived" => self.archived_last = value == "last",
+            "kind" => self.kind = if value.is_empty() { None } else { Some(value.into()) },
+            "visibility" => self.visibility = BasketVisibility::from_str(value),
+            "after" => self.after = if value.is_empty() { None } else { Some(value.into()) },
+            "limit" => {
+                if let Ok(limit) = value.parse::<i64>() {
+                    self.limit = cmp::max(1, cmp::min(limit, MAX_PAGE_SIZE));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns the query string for these options, without `after`.
+    pub fn query_string(&self) -> String {
+        let mut params = vec![format!("sort={}", self.sort.as_str())];
+        if self.archived_last {
+            params.push("archived=last".into());
+        }
+        if let Some(ref kind) = self.kind {
+            params.push(format!("kind={}", kind));
+        }
+        if let Some(visibility) = self.visibility {
+            params.push(format!("visibility={}", visibility.as_str()));
+        }
+        if self.limit != DEFAULT_PAGE_SIZE {
+            params.push(format!("limit={}", self.limit));
+        }
+        params.join("&")
+    }
+
+    /// Loads one page of the baskets selected by `query`, which is usually
+    /// restricted to the visible baskets of one owner.
+    pub fn load<'a>(&self, query: BasketQuery<'a>, db: &Db) -> BasketPage {
+        let conn = db.conn();
+        let mut query = query;
+
+        if let Some(ref kind) = self.kind {
+            query = query.filter(baskets::kind.eq(kind.clone()));
+        }
+        if let Some(visibility) = self.visibility {
+            query = query.filter(baskets::visibility.eq(visibility.as_str()));
+        }
+
+        // An invalid cursor starts at the beginning again.
+        let cursor = self.after.as_ref().and_then(|after| Cursor::parse(after, self.sort));
+        if let Some(ref cursor) = cursor {
+            let archived_last = self.archived_last;
+            query = match (self.sort, &cursor.value) {
+                (BasketSort::Name, &SortValue::Name(ref name)) => {
+                    after!(query, baskets::name, gt, name.clone(), cursor, archived_last)
+                }
+                (BasketSort::Created, &SortValue::Time(time)) => {
+                    after!(query, baskets::created_at, lt, time, cursor, archived_last)
+                }
+                (BasketSort::Updated, &SortValue::Time(time)) => {
+                    after!(query, baskets::updated_at, lt, time, cursor, archived_last)
+                }
+                (BasketSort::Stars, &SortValue::Stars(stars)) => {
+                    after!(query, baskets::star_count, lt, stars, cursor, archived_last)
+                }
+                // `Cursor::parse` only returns values matching the order.
+                _ => query,
+            };
+        }
+
+        query = match (self.sort, self.archived_last) {
+            (BasketSort::Name, false) => query.order((baskets::name.asc(), baskets::id.asc())),
+            (BasketSort::Name, true) => {
+                query.order((baskets::archived.asc(), baskets::name.asc(), baskets::id.asc()))
+            }
+            (BasketSort::Created, false) => {
+                query.order((baskets::created_at.desc(), baskets::id.desc()))
+            }
+            (BasketSort::Created, true) => {
+                query.order((
+                    baskets::archived.asc(),
+                    baskets::created_at.desc(),
+                    baskets::id.desc(),
+                ))
+            }
+            (BasketSort::Updated, false) => {
+                query.order((baskets::updated_at.desc(), baskets::id.desc()))
+            }
+            (BasketSort::Updated, true) => {
+                query.order((
+                    baskets::archived.asc(),
+                    baskets::updated_at.desc(),
+                    baskets::id.desc(),
+                ))
+            }
+            (BasketSort::Stars, false) => {
+                query.order((baskets::star_count.desc(), baskets::id.desc()))
+            }
+            (BasketSort::Stars, true) => {
+                query.order((
+                    baskets::archived.asc(),
+                    baskets::star_count.desc(),
+                    baskets::id.desc(),
+                ))
+            }
+        };
+
+        // Load one more row to find out whether there is another page.
+        let mut records = query
+            .limit(self.limit + 1)
+            .load::<BasketRecord>(&*conn)
+            .unwrap();
+        let next = if records.len() as i64 > self.limit {
+            records.truncate(self.limit as usize);
+            records.last().map(|r| Cursor::of(r, self.sort).to_string())
+        } else {
+            None
+        };
+
+        BasketPage {
+            baskets: Basket::with_owners(records, db),
+            next,
+        }
+    }
+}
+
+impl<'f> FromForm<'f> for BasketListing {
+    type Error = ();
+    fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
+        let mut listing = BasketListing::default();
+        for (key, value) in form_items {
+            listing.apply_param(key, value);
+        }
+        Ok(listing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono::offset::utc::UTC;
+    use diesel;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    use db::Db;
+    use db::schema::baskets;
+    use model::permissions::Visibility;
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = |id, archived, value| Cursor { id, archived, value };
+        let cursors = [
+            (BasketSort::Name, cursor(3, false, SortValue::Name("a-b".into()))),
+            (BasketSort::Name, cursor(4, true, SortValue::Name("x.y".into()))),
+            (
+                BasketSort::Created,
+                cursor(5, false, SortValue::Time(UTC.timestamp(1_500_000_000, 123_456_000))),
+            ),
+            (BasketSort::Updated, cursor(6, true, SortValue::Time(UTC.timestamp(0, 0)))),
+            (BasketSort::Stars, cursor(7, false, SortValue::Stars(42))),
+        ];
+        for &(sort, ref cursor) in &cursors {
+            assert_eq!(Cursor::parse(&cursor.to_string(), sort).as_ref(), Some(cursor));
+        }
+
+        assert_eq!(
+            Cursor::parse("5.0.1500000000123456", BasketSort::Created).map(|c| c.value),
+            Some(SortValue::Time(UTC.timestamp(1_500_000_000, 123_456_000))),
+        );
+    }
+
+    #[test]
+    fn invalid_cursors() {
+        assert_eq!(Cursor::parse("", BasketSort::Name), None);
+        assert_eq!(Cursor::parse("12", BasketSort::Name), None);
+        assert_eq!(Cursor::parse("12.0", BasketSort::Name), None);
+        assert_eq!(Cursor::parse("x.0.name", BasketSort::Name), None);
+        assert_eq!(Cursor::parse("12.2.name", BasketSort::Name), None);
+        assert_eq!(Cursor::parse("12.0.name", BasketSort::Stars), None);
+        assert_eq!(Cursor::parse("12.0.name", BasketSort::Created), None);
+        assert_eq!(Cursor::parse("12.0.-5", BasketSort::Updated), None);
+        assert_eq!(Cursor::parse("12.0.99999999999999999999", BasketSort::Updated), None);
+    }
+
+    #[test]
+    fn params() {
+        let mut listing = BasketListing::default();
+        assert!(listing.apply_param("sort", "stars"));
+        assert!(listing.apply_param("limit", "1000"));
+        assert!(listing.apply_param("after", "3.0.7"));
+        assert!(!listing.apply_param("page", "2"));
+        assert_eq!(listing.sort, BasketSort::Stars);
+        assert_eq!(listing.limit, MAX_PAGE_SIZE);
+        assert_eq!(listing.after, Some("3.0.7".to_string()));
+        assert!(!listing.is_first_page());
+        assert_eq!(listing.query_string(), "sort=stars&limit=100");
+
+        listing.apply_param("sort", "archived");
+        listing.apply_param("after", "");
+        assert_eq!(listing.sort, BasketSort::Name);
+        assert!(listing.archived_last);
+        assert!(listing.is_first_page());
+    }
+
+    /// Creates public baskets with many equal sort values, so that ties have
+    /// to be broken by the id.
+    fn setup() -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute("
+            insert into users (id, username) values (900001, 'test-lister');
+            insert into baskets
+                (id, name, user_id, kind, visibility, archived, created_at, updated_at,
+                    star_count)
+            values
+                (900101, 'b', 900001, 'generic', 'public', false,
+                    '2017-01-01', '2017-02-01', 3),
+                (900102, 'a', 900001, 'notes', 'public', true,
+                    '2017-01-01', '2017-02-03', 0),
+                (900103, 'd', 900001, 'generic', 'public', false,
+                    '2017-01-02', '2017-02-01', 3),
+                (900104, 'c', 900001, 'generic', 'public', true,
+                    '2017-01-03', '2017-02-02', 3),
+                (900105, 'f', 900001, 'notes', 'public', false,
+                    '2017-01-01', '2017-02-01', 1),
+                (900106, 'e', 900001, 'generic', 'public', false,
+                    '2017-01-03', '2017-02-04', 0);
+        ").unwrap();
+        db
+    }
+
+    fn load(listing: &BasketListing, db: &Db) -> (Vec<i64>, Option<String>) {
+        let query = Visibility::of(None, db)
+            .visible_baskets()
+            .filter(baskets::user_id.eq(900001));
+        let page = listing.load(query, db);
+        (page.baskets.iter().map(|b| b.id()).collect(), page.next)
+    }
+
+    /// Loads all pages of the listing.
+    fn load_all(listing: &BasketListing, db: &Db) -> Vec<i64> {
+        let mut listing = listing.clone();
+        let mut out = Vec::new();
+        loop {
+            let (ids, next) = load(&listing, db);
+            assert!(ids.len() as i64 <= listing.limit);
+            out.extend(ids);
+            match next {
+                Some(next) => listing.after = Some(next),
+                None => return out,
+            }
+        }
+    }
+
+    #[test]
+    fn pages() {
+        let db = setup();
+        let expected = [
+            (BasketSort::Name, false, vec![900102, 900101, 900104, 900103, 900106, 900105]),
+            (BasketSort::Name, true, vec![900101, 900103, 900106, 900105, 900102, 900104]),
+            (BasketSort::Created, false, vec![900106, 900104, 900103, 900105, 900102, 900101]),
+            (BasketSort::Created, true, vec![900106, 900103, 900105, 900101, 900104, 900102]),
+            (BasketSort::Updated, false, vec![900106, 900102, 900104, 900105, 900103, 900101]),
+            (BasketSort::Updated, true, vec![900106, 900105, 900103, 900101, 900102, 900104]),
+            (BasketSort::Stars, false, vec![900104, 900103, 900101, 900105, 900106, 900102]),
+            (BasketSort::Stars, true, vec![900103, 900101, 900105, 900106, 900104, 900102]),
+        ];
+
+        for &(sort, archived_last, ref ids) in &expected {
+            for limit in 1..8 {
+                let listing = BasketListing {
+                    sort,
+                    archived_last,
+                    limit,
+                    .. BasketListing::default()
+                };
+                assert_eq!(
+                    &load_all(&listing, &db), ids,
+                    "sort {:?}, archived last: {}, limit {}", sort, archived_last, limit,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn filters() {
+        let db = setup();
+        let listing = BasketListing {
+            kind: Some("notes".into()),
+            limit: 1,
+            .. BasketListing::default()
+        };
+        assert_eq!(load_all(&listing, &db), vec![900102, 900105]);
+    }
+
+    #[test]
+    fn cursor_survives_changes() {
+        let db = setup();
+        let listing = BasketListing {
+            sort: BasketSort::Updated,
+            limit: 2,
+            .. BasketListing::default()
+        };
+        let (ids, next) = load(&listing, &db);
+        assert_eq!(ids, vec![900106, 900102]);
+
+        // The last basket of the page is changed and another one deleted.
+        // The next page still starts where the first one ended.
+        diesel::update(baskets::table.find(900102))
+            .set(baskets::updated_at.eq(UTC::now()))
+            .execute(&*db.conn())
+            .unwrap();
+        diesel::update(baskets::table.find(900105))
+            .set(baskets::deleted_at.eq(Some(UTC::now())))
+            .execute(&*db.conn())
+            .unwrap();
+
+        let listing = BasketListing { after: next, limit: 10, .. listing };
+        assert_eq!(load(&listing, &db), (vec![900104, 900103, 900101], None));
+    }
+}
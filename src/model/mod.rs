@@ -1,14 +1,58 @@
 
 pub mod basket;
+pub mod bookmark;
+pub mod collaborator;
+pub mod content;
+pub mod email_notification;
+pub mod event;
+pub mod explore;
+pub mod feed;
+pub mod issue;
+pub mod kind;
+pub mod listing;
+pub mod notification;
+pub mod organization;
 pub mod permissions;
+pub mod record;
+pub mod search;
 mod session;
+pub mod star;
+pub mod transfer;
 mod user;
 mod user_email;
+pub mod watch;
+pub mod webhook;
+pub mod wiki;
 
-pub use self::basket::{Basket, BasketRecord};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
+
+pub use self::basket::{Basket, BasketRecord, BasketVisibility};
+pub use self::kind::BasketKind;
 pub use self::session::{NewSession, Session};
-pub use self::user::{AuthUser, PubUser, User};
+pub use self::user::{AuthUser, LoginError, PubUser, User};
 pub use self::user_email::UserEmail;
 
 pub const MAX_SL_LEN: usize = 126;
 pub const MAX_ML_LEN: usize = 32768;
+
+
+/// Starts a background thread which calls `f` every `interval`. Errors are
+/// up to `f`, but a panic is caught and logged and only skips one round, so
+/// that e.g. a lost database connection doesn't stop the thread for good.
+pub fn spawn_periodic<F>(name: &'static str, interval: Duration, f: F)
+    where F: Fn() + Send + 'static
+{
+    thread::Builder::new()
+        .name(name.into())
+        .spawn(move || {
+            loop {
+                if panic::catch_unwind(AssertUnwindSafe(&f)).is_err() {
+                    error!("{} failed, trying again in {} seconds", name, interval.as_secs());
+                }
+                thread::sleep(interval);
+            }
+        })
+        .expect("failed to spawn thread");
+}
@@ -0,0 +1,337 @@
+//! The notifications inbox. Notifications are created from the same events
+//! as the activity feed, for everybody following the basket closely enough
+//! (see `watch`).
+
+use chrono::DateTime;
+use chrono::offset::utc::UTC;
+use diesel::prelude::*;
+use diesel;
+use serde::{Serialize, Serializer};
+
+use db::Db;
+use db::schema::{baskets, basket_collaborators, events, notifications, organization_members,
+    organizations};
+use model::{AuthUser, BasketRecord, PubUser};
+use model::email_notification;
+use model::event::{Event, EventKind, EventRecord};
+use model::organization::OrgRole;
+use model::permissions;
+use model::watch::{self, WatchLevel};
+
+
+/// Number of notifications shown in the inbox.
+const INBOX_SIZE: i64 = 100;
+
+/// Why a user is notified about an event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// The user owns the basket or is an owner of the organization owning
+    /// it.
+    Owner,
+    /// The user collaborates on the basket.
+    Collaborator,
+    /// The user chose to watch all activity of the basket.
+    Watching,
+}
+
+impl Reason {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "owner" => Some(Reason::Owner),
+            "collaborator" => Some(Reason::Collaborator),
+            "watching" => Some(Reason::Watching),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Reason::Owner => "owner",
+            Reason::Collaborator => "collaborator",
+            Reason::Watching => "watching",
+        }
+    }
+
+    /// The heading of the group of notifications with this reason.
+    pub fn title(&self) -> &'static str {
+        match *self {
+            Reason::Owner => "Your baskets",
+            Reason::Collaborator => "Baskets you collaborate on",
+            Reason::Watching => "Baskets you watch",
+        }
+    }
+
+    /// All reasons in the order their groups are shown.
+    pub fn all() -> &'static [Reason] {
+        &[Reason::Owner, Reason::Collaborator, Reason::Watching]
+    }
+}
+
+/// The columns of `notifications` needed for the inbox.
+#[derive(Clone, Debug, Queryable)]
+struct NotificationRecord {
+    id: i64,
+    event_id: i64,
+    reason: String,
+    read_at: Option<DateTime<UTC>>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "notifications"]
+struct NewNotification {
+    user_id: i64,
+    event_id: i64,
+    reason: String,
+    email_delivery: Option<String>,
+}
+
+/// Returns the users participating in the basket, i.e. those who are
+/// notified with the `Participating` level, and why they participate.
+fn participants(basket: &BasketRecord, db: &Db) -> QueryResult<Vec<(i64, Reason)>> {
+    let conn = db.conn();
+    let is_organization = organizations::table
+        .find(basket.user_id())
+        .count()
+        .get_result::<i64>(&*conn)? > 0;
+    let owners = if is_organization {
+        organization_members::table
+            .filter(organization_members::organization_id.eq(basket.user_id()))
+            .filter(organization_members::role.eq(OrgRole::Owner.as_str()))
+            .select(organization_members::user_id)
+            .load::<i64>(&*conn)?
+    } else {
+        vec![basket.user_id()]
+    };
+    let collaborators = basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .filter(basket_collaborators::accepted.eq(true))
+        .select(basket_collaborators::user_id)
+        .load::<i64>(&*conn)?;
+
+    Ok(owners.into_iter()
+        .map(|id| (id, Reason::Owner))
+        .chain(collaborators.into_iter().map(|id| (id, Reason::Collaborator)))
+        .collect())
+}
+
+/// Whether participants are notified about events of this kind. Pushes are
+/// only interesting for users watching all activity.
+fn concerns_participants(kind: EventKind) -> bool {
+    match kind {
+        EventKind::Created | EventKind::Forked | EventKind::Starred
+            | EventKind::SettingsChanged => true,
+        EventKind::Pushed => false,
+    }
+}
+
+/// Creates the notifications for a new event. The actor isn't notified
+/// about their own actions, and nobody is notified about a basket they
+/// can't see.
+pub fn notify(event: &EventRecord, db: &Db) -> QueryResult<()> {
+    let basket = baskets::table
+        .find(event.basket_id())
+        .first::<BasketRecord>(&*db.conn())?;
+    let participants = participants(&basket, db)?;
+    let watchers = watch::watchers(&basket, db)?;
+
+    let mut recipients: Vec<(i64, Reason)> = vec![];
+    for &(user_id, reason) in &participants {
+        let level = watchers.iter()
+            .find(|&&(id, _)| id == user_id)
+            .map(|&(_, level)| level)
+            .unwrap_or(WatchLevel::Participating);
+        let notify = match level {
+            WatchLevel::Participating => concerns_participants(event.kind()),
+            WatchLevel::All => true,
+            WatchLevel::Ignore => false,
+        };
+        if notify && !recipients.iter().any(|&(id, _)| id == user_id) {
+            recipients.push((user_id, reason));
+        }
+    }
+    for &(user_id, level) in &watchers {
+        let known = participants.iter().any(|&(id, _)| id == user_id);
+        if level == WatchLevel::All && !known {
+            recipients.push((user_id, Reason::Watching));
+        }
+    }
+    recipients.retain(|&(id, _)| Some(id) != event.actor_id());
+    if recipients.is_empty() {
+        return Ok(());
+    }
+
+    // Users may have lost access to the basket since they started watching.
+    let ids = recipients.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+    let visible_to = permissions::viewers_among(&basket, &ids, db)?;
+
+    let deliveries = email_notification::deliveries(&visible_to, event.kind(), db)?;
+    let new_notifications = recipients.into_iter()
+        .filter(|&(id, _)| visible_to.contains(&id))
+        .map(|(user_id, reason)| NewNotification {
+            user_id,
+            event_id: event.id(),
+            reason: reason.as_str().into(),
+            email_delivery: deliveries.iter()
+                .find(|&&(id, _)| id == user_id)
+                .map(|&(_, delivery)| delivery.as_str().into()),
+        })
+        .collect::<Vec<_>>();
+    if !new_notifications.is_empty() {
+        diesel::insert(&new_notifications)
+            .into(notifications::table)
+            .execute(&*db.conn())?;
+    }
+    Ok(())
+}
+
+/// A notification in the inbox.
+pub struct Notification {
+    id: i64,
+    reason: Reason,
+    read: bool,
+    event: Event,
+}
+
+impl Notification {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn reason(&self) -> Reason {
+        self.reason
+    }
+
+    pub fn is_read(&self) -> bool {
+        self.read
+    }
+
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
+}
+
+impl Serialize for Notification {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Notification", 4)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("reason", self.reason.as_str())?;
+        s.serialize_field("read", &self.read)?;
+        s.serialize_field("event", &self.event)?;
+        s.end()
+    }
+}
+
+/// The notifications with the same reason.
+#[derive(Serialize)]
+pub struct NotificationGroup {
+    pub reason: &'static str,
+    pub title: &'static str,
+    pub notifications: Vec<Notification>,
+}
+
+/// Returns the number of unread notifications of the user.
+pub fn unread_count(user: &PubUser, db: &Db) -> i64 {
+    notifications::table
+        .filter(notifications::user_id.eq(user.id()))
+        .filter(notifications::read_at.is_null())
+        .count()
+        .get_result(&*db.conn())
+        .unwrap()
+}
+
+/// Loads the most recent notifications of the user, newest first.
+/// Notifications about baskets which were deleted in the meantime are
+/// dropped.
+pub fn recent(auth_user: &AuthUser, db: &Db) -> Vec<Notification> {
+    let conn = db.conn();
+    let records = notifications::table
+        .filter(notifications::user_id.eq(auth_user.id()))
+        .order((notifications::created_at.desc(), notifications::id.desc()))
+        .limit(INBOX_SIZE)
+        .select((
+            notifications::id,
+            notifications::event_id,
+            notifications::reason,
+            notifications::read_at,
+        ))
+        .load::<NotificationRecord>(&*conn)
+        .unwrap();
+    if records.is_empty() {
+        return vec![];
+    }
+
+    let event_ids = records.iter().map(|r| r.event_id).collect::<Vec<_>>();
+    let event_records = events::table
+        .filter(events::id.eq_any(event_ids))
+        .load::<EventRecord>(&*conn)
+        .unwrap();
+    let events = Event::with_details(event_records, db);
+
+    records.into_iter()
+        .filter_map(|record| {
+            let event = events.iter().find(|e| e.record().id() == record.event_id);
+            match (event, Reason::from_str(&record.reason)) {
+                (Some(event), Some(reason)) => Some(Notification {
+                    id: record.id,
+                    reason,
+                    read: record.read_at.is_some(),
+                    event: event.clone(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the recent notifications of the user grouped by reason. Groups
+/// without notifications are left out.
+pub fn inbox(auth_user: &AuthUser, db: &Db) -> Vec<NotificationGroup> {
+    let mut groups = Reason::all()
+        .iter()
+        .map(|reason| NotificationGroup {
+            reason: reason.as_str(),
+            title: reason.title(),
+            notifications: vec![],
+        })
+        .collect::<Vec<_>>();
+
+    for notification in recent(auth_user, db) {
+        let reason = notification.reason.as_str();
+        if let Some(group) = groups.iter_mut().find(|g| g.reason == reason) {
+            group.notifications.push(notification);
+        }
+    }
+
+    groups.retain(|group| !group.notifications.is_empty());
+    groups
+}
+
+/// Marks a notification of the user as read. Returns whether the
+/// notification exists.
+pub fn mark_read(id: i64, auth_user: &AuthUser, db: &Db) -> bool {
+    let updated = diesel::update(
+        notifications::table
+            .filter(notifications::id.eq(id))
+            .filter(notifications::user_id.eq(auth_user.id()))
+    )
+        .set(notifications::read_at.eq(Some(UTC::now())))
+        .execute(&*db.conn())
+        .unwrap();
+    updated > 0
+}
+
+/// Marks all notifications of the user as read.
+pub fn mark_all_read(auth_user: &AuthUser, db: &Db) {
+    diesel::update(
+        notifications::table
+            .filter(notifications::user_id.eq(auth_user.id()))
+            .filter(notifications::read_at.is_null())
+    )
+        .set(notifications::read_at.eq(Some(UTC::now())))
+        .execute(&*db.conn())
+        .unwrap();
+}
@@ -0,0 +1,1107 @@
+//! Organizations, which own baskets on behalf of a group of users.
+//!
+//! An organization is stored as a row in `users` without password plus a row
+//! in `organizations`. This way organizations share the username namespace
+//! with users and can own baskets just like users.
+//!
+//! Members can be grouped into teams. A team can be granted a role in some
+//! of the organization's baskets, which all its members then have.
+
+use diesel::prelude::*;
+use diesel;
+use serde::{Serialize, Serializer};
+use std::cmp;
+use std::fmt;
+use std::ops::Deref;
+
+use db::Db;
+use db::schema::{baskets, organizations, organization_members, teams, team_baskets, team_members,
+    users};
+use model::{basket, AuthUser, BasketRecord, PubUser, User};
+use model::collaborator::Role;
+use super::MAX_ML_LEN;
+
+
+/// The role of a member in an organization.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub enum OrgRole {
+    /// Can see all baskets of the organization.
+    #[serde(rename = "member")]
+    Member,
+    /// Can manage members and teams, and administrate all baskets of the
+    /// organization.
+    #[serde(rename = "owner")]
+    Owner,
+}
+
+impl OrgRole {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "member" => Some(OrgRole::Member),
+            "owner" => Some(OrgRole::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            OrgRole::Member => "member",
+            OrgRole::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Queryable)]
+struct OrganizationRecord {
+    user_id: i64,
+    members_can_create_baskets: bool,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "organizations"]
+struct NewOrganization {
+    user_id: i64,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "users"]
+struct NewOrganizationUser {
+    username: String,
+    name: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Associations)]
+#[table_name = "organization_members"]
+#[belongs_to(User)]
+struct MemberRecord {
+    organization_id: i64,
+    user_id: i64,
+    role: String,
+}
+
+#[derive(Clone, Debug, Identifiable, Queryable)]
+#[table_name = "teams"]
+struct TeamRecord {
+    id: i64,
+    organization_id: i64,
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "teams"]
+struct NewTeam {
+    organization_id: i64,
+    name: String,
+    description: Option<String>,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable, Associations)]
+#[table_name = "team_members"]
+#[belongs_to(User)]
+struct TeamMember {
+    team_id: i64,
+    user_id: i64,
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "team_baskets"]
+struct TeamBasketRecord {
+    team_id: i64,
+    basket_id: i64,
+    role: String,
+}
+
+/// A member of an organization.
+#[derive(Clone, Serialize)]
+pub struct Member {
+    pub user: PubUser,
+    pub role: OrgRole,
+}
+
+/// An organization the user is a member of.
+#[derive(Clone, Serialize)]
+pub struct Membership {
+    pub organization: PubUser,
+    pub role: OrgRole,
+}
+
+/// A group of members of an organization.
+#[derive(Clone, Serialize)]
+pub struct Team {
+    #[serde(skip_serializing)]
+    id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub members: Vec<PubUser>,
+    /// The baskets the team has access to, sorted by name.
+    pub baskets: Vec<TeamGrant>,
+}
+
+/// The role a team was granted in a basket of its organization.
+#[derive(Clone, Serialize)]
+pub struct TeamGrant {
+    /// The name of the team or of the basket, depending on where the grant
+    /// is listed.
+    pub name: String,
+    pub role: Role,
+}
+
+impl Team {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+}
+
+pub struct Organization {
+    user: PubUser,
+    members_can_create_baskets: bool,
+}
+
+impl Organization {
+    /// Creates a new organization with the given user as its only owner.
+    pub fn create(
+        username: &str,
+        name: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<Self, OrganizationError> {
+        use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+        let username = username.trim();
+        if username.is_empty() {
+            return Err(OrganizationError::NameEmpty);
+        }
+        if !super::user::is_valid_username(username) {
+            return Err(OrganizationError::NameInvalid);
+        }
+        let name = name.trim();
+
+        let conn = db.conn();
+        let result = conn.transaction::<_, DieselError, _>(|| {
+            let new_user = NewOrganizationUser {
+                username: username.into(),
+                name: if name.is_empty() { None } else { Some(name.into()) },
+            };
+            let user = diesel::insert(&new_user)
+                .into(users::table)
+                .get_result::<User>(&*conn)
+                .map(PubUser::from_user)?;
+
+            diesel::insert(&NewOrganization { user_id: user.id() })
+                .into(organizations::table)
+                .execute(&*conn)?;
+
+            let owner = MemberRecord {
+                organization_id: user.id(),
+                user_id: auth_user.id(),
+                role: OrgRole::Owner.as_str().into(),
+            };
+            diesel::insert(&owner)
+                .into(organization_members::table)
+                .execute(&*conn)?;
+
+            Ok(user)
+        });
+
+        match result {
+            Ok(user) => Ok(Organization {
+                user,
+                members_can_create_baskets: true,
+            }),
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(OrganizationError::NameAlreadyUsed)
+            }
+            Err(e) => panic!("failed to create organization: {}", e),
+        }
+    }
+
+    /// Returns the organization with the given name. Returns `None` if there
+    /// is no such organization, also if `name` belongs to a regular user.
+    pub fn from_username(name: &str, db: &Db) -> Option<Self> {
+        PubUser::from_username(name, db).and_then(|user| Self::from_user(user, db))
+    }
+
+    /// Returns the organization backed by the given user row, if it is one.
+    pub fn from_user(user: PubUser, db: &Db) -> Option<Self> {
+        organizations::table
+            .find(user.id())
+            .first::<OrganizationRecord>(&*db.conn())
+            .optional()
+            .unwrap()
+            .map(|record| Organization {
+                user,
+                members_can_create_baskets: record.members_can_create_baskets,
+            })
+    }
+
+    pub fn members_can_create_baskets(&self) -> bool {
+        self.members_can_create_baskets
+    }
+
+    /// Returns the role of the given user in this organization.
+    pub fn role_of(&self, user: &PubUser, db: &Db) -> Option<OrgRole> {
+        member_role(self.user.id(), user.id(), db)
+    }
+
+    /// Returns whether the given user may create baskets owned by this
+    /// organization.
+    pub fn can_create_baskets(&self, user: &PubUser, db: &Db) -> bool {
+        match self.role_of(user, db) {
+            Some(OrgRole::Owner) => true,
+            Some(OrgRole::Member) => self.members_can_create_baskets,
+            None => false,
+        }
+    }
+
+    /// Returns all members sorted by username.
+    pub fn members(&self, db: &Db) -> Vec<Member> {
+        organization_members::table
+            .inner_join(users::table)
+            .filter(organization_members::organization_id.eq(self.user.id()))
+            .order(users::username)
+            .load::<(MemberRecord, User)>(&*db.conn())
+            .unwrap()
+            .into_iter()
+            .filter_map(|(record, user)| {
+                OrgRole::from_str(&record.role).map(|role| Member {
+                    user: PubUser::from_user(user),
+                    role,
+                })
+            })
+            .collect()
+    }
+
+    /// Adds a member or changes the role of an existing member.
+    pub fn set_member(
+        &self,
+        username: &str,
+        role: OrgRole,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let user = self.find_user(username, db)?;
+
+        let current = self.role_of(&user, db);
+        if current == Some(OrgRole::Owner) && role != OrgRole::Owner {
+            self.check_not_last_owner(db)?;
+        }
+
+        let conn = db.conn();
+        if current.is_some() {
+            diesel::update(
+                organization_members::table
+                    .filter(organization_members::organization_id.eq(self.user.id()))
+                    .filter(organization_members::user_id.eq(user.id()))
+            )
+                .set(organization_members::role.eq(role.as_str()))
+                .execute(&*conn)
+                .unwrap();
+        } else {
+            let new = MemberRecord {
+                organization_id: self.user.id(),
+                user_id: user.id(),
+                role: role.as_str().into(),
+            };
+            diesel::insert(&new)
+                .into(organization_members::table)
+                .execute(&*conn)
+                .unwrap();
+        }
+
+        Ok(())
+    }
+
+    /// Removes a member from the organization and all its teams. Members can
+    /// remove themselves to leave the organization.
+    pub fn remove_member(
+        &self,
+        username: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        let user = self.find_user(username, db)?;
+        if user.id() != auth_user.id() {
+            self.check_owner(auth_user, db)?;
+        }
+
+        match self.role_of(&user, db) {
+            None => return Err(OrganizationError::NotAMember { username: username.into() }),
+            Some(OrgRole::Owner) => self.check_not_last_owner(db)?,
+            Some(OrgRole::Member) => {}
+        }
+
+        let conn = db.conn();
+        let org_id = self.user.id();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let team_ids = teams::table
+                .filter(teams::organization_id.eq(org_id))
+                .select(teams::id)
+                .load::<i64>(&*conn)?;
+            if !team_ids.is_empty() {
+                diesel::delete(
+                    team_members::table
+                        .filter(team_members::team_id.eq_any(team_ids))
+                        .filter(team_members::user_id.eq(user.id()))
+                ).execute(&*conn)?;
+            }
+
+            diesel::delete(
+                organization_members::table
+                    .filter(organization_members::organization_id.eq(org_id))
+                    .filter(organization_members::user_id.eq(user.id()))
+            ).execute(&*conn)?;
+            Ok(())
+        }).unwrap();
+
+        Ok(())
+    }
+
+    pub fn set_members_can_create_baskets(
+        &mut self,
+        value: bool,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+
+        diesel::update(organizations::table.find(self.user.id()))
+            .set(organizations::members_can_create_baskets.eq(value))
+            .execute(&*db.conn())
+            .unwrap();
+        self.members_can_create_baskets = value;
+        Ok(())
+    }
+
+    /// Returns all teams with their members, sorted by name.
+    pub fn teams(&self, db: &Db) -> Vec<Team> {
+        let conn = db.conn();
+        let records = teams::table
+            .filter(teams::organization_id.eq(self.user.id()))
+            .order(teams::name)
+            .load::<TeamRecord>(&*conn)
+            .unwrap();
+        if records.is_empty() {
+            return vec![];
+        }
+
+        let ids = records.iter().map(|t| t.id).collect::<Vec<_>>();
+        let members = team_members::table
+            .inner_join(users::table)
+            .filter(team_members::team_id.eq_any(ids.clone()))
+            .order(users::username)
+            .load::<(TeamMember, User)>(&*conn)
+            .unwrap();
+
+        let grants = team_baskets::table
+            .filter(team_baskets::team_id.eq_any(ids))
+            .load::<TeamBasketRecord>(&*conn)
+            .unwrap();
+        let basket_ids = grants.iter().map(|g| g.basket_id).collect::<Vec<_>>();
+        let basket_names = if basket_ids.is_empty() {
+            vec![]
+        } else {
+            baskets::table
+                .filter(baskets::id.eq_any(basket_ids))
+                .filter(baskets::deleted_at.is_null())
+                .order(baskets::name)
+                .select((baskets::id, baskets::name))
+                .load::<(i64, String)>(&*conn)
+                .unwrap()
+        };
+
+        records.into_iter()
+            .map(|record| Team {
+                id: record.id,
+                members: members.iter()
+                    .filter(|&&(ref m, _)| m.team_id == record.id)
+                    .map(|&(_, ref user)| PubUser::from_user(user.clone()))
+                    .collect(),
+                baskets: basket_names.iter()
+                    .filter_map(|&(basket_id, ref name)| {
+                        grants.iter()
+                            .find(|g| g.team_id == record.id && g.basket_id == basket_id)
+                            .and_then(|g| Role::from_str(&g.role))
+                            .map(|role| TeamGrant { name: name.clone(), role })
+                    })
+                    .collect(),
+                name: record.name,
+                description: record.description,
+            })
+            .collect()
+    }
+
+    /// Returns the team with the given name (case insensitive).
+    pub fn team(&self, name: &str, db: &Db) -> Option<Team> {
+        let lower = name.to_lowercase();
+        self.teams(db).into_iter().find(|t| t.name.to_lowercase() == lower)
+    }
+
+    pub fn create_team(
+        &self,
+        name: &str,
+        description: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+        self.check_owner(auth_user, db)?;
+
+        let name = name.trim();
+        if name.is_empty() || !basket::is_valid_name(name) {
+            return Err(OrganizationError::TeamNameInvalid);
+        }
+        let description = description.trim();
+        if description.len() > MAX_ML_LEN {
+            return Err(OrganizationError::DescriptionTooLong);
+        }
+
+        let new = NewTeam {
+            organization_id: self.user.id(),
+            name: name.into(),
+            description: if description.is_empty() { None } else { Some(description.into()) },
+        };
+        let result = diesel::insert(&new)
+            .into(teams::table)
+            .execute(&*db.conn());
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+                Err(OrganizationError::TeamNameAlreadyUsed)
+            }
+            Err(e) => panic!("failed to create team: {}", e),
+        }
+    }
+
+    pub fn delete_team(
+        &self,
+        team: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let team = self.find_team(team, db)?;
+
+        diesel::delete(teams::table.find(team.id))
+            .execute(&*db.conn())
+            .unwrap();
+        Ok(())
+    }
+
+    /// Adds a member of the organization to a team.
+    pub fn add_team_member(
+        &self,
+        team: &str,
+        username: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let team = self.find_team(team, db)?;
+        let user = self.find_user(username, db)?;
+
+        if self.role_of(&user, db).is_none() {
+            return Err(OrganizationError::NotAMember { username: username.into() });
+        }
+        if team.members.iter().any(|m| m.id() == user.id()) {
+            return Ok(());
+        }
+
+        diesel::insert(&TeamMember { team_id: team.id, user_id: user.id() })
+            .into(team_members::table)
+            .execute(&*db.conn())
+            .unwrap();
+        Ok(())
+    }
+
+    pub fn remove_team_member(
+        &self,
+        team: &str,
+        username: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let team = self.find_team(team, db)?;
+        let user = self.find_user(username, db)?;
+
+        diesel::delete(
+            team_members::table
+                .filter(team_members::team_id.eq(team.id))
+                .filter(team_members::user_id.eq(user.id()))
+        )
+            .execute(&*db.conn())
+            .unwrap();
+        Ok(())
+    }
+
+    /// Grants the team the given role in a basket of the organization or
+    /// changes the role it already has.
+    pub fn grant_team(
+        &self,
+        team: &str,
+        basket: &str,
+        role: Role,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let team = self.find_team(team, db)?;
+        let basket_id = self.find_basket(basket, db)?;
+
+        let conn = db.conn();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let existing = team_baskets::table
+                .filter(team_baskets::team_id.eq(team.id))
+                .filter(team_baskets::basket_id.eq(basket_id));
+            let updated = diesel::update(existing)
+                .set(team_baskets::role.eq(role.as_str()))
+                .execute(&*conn)?;
+
+            if updated == 0 {
+                let new = TeamBasketRecord {
+                    team_id: team.id,
+                    basket_id,
+                    role: role.as_str().into(),
+                };
+                diesel::insert(&new)
+                    .into(team_baskets::table)
+                    .execute(&*conn)?;
+            }
+            Ok(())
+        }).unwrap();
+
+        Ok(())
+    }
+
+    /// Removes the team's access to a basket of the organization.
+    pub fn revoke_team(
+        &self,
+        team: &str,
+        basket: &str,
+        auth_user: &AuthUser,
+        db: &Db,
+    ) -> Result<(), OrganizationError> {
+        self.check_owner(auth_user, db)?;
+        let team = self.find_team(team, db)?;
+        let basket_id = self.find_basket(basket, db)?;
+
+        diesel::delete(
+            team_baskets::table
+                .filter(team_baskets::team_id.eq(team.id))
+                .filter(team_baskets::basket_id.eq(basket_id))
+        )
+            .execute(&*db.conn())
+            .unwrap();
+        Ok(())
+    }
+
+    fn check_owner(&self, auth_user: &AuthUser, db: &Db) -> Result<(), OrganizationError> {
+        if self.role_of(auth_user, db) == Some(OrgRole::Owner) {
+            Ok(())
+        } else {
+            Err(OrganizationError::NoPermission)
+        }
+    }
+
+    fn check_not_last_owner(&self, db: &Db) -> Result<(), OrganizationError> {
+        let owners = self.members(db)
+            .into_iter()
+            .filter(|m| m.role == OrgRole::Owner)
+            .count();
+        if owners > 1 {
+            Ok(())
+        } else {
+            Err(OrganizationError::LastOwner)
+        }
+    }
+
+    fn find_user(&self, username: &str, db: &Db) -> Result<PubUser, OrganizationError> {
+        let username = username.trim();
+        PubUser::from_username(username, db)
+            .and_then(|u| if is_organization(&u, db) { None } else { Some(u) })
+            .ok_or_else(|| OrganizationError::UserNotFound { username: username.into() })
+    }
+
+    fn find_team(&self, name: &str, db: &Db) -> Result<Team, OrganizationError> {
+        self.team(name, db)
+            .ok_or_else(|| OrganizationError::TeamNotFound { name: name.into() })
+    }
+
+    /// Returns the id of the organization's basket with the given name.
+    fn find_basket(&self, name: &str, db: &Db) -> Result<i64, OrganizationError> {
+        let name = name.trim();
+        baskets::table
+            .filter(baskets::user_id.eq(self.user.id()))
+            .filter(baskets::name.eq(name))
+            .filter(baskets::deleted_at.is_null())
+            .select(baskets::id)
+            .first::<i64>(&*db.conn())
+            .optional()
+            .unwrap()
+            .ok_or_else(|| OrganizationError::BasketNotFound { name: name.into() })
+    }
+}
+
+impl Deref for Organization {
+    type Target = PubUser;
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl Serialize for Organization {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        let mut s = serializer.serialize_struct("Organization", 4)?;
+        // Skipping id: the id should never be sent to the user
+        s.serialize_field("username", self.username())?;
+        s.serialize_field("name", &self.name())?;
+        s.serialize_field("bio", &self.bio())?;
+        s.serialize_field("members_can_create_baskets", &self.members_can_create_baskets)?;
+        s.end()
+    }
+}
+
+/// Returns whether the given user row belongs to an organization.
+pub fn is_organization(user: &PubUser, db: &Db) -> bool {
+    organizations::table
+        .find(user.id())
+        .select(organizations::user_id)
+        .first::<i64>(&*db.conn())
+        .optional()
+        .unwrap()
+        .is_some()
+}
+
+/// Returns the role of the user in the organization with the given id.
+/// Returns `None` if the user is not a member or `organization_id` doesn't
+/// belong to an organization.
+pub fn member_role(organization_id: i64, user_id: i64, db: &Db) -> Option<OrgRole> {
+    organization_members::table
+        .filter(organization_members::organization_id.eq(organization_id))
+        .filter(organization_members::user_id.eq(user_id))
+        .select(organization_members::role)
+        .first::<String>(&*db.conn())
+        .optional()
+        .unwrap()
+        .and_then(|role| OrgRole::from_str(&role))
+}
+
+/// Returns the highest role the user was granted in the basket through the
+/// teams they are in.
+pub fn team_role(basket: &BasketRecord, user: &PubUser, db: &Db) -> Option<Role> {
+    let conn = db.conn();
+    let team_ids = team_members::table
+        .filter(team_members::user_id.eq(user.id()))
+        .select(team_members::team_id)
+        .load::<i64>(&*conn)
+        .unwrap();
+    if team_ids.is_empty() {
+        return None;
+    }
+
+    team_baskets::table
+        .filter(team_baskets::basket_id.eq(basket.id()))
+        .filter(team_baskets::team_id.eq_any(team_ids))
+        .select(team_baskets::role)
+        .load::<String>(&*conn)
+        .unwrap()
+        .iter()
+        .filter_map(|role| Role::from_str(role))
+        .fold(None, |max, role| cmp::max(max, Some(role)))
+}
+
+/// Returns the ids of all baskets the user was granted access to through a
+/// team.
+pub fn team_basket_ids_of(user: &PubUser, db: &Db) -> Vec<i64> {
+    let conn = db.conn();
+    let team_ids = team_members::table
+        .filter(team_members::user_id.eq(user.id()))
+        .select(team_members::team_id)
+        .load::<i64>(&*conn)
+        .unwrap();
+    if team_ids.is_empty() {
+        return vec![];
+    }
+
+    team_baskets::table
+        .filter(team_baskets::team_id.eq_any(team_ids))
+        .select(team_baskets::basket_id)
+        .load(&*conn)
+        .unwrap()
+}
+
+/// Returns the teams which were granted access to the basket, sorted by
+/// name.
+pub fn team_grants(basket: &BasketRecord, db: &Db) -> Vec<TeamGrant> {
+    let conn = db.conn();
+    let grants = team_baskets::table
+        .filter(team_baskets::basket_id.eq(basket.id()))
+        .load::<TeamBasketRecord>(&*conn)
+        .unwrap();
+    if grants.is_empty() {
+        return vec![];
+    }
+
+    let team_ids = grants.iter().map(|g| g.team_id).collect::<Vec<_>>();
+    teams::table
+        .filter(teams::id.eq_any(team_ids))
+        .order(teams::name)
+        .select((teams::id, teams::name))
+        .load::<(i64, String)>(&*conn)
+        .unwrap()
+        .into_iter()
+        .filter_map(|(id, name)| {
+            grants.iter()
+                .find(|g| g.team_id == id)
+                .and_then(|g| Role::from_str(&g.role))
+                .map(|role| TeamGrant { name, role })
+        })
+        .collect()
+}
+
+/// Returns the ids of all organizations the user is a member of.
+pub fn organization_ids_of(user: &PubUser, db: &Db) -> Vec<i64> {
+    organization_members::table
+        .filter(organization_members::user_id.eq(user.id()))
+        .select(organization_members::organization_id)
+        .load(&*db.conn())
+        .unwrap()
+}
+
+/// Returns the ids of all organizations the user is an owner of.
+pub fn owned_organization_ids(user: &PubUser, db: &Db) -> Vec<i64> {
+    organization_members::table
+        .filter(organization_members::user_id.eq(user.id()))
+        .filter(organization_members::role.eq(OrgRole::Owner.as_str()))
+        .select(organization_members::organization_id)
+        .load(&*db.conn())
+        .unwrap()
+}
+
+/// Returns all organizations the user is a member of, sorted by name.
+pub fn memberships(user: &PubUser, db: &Db) -> Vec<Membership> {
+    let conn = db.conn();
+    let records = organization_members::table
+        .filter(organization_members::user_id.eq(user.id()))
+        .load::<MemberRecord>(&*conn)
+        .unwrap();
+    if records.is_empty() {
+        return vec![];
+    }
+
+    let ids = records.iter().map(|r| r.organization_id).collect::<Vec<_>>();
+    users::table
+        .filter(users::id.eq_any(ids))
+        .order(users::username)
+        .load::<User>(&*conn)
+        .unwrap()
+        .into_iter()
+        .map(PubUser::from_user)
+        .filter_map(|org| {
+            records.iter()
+                .find(|r| r.organization_id == org.id())
+                .and_then(|r| OrgRole::from_str(&r.role))
+                .map(|role| Membership { organization: org, role })
+        })
+        .collect()
+}
+
+
+pub enum OrganizationError {
+    NoPermission,
+    NameEmpty,
+    NameInvalid,
+    NameAlreadyUsed,
+    UserNotFound {
+        username: String,
+    },
+    NotAMember {
+        username: String,
+    },
+    RoleInvalid,
+    /// The role of a team in a basket is invalid.
+    BasketRoleInvalid,
+    /// The last owner can't leave or be demoted.
+    LastOwner,
+    TeamNameInvalid,
+    TeamNameAlreadyUsed,
+    TeamNotFound {
+        name: String,
+    },
+    DescriptionTooLong,
+    /// The organization doesn't own a basket with this name.
+    BasketNotFound {
+        name: String,
+    },
+}
+
+impl fmt::Display for OrganizationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OrganizationError::*;
+
+        match *self {
+            NoPermission => {
+                "Only owners of the organization can do that!".fmt(f)
+            }
+            NameEmpty => {
+                "The organization's name can't be empty!".fmt(f)
+            }
+            NameInvalid => {
+                "The organization's name contains invalid characters! Only \
+                alphanumerical ASCII characters and dashes are allowed."
+                    .fmt(f)
+            }
+            NameAlreadyUsed => {
+                "This name is already used by a user or an organization.".fmt(f)
+            }
+            UserNotFound { ref username } => {
+                write!(f, "There is no user named '{}'!", username)
+            }
+            NotAMember { ref username } => {
+                write!(f, "'{}' is not a member of this organization!", username)
+            }
+            RoleInvalid => {
+                "The role has to be either 'owner' or 'member'.".fmt(f)
+            }
+            BasketRoleInvalid => {
+                "The role has to be one of 'read', 'write' or 'admin'.".fmt(f)
+            }
+            LastOwner => {
+                "An organization needs at least one owner.".fmt(f)
+            }
+            TeamNameInvalid => {
+                "The team's name must not be empty and may only contain \
+                alphanumerical ASCII characters and dashes."
+                    .fmt(f)
+            }
+            TeamNameAlreadyUsed => {
+                "A team with this name already exists.".fmt(f)
+            }
+            TeamNotFound { ref name } => {
+                write!(f, "There is no team named '{}'!", name)
+            }
+            DescriptionTooLong => {
+                "The description is too long!".fmt(f)
+            }
+            BasketNotFound { ref name } => {
+                write!(f, "This organization has no basket named '{}'!", name)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+
+    use db::Db;
+    use db::schema::{baskets, users};
+    use model::{feed, AuthUser, BasketRecord, PubUser, User};
+    use model::collaborator::Role;
+    use super::{team_role, OrgRole, Organization, OrganizationError};
+
+    const OWNER: i64 = 900_001;
+    const MEMBER: i64 = 900_002;
+    const STRANGER: i64 = 900_003;
+
+    /// Passes if `$result` is the given error.
+    macro_rules! assert_err {
+        ($result:expr, $pattern:pat) => {
+            match $result {
+                Err($pattern) => {}
+                _ => panic!("expected {}", stringify!($pattern)),
+            }
+        }
+    }
+
+    /// Creates an organization with an owner, a member in two teams and a
+    /// basket both teams were granted roles in.
+    fn setup() -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute("
+            insert into users (id, username) values
+                (900001, 'test-owner'),
+                (900002, 'test-member'),
+                (900003, 'test-stranger'),
+                (900004, 'test-org');
+
+            insert into organizations (user_id) values (900004);
+            insert into organization_members (organization_id, user_id, role) values
+                (900004, 900001, 'owner'),
+                (900004, 900002, 'member');
+
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'notes', 900004, 'generic', 'private'),
+                (900102, 'other', 900004, 'generic', 'private');
+
+            insert into teams (id, organization_id, name) values
+                (900201, 900004, 'Editors'),
+                (900202, 900004, 'readers');
+            insert into team_members (team_id, user_id) values
+                (900201, 900002),
+                (900202, 900002);
+            insert into team_baskets (team_id, basket_id, role) values
+                (900201, 900101, 'write'),
+                (900202, 900101, 'read');
+        ").unwrap();
+        db
+    }
+
+    fn user(id: i64, db: &Db) -> PubUser {
+        let user = users::table.find(id).first::<User>(&*db.conn()).unwrap();
+        PubUser::from_user(user)
+    }
+
+    fn auth_user(id: i64, db: &Db) -> AuthUser {
+        let token = feed::reset_token(&user(id, db), db);
+        AuthUser::from_feed_token(&token, db).unwrap()
+    }
+
+    fn org(db: &Db) -> Organization {
+        Organization::from_username("test-org", db).unwrap()
+    }
+
+    fn basket(id: i64, db: &Db) -> BasketRecord {
+        baskets::table.find(id).first::<BasketRecord>(&*db.conn()).unwrap()
+    }
+
+    #[test]
+    fn only_owners_manage_the_organization() {
+        let db = setup();
+        let mut org = org(&db);
+
+        for &id in &[MEMBER, STRANGER] {
+            let auth_user = auth_user(id, &db);
+            assert_err!(
+                org.set_member("test-stranger", OrgRole::Member, &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.remove_member("test-owner", &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.set_members_can_create_baskets(false, &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.create_team("writers", "", &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.delete_team("readers", &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.add_team_member("readers", "test-owner", &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.grant_team("readers", "other", Role::Admin, &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+            assert_err!(
+                org.revoke_team("readers", "notes", &auth_user, &db),
+                OrganizationError::NoPermission
+            );
+        }
+        assert!(org.members_can_create_baskets());
+        assert_eq!(org.teams(&db).len(), 2);
+
+        let owner = auth_user(OWNER, &db);
+        assert!(org.set_member("test-stranger", OrgRole::Member, &owner, &db).is_ok());
+        assert_eq!(org.role_of(&user(STRANGER, &db), &db), Some(OrgRole::Member));
+    }
+
+    #[test]
+    fn members_can_leave() {
+        let db = setup();
+        let org = org(&db);
+
+        let member = auth_user(MEMBER, &db);
+        assert!(org.remove_member("test-member", &member, &db).is_ok());
+        assert_eq!(org.role_of(&user(MEMBER, &db), &db), None);
+        // Leaving also removes the user from all teams.
+        assert!(org.teams(&db).iter().all(|t| t.members.is_empty()));
+    }
+
+    #[test]
+    fn last_owner_is_kept() {
+        let db = setup();
+        let org = org(&db);
+        let owner = auth_user(OWNER, &db);
+
+        assert_err!(
+            org.set_member("test-owner", OrgRole::Member, &owner, &db),
+            OrganizationError::LastOwner
+        );
+        assert_err!(org.remove_member("test-owner", &owner, &db), OrganizationError::LastOwner);
+        assert_eq!(org.role_of(&owner, &db), Some(OrgRole::Owner));
+
+        // With a second owner, the first one can leave.
+        assert!(org.set_member("test-member", OrgRole::Owner, &owner, &db).is_ok());
+        assert!(org.remove_member("test-owner", &owner, &db).is_ok());
+        assert_eq!(org.role_of(&owner, &db), None);
+
+        let member = auth_user(MEMBER, &db);
+        assert_err!(
+            org.set_member("test-member", OrgRole::Member, &member, &db),
+            OrganizationError::LastOwner
+        );
+    }
+
+    #[test]
+    fn team_names_are_case_insensitive() {
+        let db = setup();
+        let org = org(&db);
+        let owner = auth_user(OWNER, &db);
+
+        assert_eq!(org.team("EDITORS", &db).map(|t| t.name), Some("Editors".to_string()));
+        assert_eq!(org.team("Readers", &db).map(|t| t.name), Some("readers".to_string()));
+        assert!(org.team("writers", &db).is_none());
+
+        assert!(org.grant_team("READERS", "other", Role::Read, &owner, &db).is_ok());
+        let member = user(MEMBER, &db);
+        assert_eq!(team_role(&basket(900102, &db), &member, &db), Some(Role::Read));
+
+        // Enforced by `teams_unique_lower_name_per_org_idx`. This aborts the
+        // test transaction, so it has to come last.
+        assert!(org.create_team("writers", "", &owner, &db).is_ok());
+        assert_err!(
+            org.create_team("editors", "", &owner, &db),
+            OrganizationError::TeamNameAlreadyUsed
+        );
+    }
+
+    #[test]
+    fn team_role_is_the_highest() {
+        let db = setup();
+        let org = org(&db);
+        let owner = auth_user(OWNER, &db);
+        let notes = basket(900101, &db);
+        let member = user(MEMBER, &db);
+
+        assert_eq!(team_role(&notes, &member, &db), Some(Role::Write));
+        assert_eq!(team_role(&notes, &user(STRANGER, &db), &db), None);
+        // Owners see the basket through the organization, not a team.
+        assert_eq!(team_role(&notes, &user(OWNER, &db), &db), None);
+
+        // Granting a team a role it already has a grant for changes it.
+        assert!(org.grant_team("readers", "notes", Role::Admin, &owner, &db).is_ok());
+        assert_eq!(team_role(&notes, &member, &db), Some(Role::Admin));
+
+        assert!(org.revoke_team("readers", "notes", &owner, &db).is_ok());
+        assert!(org.revoke_team("Editors", "notes", &owner, &db).is_ok());
+        assert_eq!(team_role(&notes, &member, &db), None);
+    }
+}
@@ -1,4 +1,15 @@
-use model::{AuthUser, BasketRecord, PubUser};
+use diesel::pg::Pg;
+use diesel::prelude::*;
+use diesel::query_builder::BoxedSelectStatement;
+use diesel::types::BigInt;
+use std::cmp;
+
+use db::Db;
+use db::schema::{baskets, basket_collaborators, organization_members, team_baskets, team_members};
+use model::{AuthUser, BasketRecord, BasketVisibility, PubUser};
+use model::collaborator::{self, Role};
+use model::organization::{self, Organization, OrgRole};
+use model::issue::IssueRecord;
 
 
 pub enum UserAction<'a> {
@@ -10,19 +21,502 @@ pub enum UserAction<'a> {
         owner: &'a PubUser,
         basket: &'a BasketRecord,
     },
+    /// Changing the content of a basket (creating commits).
+    EditBasket {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Changing the settings of a basket and managing its collaborators.
+    ManageBasket {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    DeleteBasket {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Archiving and unarchiving a basket. This is the only change allowed
+    /// while a basket is archived.
+    ArchiveBasket {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Nominating a new owner for a basket. Only the owner itself can do
+    /// that, or the owners of the organization owning the basket.
+    TransferBasket {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Accepting a basket transferred to the given user or organization.
+    AcceptTransfer {
+        to: &'a PubUser,
+    },
+    /// Opening a new issue in a basket.
+    CreateIssue {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Writing a comment on an issue.
+    CommentOnIssue {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Changing title and body of an issue as well as closing and reopening
+    /// it.
+    EditIssue {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+        issue: &'a IssueRecord,
+    },
+    /// Changing the labels and assignees of issues.
+    TriageIssues {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+    },
+    /// Assigning the given user to an issue of the basket.
+    AssignIssue {
+        owner: &'a PubUser,
+        basket: &'a BasketRecord,
+        assignee: &'a PubUser,
+    },
 }
 
-pub fn has_permission(user: Option<&AuthUser>, action: UserAction) -> bool {
+/// Returns whether the user may perform the given action. All actions
+/// changing a basket are refused while it is archived, except for
+/// `ArchiveBasket`.
+pub fn has_permission(user: Option<&AuthUser>, action: UserAction, db: &Db) -> bool {
     use self::UserAction::*;
 
+    let user = user.map(|u| &**u);
+
     match action {
         CreateBasket { owner } => {
-            // TODO: this will change in the far future
-            user.map(|u| owner == u.username()).unwrap_or(false)
+            match user {
+                None => false,
+                Some(u) if owner == u.username() => true,
+                Some(u) => {
+                    Organization::from_username(owner, db)
+                        .map(|org| org.can_create_baskets(u, db))
+                        .unwrap_or(false)
+                }
+            }
+        }
+        ViewBasket { basket, .. } => {
+            can_view(user, basket, db)
+        }
+        EditBasket { owner, basket } => {
+            !basket.is_archived() && has_role(user, owner, basket, Role::Write, db)
+        }
+        ManageBasket { owner, basket } | DeleteBasket { owner, basket } => {
+            !basket.is_archived() && has_role(user, owner, basket, Role::Admin, db)
+        }
+        ArchiveBasket { owner, basket } => {
+            has_role(user, owner, basket, Role::Admin, db)
+        }
+        TransferBasket { owner, basket } => {
+            !basket.is_archived() && user.map(|u| is_owner(u, owner, db)).unwrap_or(false)
+        }
+        AcceptTransfer { to } => {
+            user.map(|u| is_owner(u, to, db)).unwrap_or(false)
+        }
+        CreateIssue { basket, .. } | CommentOnIssue { basket, .. } => {
+            !basket.is_archived() && user.is_some() && can_view(user, basket, db)
+        }
+        EditIssue { owner, basket, issue } => {
+            let is_author = match (user, issue.author_id()) {
+                (Some(u), Some(author_id)) => u.id() == author_id,
+                _ => false,
+            };
+            !basket.is_archived() && (
+                (is_author && can_view(user, basket, db))
+                    || has_role(user, owner, basket, Role::Write, db)
+            )
+        }
+        TriageIssues { owner, basket } => {
+            !basket.is_archived() && has_role(user, owner, basket, Role::Write, db)
+        }
+        AssignIssue { owner, basket, assignee } => {
+            // Only users which can work on the basket can be assigned.
+            !basket.is_archived()
+                && has_role(user, owner, basket, Role::Write, db)
+                && has_role(Some(assignee), owner, basket, Role::Write, db)
+        }
+    }
+}
+
+/// Returns the role the given user has in the basket. The owner implicitly
+/// has the admin role. Otherwise the effective role is the highest of the
+/// role as collaborator, the roles granted to the user's teams and the role
+/// in the organization owning the basket: owners of the organization are
+/// admins and its members can read the basket.
+pub fn role_in(
+    user: Option<&PubUser>,
+    owner: &PubUser,
+    basket: &BasketRecord,
+    db: &Db,
+) -> Option<Role> {
+    match user {
+        None => None,
+        Some(u) if u.id() == owner.id() => Some(Role::Admin),
+        Some(u) => {
+            let org_role = organization::member_role(owner.id(), u.id(), db)
+                .map(|role| match role {
+                    OrgRole::Owner => Role::Admin,
+                    OrgRole::Member => Role::Read,
+                });
+            let team_role = organization::team_role(basket, u, db);
+            cmp::max(cmp::max(collaborator::role_of(basket, u, db), team_role), org_role)
+        }
+    }
+}
+
+/// A boxed query over `baskets`, as returned by `Visibility::visible_baskets`.
+pub type BasketQuery<'a> = BoxedSelectStatement<'a, baskets::SqlType, baskets::table, Pg>;
+
+/// A boxed query for basket ids, as returned by
+/// `Visibility::visible_basket_ids`.
+pub type BasketIdQuery<'a> = BoxedSelectStatement<'a, BigInt, baskets::table, Pg>;
+
+/// Filters a query over `baskets` by the sets of a `Visibility`.
+macro_rules! visible {
+    ($visibility:expr, $query:expr) => {
+        // `eq_any` with an empty list matches nothing, which is exactly what
+        // we need for anonymous users.
+        $query
+            .filter(
+                baskets::visibility.eq_any($visibility.levels.clone())
+                    .or(baskets::user_id.eq_any($visibility.owner_ids.clone()))
+                    .or(baskets::id.eq_any($visibility.basket_ids.clone()))
+            )
+            .filter(baskets::deleted_at.is_null())
+    }
+}
+
+/// The baskets a user can see.
+///
+/// This is the `ViewBasket` rule in a form which can be evaluated in Rust for
+/// a single basket (`allows`) as well as by the database (`visible_baskets`),
+/// so that listings only fetch the baskets the user can see. Both are
+/// computed from the same sets of ids and thus always agree. A user can see
+/// a basket if its visibility level allows it (public baskets for everyone,
+/// internal ones for logged in users) or the user has any role in it (see
+/// `role_in`).
+pub struct Visibility {
+    /// The visibility levels which are visible regardless of roles.
+    levels: Vec<&'static str>,
+    /// Owners whose baskets are all visible: the user and the organizations
+    /// the user is a member of.
+    owner_ids: Vec<i64>,
+    /// Baskets visible through a grant as collaborator or to a team.
+    basket_ids: Vec<i64>,
+}
+
+impl Visibility {
+    pub fn of(user: Option<&PubUser>, db: &Db) -> Self {
+        match user {
+            None => Visibility {
+                levels: vec![BasketVisibility::Public.as_str()],
+                owner_ids: vec![],
+                basket_ids: vec![],
+            },
+            Some(u) => {
+                let mut owner_ids = organization::organization_ids_of(u, db);
+                owner_ids.push(u.id());
+
+                let mut basket_ids = collaborator::basket_ids_of(u, db);
+                basket_ids.extend(organization::team_basket_ids_of(u, db));
+
+                Visibility {
+                    levels: vec![
+                        BasketVisibility::Public.as_str(),
+                        BasketVisibility::Internal.as_str(),
+                    ],
+                    owner_ids,
+                    basket_ids,
+                }
+            }
         }
-        ViewBasket { owner, basket } => {
-            basket.is_public() ||
-                user.map(|u| owner.username() == u.username()).unwrap_or(false)
+    }
+
+    /// Whether the basket is visible. For baskets which aren't deleted, this
+    /// is equivalent to `visible_baskets` containing the basket.
+    pub fn allows(&self, basket: &BasketRecord) -> bool {
+        self.levels.contains(&basket.visibility().as_str())
+            || self.owner_ids.contains(&basket.user_id())
+            || self.basket_ids.contains(&basket.id())
+    }
+
+    /// Returns a query for all visible baskets, which can be refined with
+    /// further filters, ordered, counted and so on. Deleted baskets are
+    /// excluded.
+    pub fn visible_baskets<'a>(&self) -> BasketQuery<'a> {
+        visible!(self, baskets::table).into_boxed()
+    }
+
+    /// Returns a query for the ids of all visible baskets, to restrict other
+    /// tables to them with `basket_id.eq_any(...)`.
+    pub fn visible_basket_ids<'a>(&self) -> BasketIdQuery<'a> {
+        visible!(self, baskets::table.select(baskets::id)).into_boxed()
+    }
+}
+
+/// Whether the user is the given owner or an owner of the organization.
+fn is_owner(user: &PubUser, owner: &PubUser, db: &Db) -> bool {
+    user.id() == owner.id()
+        || organization::member_role(owner.id(), user.id(), db) == Some(OrgRole::Owner)
+}
+
+/// Whether the given user can see the basket and its content. This is
+/// `Visibility::allows`, but only looks at this one basket.
+fn can_view(user: Option<&PubUser>, basket: &BasketRecord, db: &Db) -> bool {
+    match (basket.visibility(), user) {
+        (BasketVisibility::Public, _) => true,
+        (BasketVisibility::Internal, Some(_)) => true,
+        (_, None) => false,
+        (_, Some(u)) => {
+            u.id() == basket.user_id()
+                || organization::member_role(basket.user_id(), u.id(), db).is_some()
+                || collaborator::role_of(basket, u, db).is_some()
+                || organization::team_role(basket, u, db).is_some()
+        }
+    }
+}
+
+/// Returns those of the given users who can see the basket. This is
+/// `can_view` for many users at once, with a fixed number of queries.
+pub fn viewers_among(basket: &BasketRecord, user_ids: &[i64], db: &Db) -> QueryResult<Vec<i64>> {
+    if basket.visibility() != BasketVisibility::Private {
+        return Ok(user_ids.to_vec());
+    }
+
+    let conn = db.conn();
+    let mut viewers = organization_members::table
+        .filter(organization_members::organization_id.eq(basket.user_id()))
+        .filter(organization_members::user_id.eq_any(user_ids.to_vec()))
+        .select(organization_members::user_id)
+        .load::<i64>(&*conn)?;
+    viewers.extend(basket_collaborators::table
+        .filter(basket_collaborators::basket_id.eq(basket.id()))
+        .filter(basket_collaborators::user_id.eq_any(user_ids.to_vec()))
+        .filter(basket_collaborators::accepted.eq(true))
+        .select(basket_collaborators::user_id)
+        .load::<i64>(&*conn)?);
+    let team_ids = team_baskets::table
+        .filter(team_baskets::basket_id.eq(basket.id()))
+        .select(team_baskets::team_id);
+    viewers.extend(team_members::table
+        .filter(team_members::team_id.eq_any(team_ids))
+        .filter(team_members::user_id.eq_any(user_ids.to_vec()))
+        .select(team_members::user_id)
+        .load::<i64>(&*conn)?);
+
+    Ok(user_ids.iter()
+        .cloned()
+        .filter(|&id| id == basket.user_id() || viewers.contains(&id))
+        .collect())
+}
+
+/// Whether the given user has at least the given role in the basket.
+fn has_role(
+    user: Option<&PubUser>,
+    owner: &PubUser,
+    basket: &BasketRecord,
+    min: Role,
+    db: &Db,
+) -> bool {
+    role_in(user, owner, basket, db).map(|role| role >= min).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use diesel;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+    use chrono::offset::utc::UTC;
+
+    use db::Db;
+    use db::schema::{baskets, users};
+    use model::{BasketRecord, PubUser, User};
+    use super::{can_view, viewers_among, Visibility};
+
+    const OWNER: i64 = 900_001;
+    const COLLABORATOR: i64 = 900_002;
+    const TEAM_MEMBER: i64 = 900_003;
+    const ORG_MEMBER: i64 = 900_004;
+    const STRANGER: i64 = 900_005;
+    const ORG: i64 = 900_006;
+    const INVITED: i64 = 900_007;
+
+    /// Creates users and baskets covering all the ways to see a basket.
+    fn setup() -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute("
+            insert into users (id, username) values
+                (900001, 'test-owner'),
+                (900002, 'test-collaborator'),
+                (900003, 'test-team-member'),
+                (900004, 'test-org-member'),
+                (900005, 'test-stranger'),
+                (900006, 'test-org'),
+                (900007, 'test-invited');
+
+            insert into organizations (user_id) values (900006);
+            insert into organization_members (organization_id, user_id, role) values
+                (900006, 900001, 'owner'),
+                (900006, 900004, 'member');
+
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'public', 900001, 'generic', 'public'),
+                (900102, 'internal', 900001, 'generic', 'internal'),
+                (900103, 'private', 900001, 'generic', 'private'),
+                (900104, 'shared', 900001, 'generic', 'private'),
+                (900105, 'org-private', 900006, 'generic', 'private'),
+                (900106, 'org-team', 900006, 'generic', 'private'),
+                (900107, 'org-public', 900006, 'generic', 'public');
+
+            insert into basket_collaborators (basket_id, user_id, role, accepted) values
+                (900104, 900002, 'read', true),
+                (900103, 900007, 'write', false);
+
+            insert into teams (id, organization_id, name) values (900201, 900006, 'test-team');
+            insert into team_members (team_id, user_id) values (900201, 900003);
+            insert into team_baskets (team_id, basket_id, role) values (900201, 900106, 'read');
+        ").unwrap();
+        db
+    }
+
+    fn user(id: i64, db: &Db) -> PubUser {
+        let user = users::table.find(id).first::<User>(&*db.conn()).unwrap();
+        PubUser::from_user(user)
+    }
+
+    /// Returns the ids of the test baskets which are visible, according to
+    /// `allows`, `visible_baskets` and `can_view`.
+    fn visible_ids(user: Option<i64>, db: &Db) -> (Vec<i64>, Vec<i64>, Vec<i64>) {
+        let user = user.map(|id| self::user(id, db));
+        let visibility = Visibility::of(user.as_ref(), db);
+
+        let test_baskets = baskets::table
+            .filter(baskets::id.ge(900_000))
+            .filter(baskets::id.lt(901_000))
+            .filter(baskets::deleted_at.is_null())
+            .order(baskets::id)
+            .load::<BasketRecord>(&*db.conn())
+            .unwrap();
+        let allowed = test_baskets.iter()
+            .filter(|b| visibility.allows(b))
+            .map(|b| b.id())
+            .collect();
+        let viewable = test_baskets.iter()
+            .filter(|b| can_view(user.as_ref(), b, db))
+            .map(|b| b.id())
+            .collect();
+
+        let listed = visibility.visible_baskets()
+            .filter(baskets::id.ge(900_000))
+            .filter(baskets::id.lt(901_000))
+            .order(baskets::id)
+            .select(baskets::id)
+            .load::<i64>(&*db.conn())
+            .unwrap();
+
+        (allowed, listed, viewable)
+    }
+
+    fn assert_visible(user: Option<i64>, expected: &[i64], db: &Db) {
+        let (allowed, listed, viewable) = visible_ids(user, db);
+        assert_eq!(allowed, listed, "allows and visible_baskets disagree for {:?}", user);
+        assert_eq!(allowed, viewable, "allows and can_view disagree for {:?}", user);
+        assert_eq!(allowed, expected, "wrong baskets visible for {:?}", user);
+    }
+
+    #[test]
+    fn anonymous() {
+        let db = setup();
+        assert_visible(None, &[900101, 900107], &db);
+    }
+
+    #[test]
+    fn stranger() {
+        let db = setup();
+        assert_visible(Some(STRANGER), &[900101, 900102, 900107], &db);
+    }
+
+    #[test]
+    fn owner() {
+        // Also an owner of the organization.
+        let db = setup();
+        assert_visible(
+            Some(OWNER),
+            &[900101, 900102, 900103, 900104, 900105, 900106, 900107],
+            &db,
+        );
+    }
+
+    #[test]
+    fn collaborator() {
+        let db = setup();
+        assert_visible(Some(COLLABORATOR), &[900101, 900102, 900104, 900107], &db);
+    }
+
+    #[test]
+    fn pending_invitation() {
+        let db = setup();
+        assert_visible(Some(INVITED), &[900101, 900102, 900107], &db);
+    }
+
+    #[test]
+    fn team_grant() {
+        let db = setup();
+        assert_visible(Some(TEAM_MEMBER), &[900101, 900102, 900106, 900107], &db);
+    }
+
+    #[test]
+    fn organization_member() {
+        let db = setup();
+        assert_visible(Some(ORG_MEMBER), &[900101, 900102, 900105, 900106, 900107], &db);
+    }
+
+    #[test]
+    fn organization() {
+        let db = setup();
+        assert_visible(Some(ORG), &[900101, 900102, 900105, 900106, 900107], &db);
+    }
+
+    #[test]
+    fn deleted_baskets_are_not_listed() {
+        let db = setup();
+        diesel::update(baskets::table.find(900103))
+            .set(baskets::deleted_at.eq(Some(UTC::now())))
+            .execute(&*db.conn())
+            .unwrap();
+
+        let listed = Visibility::of(Some(&user(OWNER, &db)), &db)
+            .visible_baskets()
+            .filter(baskets::id.eq(900103))
+            .count()
+            .get_result::<i64>(&*db.conn())
+            .unwrap();
+        assert_eq!(listed, 0);
+    }
+
+    #[test]
+    fn viewers_among_agrees_with_can_view() {
+        let db = setup();
+        let user_ids = [OWNER, COLLABORATOR, TEAM_MEMBER, ORG_MEMBER, STRANGER, ORG, INVITED];
+        let test_baskets = baskets::table
+            .filter(baskets::id.ge(900_000))
+            .filter(baskets::id.lt(901_000))
+            .load::<BasketRecord>(&*db.conn())
+            .unwrap();
+
+        for basket in &test_baskets {
+            let expected = user_ids.iter()
+                .cloned()
+                .filter(|&id| can_view(Some(&user(id, &db)), basket, &db))
+                .collect::<Vec<_>>();
+            let viewers = viewers_among(basket, &user_ids, &db).unwrap();
+            assert_eq!(viewers, expected, "wrong viewers of {}", basket.id());
         }
     }
 }
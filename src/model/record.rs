@@ -0,0 +1,630 @@
+//! Structured records of "recipes" baskets.
+//!
+//! The basket contains a schema in `schema.toml` which describes the fields of
+//! every record. Records are stored as TOML files in the directory named by
+//! the schema and are validated against the schema whenever they are saved.
+
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use toml;
+
+use model::content::{Change, Content};
+use super::MAX_SL_LEN;
+
+
+/// Path of the schema file in the basket.
+pub const SCHEMA_PATH: &str = "schema.toml";
+
+/// File extension of records.
+const RECORD_EXT: &str = ".toml";
+
+
+/// The type of a field.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum FieldType {
+    /// A single line of text.
+    #[serde(rename = "string")]
+    String,
+    /// Multiple lines of text.
+    #[serde(rename = "text")]
+    Text,
+    #[serde(rename = "integer")]
+    Integer,
+    #[serde(rename = "number")]
+    Number,
+    #[serde(rename = "boolean")]
+    Boolean,
+    /// A list of single line strings, entered one per line.
+    #[serde(rename = "list")]
+    List,
+    /// One of the values given in `options`.
+    #[serde(rename = "choice")]
+    Choice,
+}
+
+/// A field of the schema.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    pub label: Option<String>,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+    #[serde(default)]
+    pub required: bool,
+    /// Minimum for numbers, minimum length for strings and lists.
+    pub min: Option<f64>,
+    /// Maximum for numbers, maximum length for strings and lists.
+    pub max: Option<f64>,
+    /// Allowed values of choice fields.
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+impl Field {
+    pub fn label(&self) -> &str {
+        self.label.as_ref().unwrap_or(&self.name)
+    }
+
+    /// Parses and validates the raw value entered into the form.
+    fn parse(&self, raw: Option<&str>) -> Result<Option<toml::Value>, FieldError> {
+        let raw = raw.map(str::trim).unwrap_or("");
+        let err = |msg: &str| FieldError { field: self.name.clone(), msg: msg.into() };
+
+        // Booleans are checkboxes, which are not sent at all when unchecked.
+        if self.ty == FieldType::Boolean {
+            return Ok(Some(toml::Value::Boolean(raw == "true" || raw == "on")));
+        }
+
+        if raw.is_empty() {
+            return if self.required {
+                Err(err("is required"))
+            } else {
+                Ok(None)
+            };
+        }
+
+        let value = match self.ty {
+            FieldType::String | FieldType::Text => {
+                if self.ty == FieldType::String && raw.contains('\n') {
+                    return Err(err("must be a single line"));
+                }
+                self.check_len(raw.chars().count()).map_err(|m| err(&m))?;
+                toml::Value::String(raw.into())
+            }
+            FieldType::Integer => {
+                let n = raw.parse::<i64>().map_err(|_| err("must be a whole number"))?;
+                self.check_range(n as f64).map_err(|m| err(&m))?;
+                toml::Value::Integer(n)
+            }
+            FieldType::Number => {
+                // NaN and infinity can't be written as TOML, and NaN would
+                // pass any range check.
+                let n = raw.parse::<f64>()
+                    .ok()
+                    .and_then(|n| if n.is_finite() { Some(n) } else { None })
+                    .ok_or_else(|| err("must be a number"))?;
+                self.check_range(n).map_err(|m| err(&m))?;
+                toml::Value::Float(n)
+            }
+            FieldType::List => {
+                let items = raw.lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty())
+                    .map(|l| toml::Value::String(l.into()))
+                    .collect::<Vec<_>>();
+                self.check_len(items.len()).map_err(|m| err(&m))?;
+                toml::Value::Array(items)
+            }
+            FieldType::Choice => {
+                if !self.options.iter().any(|o| o == raw) {
+                    return Err(err("is not one of the allowed values"));
+                }
+                toml::Value::String(raw.into())
+            }
+            FieldType::Boolean => unreachable!(),
+        };
+
+        Ok(Some(value))
+    }
+
+    fn check_range(&self, n: f64) -> Result<(), String> {
+        if let Some(min) = self.min {
+            if n < min {
+                return Err(format!("must be at least {}", min));
+            }
+        }
+        if let Some(max) = self.max {
+            if n > max {
+                return Err(format!("must be at most {}", max));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_len(&self, len: usize) -> Result<(), String> {
+        if let Some(min) = self.min {
+            if (len as f64) < min {
+                return Err(format!("must have at least {} entries/characters", min));
+            }
+        }
+        if let Some(max) = self.max {
+            if (len as f64) > max {
+                return Err(format!("must have at most {} entries/characters", max));
+            }
+        }
+        Ok(())
+    }
+
+    /// Formats a stored value so that it can be put into the form again.
+    pub fn format(&self, value: Option<&toml::Value>) -> String {
+        match value {
+            None => String::new(),
+            Some(&toml::Value::String(ref s)) => s.clone(),
+            Some(&toml::Value::Array(ref items)) => {
+                items.iter()
+                    .map(|item| match *item {
+                        toml::Value::String(ref s) => s.clone(),
+                        ref other => other.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            Some(other) => other.to_string(),
+        }
+    }
+
+    /// Formats a stored value in a short, human readable way, e.g. for table
+    /// cells.
+    pub fn display(&self, value: Option<&toml::Value>) -> String {
+        match value {
+            Some(&toml::Value::Boolean(b)) => if b { "yes" } else { "no" }.into(),
+            Some(&toml::Value::Array(_)) => self.format(value).replace('\n', ", "),
+            _ => self.format(value),
+        }
+    }
+}
+
+/// The schema describing all records of a basket.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Schema {
+    /// Name of a single record, e.g. "Recipe".
+    pub name: String,
+    /// Directory containing the records.
+    pub directory: String,
+    /// Fields shown as columns in the table view. Defaults to all fields
+    /// except text and list fields.
+    pub columns: Option<Vec<String>>,
+    pub fields: Vec<Field>,
+}
+
+impl Schema {
+    /// Loads the schema of the basket. Returns `Ok(None)` if the basket has no
+    /// schema.
+    pub fn load(content: &Content) -> Result<Option<Self>, SchemaError> {
+        let source = match content.read_text(SCHEMA_PATH) {
+            Some(source) => source,
+            None => return Ok(None),
+        };
+
+        let schema = toml::from_str::<Schema>(&source)
+            .map_err(|e| SchemaError::Parse(e.to_string()))?;
+        schema.check()?;
+        Ok(Some(schema))
+    }
+
+    /// Checks the schema for consistency.
+    fn check(&self) -> Result<(), SchemaError> {
+        let dir = self.directory.trim_matches('/');
+        if dir.is_empty() || !::model::content::is_valid_path(dir) {
+            return Err(SchemaError::Invalid("'directory' is not a valid path".into()));
+        }
+
+        for (i, field) in self.fields.iter().enumerate() {
+            if !is_valid_field_name(&field.name) {
+                let msg = format!("'{}' is not a valid field name", field.name);
+                return Err(SchemaError::Invalid(msg));
+            }
+            if self.fields[..i].iter().any(|f| f.name == field.name) {
+                let msg = format!("field '{}' is defined twice", field.name);
+                return Err(SchemaError::Invalid(msg));
+            }
+            if field.ty == FieldType::Choice && field.options.is_empty() {
+                let msg = format!("choice field '{}' has no options", field.name);
+                return Err(SchemaError::Invalid(msg));
+            }
+        }
+
+        if let Some(ref columns) = self.columns {
+            if let Some(c) = columns.iter().find(|c| self.field(c).is_none()) {
+                return Err(SchemaError::Invalid(format!("unknown column '{}'", c)));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    /// Returns the fields shown in the table view.
+    pub fn columns(&self) -> Vec<&Field> {
+        match self.columns {
+            Some(ref columns) => columns.iter().filter_map(|c| self.field(c)).collect(),
+            None => {
+                self.fields.iter()
+                    .filter(|f| f.ty != FieldType::Text && f.ty != FieldType::List)
+                    .collect()
+            }
+        }
+    }
+
+    fn record_path(&self, id: &str) -> String {
+        format!("{}/{}{}", self.directory.trim_matches('/'), id, RECORD_EXT)
+    }
+
+    /// Validates the raw form input (field name to entered value) and turns
+    /// it into a typed record. On failure, all errors are returned at once.
+    pub fn validate(
+        &self,
+        input: &HashMap<String, String>,
+    ) -> Result<BTreeMap<String, toml::Value>, Vec<FieldError>> {
+        let mut values = BTreeMap::new();
+        let mut errors = Vec::new();
+
+        for field in &self.fields {
+            match field.parse(input.get(&field.name).map(|s| s.as_str())) {
+                Ok(Some(value)) => {
+                    values.insert(field.name.clone(), value);
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(values)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Returns the raw form values of the given record.
+    pub fn form_values(&self, record: &Record) -> HashMap<String, String> {
+        self.fields.iter()
+            .map(|f| (f.name.clone(), f.format(record.values.get(&f.name))))
+            .collect()
+    }
+}
+
+fn is_valid_field_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() < MAX_SL_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// A single record.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub id: String,
+    pub values: BTreeMap<String, toml::Value>,
+}
+
+impl Record {
+    /// Returns the change storing this record.
+    pub fn to_change(&self, schema: &Schema, exists: bool) -> Change {
+        let path = schema.record_path(&self.id);
+        let content = toml::to_string(&self.values).expect("failed to serialize record");
+        if exists {
+            Change::Update { path, content }
+        } else {
+            Change::Create { path, content }
+        }
+    }
+
+    pub fn delete_change(&self, schema: &Schema) -> Change {
+        Change::Delete { path: schema.record_path(&self.id) }
+    }
+
+    /// Returns a human readable title: the value of the first string field.
+    pub fn title(&self, schema: &Schema) -> String {
+        schema.fields.iter()
+            .filter(|f| f.ty == FieldType::String)
+            .filter_map(|f| self.values.get(&f.name))
+            .filter_map(|v| v.as_str())
+            .next()
+            .unwrap_or(&self.id)
+            .to_string()
+    }
+}
+
+/// Read access to all records of a basket.
+pub struct Records<'a> {
+    content: &'a Content,
+    schema: &'a Schema,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(content: &'a Content, schema: &'a Schema) -> Self {
+        Self { content, schema }
+    }
+
+    /// Returns all records sorted by id. Files which aren't valid TOML are
+    /// skipped.
+    pub fn all(&self) -> Vec<Record> {
+        let prefix = format!("{}/", self.schema.directory.trim_matches('/'));
+        self.content.files()
+            .into_iter()
+            .filter(|f| f.path.starts_with(&prefix) && f.path.ends_with(RECORD_EXT))
+            .map(|f| f.path[prefix.len()..f.path.len() - RECORD_EXT.len()].to_string())
+            .filter(|id| is_valid_id(id))
+            .filter_map(|id| self.get(&id))
+            .collect()
+    }
+
+    /// Returns the record with the given id.
+    pub fn get(&self, id: &str) -> Option<Record> {
+        if !is_valid_id(id) {
+            return None;
+        }
+
+        self.content.read_text(&self.schema.record_path(id))
+            .and_then(|source| toml::from_str::<BTreeMap<String, toml::Value>>(&source).ok())
+            .map(|values| Record { id: id.into(), values })
+    }
+
+    pub fn exists(&self, id: &str) -> bool {
+        self.content.exists(&self.schema.record_path(id))
+    }
+
+    /// Returns an unused id for a new record, derived from the given title.
+    pub fn new_id(&self, title: &str) -> String {
+        let base = slugify(title);
+        let base = if base.is_empty() { "entry".to_string() } else { base };
+
+        let mut id = base.clone();
+        let mut i = 2;
+        while self.exists(&id) {
+            id = format!("{}-{}", base, i);
+            i += 1;
+        }
+        id
+    }
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() < MAX_SL_LEN
+        && !id.starts_with('-')
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Turns the given string into a lowercase ASCII string containing only
+/// alphanumerical characters and single dashes.
+fn slugify(s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_lowercase());
+        } else if !out.is_empty() && !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+
+    let trimmed = out.trim_matches('-');
+    trimmed[..cmp::min(trimmed.len(), MAX_SL_LEN / 2)].trim_matches('-').to_string()
+}
+
+
+/// A validation error of a single field.
+#[derive(Clone, Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub msg: String,
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}' {}", self.field, self.msg)
+    }
+}
+
+pub enum SchemaError {
+    /// The schema file is not valid TOML or doesn't have the right structure.
+    Parse(String),
+    /// The schema is well-formed, but inconsistent.
+    Invalid(String),
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SchemaError::Parse(ref e) => {
+                write!(f, "The schema in '{}' can't be parsed: {}", SCHEMA_PATH, e)
+            }
+            SchemaError::Invalid(ref e) => {
+                write!(f, "The schema in '{}' is invalid: {}", SCHEMA_PATH, e)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+    use toml;
+
+    use model::content::Change;
+    use super::{is_valid_id, slugify, Record, Schema, SchemaError};
+
+    const SCHEMA: &str = r#"
+        name = "Recipe"
+        directory = "/recipes/"
+
+        [[fields]]
+        name = "title"
+        type = "string"
+        required = true
+
+        [[fields]]
+        name = "servings"
+        type = "integer"
+        min = 1.0
+        max = 20.0
+
+        [[fields]]
+        name = "rating"
+        type = "number"
+
+        [[fields]]
+        name = "vegan"
+        type = "boolean"
+
+        [[fields]]
+        name = "ingredients"
+        label = "Ingredients"
+        type = "list"
+
+        [[fields]]
+        name = "steps"
+        type = "text"
+
+        [[fields]]
+        name = "course"
+        type = "choice"
+        options = ["starter", "main", "dessert"]
+    "#;
+
+    fn schema() -> Schema {
+        let schema = toml::from_str::<Schema>(SCHEMA).unwrap();
+        assert!(schema.check().is_ok());
+        schema
+    }
+
+    fn input(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn invalid(source: &str) -> String {
+        let schema = toml::from_str::<Schema>(source).unwrap();
+        match schema.check() {
+            Err(SchemaError::Invalid(msg)) => msg,
+            _ => panic!("schema should be invalid"),
+        }
+    }
+
+    #[test]
+    fn schema_check() {
+        let schema = schema();
+        assert_eq!(schema.field("ingredients").unwrap().label(), "Ingredients");
+        assert_eq!(schema.field("title").unwrap().label(), "title");
+        let columns = schema.columns().iter().map(|f| f.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(columns, vec!["title", "servings", "rating", "vegan", "course"]);
+
+        let fields = "[[fields]]\nname = \"a\"\ntype = \"string\"\n";
+        assert!(invalid(&format!("name = \"R\"\ndirectory = \"/\"\n{}", fields))
+            .contains("directory"));
+        assert!(invalid(&format!("name = \"R\"\ndirectory = \"../x\"\n{}", fields))
+            .contains("directory"));
+        assert!(invalid(&format!("name = \"R\"\ndirectory = \"r\"\n{}{}", fields, fields))
+            .contains("twice"));
+        assert!(invalid(&format!(
+            "name = \"R\"\ndirectory = \"r\"\ncolumns = [\"b\"]\n{}",
+            fields,
+        )).contains("unknown column"));
+        assert!(invalid("name = \"R\"\ndirectory = \"r\"\n\
+            [[fields]]\nname = \"a b\"\ntype = \"string\"\n")
+            .contains("field name"));
+        assert!(invalid("name = \"R\"\ndirectory = \"r\"\n\
+            [[fields]]\nname = \"c\"\ntype = \"choice\"\n")
+            .contains("no options"));
+    }
+
+    #[test]
+    fn validate_collects_all_errors() {
+        let errors = schema().validate(&input(&[
+            ("title", "  "),
+            ("servings", "0"),
+            ("rating", "good"),
+            ("course", "breakfast"),
+        ])).unwrap_err();
+        let fields = errors.iter().map(|e| e.field.as_str()).collect::<Vec<_>>();
+        assert_eq!(fields, vec!["title", "servings", "rating", "course"]);
+
+        let errors = schema().validate(&input(&[("title", "Two\nlines")])).unwrap_err();
+        assert_eq!(errors[0].msg, "must be a single line");
+
+        for rating in &["NaN", "inf", "-inf", "1e400"] {
+            let errors = schema()
+                .validate(&input(&[("title", "Toast"), ("rating", *rating)]))
+                .unwrap_err();
+            assert_eq!(errors[0].field, "rating");
+            assert_eq!(errors[0].msg, "must be a number");
+        }
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let schema = schema();
+        let values = schema.validate(&input(&[
+            ("title", " Pancakes \"deluxe\" "),
+            ("servings", "4"),
+            ("rating", "4.5"),
+            ("vegan", "on"),
+            ("ingredients", "flour\n\n  milk \neggs"),
+            ("steps", "Mix.\nFry.\n"),
+            ("course", "dessert"),
+        ])).unwrap();
+        assert_eq!(values["title"], toml::Value::String("Pancakes \"deluxe\"".into()));
+        assert_eq!(values["servings"], toml::Value::Integer(4));
+        assert_eq!(values["rating"], toml::Value::Float(4.5));
+        assert_eq!(values["vegan"], toml::Value::Boolean(true));
+        assert_eq!(values["steps"], toml::Value::String("Mix.\nFry.".into()));
+
+        let record = Record { id: "pancakes".into(), values };
+        assert_eq!(record.title(&schema), "Pancakes \"deluxe\"");
+
+        // What is written to the repository reads back as the same record.
+        let (path, content) = match record.to_change(&schema, false) {
+            Change::Create { path, content } => (path, content),
+            _ => panic!("expected a create"),
+        };
+        assert_eq!(path, "recipes/pancakes.toml");
+        let parsed = toml::from_str::<BTreeMap<String, toml::Value>>(&content).unwrap();
+        assert_eq!(parsed, record.values);
+
+        // And so does editing it in the form without changes.
+        let form = schema.form_values(&record);
+        assert_eq!(form["ingredients"], "flour\nmilk\neggs");
+        assert_eq!(schema.validate(&form).unwrap(), record.values);
+    }
+
+    #[test]
+    fn optional_fields_are_left_out() {
+        let schema = schema();
+        let values = schema.validate(&input(&[("title", "Toast")])).unwrap();
+        let names = values.keys().map(|k| k.as_str()).collect::<Vec<_>>();
+        // Unchecked checkboxes aren't sent, but are still stored as `false`.
+        assert_eq!(names, vec!["title", "vegan"]);
+
+        let record = Record { id: "toast".into(), values };
+        let form = schema.form_values(&record);
+        assert_eq!(form["servings"], "");
+        assert_eq!(form["vegan"], "false");
+        assert_eq!(schema.field("vegan").unwrap().display(record.values.get("vegan")), "no");
+        assert_eq!(schema.validate(&form).unwrap(), record.values);
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(slugify("  Crème brûlée, Mom's! "), "cr-me-br-l-e-mom-s");
+        assert_eq!(slugify("!!!"), "");
+        assert!(is_valid_id("pancakes-2"));
+        assert!(!is_valid_id("-pancakes"));
+        assert!(!is_valid_id("a/b"));
+        assert!(!is_valid_id(""));
+    }
+}
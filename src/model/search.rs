@@ -0,0 +1,411 @@
+//! Full-text search over baskets, users and the content of baskets.
+//!
+//! The documents and the query are built by the SQL functions defined in the
+//! `add_search` migration. They use the 'simple' configuration, which only
+//! splits the text into lowercase words. This makes it easy to highlight the
+//! matches here without asking the database again.
+
+use diesel::prelude::*;
+use diesel::types::{Bool, Float, Nullable, Text};
+use diesel;
+use std::collections::HashSet;
+use std::thread;
+
+use db::Db;
+use db::schema::{basket_contents, baskets, users};
+use model::{AuthUser, Basket, BasketRecord, PubUser, User};
+use model::content::Content;
+use model::permissions::Visibility;
+
+
+/// Larger files aren't indexed.
+const MAX_INDEXED_FILE_SIZE: usize = 64 * 1024;
+
+/// Maximum number of results of each category.
+const MAX_RESULTS: i64 = 20;
+
+/// Number of characters shown before and after a match in a file.
+const SNIPPET_CONTEXT: usize = 80;
+
+/// The SQL type `tsvector`, which diesel doesn't know.
+pub struct TsVector;
+
+/// The SQL type `tsquery`, which diesel doesn't know.
+pub struct TsQuery;
+
+sql_function!(basket_document, basket_document_t,
+    (name: Text, description: Nullable<Text>) -> TsVector);
+sql_function!(user_document, user_document_t,
+    (username: Text, name: Nullable<Text>, bio: Nullable<Text>) -> TsVector);
+sql_function!(content_document, content_document_t, (body: Text) -> TsVector);
+sql_function!(search_query, search_query_t, (query: Text) -> TsQuery);
+sql_function!(search_matches, search_matches_t, (document: TsVector, query: TsQuery) -> Bool);
+sql_function!(ts_rank, ts_rank_t, (document: TsVector, query: TsQuery) -> Float);
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "basket_contents"]
+struct NewDocument {
+    basket_id: i64,
+    path: String,
+    body: String,
+}
+
+/// A basket whose name or description matches.
+#[derive(Serialize)]
+pub struct BasketHit {
+    pub basket: Basket,
+    /// The name as HTML with the matches highlighted.
+    pub name: String,
+    /// The description as HTML with the matches highlighted.
+    pub description: Option<String>,
+}
+
+/// A user or organization whose username, name or bio matches.
+#[derive(Serialize)]
+pub struct UserHit {
+    pub user: PubUser,
+    /// The username as HTML with the matches highlighted.
+    pub username: String,
+    /// The name as HTML with the matches highlighted.
+    pub name: Option<String>,
+    /// The bio as HTML with the matches highlighted.
+    pub bio: Option<String>,
+}
+
+/// A file of a basket whose content matches.
+#[derive(Serialize)]
+pub struct ContentHit {
+    pub basket: Basket,
+    pub path: String,
+    /// The part of the file around the first match as HTML, with the
+    /// matches highlighted.
+    pub snippet: String,
+}
+
+/// The results of a search, each category sorted by relevance.
+#[derive(Default, Serialize)]
+pub struct SearchResults {
+    pub baskets: Vec<BasketHit>,
+    pub users: Vec<UserHit>,
+    pub contents: Vec<ContentHit>,
+}
+
+/// Searches everything the user can see.
+pub fn search(query: &str, auth_user: Option<&AuthUser>, db: &Db) -> SearchResults {
+    let terms = terms(query);
+    if terms.is_empty() {
+        return SearchResults::default();
+    }
+
+    SearchResults {
+        baskets: search_baskets(query, &terms, auth_user, db),
+        users: search_users(query, &terms, db),
+        contents: search_contents(query, &terms, auth_user, db),
+    }
+}
+
+fn search_baskets(
+    query: &str,
+    terms: &[String],
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> Vec<BasketHit> {
+    let document = basket_document(baskets::name, baskets::description);
+    let records = Visibility::of(auth_user.map(|u| &**u), db)
+        .visible_baskets()
+        .filter(search_matches(document, search_query(query.to_string())))
+        .order(ts_rank(document, search_query(query.to_string())).desc())
+        .limit(MAX_RESULTS)
+        .load::<BasketRecord>(&*db.conn())
+        .unwrap();
+
+    Basket::with_owners(records, db)
+        .into_iter()
+        .map(|basket| BasketHit {
+            name: highlight(basket.name(), terms),
+            description: basket.description().map(|d| highlight(d, terms)),
+            basket,
+        })
+        .collect()
+}
+
+fn search_users(query: &str, terms: &[String], db: &Db) -> Vec<UserHit> {
+    let document = user_document(users::username, users::name, users::bio);
+    users::table
+        .filter(search_matches(document, search_query(query.to_string())))
+        .order(ts_rank(document, search_query(query.to_string())).desc())
+        .limit(MAX_RESULTS)
+        .load::<User>(&*db.conn())
+        .unwrap()
+        .into_iter()
+        .map(|user| {
+            let user = PubUser::from_user(user);
+            UserHit {
+                username: highlight(user.username(), terms),
+                name: user.name().map(|n| highlight(n, terms)),
+                bio: user.bio().map(|b| highlight(b, terms)),
+                user,
+            }
+        })
+        .collect()
+}
+
+fn search_contents(
+    query: &str,
+    terms: &[String],
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> Vec<ContentHit> {
+    let conn = db.conn();
+
+    let visible = Visibility::of(auth_user.map(|u| &**u), db).visible_basket_ids();
+    let document = content_document(basket_contents::body);
+    let rows = basket_contents::table
+        .filter(basket_contents::basket_id.eq_any(visible))
+        .filter(search_matches(document, search_query(query.to_string())))
+        .order(ts_rank(document, search_query(query.to_string())).desc())
+        .limit(MAX_RESULTS)
+        .load::<(i64, String, String)>(&*conn)
+        .unwrap();
+    if rows.is_empty() {
+        return vec![];
+    }
+
+    let mut ids = rows.iter().map(|&(id, _, _)| id).collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+    let records = baskets::table
+        .filter(baskets::id.eq_any(ids))
+        .load::<BasketRecord>(&*conn)
+        .unwrap();
+    let baskets = Basket::with_owners(records, db);
+
+    rows.into_iter()
+        .filter_map(|(id, path, body)| {
+            baskets.iter().find(|b| b.id() == id).map(|basket| ContentHit {
+                basket: basket.clone(),
+                snippet: snippet(&body, terms),
+                path,
+            })
+        })
+        .collect()
+}
+
+/// Replaces the indexed content of the basket with the text files currently
+/// in its repository. Binary and large files are skipped.
+pub fn index_content(basket: &BasketRecord, db: &Db) {
+    let content = match Content::open(basket) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+
+    let documents = content.files()
+        .into_iter()
+        .filter(|file| file.size <= MAX_INDEXED_FILE_SIZE)
+        .filter_map(|file| {
+            content.read(&file.path)
+                .and_then(|raw| String::from_utf8(raw).ok())
+                // Postgres can't store NUL characters in text columns.
+                .and_then(|body| if body.contains('\0') { None } else { Some(body) })
+                .map(|body| NewDocument {
+                    basket_id: basket.id(),
+                    path: file.path,
+                    body,
+                })
+        })
+        .collect::<Vec<_>>();
+
+    // This runs after the content was changed successfully, so a failure
+    // only leaves the index outdated until the next change.
+    let conn = db.conn();
+    let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(basket_contents::table.filter(basket_contents::basket_id.eq(basket.id())))
+            .execute(&*conn)?;
+        if !documents.is_empty() {
+            diesel::insert(&documents)
+                .into(basket_contents::table)
+                .execute(&*conn)?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        error!("failed to index the content of basket {}: {}", basket.id(), e);
+    }
+}
+
+/// Indexes the content of all baskets which don't have any indexed content
+/// yet, e.g. because they were created before the search existed. Runs in
+/// the background, so that the server starts right away.
+pub fn spawn_index_thread(db: Db) {
+    thread::spawn(move || {
+        if let Err(e) = index_missing(&db) {
+            error!("failed to index the content of all baskets: {}", e);
+        }
+    });
+}
+
+fn index_missing(db: &Db) -> QueryResult<()> {
+    let indexed = basket_contents::table
+        .select(basket_contents::basket_id)
+        .distinct()
+        .load::<i64>(&*db.conn())?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let records = baskets::table
+        .filter(baskets::deleted_at.is_null())
+        .load::<BasketRecord>(&*db.conn())?;
+
+    for record in records.iter().filter(|r| !indexed.contains(&r.id())) {
+        index_content(record, db);
+    }
+    Ok(())
+}
+
+/// Splits the query into lowercase words, like the 'simple' configuration.
+fn terms(query: &str) -> Vec<String> {
+    query.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+/// Escapes the text for HTML and marks all words matching one of the terms.
+fn highlight(text: &str, terms: &[String]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        if !c.is_alphanumeric() {
+            push_escaped(&mut out, c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        let end = rest.find(|c: char| !c.is_alphanumeric()).unwrap_or(rest.len());
+        let word = &rest[..end];
+        if terms.contains(&word.to_lowercase()) {
+            out.push_str("<mark>");
+            out.push_str(word);
+            out.push_str("</mark>");
+        } else {
+            out.push_str(word);
+        }
+        rest = &rest[end..];
+    }
+
+    out
+}
+
+fn push_escaped(out: &mut String, c: char) {
+    match c {
+        '&' => out.push_str("&amp;"),
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        '\n' | '\r' | '\t' => out.push(' '),
+        c => out.push(c),
+    }
+}
+
+/// Returns the highlighted part of the body around the first match.
+fn snippet(body: &str, terms: &[String]) -> String {
+    // Find the byte offset of the first matching word.
+    let mut pos = 0;
+    let mut offset = 0;
+    for word in body.split(|c: char| !c.is_alphanumeric()) {
+        if !word.is_empty() && terms.contains(&word.to_lowercase()) {
+            pos = offset;
+            break;
+        }
+        // The separator is a single character.
+        offset += word.len() + body[offset + word.len()..].chars().next()
+            .map(|c| c.len_utf8())
+            .unwrap_or(0);
+    }
+
+    let start = body[..pos].char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT)
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    let end = body[pos..].char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map(|(i, _)| pos + i)
+        .unwrap_or(body.len());
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push_str("… ");
+    }
+    out.push_str(&highlight(&body[start..end], terms));
+    if end < body.len() {
+        out.push_str(" …");
+    }
+    out
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, snippet, terms, SNIPPET_CONTEXT};
+
+    fn t(terms: &[&str]) -> Vec<String> {
+        terms.iter().map(|t| t.to_string()).collect()
+    }
+
+    #[test]
+    fn query_terms() {
+        assert_eq!(terms("Hello, World!  foo_bar"), t(&["hello", "world", "foo", "bar"]));
+        assert_eq!(terms(" -- "), t(&[]));
+    }
+
+    #[test]
+    fn highlight_marks_whole_words() {
+        assert_eq!(
+            highlight("Cats and a cat, CAT.", &t(&["cat"])),
+            "Cats and a <mark>cat</mark>, <mark>CAT</mark>.",
+        );
+        assert_eq!(
+            highlight("Grüße aus Köln", &t(&["köln", "grüße"])),
+            "<mark>Grüße</mark> aus <mark>Köln</mark>",
+        );
+    }
+
+    #[test]
+    fn highlight_escapes() {
+        assert_eq!(
+            highlight("<b>tom</b> & \"jerry's\"\nend", &t(&["b", "tom"])),
+            "&lt;<mark>b</mark>&gt;<mark>tom</mark>&lt;/<mark>b</mark>&gt; &amp; \
+                &quot;jerry&#39;s&quot; end",
+        );
+    }
+
+    #[test]
+    fn snippet_of_short_body() {
+        assert_eq!(snippet("a short text", &t(&["short"])), "a <mark>short</mark> text");
+        assert_eq!(snippet("no match", &t(&["other"])), "no match");
+    }
+
+    #[test]
+    fn snippet_of_long_body() {
+        let before = "ä ".repeat(200);
+        let after = " ö".repeat(200);
+        let body = format!("{}needle{}", before, after);
+        let out = snippet(&body, &t(&["needle"]));
+
+        assert!(out.starts_with("… "));
+        assert!(out.ends_with(" …"));
+        assert!(out.contains(" <mark>needle</mark> "));
+        // The context on both sides plus the ellipses.
+        assert!(out.chars().count() <= 2 * SNIPPET_CONTEXT + 30);
+    }
+
+    #[test]
+    fn snippet_without_match_starts_at_the_beginning() {
+        let body = "word ".repeat(100);
+        let out = snippet(&body, &t(&["missing"]));
+        assert!(out.starts_with("word"));
+        assert!(out.ends_with(" …"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Stars: users can star baskets to find them again later.
+
+use diesel::prelude::*;
+use diesel;
+
+use db::Db;
+use db::schema::{baskets, stars};
+use model::{AuthUser, Basket, BasketRecord, PubUser};
+use model::event::{self, EventKind};
+use model::permissions::Visibility;
+
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "stars"]
+struct NewStar {
+    user_id: i64,
+    basket_id: i64,
+}
+
+/// Whether the user starred the basket.
+pub fn is_starred(basket: &BasketRecord, user: &PubUser, db: &Db) -> bool {
+    stars::table
+        .find((user.id(), basket.id()))
+        .select(stars::user_id)
+        .first::<i64>(&*db.conn())
+        .optional()
+        .unwrap()
+        .is_some()
+}
+
+/// Returns how many users starred the basket.
+pub fn count(basket: &BasketRecord, db: &Db) -> i64 {
+    stars::table
+        .filter(stars::basket_id.eq(basket.id()))
+        .count()
+        .get_result(&*db.conn())
+        .unwrap()
+}
+
+/// Stars the basket. Starring a basket twice has no effect.
+pub fn star(basket: &Basket, auth_user: &AuthUser, db: &Db) {
+    use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+    let conn = db.conn();
+    let result = conn.transaction::<_, DieselError, _>(|| {
+        diesel::insert(&NewStar { user_id: auth_user.id(), basket_id: basket.id() })
+            .into(stars::table)
+            .execute(&*conn)?;
+        diesel::update(baskets::table.find(basket.id()))
+            .set(baskets::star_count.eq(baskets::star_count + 1))
+            .execute(&*conn)?;
+        Ok(())
+    });
+
+    match result {
+        Ok(()) => {
+            event::record(EventKind::Starred, auth_user, basket, None, db);
+        }
+        // The basket was starred already.
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {}
+        Err(e) => panic!("failed to star basket: {}", e),
+    }
+}
+
+/// Removes the star of the user from the basket, if there is one.
+pub fn unstar(basket: &Basket, auth_user: &AuthUser, db: &Db) {
+    let conn = db.conn();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        let deleted = diesel::delete(stars::table.find((auth_user.id(), basket.id())))
+            .execute(&*conn)?;
+        if deleted > 0 {
+            diesel::update(baskets::table.find(basket.id()))
+                .set(baskets::star_count.eq(baskets::star_count - 1))
+                .execute(&*conn)?;
+        }
+        Ok(())
+    }).unwrap();
+}
+
+/// Returns the baskets starred by `user` which are visible to `auth_user`,
+/// most recently starred first.
+pub fn starred_by(user: &PubUser, auth_user: Option<&AuthUser>, db: &Db) -> Vec<Basket> {
+    let conn = db.conn();
+    let ids = stars::table
+        .filter(stars::user_id.eq(user.id()))
+        .order(stars::created_at.desc())
+        .select(stars::basket_id)
+        .load::<i64>(&*conn)
+        .unwrap();
+    if ids.is_empty() {
+        return vec![];
+    }
+
+    let records = Visibility::of(auth_user.map(|u| &**u), db)
+        .visible_baskets()
+        .filter(baskets::id.eq_any(ids.clone()))
+        .load::<BasketRecord>(&*conn)
+        .unwrap();
+
+    let mut baskets = Basket::with_owners(records, db);
+    baskets.sort_by_key(|b| ids.iter().position(|&id| id == b.id()));
+    baskets
+}
@@ -0,0 +1,489 @@
+//! Transferring baskets to a new owner.
+//!
+//! The current owner nominates a user or an organization as new owner, who
+//! has to accept the transfer. Afterwards, the old URL of the basket
+//! redirects to its new location.
+
+use diesel::prelude::*;
+use diesel;
+use std::fmt;
+
+use db::Db;
+use db::schema::{basket_collaborators, basket_redirects, basket_transfers, baskets,
+    team_baskets, users};
+use model::{AuthUser, Basket, BasketRecord, PubUser, User};
+use model::basket::ARCHIVED_MESSAGE;
+use model::organization;
+use model::permissions::{has_permission, UserAction};
+
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "basket_transfers"]
+struct NewTransfer {
+    basket_id: i64,
+    to_user_id: i64,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "basket_redirects"]
+struct NewRedirect {
+    user_id: i64,
+    name: String,
+    basket_id: i64,
+}
+
+/// A transfer waiting to be accepted by the new owner.
+#[derive(Serialize)]
+pub struct Transfer {
+    pub basket: Basket,
+    /// The nominated new owner.
+    pub to: PubUser,
+}
+
+/// Returns the nominated new owner, if a transfer of the basket is pending.
+pub fn pending_owner(basket: &BasketRecord, db: &Db) -> Option<PubUser> {
+    let conn = db.conn();
+    basket_transfers::table
+        .find(basket.id())
+        .select(basket_transfers::to_user_id)
+        .first::<i64>(&*conn)
+        .optional()
+        .unwrap()
+        .and_then(|id| {
+            users::table
+                .find(id)
+                .first::<User>(&*conn)
+                .optional()
+                .unwrap()
+        })
+        .map(PubUser::from_user)
+}
+
+/// Nominates a new owner for the basket, replacing an earlier nomination.
+/// Returns the nominated owner.
+pub fn nominate(
+    basket: &Basket,
+    new_owner: &str,
+    auth_user: &AuthUser,
+    db: &Db,
+) -> Result<PubUser, TransferError> {
+    if basket.is_archived() {
+        return Err(TransferError::Archived);
+    }
+    let can_transfer = has_permission(Some(auth_user), UserAction::TransferBasket {
+        owner: basket.owner_user(),
+        basket: basket,
+    }, db);
+    if !can_transfer {
+        return Err(TransferError::NoPermission);
+    }
+
+    let new_owner = new_owner.trim();
+    let user = PubUser::from_username(new_owner, db)
+        .ok_or_else(|| TransferError::UserNotFound { username: new_owner.into() })?;
+    if user.id() == basket.owner_user().id() {
+        return Err(TransferError::SameOwner);
+    }
+    if name_taken(user.id(), basket.name(), db) {
+        return Err(TransferError::NameAlreadyUsed { owner: user.username().into() });
+    }
+
+    let conn = db.conn();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(basket_transfers::table.find(basket.id()))
+            .execute(&*conn)?;
+        diesel::insert(&NewTransfer { basket_id: basket.id(), to_user_id: user.id() })
+            .into(basket_transfers::table)
+            .execute(&*conn)?;
+        Ok(())
+    }).unwrap();
+
+    Ok(user)
+}
+
+/// Cancels the pending transfer of the basket.
+pub fn cancel(basket: &Basket, auth_user: &AuthUser, db: &Db) -> Result<(), TransferError> {
+    let can_transfer = has_permission(Some(auth_user), UserAction::TransferBasket {
+        owner: basket.owner_user(),
+        basket: basket,
+    }, db);
+    if !can_transfer {
+        return Err(TransferError::NoPermission);
+    }
+
+    let deleted = diesel::delete(basket_transfers::table.find(basket.id()))
+        .execute(&*db.conn())
+        .unwrap();
+    if deleted == 0 {
+        Err(TransferError::NotFound)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns all transfers the user can accept: those to the user and to the
+/// organizations the user owns.
+pub fn incoming(auth_user: &AuthUser, db: &Db) -> Vec<Transfer> {
+    let mut recipient_ids = organization::owned_organization_ids(auth_user, db);
+    recipient_ids.push(auth_user.id());
+
+    let conn = db.conn();
+
+    let pending = basket_transfers::table
+        .filter(basket_transfers::to_user_id.eq_any(recipient_ids.clone()))
+        .select((basket_transfers::basket_id, basket_transfers::to_user_id))
+        .load::<(i64, i64)>(&*conn)
+        .unwrap();
+    if pending.is_empty() {
+        return vec![];
+    }
+
+    let recipients = users::table
+        .filter(users::id.eq_any(recipient_ids))
+        .load::<User>(&*conn)
+        .unwrap()
+        .into_iter()
+        .map(PubUser::from_user)
+        .collect::<Vec<_>>();
+
+    let ids = pending.iter().map(|&(id, _)| id).collect::<Vec<_>>();
+    baskets::table
+        .inner_join(users::table)
+        .filter(baskets::id.eq_any(ids))
+        .filter(baskets::deleted_at.is_null())
+        .order((users::username, baskets::name))
+        .load::<(BasketRecord, User)>(&*conn)
+        .unwrap()
+        .into_iter()
+        .filter_map(|(record, owner)| {
+            pending.iter()
+                .find(|&&(id, _)| id == record.id())
+                .and_then(|&(_, to_id)| recipients.iter().find(|r| r.id() == to_id))
+                .map(|to| Transfer {
+                    basket: Basket::from_parts(record, PubUser::from_user(owner)),
+                    to: to.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Accepts or declines the transfer of the basket `owner/name`. Returns the
+/// basket at its new location if the transfer was accepted.
+pub fn respond(
+    owner: &str,
+    name: &str,
+    auth_user: &AuthUser,
+    accept: bool,
+    db: &Db,
+) -> Result<Option<Basket>, TransferError> {
+    use diesel::result::{Error as DieselError, DatabaseErrorKind};
+
+    let (basket_id, old_owner_id) = baskets::table
+        .inner_join(users::table)
+        .filter(baskets::name.eq(name))
+        .filter(users::username.eq(owner))
+        .filter(baskets::deleted_at.is_null())
+        .select((baskets::id, users::id))
+        .first::<(i64, i64)>(&*db.conn())
+        .optional()
+        .unwrap()
+        .ok_or(TransferError::NotFound)?;
+
+    let to_id = basket_transfers::table
+        .find(basket_id)
+        .select(basket_transfers::to_user_id)
+        .first::<i64>(&*db.conn())
+        .optional()
+        .unwrap()
+        .ok_or(TransferError::NotFound)?;
+    let to = users::table
+        .find(to_id)
+        .first::<User>(&*db.conn())
+        .optional()
+        .unwrap()
+        .map(PubUser::from_user)
+        .ok_or(TransferError::NotFound)?;
+
+    // Users who can't accept the transfer shouldn't learn about it.
+    if !has_permission(Some(auth_user), UserAction::AcceptTransfer { to: &to }, db) {
+        return Err(TransferError::NotFound);
+    }
+
+    if !accept {
+        diesel::delete(basket_transfers::table.find(basket_id))
+            .execute(&*db.conn())
+            .unwrap();
+        return Ok(None);
+    }
+
+    // Check for a basket with the same name up front, to give a proper error
+    // message. The unique index catches concurrent changes.
+    if name_taken(to.id(), name, db) {
+        return Err(TransferError::NameAlreadyUsed { owner: to.username().into() });
+    }
+
+    let conn = db.conn();
+    let result = conn.transaction::<_, DieselError, _>(|| {
+        diesel::update(baskets::table.find(basket_id))
+            .set(baskets::user_id.eq(to.id()))
+            .execute(&*conn)?;
+        diesel::delete(basket_transfers::table.find(basket_id))
+            .execute(&*conn)?;
+
+        // The new owner can't be a collaborator, and teams belong to the
+        // organization which owned the basket before.
+        diesel::delete(
+            basket_collaborators::table
+                .filter(basket_collaborators::basket_id.eq(basket_id))
+                .filter(basket_collaborators::user_id.eq(to.id()))
+        ).execute(&*conn)?;
+        diesel::delete(team_baskets::table.filter(team_baskets::basket_id.eq(basket_id)))
+            .execute(&*conn)?;
+
+        // Redirect the old location to the basket. A redirect at the new
+        // location is obsolete now.
+        for &user_id in &[old_owner_id, to.id()] {
+            diesel::delete(
+                basket_redirects::table
+                    .filter(basket_redirects::user_id.eq(user_id))
+                    .filter(basket_redirects::name.eq(name))
+            ).execute(&*conn)?;
+        }
+        let redirect = NewRedirect {
+            user_id: old_owner_id,
+            name: name.into(),
+            basket_id,
+        };
+        diesel::insert(&redirect)
+            .into(basket_redirects::table)
+            .execute(&*conn)?;
+        Ok(())
+    });
+    drop(conn);
+
+    match result {
+        Ok(()) => Ok(Basket::load_by_id(basket_id, Some(auth_user), db)),
+        Err(DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => {
+            Err(TransferError::NameAlreadyUsed { owner: to.username().into() })
+        }
+        Err(e) => panic!("failed to transfer basket: {}", e),
+    }
+}
+
+/// Returns the id of the basket which was located at `owner/name` before it
+/// was transferred.
+pub fn redirect_target(owner: &str, name: &str, db: &Db) -> Option<i64> {
+    let conn = db.conn();
+    users::table
+        .filter(users::username.eq(owner))
+        .select(users::id)
+        .first::<i64>(&*conn)
+        .optional()
+        .unwrap()
+        .and_then(|user_id| {
+            basket_redirects::table
+                .filter(basket_redirects::user_id.eq(user_id))
+                .filter(basket_redirects::name.eq(name))
+                .select(basket_redirects::basket_id)
+                .first::<i64>(&*conn)
+                .optional()
+                .unwrap()
+        })
+}
+
+/// Whether the user already has a basket with the given name. Deleted
+/// baskets count as well, since they keep their name until they are purged.
+fn name_taken(user_id: i64, name: &str, db: &Db) -> bool {
+    baskets::table
+        .filter(baskets::user_id.eq(user_id))
+        .filter(baskets::name.eq(name))
+        .select(baskets::id)
+        .first::<i64>(&*db.conn())
+        .optional()
+        .unwrap()
+        .is_some()
+}
+
+
+pub enum TransferError {
+    NoPermission,
+    Archived,
+    UserNotFound {
+        username: String,
+    },
+    /// The basket can't be transferred to its current owner.
+    SameOwner,
+    /// The new owner already has a basket with the same name.
+    NameAlreadyUsed {
+        owner: String,
+    },
+    /// There is no pending transfer of this basket.
+    NotFound,
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::TransferError::*;
+
+        match *self {
+            NoPermission => {
+                "Only the owner can transfer this basket!".fmt(f)
+            }
+            Archived => {
+                ARCHIVED_MESSAGE.fmt(f)
+            }
+            UserNotFound { ref username } => {
+                write!(f, "There is no user or organization named '{}'!", username)
+            }
+            SameOwner => {
+                "The basket already belongs to this owner.".fmt(f)
+            }
+            NameAlreadyUsed { ref owner } => {
+                write!(f, "'{}' already has a basket with the same name!", owner)
+            }
+            NotFound => {
+                "The transfer doesn't exist anymore.".fmt(f)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+    use rocket;
+    use rocket::http::{Method, Status};
+    use rocket::testing::MockRequest;
+
+    use db::Db;
+    use db::schema::users;
+    use model::{feed, AuthUser, Basket, PubUser, User};
+    use routes::basket::not_found;
+    use super::{nominate, pending_owner, redirect_target, respond, TransferError};
+
+    const ALICE: i64 = 900_001;
+    const BOB: i64 = 900_002;
+    const EVE: i64 = 900_003;
+
+    /// Passes if `$result` is the given error.
+    macro_rules! assert_err {
+        ($result:expr, $pattern:pat) => {
+            match $result {
+                Err($pattern) => {}
+                _ => panic!("expected {}", stringify!($pattern)),
+            }
+        }
+    }
+
+    /// Creates Alice's basket `notes` and nominates Bob as its new owner.
+    fn setup() -> Db {
+        let db = Db::open_test_connection();
+        db.conn().batch_execute("
+            insert into users (id, username) values
+                (900001, 'test-alice'),
+                (900002, 'test-bob'),
+                (900003, 'test-eve');
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'notes', 900001, 'generic', 'public'),
+                (900102, 'tasks', 900001, 'generic', 'public'),
+                (900103, 'tasks', 900002, 'generic', 'public');
+        ").unwrap();
+
+        let alice = auth_user(ALICE, &db);
+        let basket = Basket::load("notes", "test-alice", Some(&alice), &db).unwrap();
+        assert!(nominate(&basket, "test-bob", &alice, &db).is_ok());
+        db
+    }
+
+    fn auth_user(id: i64, db: &Db) -> AuthUser {
+        let user = users::table.find(id).first::<User>(&*db.conn()).unwrap();
+        let token = feed::reset_token(&PubUser::from_user(user), db);
+        AuthUser::from_feed_token(&token, db).unwrap()
+    }
+
+    fn pending_owner_id(db: &Db) -> Option<i64> {
+        let basket = Basket::load_by_id(900101, None, db).unwrap();
+        pending_owner(&basket, db).map(|u| u.id())
+    }
+
+    #[test]
+    fn name_collisions_are_detected() {
+        let db = setup();
+        let alice = auth_user(ALICE, &db);
+        let bob = auth_user(BOB, &db);
+
+        // Bob already has a basket named `tasks`.
+        let tasks = Basket::load("tasks", "test-alice", Some(&alice), &db).unwrap();
+        assert_err!(
+            nominate(&tasks, "test-bob", &alice, &db),
+            TransferError::NameAlreadyUsed { .. }
+        );
+
+        // Bob created a basket named `notes` after the nomination.
+        db.conn().batch_execute("
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900104, 'notes', 900002, 'generic', 'public');
+        ").unwrap();
+        assert_err!(
+            respond("test-alice", "notes", &bob, true, &db),
+            TransferError::NameAlreadyUsed { .. }
+        );
+        assert_eq!(pending_owner_id(&db), Some(BOB));
+        assert_eq!(redirect_target("test-alice", "notes", &db), None);
+    }
+
+    #[test]
+    fn only_the_recipient_can_respond() {
+        let db = setup();
+
+        for &id in &[ALICE, EVE] {
+            let auth_user = auth_user(id, &db);
+            assert_err!(
+                respond("test-alice", "notes", &auth_user, true, &db),
+                TransferError::NotFound
+            );
+            assert_err!(
+                respond("test-alice", "notes", &auth_user, false, &db),
+                TransferError::NotFound
+            );
+        }
+        assert_eq!(pending_owner_id(&db), Some(BOB));
+
+        let bob = auth_user(BOB, &db);
+        match respond("test-alice", "notes", &bob, false, &db) {
+            Ok(None) => {}
+            _ => panic!("declining failed"),
+        }
+        assert_eq!(pending_owner_id(&db), None);
+        assert_err!(respond("test-alice", "notes", &bob, true, &db), TransferError::NotFound);
+    }
+
+    #[test]
+    fn old_url_redirects() {
+        let db = setup();
+        let bob = auth_user(BOB, &db);
+
+        let basket = match respond("test-alice", "notes", &bob, true, &db) {
+            Ok(Some(basket)) => basket,
+            _ => panic!("accepting failed"),
+        };
+        assert_eq!(basket.url(), "/test-bob/notes");
+        assert_eq!(pending_owner_id(&db), None);
+        assert_eq!(redirect_target("test-alice", "notes", &db), Some(900101));
+
+        let rocket = rocket::ignite().manage(db).catch(errors![not_found]);
+        let mut req = MockRequest::new(Method::Get, "/test-alice/notes/wiki/Home?edit=1");
+        let response = req.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::SeeOther);
+        assert_eq!(
+            response.headers().get_one("Location"),
+            Some("/test-bob/notes/wiki/Home?edit=1"),
+        );
+
+        let mut req = MockRequest::new(Method::Get, "/test-alice/other");
+        let response = req.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -10,7 +10,13 @@ use std::ops::Deref;
 use serde::{Serialize, Serializer};
 
 use model::{self, Basket, BasketRecord, UserEmail, Session};
-use model::permissions::{has_permission, UserAction};
+use model::collaborator;
+use model::feed;
+use model::organization;
+use model::content::Author;
+use model::listing::{BasketListing, BasketPage};
+use model::notification;
+use model::permissions::Visibility;
 use db::Db;
 use db::schema::{baskets, users, user_emails, sessions};
 
@@ -48,6 +54,8 @@ pub struct User {
 pub struct AuthUser {
     user: PubUser,
     session: Option<Session>,
+    /// Loaded along with the session, so that every page can show it.
+    unread_notifications: i64,
 }
 
 impl AuthUser {
@@ -84,6 +92,12 @@ impl AuthUser {
             None
         };
 
+        // Organizations are stored as users, but nobody can log in as one.
+        let user = user.and_then(|user| {
+            let user = PubUser(user);
+            if organization::is_organization(&user, db) { None } else { Some(user.0) }
+        });
+
         user.ok_or(LoginError::UserNotFound).and_then(|user| {
             if user.password.is_none() {
                 Err(LoginError::NoPasswordSet)
@@ -91,6 +105,9 @@ impl AuthUser {
                 Ok(AuthUser {
                     user: PubUser(user),
                     session: None,
+                    // Pages are only rendered for users authenticated by
+                    // session, which loads the actual number.
+                    unread_notifications: 0,
                 })
             } else {
                 Err(LoginError::PasswordIncorrect)
@@ -98,10 +115,83 @@ impl AuthUser {
         })
     }
 
+    /// Authenticates the owner of a feed token (see `model::feed`). Such a
+    /// user has no session.
+    pub fn from_feed_token(token: &str, db: &Db) -> Option<Self> {
+        feed::token_owner(token, db).map(|user| AuthUser {
+            user,
+            session: None,
+            unread_notifications: 0,
+        })
+    }
+
     pub fn into_pub_user(self) -> PubUser {
         self.user
     }
 
+    /// Returns the number of unread notifications when the user was
+    /// authenticated.
+    pub fn unread_notifications(&self) -> i64 {
+        self.unread_notifications
+    }
+
+    /// Returns the primary email address of this user, if one is set.
+    pub fn primary_email(&self, db: &Db) -> Option<UserEmail> {
+        UserEmail::belonging_to(&self.user.0)
+            .filter(user_emails::is_primary.eq(true))
+            .first(&*db.conn())
+            .optional()
+            .unwrap()
+    }
+
+    /// Returns all email addresses of this user, sorted alphabetically.
+    pub fn emails(&self, db: &Db) -> Vec<UserEmail> {
+        UserEmail::belonging_to(&self.user.0)
+            .order(user_emails::email)
+            .load(&*db.conn())
+            .unwrap()
+    }
+
+    /// Makes the given address the primary one of this user, replacing the
+    /// old primary address. Returns `false` if the address doesn't belong to
+    /// the user.
+    pub fn set_primary_email(&self, email: &str, db: &Db) -> bool {
+        let own_emails = user_emails::table.filter(user_emails::user_id.eq(self.id()));
+
+        let conn = db.conn();
+        conn.transaction::<_, diesel::result::Error, _>(|| {
+            let owned = own_emails.clone()
+                .filter(user_emails::email.eq(email))
+                .count()
+                .get_result::<i64>(&*conn)?;
+            if owned == 0 {
+                return Ok(false);
+            }
+
+            // The old primary address has to go first, as only one address
+            // per user may be primary.
+            diesel::update(own_emails.clone().filter(user_emails::is_primary.eq(true)))
+                .set(user_emails::is_primary.eq(false))
+                .execute(&*conn)?;
+            diesel::update(own_emails.filter(user_emails::email.eq(email)))
+                .set(user_emails::is_primary.eq(true))
+                .execute(&*conn)?;
+            Ok(true)
+        }).unwrap()
+    }
+
+    /// Returns the author information used for commits created by this user
+    /// via the web interface. Returns `None` if the user has no primary email
+    /// address, since git requires one.
+    pub fn commit_author(&self, db: &Db) -> Option<Author> {
+        self.primary_email(db).map(|email| {
+            Author {
+                name: self.name().unwrap_or(self.username()).to_string(),
+                email: email.email().to_string(),
+            }
+        })
+    }
+
     pub fn create_session(&mut self, cookies: &Cookies, db: &Db) {
         // Generate a random session id.
         let mut id = [0u8; SESSION_ID_LEN];
@@ -200,8 +290,12 @@ impl<'a, 'r> FromRequest<'a, 'r> for AuthUser {
             })
             // TODO: maybe check age of session
             .map(|(session, user)| {
+                let db = <State<Db> as FromRequest>::from_request(req)
+                    .expect("cannot retrieve DB connection from request");
+                let user = PubUser(user);
                 Outcome::Success(AuthUser {
-                    user: PubUser(user),
+                    unread_notifications: notification::unread_count(&user, &db),
+                    user,
                     session: Some(session),
                 })
             })
@@ -275,19 +369,36 @@ impl PubUser {
         self.0.bio.as_ref().map(AsRef::as_ref)
     }
 
-    pub fn baskets(&self, auth_user: Option<&AuthUser>, db: &Db) -> Vec<Basket> {
-        BasketRecord::belonging_to(&self.0)
-            .load(&*db.conn())
-            .unwrap()
-            .into_iter()
-            .filter(|record| {
-                has_permission(auth_user, UserAction::ViewBasket {
-                    owner: self,
-                    basket: record,
-                })
-            })
-            .map(|record| Basket::from_parts(record, self.clone()))
-            .collect()
+    /// Returns a page of the baskets of this user visible to `auth_user`.
+    pub fn baskets(
+        &self,
+        auth_user: Option<&AuthUser>,
+        listing: &BasketListing,
+        db: &Db,
+    ) -> BasketPage {
+        let query = Visibility::of(auth_user.map(|u| &**u), db)
+            .visible_baskets()
+            .filter(baskets::user_id.eq(self.id()));
+        listing.load(query, db)
+    }
+
+    /// Returns the baskets of other users this user collaborates on, as far
+    /// as they are visible to `auth_user`.
+    pub fn shared_baskets(&self, auth_user: Option<&AuthUser>, db: &Db) -> Vec<Basket> {
+        let ids = collaborator::basket_ids_of(self, db);
+        if ids.is_empty() {
+            return vec![];
+        }
+
+        let records = Visibility::of(auth_user.map(|u| &**u), db)
+            .visible_baskets()
+            .filter(baskets::id.eq_any(ids))
+            .load::<BasketRecord>(&*db.conn())
+            .unwrap();
+
+        let mut baskets = Basket::with_owners(records, db);
+        baskets.sort_by(|a, b| (a.owner(), a.name()).cmp(&(b.owner(), b.name())));
+        baskets
     }
 }
 
@@ -306,7 +417,7 @@ impl Serialize for PubUser {
     }
 }
 
-fn is_valid_username(username: &str) -> bool {
+pub fn is_valid_username(username: &str) -> bool {
     use std::ascii::AsciiExt;
 
     username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
@@ -8,4 +8,15 @@ use db::schema::user_emails;
 pub struct UserEmail {
     email: String,
     user_id: i64,
+    is_primary: bool,
+}
+
+impl UserEmail {
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.is_primary
+    }
 }
@@ -0,0 +1,105 @@
+//! Watching baskets: how closely users want to follow what happens in them.
+
+use diesel::prelude::*;
+use diesel;
+
+use db::Db;
+use db::schema::watches;
+use model::{BasketRecord, PubUser};
+
+
+/// How closely a user follows a basket.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum WatchLevel {
+    /// Only be notified about events concerning the user's role in the
+    /// basket. This is the default for owners and collaborators.
+    #[serde(rename = "participating")]
+    Participating,
+    /// Be notified about everything happening in the basket.
+    #[serde(rename = "all")]
+    All,
+    /// Never be notified about the basket.
+    #[serde(rename = "ignore")]
+    Ignore,
+}
+
+impl WatchLevel {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "participating" => Some(WatchLevel::Participating),
+            "all" => Some(WatchLevel::All),
+            "ignore" => Some(WatchLevel::Ignore),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            WatchLevel::Participating => "participating",
+            WatchLevel::All => "all",
+            WatchLevel::Ignore => "ignore",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Insertable, Queryable)]
+#[table_name = "watches"]
+struct WatchRecord {
+    user_id: i64,
+    basket_id: i64,
+    level: String,
+}
+
+/// Returns the level the user chose for the basket, if any.
+pub fn level_of(basket: &BasketRecord, user: &PubUser, db: &Db) -> Option<WatchLevel> {
+    watches::table
+        .find((user.id(), basket.id()))
+        .select(watches::level)
+        .first::<String>(&*db.conn())
+        .optional()
+        .unwrap()
+        .and_then(|level| WatchLevel::from_str(&level))
+}
+
+/// Sets the level of the user for the basket. `None` removes the choice, so
+/// that the default applies again.
+pub fn set_level(basket: &BasketRecord, user: &PubUser, level: Option<WatchLevel>, db: &Db) {
+    let conn = db.conn();
+    conn.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::delete(watches::table.find((user.id(), basket.id())))
+            .execute(&*conn)?;
+        if let Some(level) = level {
+            let record = WatchRecord {
+                user_id: user.id(),
+                basket_id: basket.id(),
+                level: level.as_str().into(),
+            };
+            diesel::insert(&record)
+                .into(watches::table)
+                .execute(&*conn)?;
+        }
+        Ok(())
+    }).unwrap();
+}
+
+/// Returns the ids of all users who chose a level for the basket, together
+/// with the level.
+pub fn watchers(basket: &BasketRecord, db: &Db) -> QueryResult<Vec<(i64, WatchLevel)>> {
+    Ok(watches::table
+        .filter(watches::basket_id.eq(basket.id()))
+        .load::<WatchRecord>(&*db.conn())?
+        .into_iter()
+        .filter_map(|w| WatchLevel::from_str(&w.level).map(|level| (w.user_id, level)))
+        .collect())
+}
+
+/// Returns the ids of the baskets the user watches, i.e. chose a level other
+/// than `Ignore` for.
+pub fn watched_basket_ids(user: &PubUser, db: &Db) -> Vec<i64> {
+    watches::table
+        .filter(watches::user_id.eq(user.id()))
+        .filter(watches::level.ne(WatchLevel::Ignore.as_str()))
+        .select(watches::basket_id)
+        .load::<i64>(&*db.conn())
+        .unwrap()
+}
@@ -0,0 +1,780 @@
+//! Webhooks: URLs which are sent a request whenever certain events happen in
+//! a basket, e.g. to trigger a CI build or post to a chat.
+//!
+//! Every event creates a delivery for each webhook of the basket which is
+//! triggered by its kind. A background thread sends the pending deliveries
+//! and retries failed ones with increasing delays, so that recording an
+//! event never waits for a slow receiver.
+//!
+//! Each request is a `POST` with a JSON body and these headers:
+//!
+//! - `X-BasGit-Event`: the kind of the event, or `ping` for test deliveries.
+//! - `X-BasGit-Delivery`: the id of the delivery, which stays the same for
+//!   all attempts.
+//! - `X-BasGit-Signature`: `sha256=` followed by the hex encoded
+//!   HMAC-SHA256 of the body, keyed with the secret of the webhook.
+//!
+//! Webhooks can't send requests to the server itself or its local network,
+//! i.e. to loopback, private, link-local and similar addresses, so that
+//! users can't reach services which aren't public through them. The address
+//! is checked when the webhook is added and again right before connecting.
+//! Setting `WEBHOOKS_ALLOW_LOCAL=1` lifts the restriction, e.g. to try out
+//! webhooks with a local HTTP server at `http://localhost:9000/`.
+
+use chrono::{DateTime, Duration};
+use chrono::offset::utc::UTC;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha2::Sha256;
+use diesel::prelude::*;
+use diesel;
+use hex;
+use hyper::{self, Client, Url};
+use hyper::client::RedirectPolicy;
+use hyper::header::{ContentType, Headers, UserAgent};
+use hyper::net::{HttpStream, HttpsConnector, NetworkConnector};
+use hyper_native_tls::NativeTlsClient;
+use rand::Rng;
+use rand::os::OsRng;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use db::Db;
+use db::schema::{webhook_deliveries, webhooks};
+use mail;
+use model::{self, Basket, BasketRecord};
+use model::event::{Event, EventKind, EventRecord};
+
+
+/// How often the background thread looks for due deliveries.
+const DELIVERY_INTERVAL_SECS: u64 = 5;
+
+/// How long to wait for the receiver to accept the request and to respond.
+const TIMEOUT_SECS: u64 = 10;
+
+/// Number of attempts after which a delivery is given up.
+const MAX_ATTEMPTS: i32 = 6;
+
+/// Only the beginning of longer responses is kept in the delivery log.
+const MAX_RESPONSE_LEN: u64 = 16 * 1024;
+
+/// Number of deliveries shown in the delivery log.
+const LOG_SIZE: i64 = 50;
+
+/// Number of characters of generated secrets.
+const SECRET_LEN: usize = 32;
+
+#[derive(Clone, Debug, Queryable)]
+pub struct Webhook {
+    id: i64,
+    basket_id: i64,
+    url: String,
+    secret: String,
+    kinds: Vec<String>,
+    created_at: DateTime<UTC>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "webhooks"]
+struct NewWebhook {
+    basket_id: i64,
+    url: String,
+    secret: String,
+    kinds: Vec<String>,
+}
+
+impl Webhook {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The key of the request signatures. Only administrators of the basket
+    /// may see it.
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    /// The event kinds which trigger this webhook.
+    pub fn kinds(&self) -> Vec<EventKind> {
+        self.kinds.iter().filter_map(|k| EventKind::from_str(k)).collect()
+    }
+
+    fn is_triggered_by(&self, kind: EventKind) -> bool {
+        self.kinds.iter().any(|k| k == kind.as_str())
+    }
+
+    /// Adds a webhook to the basket. If the secret is empty, a random one is
+    /// generated. The caller has to check the permissions.
+    pub fn create(
+        basket: &BasketRecord,
+        url: &str,
+        secret: &str,
+        kinds: &[EventKind],
+        db: &Db,
+    ) -> Result<Self, WebhookError> {
+        let url = url.trim();
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return Err(WebhookError::UrlInvalid),
+        };
+        if parsed.scheme() != "http" && parsed.scheme() != "https" {
+            return Err(WebhookError::UrlInvalid);
+        }
+        if !allow_local() && is_local_url(&parsed) {
+            return Err(WebhookError::UrlLocal);
+        }
+        if kinds.is_empty() {
+            return Err(WebhookError::NoKinds);
+        }
+
+        let secret = match secret.trim() {
+            "" => {
+                let mut rng = OsRng::new().expect("could not use system rng");
+                rng.gen_ascii_chars().take(SECRET_LEN).collect()
+            }
+            s => s.to_string(),
+        };
+        let new_webhook = NewWebhook {
+            basket_id: basket.id(),
+            url: url.into(),
+            secret,
+            kinds: kinds.iter().map(|k| k.as_str().to_string()).collect(),
+        };
+
+        let webhook = diesel::insert(&new_webhook)
+            .into(webhooks::table)
+            .get_result(&*db.conn())
+            .unwrap();
+        Ok(webhook)
+    }
+
+    /// Returns all webhooks of the basket, oldest first.
+    pub fn list(basket: &BasketRecord, db: &Db) -> Vec<Self> {
+        webhooks::table
+            .filter(webhooks::basket_id.eq(basket.id()))
+            .order(webhooks::id)
+            .load(&*db.conn())
+            .unwrap()
+    }
+
+    /// Loads the webhook with the given id, if it belongs to the basket.
+    pub fn load(id: i64, basket: &BasketRecord, db: &Db) -> Option<Self> {
+        webhooks::table
+            .find(id)
+            .filter(webhooks::basket_id.eq(basket.id()))
+            .first(&*db.conn())
+            .optional()
+            .unwrap()
+    }
+
+    /// Removes the webhook together with its delivery log.
+    pub fn delete(self, db: &Db) {
+        diesel::delete(webhooks::table.find(self.id))
+            .execute(&*db.conn())
+            .unwrap();
+    }
+
+    /// Queues a test delivery, which isn't about any event.
+    pub fn ping(&self, basket: &Basket, db: &Db) {
+        let body = json!({
+            "event": "ping",
+            "basket": basket_payload(basket),
+            "webhook": {
+                "id": self.id,
+                "url": self.url,
+                "events": self.kinds,
+            },
+        });
+        queue(&[self.id], None, "ping", &body.to_string(), db).unwrap();
+    }
+
+    /// Returns the most recent deliveries of this webhook, newest first.
+    pub fn deliveries(&self, db: &Db) -> Vec<Delivery> {
+        webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(self.id))
+            .order(webhook_deliveries::id.desc())
+            .limit(LOG_SIZE)
+            .load(&*db.conn())
+            .unwrap()
+    }
+
+    /// Queues a new delivery with the same body as an earlier one. Returns
+    /// whether the earlier delivery exists.
+    pub fn redeliver(&self, delivery_id: i64, db: &Db) -> bool {
+        let delivery = webhook_deliveries::table
+            .find(delivery_id)
+            .filter(webhook_deliveries::webhook_id.eq(self.id))
+            .first::<Delivery>(&*db.conn())
+            .optional()
+            .unwrap();
+
+        match delivery {
+            Some(d) => {
+                queue(&[self.id], d.event_id, &d.kind, &d.request_body, db).unwrap();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Serialize for Webhook {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        use serde::ser::SerializeStruct;
+
+        // The secret is left out on purpose, so that it's only shown where
+        // it's explicitly asked for.
+        let mut s = serializer.serialize_struct("Webhook", 4)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("url", &self.url)?;
+        s.serialize_field("kinds", &self.kinds)?;
+        s.serialize_field("created_at", &self.created_at.to_rfc3339())?;
+        s.end()
+    }
+}
+
+/// One request of a webhook, including its retries.
+#[derive(Clone, Debug, Queryable, Serialize)]
+pub struct Delivery {
+    id: i64,
+    webhook_id: i64,
+    event_id: Option<i64>,
+    kind: String,
+    request_body: String,
+    /// One of `pending`, `succeeded` and `failed`.
+    status: String,
+    attempts: i32,
+    next_attempt_at: Option<DateTime<UTC>>,
+    response_status: Option<i32>,
+    response_body: Option<String>,
+    error: Option<String>,
+    created_at: DateTime<UTC>,
+}
+
+#[derive(Clone, Debug, Insertable)]
+#[table_name = "webhook_deliveries"]
+struct NewDelivery {
+    webhook_id: i64,
+    event_id: Option<i64>,
+    kind: String,
+    request_body: String,
+}
+
+fn queue(
+    webhook_ids: &[i64],
+    event_id: Option<i64>,
+    kind: &str,
+    body: &str,
+    db: &Db,
+) -> QueryResult<()> {
+    let new_deliveries = webhook_ids.iter()
+        .map(|&webhook_id| NewDelivery {
+            webhook_id,
+            event_id,
+            kind: kind.into(),
+            request_body: body.into(),
+        })
+        .collect::<Vec<_>>();
+    if !new_deliveries.is_empty() {
+        diesel::insert(&new_deliveries)
+            .into(webhook_deliveries::table)
+            .execute(&*db.conn())?;
+    }
+    Ok(())
+}
+
+fn basket_payload(basket: &Basket) -> Value {
+    json!({
+        "owner": basket.owner(),
+        "name": basket.name(),
+        "url": format!("{}{}", mail::base_url(), basket.url()),
+    })
+}
+
+/// Queues a delivery of a new event for every webhook of its basket which
+/// is triggered by the event's kind.
+pub fn enqueue(event: &EventRecord, db: &Db) -> QueryResult<()> {
+    let ids = webhooks::table
+        .filter(webhooks::basket_id.eq(event.basket_id()))
+        .load::<Webhook>(&*db.conn())?
+        .into_iter()
+        .filter(|w| w.is_triggered_by(event.kind()))
+        .map(|w| w.id)
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    let event = match Event::with_details(vec![event.clone()], db).pop() {
+        Some(event) => event,
+        None => return Ok(()),
+    };
+    let body = json!({
+        "event": event.record().kind().as_str(),
+        "basket": basket_payload(event.basket()),
+        "actor": event.actor().map(|a| a.username()),
+        "details": event.record().details(),
+        "created_at": event.record().created_at().to_rfc3339(),
+    });
+    let kind = event.record().kind().as_str();
+    queue(&ids, Some(event.record().id()), kind, &body.to_string(), db)
+}
+
+/// Whether webhooks may send requests to local addresses, see the module
+/// documentation.
+fn allow_local() -> bool {
+    env::var("WEBHOOKS_ALLOW_LOCAL").map(|v| v == "1" || v == "true").unwrap_or(false)
+}
+
+/// Whether the address belongs to the server itself or a local network:
+/// loopback, private, link-local, shared (carrier-grade NAT), unspecified,
+/// broadcast and multicast addresses, including IPv4 addresses embedded in
+/// IPv6 ones.
+fn is_local_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || octets[0] == 0
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // Unique local (fc00::/7) and link-local (fe80::/10)
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80
+                || ip.to_ipv4().map(|v4| is_local_address(IpAddr::V4(v4))).unwrap_or(false)
+        }
+    }
+}
+
+/// Whether the host of the URL is local or resolves to a local address.
+/// Hosts which can't be resolved (yet) are fine here, sending to them fails
+/// anyway.
+fn is_local_url(url: &Url) -> bool {
+    let host = match url.host_str() {
+        Some(host) => host.trim_matches(|c| c == '[' || c == ']').to_lowercase(),
+        None => return true,
+    };
+    if host == "localhost" || host.ends_with(".localhost") {
+        return true;
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    (&host[..], port).to_socket_addrs()
+        .map(|mut addrs| addrs.any(|addr| is_local_address(addr.ip())))
+        .unwrap_or(false)
+}
+
+/// Connects like hyper's `HttpConnector`, but refuses local addresses unless
+/// they are allowed. The check happens right before connecting to the
+/// checked address, so changing the DNS record of the host after adding the
+/// webhook doesn't get around it.
+struct CheckedConnector {
+    allow_local: bool,
+}
+
+impl NetworkConnector for CheckedConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, _scheme: &str) -> hyper::Result<HttpStream> {
+        let host = host.trim_matches(|c| c == '[' || c == ']');
+        let addrs = (host, port).to_socket_addrs()?.collect::<Vec<_>>();
+        if !self.allow_local {
+            if let Some(addr) = addrs.iter().find(|addr| is_local_address(addr.ip())) {
+                let msg = format!("{} resolves to the local address {}", host, addr.ip());
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, msg).into());
+            }
+        }
+
+        let mut last_error = None;
+        for addr in addrs {
+            match connect_timeout(addr) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        let error = last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", host))
+        });
+        Err(error.into())
+    }
+}
+
+/// Connects to the address, giving up after `TIMEOUT_SECS`, so that an
+/// unreachable host doesn't hold up the other deliveries. The connection is
+/// opened on a helper thread since there's no `TcpStream::connect_timeout`
+/// yet. If it takes too long, the helper is left to finish on its own.
+fn connect_timeout(addr: SocketAddr) -> io::Result<TcpStream> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(TcpStream::connect(addr));
+    });
+    match rx.recv_timeout(time::Duration::from_secs(TIMEOUT_SECS)) {
+        Ok(result) => result,
+        Err(_) => {
+            let msg = format!("connecting to {} timed out", addr);
+            Err(io::Error::new(io::ErrorKind::TimedOut, msg))
+        }
+    }
+}
+
+/// Returns the signature of the body with the given secret, as sent in the
+/// `X-BasGit-Signature` header (without the `sha256=` prefix).
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::new(Sha256::new(), secret.as_bytes());
+    mac.input(body.as_bytes());
+    hex::encode(mac.result().code())
+}
+
+/// The columns of a due delivery needed to send it.
+#[derive(Clone, Debug, Queryable)]
+struct DueDelivery {
+    id: i64,
+    webhook_id: i64,
+    kind: String,
+    request_body: String,
+    attempts: i32,
+}
+
+/// The delay before the next attempt after the given number of failed
+/// attempts: one minute, then four times as long each time.
+fn backoff(attempts: i32) -> Duration {
+    Duration::minutes(1 << (2 * (attempts - 1)))
+}
+
+/// Sends the request of a delivery. Returns the status code and the
+/// (possibly shortened) body of the response.
+fn send(
+    client: &Client,
+    webhook: &Webhook,
+    delivery: &DueDelivery,
+) -> hyper::Result<(u16, String)> {
+    let signature = sign(&webhook.secret, &delivery.request_body);
+    let mut headers = Headers::new();
+    headers.set(ContentType::json());
+    headers.set(UserAgent("BasGit-Webhook".into()));
+    headers.set_raw("X-BasGit-Event", vec![delivery.kind.clone().into_bytes()]);
+    headers.set_raw("X-BasGit-Delivery", vec![delivery.id.to_string().into_bytes()]);
+    headers.set_raw("X-BasGit-Signature", vec![format!("sha256={}", signature).into_bytes()]);
+
+    let response = client.post(&webhook.url)
+        .headers(headers)
+        .body(&delivery.request_body[..])
+        .send()?;
+    let status = response.status.to_u16();
+
+    let mut body = vec![];
+    response.take(MAX_RESPONSE_LEN).read_to_end(&mut body)?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Sends all deliveries which are due and records the outcome. Failed
+/// deliveries are retried later until `MAX_ATTEMPTS` is reached.
+pub fn deliver_due(client: &Client, db: &Db) {
+    let due = webhook_deliveries::table
+        .filter(webhook_deliveries::status.eq("pending"))
+        .filter(webhook_deliveries::next_attempt_at.le(UTC::now()))
+        .order(webhook_deliveries::id)
+        .select((
+            webhook_deliveries::id,
+            webhook_deliveries::webhook_id,
+            webhook_deliveries::kind,
+            webhook_deliveries::request_body,
+            webhook_deliveries::attempts,
+        ))
+        .load::<DueDelivery>(&*db.conn())
+        .unwrap();
+    if due.is_empty() {
+        return;
+    }
+
+    let webhook_ids = due.iter().map(|d| d.webhook_id).collect::<Vec<_>>();
+    let hooks = webhooks::table
+        .filter(webhooks::id.eq_any(webhook_ids))
+        .load::<Webhook>(&*db.conn())
+        .unwrap();
+
+    for delivery in due {
+        // Deliveries of deleted webhooks are deleted along with them.
+        let webhook = match hooks.iter().find(|w| w.id == delivery.webhook_id) {
+            Some(webhook) => webhook,
+            None => continue,
+        };
+
+        let attempts = delivery.attempts + 1;
+        let (response_status, response_body, error) = match send(client, webhook, &delivery) {
+            Ok((status, body)) => (Some(status as i32), Some(body), None),
+            Err(e) => (None, None, Some(e.to_string())),
+        };
+        let succeeded = response_status.map(|s| s >= 200 && s < 300).unwrap_or(false);
+        let (status, next_attempt_at) = if succeeded {
+            ("succeeded", None)
+        } else if attempts >= MAX_ATTEMPTS {
+            ("failed", None)
+        } else {
+            ("pending", Some(UTC::now() + backoff(attempts)))
+        };
+
+        diesel::update(webhook_deliveries::table.find(delivery.id))
+            .set((
+                webhook_deliveries::status.eq(status),
+                webhook_deliveries::attempts.eq(attempts),
+                webhook_deliveries::next_attempt_at.eq(next_attempt_at),
+                webhook_deliveries::response_status.eq(response_status),
+                webhook_deliveries::response_body.eq(response_body),
+                webhook_deliveries::error.eq(error),
+            ))
+            .execute(&*db.conn())
+            .unwrap();
+    }
+}
+
+/// Starts a thread which regularly sends the due deliveries.
+pub fn spawn_delivery_thread(db: Db) {
+    let tls = NativeTlsClient::new().expect("failed to initialize TLS");
+    let connector = CheckedConnector { allow_local: allow_local() };
+    let mut client = Client::with_connector(HttpsConnector::with_connector(tls, connector));
+    client.set_read_timeout(Some(time::Duration::from_secs(TIMEOUT_SECS)));
+    client.set_write_timeout(Some(time::Duration::from_secs(TIMEOUT_SECS)));
+    // A redirect would send the signed payload somewhere else.
+    client.set_redirect_policy(RedirectPolicy::FollowNone);
+
+    let interval = time::Duration::from_secs(DELIVERY_INTERVAL_SECS);
+    model::spawn_periodic("webhooks", interval, move || deliver_due(&client, &db));
+}
+
+#[derive(Debug, Clone)]
+pub enum WebhookError {
+    UrlInvalid,
+    /// The URL points to the server itself or its local network.
+    UrlLocal,
+    /// A webhook has to be triggered by at least one event kind.
+    NoKinds,
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::WebhookError::*;
+
+        match *self {
+            UrlInvalid => {
+                "The URL has to be a valid 'http://' or 'https://' URL.".fmt(f)
+            }
+            UrlLocal => {
+                "Webhooks can't send requests to local or private network addresses.".fmt(f)
+            }
+            NoKinds => {
+                "Please choose at least one event which triggers the webhook.".fmt(f)
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use chrono::offset::utc::UTC;
+    use diesel::connection::SimpleConnection;
+    use diesel::prelude::*;
+    use diesel;
+    use hyper::{Client, Url};
+    use hyper::net::NetworkConnector;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{IpAddr, TcpListener};
+    use std::thread;
+    use std::time;
+
+    use db::Db;
+    use db::schema::webhook_deliveries;
+    use super::{backoff, deliver_due, is_local_address, is_local_url, queue, sign};
+    use super::{CheckedConnector, Delivery, TIMEOUT_SECS};
+
+    #[test]
+    fn signature() {
+        // Test case 2 of RFC 4231.
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+        );
+        assert_ne!(sign("a", "body"), sign("b", "body"));
+    }
+
+    #[test]
+    fn backoff_grows() {
+        assert_eq!(backoff(1), Duration::minutes(1));
+        assert_eq!(backoff(2), Duration::minutes(4));
+        assert_eq!(backoff(3), Duration::minutes(16));
+        assert_eq!(backoff(5), Duration::minutes(256));
+    }
+
+    fn is_local(ip: &str) -> bool {
+        is_local_address(ip.parse::<IpAddr>().unwrap())
+    }
+
+    #[test]
+    fn local_addresses() {
+        for ip in &[
+            "127.0.0.1", "127.1.2.3", "10.0.0.1", "172.16.0.1", "172.31.255.255",
+            "192.168.1.1", "169.254.169.254", "100.64.0.1", "0.0.0.0", "0.1.2.3",
+            "255.255.255.255", "224.0.0.1", "::1", "::", "fc00::1", "fd12::1", "fe80::1",
+            "ff02::1", "::ffff:127.0.0.1", "::ffff:10.1.1.1",
+        ] {
+            assert!(is_local(ip), "{} should be local", ip);
+        }
+        for ip in &[
+            "8.8.8.8", "172.32.0.1", "100.128.0.1", "192.169.0.1", "2001:4860::8888",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(!is_local(ip), "{} shouldn't be local", ip);
+        }
+    }
+
+    #[test]
+    fn local_urls() {
+        for url in &[
+            "http://localhost/", "http://LOCALHOST:9000/hook", "http://app.localhost/",
+            "http://127.0.0.1/", "https://10.0.0.5:8443/", "http://[::1]/",
+            "http://169.254.169.254/latest/meta-data/",
+        ] {
+            assert!(is_local_url(&Url::parse(url).unwrap()), "{} should be local", url);
+        }
+        assert!(!is_local_url(&Url::parse("https://93.184.216.34/hook").unwrap()));
+    }
+
+    #[test]
+    fn connector_refuses_local_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let refused = CheckedConnector { allow_local: false }.connect("127.0.0.1", port, "http");
+        assert!(refused.is_err());
+        let allowed = CheckedConnector { allow_local: true }.connect("127.0.0.1", port, "http");
+        assert!(allowed.is_ok());
+    }
+
+    /// Answers one request after the other with the given statuses and
+    /// returns the head and the body of each request.
+    fn serve(
+        listener: TcpListener,
+        statuses: Vec<&'static str>,
+    ) -> thread::JoinHandle<Vec<(String, String)>> {
+        thread::spawn(move || {
+            statuses.into_iter()
+                .map(|status| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut head = String::new();
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        head.push_str(&line.to_lowercase());
+                    }
+
+                    let len = head.lines()
+                        .find(|l| l.starts_with("content-length:"))
+                        .and_then(|l| l["content-length:".len()..].trim().parse().ok())
+                        .unwrap_or(0);
+                    let mut body = vec![0; len];
+                    reader.read_exact(&mut body).unwrap();
+
+                    write!(
+                        stream,
+                        "HTTP/1.1 {}\r\nContent-Length: 6\r\nConnection: close\r\n\r\nthanks",
+                        status,
+                    ).unwrap();
+                    (head, String::from_utf8(body).unwrap())
+                })
+                .collect()
+        })
+    }
+
+    fn delivery(db: &Db) -> Delivery {
+        webhook_deliveries::table
+            .filter(webhook_deliveries::webhook_id.eq(900_301))
+            .first::<Delivery>(&*db.conn())
+            .unwrap()
+    }
+
+    #[test]
+    fn delivers_to_local_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve(listener, vec!["500 Internal Server Error", "200 OK"]);
+
+        let db = Db::open_test_connection();
+        db.conn().batch_execute(&format!("
+            insert into users (id, username) values (900001, 'test-owner');
+            insert into baskets (id, name, user_id, kind, visibility) values
+                (900101, 'hooked', 900001, 'generic', 'private');
+            insert into webhooks (id, basket_id, url, secret, kinds) values
+                (900301, 900101, '{}', 'test-secret', '{{pushed}}');
+        ", url)).unwrap();
+        let body = r#"{"event":"ping"}"#;
+        queue(&[900_301], None, "ping", body, &db).unwrap();
+
+        let mut client = Client::with_connector(CheckedConnector { allow_local: true });
+        client.set_read_timeout(Some(time::Duration::from_secs(TIMEOUT_SECS)));
+
+        // The receiver fails, so the delivery is retried later.
+        deliver_due(&client, &db);
+        let failed = delivery(&db);
+        assert_eq!(failed.status, "pending");
+        assert_eq!(failed.attempts, 1);
+        assert_eq!(failed.response_status, Some(500));
+        assert_eq!(failed.response_body, Some("thanks".to_string()));
+        let retry_in = failed.next_attempt_at.unwrap() - UTC::now();
+        assert!(retry_in > Duration::seconds(50) && retry_in <= backoff(1));
+
+        // It's not due yet.
+        deliver_due(&client, &db);
+        assert_eq!(delivery(&db).attempts, 1);
+
+        diesel::update(webhook_deliveries::table.find(failed.id))
+            .set(webhook_deliveries::next_attempt_at.eq(Some(UTC::now())))
+            .execute(&*db.conn())
+            .unwrap();
+        deliver_due(&client, &db);
+        let succeeded = delivery(&db);
+        assert_eq!(succeeded.status, "succeeded");
+        assert_eq!(succeeded.attempts, 2);
+        assert_eq!(succeeded.response_status, Some(200));
+        assert_eq!(succeeded.next_attempt_at, None);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 2);
+        for &(ref head, ref received) in &requests {
+            assert!(head.starts_with("post /hook http/1.1\r\n"), "{}", head);
+            assert!(head.contains("x-basgit-event: ping\r\n"));
+            assert!(head.contains(&format!("x-basgit-delivery: {}\r\n", failed.id)));
+            let signature = format!("x-basgit-signature: sha256={}\r\n", sign("test-secret", body));
+            assert!(head.contains(&signature));
+            assert_eq!(received, body);
+        }
+    }
+}
@@ -0,0 +1,573 @@
+//! Wiki-style view onto a basket of Markdown pages.
+//!
+//! Every page is stored as `<Page Name>.md` in the root directory of the
+//! basket. Pages can link to each other with `[[Page Name]]`.
+
+use pulldown_cmark::{html, Event, Parser, Tag};
+use rocket::http::uri::URI;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use model::content::{CommitInfo, Content};
+use super::MAX_SL_LEN;
+
+
+/// The page shown when opening the wiki.
+pub const HOME_PAGE: &str = "Home";
+
+/// File extension of wiki pages.
+const PAGE_EXT: &str = ".md";
+
+/// Prefix used internally to mark wiki links while rendering.
+const WIKI_LINK_SCHEME: &str = "wiki:";
+
+
+/// Checks whether the given string can be used as a page name.
+pub fn is_valid_page_name(name: &str) -> bool {
+    !name.trim().is_empty()
+        && name.trim() == name
+        && name.len() < MAX_SL_LEN
+        && !name.starts_with('.')
+        && !name.chars().any(|c| "/\\[]|#".contains(c) || c.is_control())
+}
+
+/// Returns the path of the file storing the given page.
+pub fn page_path(name: &str) -> String {
+    format!("{}{}", name, PAGE_EXT)
+}
+
+/// Returns the URL of the given page in the wiki of the basket with the given
+/// URL.
+pub fn page_url(basket_url: &str, name: &str) -> String {
+    format!("{}/wiki/{}", basket_url, URI::percent_encode(name))
+}
+
+/// A link to a wiki page.
+#[derive(Clone, Debug, Serialize)]
+pub struct PageLink {
+    pub name: String,
+    pub url: String,
+}
+
+/// A rendered wiki page.
+#[derive(Clone, Debug, Serialize)]
+pub struct Page {
+    pub name: String,
+    pub source: String,
+    pub html: String,
+    /// Pages linking to this page.
+    pub backlinks: Vec<PageLink>,
+}
+
+/// The wiki of a single basket.
+pub struct Wiki<'a> {
+    content: &'a Content,
+    basket_url: String,
+}
+
+impl<'a> Wiki<'a> {
+    pub fn new(content: &'a Content, basket_url: &str) -> Self {
+        Self {
+            content,
+            basket_url: basket_url.into(),
+        }
+    }
+
+    /// Returns the names of all pages, sorted alphabetically.
+    pub fn pages(&self) -> Vec<String> {
+        self.content.files()
+            .into_iter()
+            .filter(|f| !f.path.contains('/') && f.path.ends_with(PAGE_EXT))
+            .map(|f| f.path[..f.path.len() - PAGE_EXT.len()].to_string())
+            .filter(|name| is_valid_page_name(name))
+            .collect()
+    }
+
+    /// Returns links to all pages, sorted alphabetically.
+    pub fn page_links(&self) -> Vec<PageLink> {
+        self.pages().into_iter().map(|name| self.link(name)).collect()
+    }
+
+    fn link(&self, name: String) -> PageLink {
+        PageLink {
+            url: page_url(&self.basket_url, &name),
+            name,
+        }
+    }
+
+    /// Returns whether the given page exists.
+    pub fn exists(&self, name: &str) -> bool {
+        self.content.exists(&page_path(name))
+    }
+
+    /// Returns the Markdown source of the given page.
+    pub fn source(&self, name: &str) -> Option<String> {
+        self.content.read_text(&page_path(name))
+    }
+
+    /// Loads and renders the given page.
+    pub fn page(&self, name: &str) -> Option<Page> {
+        self.source(name).map(|source| {
+            Page {
+                name: name.into(),
+                html: self.render(&source),
+                backlinks: self.backlinks(name),
+                source,
+            }
+        })
+    }
+
+    /// Returns the commits changing the given page, newest first.
+    pub fn history(&self, name: &str, limit: usize) -> Vec<CommitInfo> {
+        self.content.history(Some(&page_path(name)), limit)
+    }
+
+    /// Returns all pages linking to the given page.
+    pub fn backlinks(&self, name: &str) -> Vec<PageLink> {
+        self.pages()
+            .into_iter()
+            .filter(|other| other != name)
+            .filter(|other| {
+                self.source(other)
+                    .map(|src| links_in(&src).contains(name))
+                    .unwrap_or(false)
+            })
+            .map(|other| self.link(other))
+            .collect()
+    }
+
+    /// Renders the given Markdown source to HTML. Wiki links are resolved
+    /// and links to pages that don't exist yet get the class `wiki-missing`.
+    pub fn render(&self, source: &str) -> String {
+        render(source, &self.basket_url, |name| self.exists(name))
+    }
+}
+
+/// How a link which is currently open while rendering was emitted.
+enum OpenLink {
+    /// A wiki link, which was emitted as raw HTML and has to be closed
+    /// manually.
+    Wiki,
+    /// A regular Markdown link.
+    Markdown,
+    /// A link with an unsafe URL, of which only the text is emitted.
+    Dropped,
+}
+
+/// Renders Markdown source of the wiki of the basket with the given URL.
+/// `exists` tells whether a page exists.
+///
+/// Raw HTML in the source is escaped and links and images with URLs which
+/// aren't `is_safe_url` are reduced to their text, since the source is user
+/// provided.
+fn render<F: Fn(&str) -> bool>(source: &str, basket_url: &str, exists: F) -> String {
+    let source = replace_wiki_links(source);
+
+    let mut open_links = Vec::new();
+    let mut open_images = Vec::new();
+
+    let events = Parser::new(&source).filter_map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Some(Event::Text(html)),
+        Event::Start(Tag::Link(url, title)) => {
+            if url.starts_with(WIKI_LINK_SCHEME) {
+                let encoded = url[WIKI_LINK_SCHEME.len()..].as_bytes();
+                let name = URI::percent_decode(encoded)
+                    .map(|name| name.into_owned())
+                    .unwrap_or_default();
+                let class = if exists(&name) { "wiki-link" } else { "wiki-missing" };
+                open_links.push(OpenLink::Wiki);
+                Some(Event::Html(Cow::Owned(format!(
+                    r#"<a class="{}" href="{}">"#,
+                    class,
+                    page_url(basket_url, &name),
+                ))))
+            } else if is_safe_url(&url) {
+                open_links.push(OpenLink::Markdown);
+                Some(Event::Start(Tag::Link(url, title)))
+            } else {
+                open_links.push(OpenLink::Dropped);
+                None
+            }
+        }
+        Event::End(Tag::Link(url, title)) => {
+            match open_links.pop() {
+                Some(OpenLink::Wiki) => Some(Event::Html(Cow::Borrowed("</a>"))),
+                Some(OpenLink::Dropped) => None,
+                _ => Some(Event::End(Tag::Link(url, title))),
+            }
+        }
+        Event::Start(Tag::Image(url, title)) => {
+            let safe = is_safe_url(&url);
+            open_images.push(safe);
+            if safe {
+                Some(Event::Start(Tag::Image(url, title)))
+            } else {
+                None
+            }
+        }
+        Event::End(Tag::Image(url, title)) => {
+            if open_images.pop().unwrap_or(false) {
+                Some(Event::End(Tag::Image(url, title)))
+            } else {
+                None
+            }
+        }
+        other => Some(other),
+    });
+
+    let mut out = String::new();
+    html::push_html(&mut out, events);
+    out
+}
+
+/// Checks whether a URL from user provided Markdown may be used as link
+/// target or image source: relative URLs, fragments and the schemes `http`,
+/// `https` and `mailto` are allowed, everything else (e.g. `javascript:` or
+/// `data:`) isn't.
+fn is_safe_url(url: &str) -> bool {
+    // Browsers ignore leading whitespace and control characters as well as
+    // tabs and newlines anywhere in the URL, so `java\tscript:` is a scheme,
+    // too.
+    let url = url.trim_left_matches(|c: char| c <= ' ')
+        .chars()
+        .filter(|&c| c != '\t' && c != '\n' && c != '\r')
+        .collect::<String>();
+
+    match url.find(|c: char| c == ':' || c == '/' || c == '?' || c == '#') {
+        Some(pos) if url[pos..].starts_with(':') => {
+            let scheme = url[..pos].to_lowercase();
+            scheme == "http" || scheme == "https" || scheme == "mailto"
+        }
+        _ => true,
+    }
+}
+
+/// Returns the names of all pages linked from the given source. `[[...]]`
+/// in code isn't a link.
+pub fn links_in(source: &str) -> BTreeSet<String> {
+    let mut out = BTreeSet::new();
+    for (is_code, part) in split_code(source) {
+        if is_code {
+            continue;
+        }
+
+        let mut rest = part;
+        while let Some(start) = rest.find("[[") {
+            rest = &rest[start + 2..];
+            match rest.find("]]") {
+                Some(end) => {
+                    let name = rest[..end].trim();
+                    if is_valid_page_name(name) {
+                        out.insert(name.to_string());
+                    }
+                    rest = &rest[end + 2..];
+                }
+                None => break,
+            }
+        }
+    }
+    out
+}
+
+/// Replaces all `[[Page Name]]` occurrences outside of code with regular
+/// Markdown links using the internal `wiki:` scheme, which are resolved while
+/// rendering.
+fn replace_wiki_links(source: &str) -> String {
+    let mut out = String::with_capacity(source.len());
+    for (is_code, part) in split_code(source) {
+        if is_code {
+            out.push_str(part);
+            continue;
+        }
+
+        let mut rest = part;
+        while let Some(start) = rest.find("[[") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after.find("]]") {
+                Some(end) if is_valid_page_name(after[..end].trim()) => {
+                    let name = after[..end].trim();
+                    out.push_str(&format!(
+                        "[{}]({}{})",
+                        escape_markdown(name),
+                        WIKI_LINK_SCHEME,
+                        URI::percent_encode(name),
+                    ));
+                    rest = &after[end + 2..];
+                }
+                _ => {
+                    out.push_str("[[");
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+    }
+    out
+}
+
+/// Escapes all ASCII punctuation with backslashes, so that e.g. the `*` in a
+/// page name isn't taken as emphasis in the link text.
+fn escape_markdown(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Splits Markdown source into consecutive parts, each marked with whether
+/// it is code: a fenced or indented code block or a code span.
+///
+/// This follows the CommonMark rules closely enough for finding wiki links,
+/// but e.g. treats indented list continuations after a blank line as code.
+fn split_code(source: &str) -> Vec<(bool, &str)> {
+    let mut parts = Vec::new();
+    // Start of the current run of lines which aren't in a code block.
+    let mut text_start = 0;
+    // Start of the current code block.
+    let mut block_start = 0;
+    // The character and length of the fence of the current fenced block.
+    let mut fence: Option<(char, usize)> = None;
+    let mut in_indented = false;
+    let mut prev_blank = true;
+
+    let mut pos = 0;
+    while pos < source.len() {
+        let end = source[pos..].find('\n').map(|i| pos + i + 1).unwrap_or(source.len());
+        let line = source[pos..end].trim_right_matches(|c: char| c == '\n' || c == '\r');
+        let blank = line.trim().is_empty();
+
+        if let Some((fence_char, fence_len)) = fence {
+            let closes = match fence_of(line) {
+                Some((c, len, info)) => c == fence_char && len >= fence_len && info.is_empty(),
+                None => false,
+            };
+            if closes {
+                parts.push((true, &source[block_start..end]));
+                text_start = end;
+                fence = None;
+            }
+        } else if let Some((c, len, _)) = fence_of(line) {
+            if in_indented {
+                parts.push((true, &source[block_start..pos]));
+            } else {
+                push_text(&mut parts, &source[text_start..pos]);
+            }
+            block_start = pos;
+            fence = Some((c, len));
+            in_indented = false;
+        } else if is_indented(line) && !blank && (prev_blank || in_indented) {
+            if !in_indented {
+                push_text(&mut parts, &source[text_start..pos]);
+                block_start = pos;
+                in_indented = true;
+            }
+        } else if in_indented && !blank {
+            parts.push((true, &source[block_start..pos]));
+            text_start = pos;
+            in_indented = false;
+        }
+
+        prev_blank = blank;
+        pos = end;
+    }
+
+    if fence.is_some() || in_indented {
+        // Unclosed fences run until the end of the document.
+        parts.push((true, &source[block_start..]));
+    } else {
+        push_text(&mut parts, &source[text_start..]);
+    }
+    parts
+}
+
+/// If the line is a code fence, returns the fence character, the length of
+/// the fence and the info string after it.
+fn fence_of(line: &str) -> Option<(char, usize, &str)> {
+    let indent = line.len() - line.trim_left_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+
+    let line = &line[indent..];
+    let c = match line.chars().next() {
+        Some(c) if c == '`' || c == '~' => c,
+        _ => return None,
+    };
+    let len = line.len() - line.trim_left_matches(c).len();
+    let info = line[len..].trim();
+    if len < 3 || (c == '`' && info.contains('`')) {
+        None
+    } else {
+        Some((c, len, info))
+    }
+}
+
+fn is_indented(line: &str) -> bool {
+    line.starts_with("    ") || line.starts_with('\t')
+}
+
+/// Splits text outside of code blocks at its code spans and adds the parts.
+fn push_text<'a>(parts: &mut Vec<(bool, &'a str)>, text: &'a str) {
+    let bytes = text.as_bytes();
+    let backticks_at = |pos: usize| bytes[pos..].iter().take_while(|&&b| b == b'`').count();
+
+    let mut plain_start = 0;
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if bytes[pos] != b'`' {
+            pos += 1;
+            continue;
+        }
+
+        // A code span ends with a run of backticks of the same length.
+        let run = backticks_at(pos);
+        let mut search = pos + run;
+        let mut close = None;
+        while search < bytes.len() {
+            if bytes[search] == b'`' {
+                let len = backticks_at(search);
+                if len == run {
+                    close = Some(search);
+                    break;
+                }
+                search += len;
+            } else {
+                search += 1;
+            }
+        }
+
+        match close {
+            Some(close) => {
+                if plain_start < pos {
+                    parts.push((false, &text[plain_start..pos]));
+                }
+                parts.push((true, &text[pos..close + run]));
+                pos = close + run;
+                plain_start = pos;
+            }
+            None => pos += run,
+        }
+    }
+
+    if plain_start < text.len() {
+        parts.push((false, &text[plain_start..]));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_test(source: &str) -> String {
+        render(source, "/alice/notes", |name| name == "Home")
+    }
+
+    #[test]
+    fn safe_urls() {
+        assert!(is_safe_url("http://example.com"));
+        assert!(is_safe_url("HTTPS://example.com"));
+        assert!(is_safe_url("mailto:alice@example.com"));
+        assert!(is_safe_url("other page"));
+        assert!(is_safe_url("../files/a.txt"));
+        assert!(is_safe_url("/alice/notes"));
+        assert!(is_safe_url("#section"));
+        assert!(is_safe_url("?q=a:b"));
+        assert!(is_safe_url("dir/a:b"));
+
+        assert!(!is_safe_url("javascript:alert(1)"));
+        assert!(!is_safe_url("JavaScript:alert(1)"));
+        assert!(!is_safe_url(" javascript:alert(1)"));
+        assert!(!is_safe_url("java\tscript:alert(1)"));
+        assert!(!is_safe_url("java\nscript:alert(1)"));
+        assert!(!is_safe_url("data:text/html,<script>alert(1)</script>"));
+        assert!(!is_safe_url("vbscript:msgbox(1)"));
+    }
+
+    #[test]
+    fn unsafe_links_are_rendered_as_text() {
+        let html = render_test("[click *me*](javascript:alert(1))");
+        assert!(!html.contains("<a"));
+        assert!(!html.contains("javascript"));
+        assert!(html.contains("click <em>me</em>"));
+
+        let html = render_test("[ok](https://example.com) [bad](data:text/html,x)");
+        assert!(html.contains(r#"<a href="https://example.com">ok</a>"#));
+        assert!(!html.contains("data:"));
+        assert!(html.contains("bad"));
+    }
+
+    #[test]
+    fn unsafe_images_are_dropped() {
+        let html = render_test("![alt](javascript:alert(1))");
+        assert!(!html.contains("<img"));
+        assert!(!html.contains("javascript"));
+
+        let html = render_test("![alt](/logo.png)");
+        assert!(html.contains(r#"<img src="/logo.png""#));
+    }
+
+    #[test]
+    fn wiki_links_are_resolved() {
+        let html = render_test("[[Home]] and [[New Page]]");
+        assert!(html.contains(r#"<a class="wiki-link" href="/alice/notes/wiki/Home">Home</a>"#));
+        assert!(html.contains(
+            r#"<a class="wiki-missing" href="/alice/notes/wiki/New%20Page">New Page</a>"#
+        ));
+    }
+
+    #[test]
+    fn wiki_link_names_are_escaped() {
+        assert!(replace_wiki_links("[[a*b*c]]").starts_with(r"[a\*b\*c](wiki:"));
+
+        let html = render_test("[[a*b*c]] [[_d_]] [[<b>e]] [[f &amp; g]]");
+        assert!(!html.contains("<em>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains(">a*b*c</a>"));
+        assert!(html.contains(">_d_</a>"));
+        assert!(html.contains(">&lt;b&gt;e</a>"));
+        assert!(html.contains(">f &amp;amp; g</a>"));
+    }
+
+    #[test]
+    fn raw_html_is_escaped() {
+        let html = render_test("<script>alert(1)</script>");
+        assert!(!html.contains("<script>"));
+    }
+
+    #[test]
+    fn wiki_links_in_code_are_kept() {
+        let source = "`[[A]]` [[B]]\n\n```\n[[C]]\n```\n\n    [[D]]\n\n~~~~ text\n[[E]]\n~~~~\n";
+        assert_eq!(replace_wiki_links(source), source.replace("[[B]]", "[B](wiki:B)"));
+
+        let links = links_in(source);
+        assert_eq!(links.into_iter().collect::<Vec<_>>(), vec!["B".to_string()]);
+    }
+
+    #[test]
+    fn code_spans() {
+        assert_eq!(
+            split_code("a ``b ` [[c]]`` d `e"),
+            vec![(false, "a "), (true, "``b ` [[c]]``"), (false, " d `e")],
+        );
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            split_code("a\n```rust\nb\n```\nc\n"),
+            vec![(false, "a\n"), (true, "```rust\nb\n```\n"), (false, "c\n")],
+        );
+
+        // Indented lines continuing a paragraph aren't code.
+        assert_eq!(split_code("a\n    [[b]]\n"), vec![(false, "a\n    [[b]]\n")]);
+
+        // Unclosed fences run until the end.
+        assert_eq!(split_code("a\n```\n[[b]]\n"), vec![(false, "a\n"), (true, "```\n[[b]]\n")]);
+    }
+}
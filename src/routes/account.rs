@@ -0,0 +1,65 @@
+//! Settings of the current user which don't belong to any basket.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FlashMessage};
+use rocket::State;
+
+use context::Context;
+use db::Db;
+use mail;
+use model::AuthUser;
+use model::feed;
+
+
+/// Shows the settings of the current user.
+#[get("/settings")]
+pub fn index(auth_user: AuthUser, flash: Option<FlashMessage>, db: State<Db>) -> Template {
+    let feed_url = feed::token_of(&auth_user, &db).map(|token| {
+        format!("{}/{}.atom?token={}", mail::base_url(), auth_user.username(), token)
+    });
+    let context = Context {
+        content: Some(json!({
+            "emails": auth_user.emails(&db),
+            "feed_url": feed_url,
+        })),
+        flash: flash.map(|f| f.into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("account", &context)
+}
+
+#[derive(FromForm)]
+pub struct PrimaryEmailForm {
+    email: String,
+}
+
+/// Changes the primary email address, which is used for commits and
+/// notifications.
+#[post("/settings/primary-email", data = "<form>")]
+pub fn primary_email(
+    auth_user: AuthUser,
+    form: Form<PrimaryEmailForm>,
+    db: State<Db>,
+) -> Flash<Redirect> {
+    let email = &form.get().email;
+    if auth_user.set_primary_email(email, &db) {
+        let msg = format!("'{}' is your primary email address now.", email);
+        Flash::success(Redirect::to("/settings"), msg)
+    } else {
+        Flash::error(Redirect::to("/settings"), "This email address doesn't belong to you.")
+    }
+}
+
+/// Creates the feed token of the current user, or replaces it with a new one
+/// e.g. after a feed URL containing it leaked.
+#[post("/settings/feed-token")]
+pub fn feed_token(auth_user: AuthUser, db: State<Db>) -> Flash<Redirect> {
+    let msg = if feed::token_of(&auth_user, &db).is_some() {
+        "Your feed token was reset. Feed URLs with the old token don't work anymore."
+    } else {
+        "Your feed token was created."
+    };
+    feed::reset_token(&auth_user, &db);
+    Flash::success(Redirect::to("/settings"), msg)
+}
@@ -0,0 +1,488 @@
+//! The JSON API, mounted at `/api/v1`.
+//!
+//! The endpoints mirror the web routes, but return JSON instead of rendered
+//! templates. Users authenticate with the same session cookie as in the web
+//! interface; it can be obtained via `POST /api/v1/session`.
+//!
+//! Errors are returned as `{ "error": { "code": ..., "message": ... } }`
+//! with a matching HTTP status. The code is a stable identifier, the message
+//! is meant for humans.
+
+use rocket::http::{Cookies, Status};
+use rocket::response::{self, status, Responder};
+use rocket::{Route, State};
+use rocket_contrib::JSON;
+use serde_json::Value;
+use std::fmt::Display;
+use std::path::PathBuf;
+
+use db::Db;
+use model::{AuthUser, Basket, BasketVisibility, LoginError, PubUser};
+use model::basket::{CreateError, DeleteError, SettingsError};
+use model::bookmark::Bookmarks;
+use model::content::Content;
+use model::issue::{Issue, IssueFilter};
+use model::kind;
+use model::listing::{BasketListing, BasketPage};
+use model::record::{Records, Schema};
+use model::star;
+use model::wiki::Wiki;
+use routes::new::NewBasketForm;
+use routes::openapi::ApiSpec;
+
+
+/// The prefix all API routes are mounted at.
+pub const PREFIX: &str = "/api/v1";
+
+/// Returns all API routes, to be mounted at `PREFIX`.
+pub fn routes() -> Vec<Route> {
+    routes![
+        // Session and users
+        login,
+        logout,
+        current_user,
+        user,
+        user_baskets,
+        user_baskets_listed,
+        user_stars,
+
+        // Baskets and their stars
+        create_basket,
+        basket,
+        update_basket,
+        delete_basket,
+        stars,
+        add_star,
+        remove_star,
+
+        // Facade content
+        facade,
+        file,
+
+        // The OpenAPI description of all these routes
+        openapi,
+    ]
+}
+
+/// Whether the path belongs to the API.
+pub fn is_api_path(path: &str) -> bool {
+    path.starts_with(PREFIX)
+        && (path.len() == PREFIX.len() || path[PREFIX.len()..].starts_with('/'))
+}
+
+pub type ApiResult<T> = Result<JSON<T>, ApiError>;
+
+/// An error returned by the API.
+#[derive(Debug)]
+pub struct ApiError {
+    status: Status,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new<M: Display>(status: Status, code: &'static str, message: M) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    /// The requested object doesn't exist or isn't visible to the user.
+    pub fn not_found() -> Self {
+        Self::new(Status::NotFound, "not_found", "The requested object doesn't exist.")
+    }
+
+    /// The endpoint requires a login session.
+    pub fn unauthorized() -> Self {
+        Self::new(Status::Unauthorized, "unauthorized", "You have to log in first.")
+    }
+
+    /// The request body is missing or isn't valid JSON of the right shape.
+    pub fn invalid_body() -> Self {
+        Self::new(Status::BadRequest, "invalid_body", "The request body is invalid.")
+    }
+
+    /// The JSON body of the response.
+    pub fn body(&self) -> Value {
+        json!({
+            "error": {
+                "code": self.code,
+                "message": self.message,
+            }
+        })
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond(self) -> response::Result<'r> {
+        let body = JSON(self.body());
+        status::Custom(self.status, body).respond()
+    }
+}
+
+impl From<CreateError> for ApiError {
+    fn from(e: CreateError) -> Self {
+        let (status, code) = match e {
+            CreateError::NoPermission { .. } => (Status::Forbidden, "no_permission"),
+            CreateError::NameEmpty => (Status::UnprocessableEntity, "name_empty"),
+            CreateError::NameInvalid => (Status::UnprocessableEntity, "name_invalid"),
+            CreateError::NameAlreadyUsed => (Status::Conflict, "name_already_used"),
+            CreateError::KindInvalid { .. } => (Status::UnprocessableEntity, "kind_invalid"),
+            CreateError::VisibilityInvalid => {
+                (Status::UnprocessableEntity, "visibility_invalid")
+            }
+            CreateError::TemplateInvalid => (Status::UnprocessableEntity, "template_invalid"),
+            CreateError::ContentFailed { .. } => {
+                (Status::InternalServerError, "content_failed")
+            }
+        };
+        Self::new(status, code, e)
+    }
+}
+
+impl From<LoginError> for ApiError {
+    fn from(e: LoginError) -> Self {
+        let code = match e {
+            LoginError::UserNotFound => "user_not_found",
+            LoginError::PasswordIncorrect => "password_incorrect",
+            LoginError::NoPasswordSet => "no_password_set",
+        };
+        Self::new(Status::Unauthorized, code, e.description())
+    }
+}
+
+impl From<SettingsError> for ApiError {
+    fn from(e: SettingsError) -> Self {
+        let (status, code) = match e {
+            SettingsError::NoPermission => (Status::Forbidden, "no_permission"),
+            SettingsError::Archived => (Status::Conflict, "archived"),
+        };
+        Self::new(status, code, e)
+    }
+}
+
+impl From<DeleteError> for ApiError {
+    fn from(e: DeleteError) -> Self {
+        let (status, code) = match e {
+            DeleteError::NoPermission => (Status::Forbidden, "no_permission"),
+            DeleteError::Archived => (Status::Conflict, "archived"),
+        };
+        Self::new(status, code, e)
+    }
+}
+
+fn require_login(auth_user: Option<AuthUser>) -> Result<AuthUser, ApiError> {
+    auth_user.ok_or_else(ApiError::unauthorized)
+}
+
+fn load_basket(
+    owner: &str,
+    name: &str,
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> Result<Basket, ApiError> {
+    Basket::load(name, owner, auth_user, db).ok_or_else(ApiError::not_found)
+}
+
+
+#[derive(Deserialize)]
+pub struct LoginBody {
+    id: String,
+    password: String,
+}
+
+/// Logs in and sets the session cookie. Returns the logged in user.
+#[post("/session", data = "<body>")]
+pub fn login(
+    cookies: &Cookies,
+    body: Option<JSON<LoginBody>>,
+    db: State<Db>,
+) -> ApiResult<AuthUser> {
+    let body = body.ok_or_else(ApiError::invalid_body)?.into_inner();
+    let mut user = AuthUser::login(&body.id, &body.password, &db)?;
+    user.create_session(cookies, &db);
+    Ok(JSON(user))
+}
+
+/// Ends the current login session.
+#[delete("/session")]
+pub fn logout(
+    cookies: &Cookies,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Result<status::NoContent, ApiError> {
+    require_login(auth_user)?.end_session(cookies, &db);
+    Ok(status::NoContent)
+}
+
+/// Returns the logged in user.
+#[get("/user")]
+pub fn current_user(auth_user: Option<AuthUser>) -> ApiResult<AuthUser> {
+    require_login(auth_user).map(JSON)
+}
+
+#[get("/users/<username>")]
+pub fn user(username: &str, db: State<Db>) -> ApiResult<PubUser> {
+    PubUser::from_username(username, &db)
+        .map(JSON)
+        .ok_or_else(ApiError::not_found)
+}
+
+/// Lists the baskets of a user or organization visible to the current user,
+/// one page at a time.
+#[get("/users/<username>/baskets")]
+pub fn user_baskets(
+    username: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<BasketPage> {
+    user_baskets_listed(username, BasketListing::default(), auth_user, db)
+}
+
+/// Like `user_baskets`, but with sorting, filters and the page given as
+/// query parameters.
+#[get("/users/<username>/baskets?<listing>")]
+pub fn user_baskets_listed(
+    username: &str,
+    listing: BasketListing,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<BasketPage> {
+    let user = PubUser::from_username(username, &db).ok_or_else(ApiError::not_found)?;
+    Ok(JSON(user.baskets(auth_user.as_ref(), &listing, &db)))
+}
+
+/// Lists the baskets starred by a user, most recently starred first.
+#[get("/users/<username>/stars")]
+pub fn user_stars(
+    username: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Vec<Basket>> {
+    let user = PubUser::from_username(username, &db).ok_or_else(ApiError::not_found)?;
+    Ok(JSON(star::starred_by(&user, auth_user.as_ref(), &db)))
+}
+
+
+/// The body of `POST /baskets`. Only `owner` and `name` are required.
+#[derive(Deserialize)]
+pub struct NewBasketBody {
+    owner: String,
+    name: String,
+    description: Option<String>,
+    visibility: Option<String>,
+    kind: Option<String>,
+    /// URL of the template to start from, like `/owner/name`.
+    template: Option<String>,
+}
+
+impl From<NewBasketBody> for NewBasketForm {
+    fn from(body: NewBasketBody) -> Self {
+        NewBasketForm {
+            owner: body.owner,
+            name: body.name,
+            description: body.description.unwrap_or_default(),
+            visibility: body.visibility.unwrap_or_else(|| "private".into()),
+            kind: body.kind.unwrap_or_else(|| kind::GENERIC.id.into()),
+            template: body.template.unwrap_or_default(),
+        }
+    }
+}
+
+#[post("/baskets", data = "<body>")]
+pub fn create_basket(
+    body: Option<JSON<NewBasketBody>>,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Result<status::Created<JSON<Basket>>, ApiError> {
+    let auth_user = require_login(auth_user)?;
+    let body = body.ok_or_else(ApiError::invalid_body)?.into_inner();
+
+    let basket = Basket::create(body.into(), &auth_user, &db)?;
+    let url = format!("{}/baskets{}", PREFIX, basket.url());
+    Ok(status::Created(url, Some(JSON(basket))))
+}
+
+#[get("/baskets/<owner>/<name>")]
+pub fn basket(
+    owner: &str,
+    name: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Basket> {
+    load_basket(owner, name, auth_user.as_ref(), &db).map(JSON)
+}
+
+/// The body of `PATCH /baskets/<owner>/<name>`. Missing values stay as they
+/// are.
+#[derive(Deserialize)]
+pub struct UpdateBasketBody {
+    description: Option<String>,
+    visibility: Option<String>,
+}
+
+#[patch("/baskets/<owner>/<name>", data = "<body>")]
+pub fn update_basket(
+    owner: &str,
+    name: &str,
+    body: Option<JSON<UpdateBasketBody>>,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Basket> {
+    let auth_user = require_login(auth_user)?;
+    let body = body.ok_or_else(ApiError::invalid_body)?.into_inner();
+    let mut basket = load_basket(owner, name, Some(&auth_user), &db)?;
+
+    let visibility = match body.visibility {
+        Some(ref v) => {
+            let v = BasketVisibility::from_str(v).ok_or(CreateError::VisibilityInvalid)?;
+            Some(v)
+        }
+        None => None,
+    };
+    basket.update(body.description, visibility, &auth_user, &db)?;
+    Ok(JSON(basket))
+}
+
+/// Deletes the basket. It can be restored on the "recently deleted" page for
+/// a while.
+#[delete("/baskets/<owner>/<name>")]
+pub fn delete_basket(
+    owner: &str,
+    name: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Result<status::NoContent, ApiError> {
+    let auth_user = require_login(auth_user)?;
+    let basket = load_basket(owner, name, Some(&auth_user), &db)?;
+    basket.delete(&auth_user, &db)?;
+    Ok(status::NoContent)
+}
+
+
+/// The body of the star endpoints.
+pub fn star_status(basket: &Basket, auth_user: Option<&AuthUser>, db: &Db) -> JSON<Value> {
+    JSON(json!({
+        "starred": auth_user.map(|u| star::is_starred(basket, u, db)).unwrap_or(false),
+        "count": star::count(basket, db),
+    }))
+}
+
+/// Returns the number of stars and whether the current user starred the
+/// basket.
+#[get("/baskets/<owner>/<name>/star")]
+pub fn stars(
+    owner: &str,
+    name: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Value> {
+    let basket = load_basket(owner, name, auth_user.as_ref(), &db)?;
+    Ok(star_status(&basket, auth_user.as_ref(), &db))
+}
+
+#[put("/baskets/<owner>/<name>/star")]
+pub fn add_star(
+    owner: &str,
+    name: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Value> {
+    let auth_user = require_login(auth_user)?;
+    let basket = load_basket(owner, name, Some(&auth_user), &db)?;
+    star::star(&basket, &auth_user, &db);
+    Ok(star_status(&basket, Some(&auth_user), &db))
+}
+
+#[delete("/baskets/<owner>/<name>/star")]
+pub fn remove_star(
+    owner: &str,
+    name: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Value> {
+    let auth_user = require_login(auth_user)?;
+    let basket = load_basket(owner, name, Some(&auth_user), &db)?;
+    star::unstar(&basket, &auth_user, &db);
+    Ok(star_status(&basket, Some(&auth_user), &db))
+}
+
+
+/// Returns the content of a facade of the basket, in the same structure the
+/// web interface uses. The settings facade has no content here.
+#[get("/baskets/<owner>/<name>/facades/<facade>")]
+pub fn facade(
+    owner: &str,
+    name: &str,
+    facade: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Value> {
+    let basket = load_basket(owner, name, auth_user.as_ref(), &db)?;
+    if !basket.kind_info().has_facade(facade) {
+        return Err(ApiError::not_found());
+    }
+    let content = Content::open(&basket).expect("failed to open basket repository");
+
+    let out = match facade {
+        "files" => json!(content.files()),
+        "wiki" => json!(Wiki::new(&content, &basket.url()).page_links()),
+        "bookmarks" => json!(Bookmarks::new(&content).all()),
+        "issues" => json!(Issue::list(&basket, &IssueFilter::default(), &db)),
+        "records" => {
+            let schema = match Schema::load(&content) {
+                Ok(Some(schema)) => schema,
+                Ok(None) => return Ok(JSON(json!({ "schema": null, "records": [] }))),
+                Err(e) => {
+                    return Err(ApiError::new(Status::UnprocessableEntity, "schema_invalid", e));
+                }
+            };
+            let records = Records::new(&content, &schema).all()
+                .into_iter()
+                .map(|record| json!({
+                    "id": record.id,
+                    "title": record.title(&schema),
+                    "values": record.values,
+                }))
+                .collect::<Vec<_>>();
+            json!({ "schema": schema, "records": records })
+        }
+        _ => return Err(ApiError::not_found()),
+    };
+    Ok(JSON(out))
+}
+
+/// Returns a single file of the basket. `text` is `null` for binary files.
+#[get("/baskets/<owner>/<name>/files/<path..>")]
+pub fn file(
+    owner: &str,
+    name: &str,
+    path: PathBuf,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> ApiResult<Value> {
+    let path = path.to_str().ok_or_else(ApiError::not_found)?.to_string();
+    let basket = load_basket(owner, name, auth_user.as_ref(), &db)?;
+    let content = Content::open(&basket).expect("failed to open basket repository");
+
+    let raw = content.read(&path).ok_or_else(ApiError::not_found)?;
+    Ok(JSON(file_body(path, raw, content.head().map(|oid| oid.to_string()))))
+}
+
+/// The body of `file`: the file at `path` with the given content in the
+/// commit `head`.
+pub fn file_body(path: String, raw: Vec<u8>, head: Option<String>) -> Value {
+    json!({
+        "path": path,
+        "text": String::from_utf8(raw).ok(),
+        "head": head,
+    })
+}
+
+/// Serves the OpenAPI description of the API.
+#[get("/openapi.json")]
+pub fn openapi(spec: State<ApiSpec>) -> JSON<Value> {
+    JSON(spec.to_json())
+}
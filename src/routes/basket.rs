@@ -1,9 +1,22 @@
 use rocket_contrib::Template;
+use rocket::request::{FromRequest, Request};
+use rocket::response::{self, content, status, Redirect, Responder};
 use rocket::State;
+use serde_json::Value;
 
-use model::{AuthUser, Basket};
-use context::Context;
+use model::{kind, AuthUser, Basket, BasketVisibility};
+use model::transfer;
 use db::Db;
+use routes::api::{self, ApiError};
+use routes::files::with_facade;
+
+
+/// The page shown for all requests which can't be routed.
+const NOT_FOUND_PAGE: &str = "<!DOCTYPE html>\n\
+    <html>\n\
+    <head><meta charset=\"utf-8\"><title>404 Not Found</title></head>\n\
+    <body><h1>404: Not Found</h1><p>The requested page could not be found.</p></body>\n\
+    </html>\n";
 
 
 #[get("/<username>/<basket>", rank = 10)]
@@ -34,26 +47,102 @@ fn handler(
     db: State<Db>,
     facade: Option<&str>,
 ) -> Option<Template> {
-    Basket::load(basket, username, auth_user.as_ref(), &db)
-        .map(|basket| {
-            // TODO: load facade
-
-            let active_facade = facade.unwrap_or("settings");
-            let context = Context {
-                auth_user,
-                content: Some(json!({
-                    "owner": basket.owner(),
-                    "name": basket.name(),
-                    "description": basket.description(),
-                    "basket_url": basket.url(),
-                    "facade_bar": facade_bar(&basket, active_facade, &db),
-                })),
-                .. Context::default()
-            };
-
-            let template = "basket/settings";
-            Template::render(template, &context)
-        })
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    let basket = match facade {
+        Some(facade) => with_facade(basket, facade),
+        None => basket,
+    };
+    basket.map(|basket| {
+        // TODO: load facade
+        super::settings::render(&basket, auth_user, None, &db)
+    })
+}
+
+/// The response for requests which can't be routed: the JSON error of the
+/// API for paths below `api::PREFIX` and a page for everything else.
+pub enum NotFound {
+    Page,
+    Api,
+}
+
+impl<'r> Responder<'r> for NotFound {
+    fn respond(self) -> response::Result<'r> {
+        match self {
+            NotFound::Page => status::NotFound(content::HTML(NOT_FOUND_PAGE)).respond(),
+            NotFound::Api => ApiError::not_found().respond(),
+        }
+    }
+}
+
+/// Handles all requests which couldn't be routed. If the request was meant
+/// for the former location of a transferred basket, it is redirected to the
+/// basket's current location, keeping the rest of the path.
+#[error(404)]
+pub fn not_found(req: &Request) -> Result<Redirect, NotFound> {
+    let not_found = || NotFound::Page;
+
+    let path = req.uri().path();
+    if api::is_api_path(path) {
+        return Err(NotFound::Api);
+    }
+    let mut segments = path.trim_left_matches('/').splitn(3, '/');
+    let (owner, name) = match (segments.next(), segments.next()) {
+        (Some(owner), Some(name)) if !owner.is_empty() && !name.is_empty() => (owner, name),
+        _ => return Err(not_found()),
+    };
+    let rest = segments.next();
+
+    let db = <State<Db> as FromRequest>::from_request(req)
+        .expect("cannot retrieve DB connection from request");
+    let auth_user = AuthUser::from_request(req).succeeded();
+
+    let basket = transfer::redirect_target(owner, name, &db)
+        .and_then(|id| Basket::load_by_id(id, auth_user.as_ref(), &db));
+    match basket {
+        Some(basket) => {
+            let mut url = basket.url();
+            if let Some(rest) = rest {
+                url.push('/');
+                url.push_str(rest);
+            }
+            if let Some(query) = req.uri().query() {
+                url.push('?');
+                url.push_str(query);
+            }
+            Ok(Redirect::to(&url))
+        }
+        None => Err(not_found()),
+    }
+}
+
+/// Returns the content object expected by all templates extending
+/// `basket/base`. Facade specific values can be passed via `extra`, which has
+/// to be a JSON object; its fields are merged into the result.
+pub fn facade_content(
+    basket: &Basket,
+    active_facade: &str,
+    extra: Value,
+    db: &Db,
+) -> Value {
+    let mut content = json!({
+        "owner": basket.owner(),
+        "name": basket.name(),
+        "description": basket.description(),
+        "basket_url": basket.url(),
+        "is_archived": basket.is_archived(),
+        "is_public": basket.visibility() == BasketVisibility::Public,
+        "facade_bar": facade_bar(basket, active_facade, db),
+    });
+
+    if let Value::Object(extra) = extra {
+        if let Value::Object(ref mut map) = content {
+            for (key, value) in extra {
+                map.insert(key, value);
+            }
+        }
+    }
+
+    content
 }
 
 fn facade_bar(basket: &Basket, active_facade: &str, _db: &Db) -> String {
@@ -61,24 +150,68 @@ fn facade_bar(basket: &Basket, active_facade: &str, _db: &Db) -> String {
 
     let mut s = String::new();
 
-    let facades = [
-        ("foo", "Foo", ""),
-        ("bar", "Bar", ""),
-        ("baz", "Baz", ""),
-        ("settings", "Settings", "float-right"),
-    ];
+    let settings = [kind::SETTINGS];
+    let facades = basket.kind_info().default_facades.iter()
+        .map(|f| (f, ""))
+        .chain(settings.iter().map(|f| (f, "float-right")));
 
-    for &(id, name, classes) in &facades {
+    for (facade, classes) in facades {
         write!(
             s,
             r#"<li class="{} {}" ><a href="{}/{}">{}</a></li>"#,
-            if active_facade == id { "active" } else { "" },
+            if active_facade == facade.id { "active" } else { "" },
             classes,
             basket.url(),
-            id,
-            name,
+            facade.id,
+            facade.name,
         ).unwrap();
     }
 
     s
 }
+
+
+#[cfg(test)]
+mod tests {
+    use rocket;
+    use rocket::http::{Method, Status};
+    use rocket::testing::MockRequest;
+    use serde_json::{self, Value};
+
+    use routes::api::is_api_path;
+
+    #[test]
+    fn api_paths() {
+        assert!(is_api_path("/api/v1"));
+        assert!(is_api_path("/api/v1/baskets/alice/notes"));
+        assert!(!is_api_path("/api/v10"));
+        assert!(!is_api_path("/api"));
+        assert!(!is_api_path("/alice/api"));
+    }
+
+    #[test]
+    fn not_found_in_api_is_json() {
+        let rocket = rocket::ignite().catch(errors![super::not_found]);
+
+        let mut req = MockRequest::new(Method::Get, "/api/v1/no/such/route");
+        let mut response = req.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        let body = response.body().and_then(|b| b.into_string()).unwrap();
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["error"]["code"], json!("not_found"));
+    }
+
+    #[test]
+    fn not_found_elsewhere_is_html() {
+        let rocket = rocket::ignite().catch(errors![super::not_found]);
+
+        // A single segment can't be a basket, so no database is needed.
+        let mut req = MockRequest::new(Method::Get, "/no-such-page");
+        let mut response = req.dispatch_with(&rocket);
+        assert_eq!(response.status(), Status::NotFound);
+
+        let body = response.body().and_then(|b| b.into_string()).unwrap();
+        assert!(body.starts_with("<!DOCTYPE html>"));
+    }
+}
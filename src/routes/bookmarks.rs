@@ -0,0 +1,258 @@
+//! Routes of the bookmarks facade of "bookmarks" baskets.
+
+use rocket_contrib::Template;
+use rocket::Data;
+use rocket::response::{Flash, Redirect};
+use rocket::response::content::HTML;
+use rocket::request::{Form, FlashMessage};
+use rocket::State;
+use std::io::Read;
+
+use context::Context;
+use db::Db;
+use model::{kind, AuthUser, Basket};
+use model::bookmark::{self, Bookmark, Bookmarks};
+use model::content::{Change, Content};
+use super::basket::facade_content;
+use super::files::{commit_changes, load_editable, with_facade};
+
+
+/// Maximum size of an uploaded bookmark file in bytes.
+const MAX_IMPORT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Lists all bookmarks.
+#[get("/<username>/<basket>/bookmarks", rank = 5)]
+pub fn list(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    list_handler(username, basket, None, auth_user, flash, &db)
+}
+
+#[derive(FromForm)]
+pub struct TagQuery {
+    tag: String,
+}
+
+/// Lists all bookmarks with a given tag.
+#[get("/<username>/<basket>/bookmarks?<query>", rank = 5)]
+pub fn by_tag(
+    username: &str,
+    basket: &str,
+    query: TagQuery,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    list_handler(username, basket, Some(query.tag), auth_user, flash, &db)
+}
+
+fn list_handler(
+    username: &str,
+    basket: &str,
+    tag: Option<String>,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: &Db,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), db);
+    with_facade(basket, kind::BOOKMARKS_FACADE.id).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let bookmarks = Bookmarks::new(&content);
+        let list = match tag {
+            Some(ref tag) => bookmarks.with_tag(tag),
+            None => bookmarks.all(),
+        };
+        let can_edit = basket.can_edit(auth_user.as_ref(), &db);
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::BOOKMARKS_FACADE.id, json!({
+                "bookmarks": list,
+                "tags": bookmarks.tags(),
+                "active_tag": tag,
+                "head": content.head().map(|oid| oid.to_string()),
+                "can_edit": can_edit,
+            }), db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/bookmarks", &context)
+    })
+}
+
+#[derive(FromForm)]
+pub struct BookmarkForm {
+    url: String,
+    title: String,
+    notes: String,
+    /// Comma separated list of tags.
+    tags: String,
+    parent: String,
+}
+
+/// Adds a bookmark. If a bookmark with the same URL already exists, it is
+/// replaced.
+#[post("/<username>/<basket>/bookmarks", data = "<form>", rank = 5)]
+pub fn add(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<BookmarkForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    with_facade(basket, kind::BOOKMARKS_FACADE.id).map(|basket| {
+        let form = form.into_inner();
+        let url = format!("{}/bookmarks", basket.url());
+
+        let mut new = match Bookmark::new(&form.url, &form.title, &form.notes, &form.tags) {
+            Ok(b) => b,
+            Err(e) => return Flash::error(Redirect::to(&url), e.to_string()),
+        };
+
+        // Replacing a bookmark keeps its place in the list.
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let existing = Bookmarks::new(&content).get(&new.id);
+        if let Some(ref old) = existing {
+            new.added = old.added;
+        }
+        let message = format!("Add bookmark '{}'", new.title);
+
+        let change = new.to_change(existing.is_some());
+        match commit_changes(&basket, &auth_user, &[change], &message, &form.parent, &db) {
+            Ok(()) => Flash::success(Redirect::to(&url), "The bookmark was saved."),
+            Err(e) => Flash::error(Redirect::to(&url), e),
+        }
+    })
+}
+
+#[derive(FromForm)]
+pub struct DeleteBookmarkForm {
+    parent: String,
+}
+
+/// Deletes a bookmark.
+#[post("/<username>/<basket>/bookmarks/<id>/delete", data = "<form>", rank = 5)]
+pub fn delete(
+    username: &str,
+    basket: &str,
+    id: &str,
+    auth_user: AuthUser,
+    form: Form<DeleteBookmarkForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::BOOKMARKS_FACADE.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+
+    Bookmarks::new(&content).get(id).map(|bookmark| {
+        let url = format!("{}/bookmarks", basket.url());
+        let message = format!("Remove bookmark '{}'", bookmark.title);
+        let change = Change::Delete { path: bookmark.path() };
+
+        match commit_changes(&basket, &auth_user, &[change], &message, &form.get().parent, &db) {
+            Ok(()) => Flash::success(Redirect::to(&url), "The bookmark was removed."),
+            Err(e) => Flash::error(Redirect::to(&url), e),
+        }
+    })
+}
+
+/// Exports all bookmarks as Netscape bookmark file which can be imported into
+/// browsers.
+#[get("/<username>/<basket>/bookmarks/export", rank = 5)]
+pub fn export(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Option<HTML<String>> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    with_facade(basket, kind::BOOKMARKS_FACADE.id).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let title = format!("{}/{}", basket.owner(), basket.name());
+        HTML(bookmark::export_html(&title, &Bookmarks::new(&content).all()))
+    })
+}
+
+#[derive(FromForm)]
+pub struct ImportForm {
+    html: String,
+    parent: String,
+}
+
+/// Imports bookmarks from a Netscape bookmark file pasted into a form.
+#[post("/<username>/<basket>/bookmarks/import", data = "<form>", rank = 5)]
+pub fn import_form(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<ImportForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.into_inner();
+    import(username, basket, &auth_user, &form.html, Some(&form.parent), &db)
+}
+
+/// Imports bookmarks from a Netscape bookmark file sent as request body. This
+/// is meant for bigger files, e.g. uploaded with
+/// `curl --data-binary @bookmarks.html -H 'Content-Type: text/html'`.
+#[post("/<username>/<basket>/bookmarks/import", format = "text/html", data = "<data>", rank = 4)]
+pub fn import_raw(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    data: Data,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let mut html = String::new();
+    if data.open().take(MAX_IMPORT_SIZE).read_to_string(&mut html).is_err() {
+        return None;
+    }
+    import(username, basket, &auth_user, &html, None, &db)
+}
+
+/// Imports the bookmarks of the given file in one commit. If `parent` is
+/// `None`, the changes are applied on top of the current head.
+fn import(
+    username: &str,
+    basket: &str,
+    auth_user: &AuthUser,
+    html: &str,
+    parent: Option<&str>,
+    db: &Db,
+) -> Option<Flash<Redirect>> {
+    let basket = load_editable(basket, username, auth_user, db);
+    with_facade(basket, kind::BOOKMARKS_FACADE.id).map(|basket| {
+        let url = format!("{}/bookmarks", basket.url());
+        let imported = bookmark::import_html(html);
+        if imported.is_empty() {
+            return Flash::error(Redirect::to(&url), "No bookmarks found in the file!");
+        }
+
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let head = content.head().map(|oid| oid.to_string()).unwrap_or_default();
+        let parent = parent.unwrap_or(&head[..]);
+
+        let changes = {
+            let existing = Bookmarks::new(&content);
+            imported.iter()
+                .map(|b| b.to_change(existing.exists(&b.id)))
+                .collect::<Vec<_>>()
+        };
+        let message = format!("Import {} bookmarks", imported.len());
+
+        match commit_changes(&basket, auth_user, &changes, &message, parent, db) {
+            Ok(()) => {
+                let msg = format!("{} bookmarks were imported.", imported.len());
+                Flash::success(Redirect::to(&url), msg)
+            }
+            Err(e) => Flash::error(Redirect::to(&url), e),
+        }
+    })
+}
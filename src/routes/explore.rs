@@ -0,0 +1,95 @@
+use rocket::State;
+use rocket::request::{FormItems, FromForm};
+use rocket_contrib::Template;
+
+use context::Context;
+use db::Db;
+use model::{AuthUser, BasketKind};
+use model::explore::ExploreCache;
+use model::listing::{BasketListing, BasketSort};
+use model::permissions::Visibility;
+
+
+/// Trending and new public baskets and the kinds to browse.
+#[get("/explore")]
+pub fn index(auth_user: Option<AuthUser>, cache: State<ExploreCache>, db: State<Db>) -> Template {
+    let overview = cache.overview(&db);
+    let context = Context {
+        auth_user,
+        content: Some(json!({
+            "overview": &*overview,
+        })),
+        .. Context::default()
+    };
+    Template::render("explore/index", &context)
+}
+
+/// The options for browsing the baskets of a kind. Most starred baskets come
+/// first unless requested otherwise.
+pub struct KindListing(BasketListing);
+
+impl<'f> FromForm<'f> for KindListing {
+    type Error = ();
+    fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
+        let mut listing = BasketListing {
+            sort: BasketSort::Stars,
+            .. BasketListing::default()
+        };
+        for (key, value) in form_items {
+            // The kind is given by the path and only public baskets are
+            // listed.
+            if key != "kind" && key != "visibility" {
+                listing.apply_param(key, value);
+            }
+        }
+        Ok(KindListing(listing))
+    }
+}
+
+#[get("/explore/<kind>")]
+pub fn kind(kind: &str, auth_user: Option<AuthUser>, db: State<Db>) -> Option<Template> {
+    let listing = BasketListing {
+        sort: BasketSort::Stars,
+        .. BasketListing::default()
+    };
+    render_kind(kind, auth_user, listing, &db)
+}
+
+#[get("/explore/<kind>?<listing>")]
+pub fn kind_listing(
+    kind: &str,
+    auth_user: Option<AuthUser>,
+    listing: KindListing,
+    db: State<Db>,
+) -> Option<Template> {
+    render_kind(kind, auth_user, listing.0, &db)
+}
+
+/// Lists the public baskets of a kind. The listing isn't cached: it's a
+/// single query using the listing indexes.
+fn render_kind(
+    kind: &str,
+    auth_user: Option<AuthUser>,
+    mut listing: BasketListing,
+    db: &Db,
+) -> Option<Template> {
+    BasketKind::from_id(kind).map(|kind| {
+        listing.kind = Some(kind.id.to_string());
+        let page = listing.load(Visibility::of(None, db).visible_baskets(), db);
+        let next_url = page.next.map(|cursor| {
+            format!("/explore/{}?{}&after={}", kind.id, listing.query_string(), cursor)
+        });
+
+        let context = Context {
+            auth_user,
+            content: Some(json!({
+                "kind": kind,
+                "baskets": page.baskets,
+                "next_url": next_url,
+                "sort": listing.sort.as_str(),
+            })),
+            .. Context::default()
+        };
+        Template::render("explore/kind", &context)
+    })
+}
@@ -0,0 +1,101 @@
+//! Atom feeds of users and baskets, at `/<username>.atom` and
+//! `/<username>/<basket>.atom`.
+
+use rocket::State;
+use rocket::http::ContentType;
+use rocket::request::FromParam;
+use rocket::response::content::Content;
+
+use db::Db;
+use model::{AuthUser, Basket, PubUser};
+use model::feed;
+
+
+/// A path segment ending in `.atom`, holding the part before it. Other
+/// segments are rejected, so that the feed routes forward to the user and
+/// basket routes.
+pub struct AtomName<'a>(&'a str);
+
+impl<'a> FromParam<'a> for AtomName<'a> {
+    type Error = &'a str;
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        match param.rfind(".atom") {
+            Some(pos) if pos > 0 && pos + ".atom".len() == param.len() => {
+                Ok(AtomName(&param[..pos]))
+            }
+            _ => Err(param),
+        }
+    }
+}
+
+/// The feed token of the user whose private baskets should be included.
+#[derive(FromForm)]
+pub struct FeedQuery {
+    token: String,
+}
+
+fn atom(feed: String) -> Content<String> {
+    Content(ContentType::new("application", "atom+xml"), feed)
+}
+
+#[get("/<user>", rank = 9)]
+pub fn user(user: AtomName, db: State<Db>) -> Option<Content<String>> {
+    PubUser::from_username(user.0, &db).map(|user| atom(feed::user_feed(&user, None, &db)))
+}
+
+#[get("/<user>?<query>", rank = 9)]
+pub fn user_with_token(
+    user: AtomName,
+    query: FeedQuery,
+    db: State<Db>,
+) -> Option<Content<String>> {
+    let viewer = match AuthUser::from_feed_token(&query.token, &db) {
+        Some(viewer) => viewer,
+        None => return None,
+    };
+    PubUser::from_username(user.0, &db)
+        .map(|user| atom(feed::user_feed(&user, Some(&viewer), &db)))
+}
+
+#[get("/<username>/<basket>", rank = 9)]
+pub fn basket(username: &str, basket: AtomName, db: State<Db>) -> Option<Content<String>> {
+    Basket::load(basket.0, username, None, &db)
+        .map(|basket| atom(feed::basket_feed(&basket, &db)))
+}
+
+#[get("/<username>/<basket>?<query>", rank = 9)]
+pub fn basket_with_token(
+    username: &str,
+    basket: AtomName,
+    query: FeedQuery,
+    db: State<Db>,
+) -> Option<Content<String>> {
+    let viewer = match AuthUser::from_feed_token(&query.token, &db) {
+        Some(viewer) => viewer,
+        None => return None,
+    };
+    Basket::load(basket.0, username, Some(&viewer), &db)
+        .map(|basket| atom(feed::basket_feed(&basket, &db)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rocket::request::FromParam;
+
+    use super::AtomName;
+
+    fn name(param: &str) -> Option<&str> {
+        AtomName::from_param(param).ok().map(|name| name.0)
+    }
+
+    #[test]
+    fn atom_names() {
+        assert_eq!(name("alice.atom"), Some("alice"));
+        assert_eq!(name("notes.atom.atom"), Some("notes.atom"));
+        assert_eq!(name(".atom"), None);
+        assert_eq!(name("alice"), None);
+        assert_eq!(name("alice.atom.xml"), None);
+        assert_eq!(name("alice.ATOM"), None);
+    }
+}
@@ -0,0 +1,319 @@
+//! Routes to browse and edit the files of a basket from the web interface.
+//! Every change creates a new commit in the basket's repository.
+
+use git2::Oid;
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FlashMessage};
+use rocket::State;
+use std::path::PathBuf;
+
+use context::Context;
+use db::Db;
+use model::{AuthUser, Basket};
+use model::basket::ARCHIVED_MESSAGE;
+use model::collaborator::Role;
+use model::content::{Change, Content};
+use model::event::{self, EventKind};
+use model::permissions::role_in;
+use model::search;
+use super::basket::facade_content;
+
+
+/// Number of commits shown in the history of a single file.
+const FILE_HISTORY_LEN: usize = 10;
+
+/// Lists all files of a basket.
+#[get("/<username>/<basket>/files", rank = 5)]
+pub fn list(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    Basket::load(basket, username, auth_user.as_ref(), &db).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let can_edit = basket.can_edit(auth_user.as_ref(), &db);
+
+        let context = Context {
+            content: Some(facade_content(&basket, "files", json!({
+                "files": content.files(),
+                "can_edit": can_edit,
+            }), &db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/files", &context)
+    })
+}
+
+/// Shows a single file together with its recent history.
+#[get("/<username>/<basket>/files/<path..>", rank = 5)]
+pub fn show(
+    username: &str,
+    basket: &str,
+    path: PathBuf,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    let path = match path.to_str() {
+        Some(path) => path.to_string(),
+        None => return None,
+    };
+
+    Basket::load(basket, username, auth_user.as_ref(), &db).and_then(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+
+        content.read(&path).map(|raw| {
+            // Binary files can't be displayed or edited in the browser.
+            let text = String::from_utf8(raw).ok();
+            let can_edit = basket.can_edit(auth_user.as_ref(), &db);
+
+            let context = Context {
+                content: Some(facade_content(&basket, "files", json!({
+                    "path": path,
+                    "text": text,
+                    "head": content.head().map(|oid| oid.to_string()),
+                    "history": content.history(Some(&path), FILE_HISTORY_LEN),
+                    "can_edit": can_edit,
+                }), &db)),
+                flash: flash.map(|f| f.into()),
+                auth_user,
+            };
+            Template::render("basket/file", &context)
+        })
+    })
+}
+
+/// Shows the editor to create a new file.
+#[get("/<username>/<basket>/new-file", rank = 5)]
+pub fn new_file(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    load_editable(basket, username, &auth_user, &db).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+
+        let form = FileForm {
+            original_path: String::new(),
+            path: String::new(),
+            content: String::new(),
+            message: String::new(),
+            parent: content.head().map(|oid| oid.to_string()).unwrap_or_default(),
+        };
+        render_editor(&basket, auth_user, form, None, &db)
+    })
+}
+
+/// Shows the editor for an existing file.
+#[get("/<username>/<basket>/edit/<path..>", rank = 5)]
+pub fn edit(
+    username: &str,
+    basket: &str,
+    path: PathBuf,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    let path = match path.to_str() {
+        Some(path) => path.to_string(),
+        None => return None,
+    };
+    let basket = match load_editable(basket, username, &auth_user, &db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+
+    content.read_text(&path).map(|text| {
+        let form = FileForm {
+            original_path: path.clone(),
+            message: format!("Update {}", path),
+            path: path,
+            content: text,
+            parent: content.head().map(|oid| oid.to_string()).unwrap_or_default(),
+        };
+        render_editor(&basket, auth_user, form, None, &db)
+    })
+}
+
+/// Data sent by the file editor.
+#[derive(Clone, Serialize, FromForm)]
+pub struct FileForm {
+    /// The path of the file before editing. Empty if a new file is created.
+    pub original_path: String,
+    pub path: String,
+    pub content: String,
+    pub message: String,
+    /// The head commit the user started editing from. Empty if the basket
+    /// didn't contain any commits.
+    pub parent: String,
+}
+
+/// Data sent when deleting a file.
+#[derive(Clone, FromForm)]
+pub struct DeleteFileForm {
+    pub path: String,
+    pub message: String,
+    pub parent: String,
+}
+
+/// Saves the data from the file editor: creates, updates and/or renames a
+/// file.
+#[post("/<username>/<basket>/save", data = "<form>", rank = 5)]
+pub fn save(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<FileForm>,
+    db: State<Db>,
+) -> Option<Result<Flash<Redirect>, Template>> {
+    let basket = match load_editable(basket, username, &auth_user, &db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let form = form.into_inner();
+    let path = form.path.trim().trim_matches('/').to_string();
+
+    let change = if form.original_path.is_empty() {
+        Change::Create {
+            path: path.clone(),
+            content: form.content.clone(),
+        }
+    } else if form.original_path != path {
+        Change::Rename {
+            from: form.original_path.clone(),
+            to: path.clone(),
+            content: Some(form.content.clone()),
+        }
+    } else {
+        Change::Update {
+            path: path.clone(),
+            content: form.content.clone(),
+        }
+    };
+
+    let result = commit_changes(&basket, &auth_user, &[change], &form.message, &form.parent, &db);
+    Some(match result {
+        Ok(()) => {
+            let url = format!("{}/files/{}", basket.url(), path);
+            Ok(Flash::success(Redirect::to(&url), "Your changes were saved."))
+        }
+        Err(e) => Err(render_editor(&basket, auth_user, form, Some(e), &db)),
+    })
+}
+
+/// Deletes a file.
+#[post("/<username>/<basket>/delete", data = "<form>", rank = 5)]
+pub fn delete(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<DeleteFileForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let basket = match load_editable(basket, username, &auth_user, &db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let form = form.into_inner();
+
+    let change = Change::Delete { path: form.path.clone() };
+    let result = commit_changes(&basket, &auth_user, &[change], &form.message, &form.parent, &db);
+    Some(match result {
+        Ok(()) => {
+            let url = format!("{}/files", basket.url());
+            let msg = format!("'{}' was deleted.", form.path);
+            Flash::success(Redirect::to(&url), msg)
+        }
+        Err(e) => {
+            let url = format!("{}/files/{}", basket.url(), form.path);
+            Flash::error(Redirect::to(&url), e)
+        }
+    })
+}
+
+/// Loads the basket and checks whether the user may edit it. Returns `None`
+/// otherwise, so that we don't leak the existence of private baskets.
+///
+/// Archived baskets are still returned to users who could edit them if they
+/// weren't archived, so that `commit_changes` can refuse the change with a
+/// proper message.
+pub fn load_editable(
+    basket: &str,
+    username: &str,
+    auth_user: &AuthUser,
+    db: &Db,
+) -> Option<Basket> {
+    Basket::load(basket, username, Some(auth_user), db).and_then(|b| {
+        let can_edit = if b.is_archived() {
+            role_in(Some(&**auth_user), b.owner_user(), &b, db) >= Some(Role::Write)
+        } else {
+            b.can_edit(Some(auth_user), db)
+        };
+        if can_edit { Some(b) } else { None }
+    })
+}
+
+/// Passes on a basket loaded with `Basket::load` or `load_editable` only if
+/// its kind has the given facade, so that e.g. the wiki of a bookmarks basket
+/// is a 404 as well.
+pub fn with_facade(basket: Option<Basket>, facade: &str) -> Option<Basket> {
+    basket.and_then(|b| if b.kind_info().has_facade(facade) { Some(b) } else { None })
+}
+
+/// Commits the given changes on behalf of the user. Returns a user facing
+/// error message on failure.
+pub fn commit_changes(
+    basket: &Basket,
+    auth_user: &AuthUser,
+    changes: &[Change],
+    message: &str,
+    parent: &str,
+    db: &Db,
+) -> Result<(), String> {
+    if basket.is_archived() {
+        return Err(ARCHIVED_MESSAGE.into());
+    }
+    let author = auth_user.commit_author(db).ok_or_else(|| {
+        "You need to set a primary email address in your settings before you can \
+            edit baskets.".to_string()
+    })?;
+
+    let parent = if parent.is_empty() {
+        None
+    } else {
+        Some(Oid::from_str(parent).map_err(|_| "Invalid form data!".to_string())?)
+    };
+
+    let content = Content::create(basket).map_err(|e| e.to_string())?;
+    content.commit(changes, &author, message, parent)
+        .map(|_| {
+            basket.touch(db);
+            search::index_content(basket, db);
+            let summary = message.trim().lines().next().unwrap_or("").to_string();
+            event::record(EventKind::Pushed, auth_user, basket, Some(summary), db);
+        })
+        .map_err(|e| e.to_string())
+}
+
+fn render_editor(
+    basket: &Basket,
+    auth_user: AuthUser,
+    form: FileForm,
+    error: Option<String>,
+    db: &Db,
+) -> Template {
+    let context = Context {
+        content: Some(facade_content(basket, "files", json!({
+            "is_new": form.original_path.is_empty(),
+            "form": form,
+        }), db)),
+        flash: error.map(|e| Flash::error((), e).into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("basket/edit_file", &context)
+}
@@ -1,19 +1,25 @@
+use rocket::State;
 use rocket_contrib::Template;
 
+use db::Db;
 use model::AuthUser;
+use model::event;
 
 use context::Context;
 
 
 /// The landing page in case there is a valid user login.
 ///
-/// The plan is to show recent activity in watched baskets as well as some
+/// Shows the recent activity in the baskets of the user as well as some
 /// quick action buttons, such as "create new basket".
 #[get("/")]
-fn with_login(auth_user: AuthUser) -> Template {
+fn with_login(auth_user: AuthUser, db: State<Db>) -> Template {
     let context = Context {
+        content: Some(json!({
+            "events": event::feed(&auth_user, &db),
+        })),
         auth_user: Some(auth_user),
-        .. Context::empty()
+        .. Context::default()
     };
     Template::render("index/with_login", &context)
 }
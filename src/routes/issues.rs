@@ -0,0 +1,351 @@
+//! Routes of the issue tracker facade of "tasks" baskets.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FlashMessage};
+use rocket::State;
+
+use context::Context;
+use db::Db;
+use model::{kind, AuthUser, Basket};
+use model::issue::{Issue, IssueError, IssueFilter, IssueState};
+use model::permissions::{has_permission, UserAction};
+use super::basket::facade_content;
+use super::files::with_facade;
+
+
+/// Lists the open issues of a basket.
+#[get("/<username>/<basket>/issues", rank = 5)]
+pub fn list(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    list_handler(username, basket, IssueFilter::default(), auth_user, flash, &db)
+}
+
+/// Query parameters of the issue list.
+#[derive(FromForm)]
+pub struct IssueQuery {
+    state: Option<String>,
+    label: Option<String>,
+    assignee: Option<String>,
+}
+
+/// Lists the issues of a basket matching the given filter.
+#[get("/<username>/<basket>/issues?<query>", rank = 5)]
+pub fn filtered_list(
+    username: &str,
+    basket: &str,
+    query: IssueQuery,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    let non_empty = |s: Option<String>| s.and_then(|s| {
+        if s.trim().is_empty() { None } else { Some(s.trim().to_string()) }
+    });
+
+    let filter = IssueFilter {
+        state: query.state
+            .and_then(|s| IssueState::from_str(&s))
+            .unwrap_or(IssueState::Open),
+        label: non_empty(query.label),
+        assignee: non_empty(query.assignee),
+    };
+    list_handler(username, basket, filter, auth_user, flash, &db)
+}
+
+fn list_handler(
+    username: &str,
+    basket: &str,
+    filter: IssueFilter,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: &Db,
+) -> Option<Template> {
+    load_tasks(basket, username, auth_user.as_ref(), db).map(|basket| {
+        let issues = Issue::list(&basket, &filter, db);
+        let can_create = auth_user.as_ref().map(|u| can_create(&basket, u, db)).unwrap_or(false);
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::ISSUES.id, json!({
+                "issues": issues,
+                "labels": Issue::labels_in(&basket, db),
+                "filter": {
+                    "state": filter.state.as_str(),
+                    "label": filter.label,
+                    "assignee": filter.assignee,
+                },
+                "can_create": can_create,
+            }), db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/issues", &context)
+    })
+}
+
+/// Shows the form to open a new issue.
+#[get("/<username>/<basket>/issues/new", rank = 4)]
+pub fn new_issue(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    load_tasks(basket, username, Some(&auth_user), &db)
+        .and_then(|basket| if can_create(&basket, &auth_user, &db) { Some(basket) } else { None })
+        .map(|basket| render_new_form(&basket, auth_user, None, None, &db))
+}
+
+/// Data of the form to open a new issue.
+#[derive(Clone, Serialize, FromForm)]
+pub struct NewIssueForm {
+    pub title: String,
+    pub body: String,
+    /// Comma separated list of labels.
+    pub labels: String,
+}
+
+/// Opens a new issue.
+#[post("/<username>/<basket>/issues", data = "<form>", rank = 5)]
+pub fn create(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<NewIssueForm>,
+    db: State<Db>,
+) -> Option<Result<Redirect, Template>> {
+    load_tasks(basket, username, Some(&auth_user), &db).map(|basket| {
+        let form = form.into_inner();
+        match Issue::create(&basket, &auth_user, &form.title, &form.body, &form.labels, &db) {
+            Ok(issue) => Ok(Redirect::to(&issue.url())),
+            Err(e) => Err(render_new_form(&basket, auth_user, Some(e), Some(form), &db)),
+        }
+    })
+}
+
+/// Shows a single issue with all its comments.
+#[get("/<username>/<basket>/issues/<number>", rank = 5)]
+pub fn show(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = match load_tasks(basket, username, auth_user.as_ref(), &db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+
+    Issue::load(&basket, number, &db).map(|issue| {
+        let (can_comment, can_triage) = match auth_user {
+            Some(ref u) => {
+                let owner = basket.owner_user();
+                (
+                    has_permission(Some(u), UserAction::CommentOnIssue {
+                        owner,
+                        basket: &basket,
+                    }, &db),
+                    has_permission(Some(u), UserAction::TriageIssues {
+                        owner,
+                        basket: &basket,
+                    }, &db),
+                )
+            }
+            None => (false, false),
+        };
+        let labels = issue.labels().join(", ");
+        let assignees = issue.assignees().iter()
+            .map(|u| u.username())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::ISSUES.id, json!({
+                "comments": issue.comments(&db),
+                "issue": issue,
+                "labels_input": labels,
+                "assignees_input": assignees,
+                "can_comment": can_comment,
+                "can_triage": can_triage,
+            }), &db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/issue", &context)
+    })
+}
+
+#[derive(FromForm)]
+pub struct CommentForm {
+    body: String,
+}
+
+/// Adds a comment to an issue.
+#[post("/<username>/<basket>/issues/<number>/comment", data = "<form>", rank = 5)]
+pub fn comment(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: AuthUser,
+    form: Form<CommentForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(username, basket, number, &auth_user, &db, |basket, issue| {
+        issue.add_comment(basket, &auth_user, &form.get().body, &db)
+            .map(|_| "Your comment was added.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct EditIssueForm {
+    title: String,
+    body: String,
+}
+
+/// Changes title and body of an issue.
+#[post("/<username>/<basket>/issues/<number>/edit", data = "<form>", rank = 5)]
+pub fn edit(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: AuthUser,
+    form: Form<EditIssueForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(username, basket, number, &auth_user, &db, |basket, issue| {
+        issue.edit(basket, &auth_user, &form.title, &form.body, &db)
+            .map(|_| "The issue was updated.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct StateForm {
+    is_open: bool,
+}
+
+/// Closes or reopens an issue.
+#[post("/<username>/<basket>/issues/<number>/state", data = "<form>", rank = 5)]
+pub fn set_state(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: AuthUser,
+    form: Form<StateForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let is_open = form.get().is_open;
+    modify(username, basket, number, &auth_user, &db, |basket, issue| {
+        issue.set_open(basket, &auth_user, is_open, &db)
+            .map(|_| if is_open { "The issue was reopened." } else { "The issue was closed." })
+    })
+}
+
+#[derive(FromForm)]
+pub struct LabelsForm {
+    labels: String,
+}
+
+/// Replaces the labels of an issue.
+#[post("/<username>/<basket>/issues/<number>/labels", data = "<form>", rank = 5)]
+pub fn set_labels(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: AuthUser,
+    form: Form<LabelsForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(username, basket, number, &auth_user, &db, |basket, issue| {
+        issue.set_labels(basket, &auth_user, &form.get().labels, &db)
+            .map(|_| "The labels were updated.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct AssigneesForm {
+    assignees: String,
+}
+
+/// Replaces the assignees of an issue.
+#[post("/<username>/<basket>/issues/<number>/assignees", data = "<form>", rank = 5)]
+pub fn set_assignees(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: AuthUser,
+    form: Form<AssigneesForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(username, basket, number, &auth_user, &db, |basket, issue| {
+        issue.set_assignees(basket, &auth_user, &form.get().assignees, &db)
+            .map(|_| "The assignees were updated.")
+    })
+}
+
+/// Loads basket and issue, applies the given modification and redirects back
+/// to the issue with a flash message describing the outcome.
+fn modify<F>(
+    username: &str,
+    basket: &str,
+    number: i32,
+    auth_user: &AuthUser,
+    db: &Db,
+    f: F,
+) -> Option<Flash<Redirect>>
+    where F: FnOnce(&Basket, &mut Issue) -> Result<&'static str, IssueError>
+{
+    let basket = match load_tasks(basket, username, Some(auth_user), db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+
+    Issue::load(&basket, number, db).map(|mut issue| {
+        let url = issue.url();
+        match f(&basket, &mut issue) {
+            Ok(msg) => Flash::success(Redirect::to(&url), msg),
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+/// Loads the basket if it has an issue tracker and the user may see it.
+fn load_tasks(
+    basket: &str,
+    username: &str,
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> Option<Basket> {
+    with_facade(Basket::load(basket, username, auth_user, db), kind::ISSUES.id)
+}
+
+fn can_create(basket: &Basket, auth_user: &AuthUser, db: &Db) -> bool {
+    has_permission(Some(auth_user), UserAction::CreateIssue {
+        owner: basket.owner_user(),
+        basket: basket,
+    }, db)
+}
+
+fn render_new_form(
+    basket: &Basket,
+    auth_user: AuthUser,
+    error: Option<IssueError>,
+    values: Option<NewIssueForm>,
+    db: &Db,
+) -> Template {
+    let context = Context {
+        content: Some(facade_content(basket, kind::ISSUES.id, json!({
+            "form": values,
+        }), db)),
+        flash: error.map(|e| Flash::error((), e.to_string()).into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("basket/new_issue", &context)
+}
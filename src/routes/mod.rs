@@ -1,11 +1,25 @@
 use rocket::response::NamedFile;
 
 
+pub mod account;
+pub mod api;
 pub mod basket;
+pub mod bookmarks;
+pub mod explore;
+pub mod feed;
+pub mod files;
 pub mod index;
+pub mod issues;
 pub mod login;
 pub mod new;
+pub mod notifications;
+pub mod openapi;
+pub mod organization;
+pub mod records;
+pub mod search;
+pub mod settings;
 pub mod user;
+pub mod wiki;
 
 
 /// Route to serve static file requests from the `static/` directory.
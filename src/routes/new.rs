@@ -6,14 +6,15 @@ use rocket::State;
 
 use context::Context;
 use db::Db;
-use model::{AuthUser, Basket};
+use model::{AuthUser, Basket, BasketKind};
+use model::organization::{self, Organization};
 
 
 
 ///
 #[get("/new")]
-fn with_login(auth_user: AuthUser) -> Template {
-    render_form(auth_user, None, None)
+fn with_login(auth_user: AuthUser, db: State<Db>) -> Template {
+    render_form(auth_user, None, None, &db)
 }
 
 
@@ -21,17 +22,36 @@ fn render_form(
     auth_user: AuthUser,
     error: Option<String>,
     values: Option<NewBasketForm>,
+    db: &Db,
 ) -> Template {
+    let owners = possible_owners(&auth_user, db);
 
     let context = Context {
         flash: error.map(|e| Flash::error((), e).into()),
+        content: Some(json!({
+            "form": values,
+            "owners": owners,
+            "kinds": BasketKind::all(),
+            "templates": Basket::templates(&auth_user, db),
+        })),
         auth_user: Some(auth_user),
-        content: values,
         .. Context::default()
     };
     Template::render("new/with_login", &context)
 }
 
+/// Returns the names of all owners the user can create baskets for: the user
+/// itself and all organizations which allow it.
+fn possible_owners(auth_user: &AuthUser, db: &Db) -> Vec<String> {
+    let orgs = organization::memberships(auth_user, db)
+        .into_iter()
+        .filter_map(|m| Organization::from_user(m.organization, db))
+        .filter(|org| org.can_create_baskets(auth_user, db))
+        .map(|org| org.username().to_string());
+
+    Some(auth_user.username().to_string()).into_iter().chain(orgs).collect()
+}
+
 #[get("/new", rank = 3)]
 fn without_login() -> Failure {
     Failure(Status::Unauthorized)
@@ -44,8 +64,10 @@ pub struct NewBasketForm {
     pub owner: String,
     pub name: String,
     pub description: String,
-    pub is_public: bool,
+    pub visibility: String,
     pub kind: String,
+    /// The URL of the template to start from, or empty for none.
+    pub template: String,
 }
 
 #[post("/new", data = "<new>")]
@@ -62,7 +84,8 @@ fn create(
             return Err(render_form(
                 auth_user,
                 Some("Invalid form data!".into()),
-                None
+                None,
+                &db,
             ));
         }
     };
@@ -74,6 +97,7 @@ fn create(
                 auth_user,
                 Some(e.to_string()),
                 Some(form_data_clone),
+                &db,
             )
         })?;
 
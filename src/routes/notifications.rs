@@ -0,0 +1,89 @@
+//! The notifications inbox and the email settings for notifications.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{FlashMessage, Form, FormItems, FromForm};
+use rocket::State;
+
+use context::Context;
+use db::Db;
+use model::AuthUser;
+use model::email_notification::{self, Delivery};
+use model::event::EventKind;
+use model::notification;
+
+
+/// Lists the recent notifications of the current user grouped by reason.
+#[get("/notifications")]
+pub fn index(auth_user: AuthUser, flash: Option<FlashMessage>, db: State<Db>) -> Template {
+    let context = Context {
+        content: Some(json!({
+            "groups": notification::inbox(&auth_user, &db),
+        })),
+        flash: flash.map(|f| f.into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("notifications", &context)
+}
+
+/// Marks a single notification as read.
+#[post("/notifications/<id>/read")]
+pub fn mark_read(id: i64, auth_user: AuthUser, db: State<Db>) -> Flash<Redirect> {
+    if notification::mark_read(id, &auth_user, &db) {
+        Flash::success(Redirect::to("/notifications"), "The notification was marked as read.")
+    } else {
+        Flash::error(Redirect::to("/notifications"), "The notification doesn't exist.")
+    }
+}
+
+/// Marks all notifications of the current user as read.
+#[post("/notifications/read")]
+pub fn mark_all_read(auth_user: AuthUser, db: State<Db>) -> Flash<Redirect> {
+    notification::mark_all_read(&auth_user, &db);
+    Flash::success(Redirect::to("/notifications"), "All notifications were marked as read.")
+}
+
+/// Shows which notifications the current user wants to be emailed about.
+#[get("/notifications/settings")]
+pub fn settings(auth_user: AuthUser, flash: Option<FlashMessage>, db: State<Db>) -> Template {
+    let context = Context {
+        content: Some(json!({
+            "preferences": email_notification::preferences(&auth_user, &db),
+            "has_email": auth_user.primary_email(&db).is_some(),
+        })),
+        flash: flash.map(|f| f.into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("notification_settings", &context)
+}
+
+/// The email delivery for each event kind: the kind is the key, the value is
+/// the delivery or empty to not email the kind.
+pub struct EmailSettingsForm(Vec<(EventKind, Delivery)>);
+
+impl<'f> FromForm<'f> for EmailSettingsForm {
+    type Error = ();
+    fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
+        let mut choices = vec![];
+        for (key, value) in form_items {
+            let kind = EventKind::from_str(key).ok_or(())?;
+            match Delivery::from_str(value) {
+                Some(delivery) => choices.push((kind, delivery)),
+                None if value.is_empty() => {}
+                None => return Err(()),
+            }
+        }
+        Ok(EmailSettingsForm(choices))
+    }
+}
+
+/// Saves which notifications the current user wants to be emailed about.
+#[post("/notifications/settings", data = "<form>")]
+pub fn save_settings(
+    auth_user: AuthUser,
+    form: Form<EmailSettingsForm>,
+    db: State<Db>,
+) -> Flash<Redirect> {
+    email_notification::set_preferences(&auth_user, &form.get().0, &db);
+    Flash::success(Redirect::to("/notifications/settings"), "Your email settings were saved.")
+}
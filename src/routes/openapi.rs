@@ -0,0 +1,641 @@
+//! The OpenAPI 3 description of the JSON API, served at
+//! `/api/v1/openapi.json`.
+//!
+//! Paths, methods and path parameters are taken from the API routes
+//! themselves, everything else is described in `OPERATIONS`. The tests check
+//! that every route is described there and that the response schemas match
+//! what the handlers serialize, so the spec can't silently drift from them.
+
+use rocket::Route;
+use rocket::http::Method;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::api::PREFIX;
+
+
+/// What an operation returns on success.
+enum Reply {
+    /// A single object of the given schema.
+    One(&'static str),
+    /// A list of objects of the given schema.
+    List(&'static str),
+    /// `201 Created` with the new object of the given schema.
+    Created(&'static str),
+    /// `204 No Content`.
+    Empty,
+    /// Some JSON value whose structure depends on the request.
+    Any,
+}
+
+/// The description of a single API operation.
+struct Operation {
+    method: Method,
+    /// The path as written in the route attribute.
+    path: &'static str,
+    summary: &'static str,
+    /// Whether a login session is required.
+    login: bool,
+    /// Names and descriptions of the optional query parameters.
+    query: &'static [(&'static str, &'static str)],
+    /// Schema of the JSON request body, if there is one.
+    body: Option<&'static str>,
+    reply: Reply,
+}
+
+/// The query parameters of basket listings, see `model::listing`.
+const LISTING_PARAMS: &[(&str, &str)] = &[
+    ("sort", "One of 'name' (default), 'created', 'updated' and 'stars'"),
+    ("archived", "'last' to list archived baskets after all others"),
+    ("kind", "Only list baskets of this kind"),
+    ("visibility", "Only list baskets with this visibility"),
+    ("after", "The 'next' value of the previous page"),
+    ("limit", "Number of baskets per page, at most 100"),
+];
+
+const OPERATIONS: &[Operation] = &[
+    Operation {
+        method: Method::Post,
+        path: "/session",
+        summary: "Log in and set the session cookie",
+        login: false,
+        query: &[],
+        body: Some("Login"),
+        reply: Reply::One("AuthUser"),
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/session",
+        summary: "End the current login session",
+        login: true,
+        query: &[],
+        body: None,
+        reply: Reply::Empty,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/user",
+        summary: "Get the logged in user",
+        login: true,
+        query: &[],
+        body: None,
+        reply: Reply::One("AuthUser"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/users/<username>",
+        summary: "Get a user or organization",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::One("User"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/users/<username>/baskets",
+        summary: "List the visible baskets of a user or organization, one page at a time",
+        login: false,
+        query: LISTING_PARAMS,
+        body: None,
+        reply: Reply::One("BasketPage"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/users/<username>/stars",
+        summary: "List the visible baskets starred by a user",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::List("Basket"),
+    },
+    Operation {
+        method: Method::Post,
+        path: "/baskets",
+        summary: "Create a basket",
+        login: true,
+        query: &[],
+        body: Some("NewBasket"),
+        reply: Reply::Created("Basket"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/baskets/<owner>/<name>",
+        summary: "Get a basket",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::One("Basket"),
+    },
+    Operation {
+        method: Method::Patch,
+        path: "/baskets/<owner>/<name>",
+        summary: "Change the description or visibility of a basket",
+        login: true,
+        query: &[],
+        body: Some("BasketUpdate"),
+        reply: Reply::One("Basket"),
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/baskets/<owner>/<name>",
+        summary: "Delete a basket; it can be restored for a while",
+        login: true,
+        query: &[],
+        body: None,
+        reply: Reply::Empty,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/baskets/<owner>/<name>/star",
+        summary: "Get the star count and whether the user starred the basket",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::One("StarStatus"),
+    },
+    Operation {
+        method: Method::Put,
+        path: "/baskets/<owner>/<name>/star",
+        summary: "Star a basket",
+        login: true,
+        query: &[],
+        body: None,
+        reply: Reply::One("StarStatus"),
+    },
+    Operation {
+        method: Method::Delete,
+        path: "/baskets/<owner>/<name>/star",
+        summary: "Remove the star from a basket",
+        login: true,
+        query: &[],
+        body: None,
+        reply: Reply::One("StarStatus"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/baskets/<owner>/<name>/facades/<facade>",
+        summary: "Get the content of a facade (files, wiki, bookmarks, records or issues)",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::Any,
+    },
+    Operation {
+        method: Method::Get,
+        path: "/baskets/<owner>/<name>/files/<path..>",
+        summary: "Get a single file of a basket",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::One("File"),
+    },
+    Operation {
+        method: Method::Get,
+        path: "/openapi.json",
+        summary: "Get this OpenAPI description",
+        login: false,
+        query: &[],
+        body: None,
+        reply: Reply::Any,
+    },
+];
+
+/// The generated OpenAPI document.
+pub struct ApiSpec(Value);
+
+impl ApiSpec {
+    /// Builds the spec for the given API routes. Routes which aren't
+    /// described in `OPERATIONS` are left out, see `mismatches`.
+    pub fn build(routes: &[Route]) -> Self {
+        let mut paths = BTreeMap::<String, BTreeMap<String, Value>>::new();
+
+        for route in routes {
+            if let Some(op) = find_operation(route) {
+                paths.entry(openapi_path(op.path))
+                    .or_insert_with(BTreeMap::new)
+                    .insert(op.method.as_str().to_lowercase(), operation(op));
+            }
+        }
+
+        ApiSpec(json!({
+            "openapi": "3.0.0",
+            "info": {
+                "title": "BasGit API",
+                "version": "1",
+            },
+            "servers": [{ "url": PREFIX }],
+            "paths": paths,
+            "components": {
+                "schemas": schemas(),
+                "securitySchemes": {
+                    "session": {
+                        "type": "apiKey",
+                        "in": "cookie",
+                        "name": "session",
+                    },
+                },
+            },
+        }))
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.0.clone()
+    }
+
+    /// Returns a description of every route missing in `OPERATIONS` and of
+    /// every operation without a route.
+    pub fn mismatches(routes: &[Route]) -> Vec<String> {
+        let mut out = routes.iter()
+            .filter(|route| find_operation(route).is_none())
+            .map(|route| {
+                format!("API route {} {} is not described in the OpenAPI spec",
+                    route.method, route.path)
+            })
+            .collect::<Vec<_>>();
+
+        for op in OPERATIONS {
+            let has_route = routes.iter()
+                .any(|r| op.method == r.method && op.path == r.path.path());
+            if !has_route {
+                out.push(format!("the OpenAPI spec describes {} {}, but there is no such route",
+                    op.method, op.path));
+            }
+        }
+        out
+    }
+}
+
+fn find_operation(route: &Route) -> Option<&'static Operation> {
+    OPERATIONS.iter()
+        .find(|op| op.method == route.method && op.path == route.path.path())
+}
+
+/// Converts a route path to an OpenAPI path: `<name>` and `<name..>` become
+/// `{name}`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match param_name(segment) {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the name of the dynamic parameter, if the segment is one.
+fn param_name(segment: &str) -> Option<&str> {
+    if segment.starts_with('<') && segment.ends_with('>') {
+        Some(segment[1..segment.len() - 1].trim_right_matches(".."))
+    } else {
+        None
+    }
+}
+
+fn operation(op: &Operation) -> Value {
+    let mut parameters = op.path.split('/')
+        .filter_map(param_name)
+        .map(|name| json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        }))
+        .collect::<Vec<_>>();
+    parameters.extend(op.query.iter().map(|&(name, description)| json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": { "type": "string" },
+    })));
+
+    let mut responses = BTreeMap::new();
+    let success = match op.reply {
+        Reply::One(schema) => ("200", json!({
+            "description": "Success",
+            "content": { "application/json": { "schema": schema_ref(schema) } },
+        })),
+        Reply::List(schema) => ("200", json!({
+            "description": "Success",
+            "content": {
+                "application/json": {
+                    "schema": { "type": "array", "items": schema_ref(schema) },
+                },
+            },
+        })),
+        Reply::Created(schema) => ("201", json!({
+            "description": "Created",
+            "content": { "application/json": { "schema": schema_ref(schema) } },
+        })),
+        Reply::Empty => ("204", json!({ "description": "Success" })),
+        Reply::Any => ("200", json!({
+            "description": "Success",
+            "content": { "application/json": { "schema": { "type": "object" } } },
+        })),
+    };
+    responses.insert(success.0, success.1);
+    responses.insert("default", json!({
+        "description": "Error",
+        "content": { "application/json": { "schema": schema_ref("Error") } },
+    }));
+
+    let mut out = json!({
+        "summary": op.summary,
+        "parameters": parameters,
+        "responses": responses,
+    });
+    if let Value::Object(ref mut map) = out {
+        if let Some(body) = op.body {
+            map.insert("requestBody".into(), json!({
+                "required": true,
+                "content": { "application/json": { "schema": schema_ref(body) } },
+            }));
+        }
+        if op.login {
+            map.insert("security".into(), json!([{ "session": [] }]));
+        }
+    }
+    out
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+/// The schemas of the request and response bodies. They mirror the
+/// `Serialize` impls of the models and the body types in `routes::api`.
+fn schemas() -> BTreeMap<&'static str, Value> {
+    let string = json!({ "type": "string" });
+    let nullable_string = json!({ "type": "string", "nullable": true });
+    let visibility = json!({ "type": "string", "enum": ["public", "internal", "private"] });
+
+    let mut schemas = BTreeMap::new();
+    schemas.insert("Error", json!({
+        "type": "object",
+        "properties": {
+            "error": {
+                "type": "object",
+                "properties": {
+                    "code": string,
+                    "message": string,
+                },
+                "required": ["code", "message"],
+            },
+        },
+        "required": ["error"],
+    }));
+    schemas.insert("User", json!({
+        "type": "object",
+        "properties": {
+            "username": string,
+            "name": nullable_string,
+            "bio": nullable_string,
+        },
+        "required": ["username", "name", "bio"],
+    }));
+    schemas.insert("AuthUser", json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "integer" },
+            "username": string,
+            "name": nullable_string,
+            "bio": nullable_string,
+        },
+        "required": ["id", "username", "name", "bio"],
+    }));
+    schemas.insert("Basket", json!({
+        "type": "object",
+        "properties": {
+            "name": string,
+            "description": nullable_string,
+            "visibility": visibility,
+            "is_archived": { "type": "boolean" },
+            "is_template": { "type": "boolean" },
+            "created_at": { "type": "string", "format": "date-time" },
+            "updated_at": { "type": "string", "format": "date-time" },
+            "stars": { "type": "integer" },
+            "url": string,
+            "kind": string,
+            "kind_name": string,
+            "owner": string,
+        },
+        "required": [
+            "name", "description", "visibility", "is_archived", "is_template", "url",
+            "kind", "kind_name", "owner",
+        ],
+    }));
+    schemas.insert("BasketPage", json!({
+        "type": "object",
+        "properties": {
+            "baskets": { "type": "array", "items": schema_ref("Basket") },
+            "next": { "type": "string", "nullable": true },
+        },
+        "required": ["baskets", "next"],
+    }));
+    schemas.insert("StarStatus", json!({
+        "type": "object",
+        "properties": {
+            "starred": { "type": "boolean" },
+            "count": { "type": "integer" },
+        },
+        "required": ["starred", "count"],
+    }));
+    schemas.insert("File", json!({
+        "type": "object",
+        "properties": {
+            "path": string,
+            "text": nullable_string,
+            "head": nullable_string,
+        },
+        "required": ["path", "text", "head"],
+    }));
+    schemas.insert("Login", json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string", "description": "Username or email address" },
+            "password": string,
+        },
+        "required": ["id", "password"],
+    }));
+    schemas.insert("NewBasket", json!({
+        "type": "object",
+        "properties": {
+            "owner": string,
+            "name": string,
+            "description": string,
+            "visibility": visibility,
+            "kind": string,
+            "template": { "type": "string", "description": "URL of a template basket" },
+        },
+        "required": ["owner", "name"],
+    }));
+    schemas.insert("BasketUpdate", json!({
+        "type": "object",
+        "properties": {
+            "description": string,
+            "visibility": visibility,
+        },
+    }));
+    schemas
+}
+
+
+#[cfg(test)]
+mod tests {
+    use diesel::connection::SimpleConnection;
+    use pwhash::bcrypt;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    use db::Db;
+    use model::{AuthUser, Basket, PubUser};
+    use model::listing::BasketListing;
+    use model::star;
+    use routes::api::{self, ApiError};
+    use super::ApiSpec;
+
+    #[test]
+    fn every_route_is_described() {
+        assert_eq!(ApiSpec::mismatches(&api::routes()), Vec::<String>::new());
+    }
+
+    /// Checks that `value` matches `schema` and returns the problems. Objects
+    /// may only have the described properties, so that new fields of a model
+    /// have to be added to the spec, too.
+    fn check(value: &Value, schema: &Value, spec: &Value, at: &str, out: &mut Vec<String>) {
+        if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+            let name = reference.trim_left_matches("#/components/schemas/");
+            let schema = &spec["components"]["schemas"][name];
+            return check(value, schema, spec, at, out);
+        }
+
+        if value.is_null() && schema["nullable"] == Value::Bool(true) {
+            return;
+        }
+        if let Some(values) = schema.get("enum").and_then(|e| e.as_array()) {
+            if !values.contains(value) {
+                out.push(format!("{}: {} is not one of {:?}", at, value, values));
+            }
+        }
+
+        let ty = schema["type"].as_str().unwrap_or("");
+        let matches = match ty {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            _ => false,
+        };
+        if !matches {
+            out.push(format!("{}: expected {}, found {}", at, ty, value));
+            return;
+        }
+
+        if let Value::Object(ref map) = *value {
+            // A plain `object` without properties can contain anything.
+            let properties = match schema.get("properties").and_then(|p| p.as_object()) {
+                Some(properties) => properties,
+                None => return,
+            };
+            for (key, value) in map {
+                match properties.get(key) {
+                    Some(schema) => check(value, schema, spec, &format!("{}.{}", at, key), out),
+                    None => out.push(format!("{}: {} is not described", at, key)),
+                }
+            }
+            let required = schema.get("required").and_then(|r| r.as_array());
+            for key in required.into_iter().flat_map(|r| r) {
+                let key = key.as_str().unwrap();
+                if !map.contains_key(key) {
+                    out.push(format!("{}: required {} is missing", at, key));
+                }
+            }
+        }
+        if let Value::Array(ref items) = *value {
+            for (i, item) in items.iter().enumerate() {
+                check(item, &schema["items"], spec, &format!("{}[{}]", at, i), out);
+            }
+        }
+    }
+
+    /// Serializes the models the handlers return, by the name of their
+    /// schema.
+    fn samples() -> BTreeMap<&'static str, Vec<Value>> {
+        let db = Db::open_test_connection();
+        let hash = bcrypt::hash("password").unwrap();
+        db.conn().batch_execute(&format!("
+            insert into users (id, username, name, password) values
+                (900001, 'test-alice', 'Alice', '{}'),
+                (900002, 'test-bob', null, null);
+            insert into baskets (id, name, user_id, kind, visibility, description) values
+                (900101, 'notes', 900001, 'notes', 'public', 'My notes'),
+                (900102, 'private', 900001, 'generic', 'private', null);
+            insert into stars (user_id, basket_id) values (900002, 900101);
+        ", hash)).unwrap();
+
+        let alice = AuthUser::login("test-alice", "password", &db).unwrap();
+        let bob = PubUser::from_username("test-bob", &db).unwrap();
+        let notes = Basket::load("notes", "test-alice", None, &db).unwrap();
+        let private = Basket::load("private", "test-alice", Some(&alice), &db).unwrap();
+        let listing = BasketListing { limit: 1, ..BasketListing::default() };
+
+        // `/users/<username>/stars` lists baskets, too.
+        let mut baskets = vec![json!(notes), json!(private)];
+        baskets.extend(star::starred_by(&bob, None, &db).iter().map(|b| json!(b)));
+        assert_eq!(baskets.len(), 3);
+
+        let mut out = BTreeMap::new();
+        out.insert("AuthUser", vec![json!(alice)]);
+        out.insert("User", vec![json!(*alice), json!(bob)]);
+        out.insert("Basket", baskets);
+        out.insert("BasketPage", vec![
+            json!(alice.baskets(Some(&alice), &listing, &db)),
+            json!(bob.baskets(None, &BasketListing::default(), &db)),
+        ]);
+        out.insert("StarStatus", vec![
+            api::star_status(&notes, Some(&alice), &db).0,
+            api::star_status(&notes, None, &db).0,
+        ]);
+        out.insert("File", vec![
+            api::file_body("a.txt".into(), b"text".to_vec(), Some("abc".into())),
+            api::file_body("b.bin".into(), vec![0xff, 0xfe], None),
+        ]);
+        out.insert("Error", vec![ApiError::not_found().body()]);
+        out
+    }
+
+    #[test]
+    fn responses_match_the_models() {
+        let spec = ApiSpec::build(&api::routes()).to_json();
+        let samples = samples();
+
+        // Every schema used by a response has to be checked.
+        for (path, operations) in spec["paths"].as_object().unwrap() {
+            for (method, operation) in operations.as_object().unwrap() {
+                for response in operation["responses"].as_object().unwrap().values() {
+                    let schema = &response["content"]["application/json"]["schema"];
+                    let reference = schema.get("$ref")
+                        .or_else(|| schema.get("items").and_then(|i| i.get("$ref")))
+                        .and_then(|r| r.as_str());
+                    if let Some(reference) = reference {
+                        let name = reference.trim_left_matches("#/components/schemas/");
+                        assert!(
+                            samples.contains_key(name),
+                            "no sample for schema {} of {} {}", name, method, path,
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut problems = Vec::new();
+        for (name, values) in &samples {
+            let schema = json!({ "$ref": format!("#/components/schemas/{}", name) });
+            for value in values {
+                check(value, &schema, &spec, name, &mut problems);
+            }
+        }
+        assert_eq!(problems, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,256 @@
+//! Routes to create and manage organizations. Organizations are shown on
+//! the same pages as users (see `routes::user`).
+
+use rocket_contrib::Template;
+use rocket::http::Status;
+use rocket::response::{Failure, Flash, Redirect};
+use rocket::request::Form;
+use rocket::State;
+
+use context::Context;
+use db::Db;
+use model::AuthUser;
+use model::collaborator::Role;
+use model::organization::{Organization, OrganizationError, OrgRole};
+
+
+#[get("/organizations/new")]
+pub fn new_form(auth_user: AuthUser) -> Template {
+    render_form(auth_user, None, None)
+}
+
+#[get("/organizations/new", rank = 3)]
+pub fn new_form_without_login() -> Failure {
+    Failure(Status::Unauthorized)
+}
+
+#[derive(Clone, Serialize, FromForm)]
+pub struct NewOrganizationForm {
+    pub username: String,
+    pub name: String,
+}
+
+/// Creates a new organization with the current user as owner.
+#[post("/organizations/new", data = "<form>")]
+pub fn create(
+    auth_user: AuthUser,
+    form: Form<NewOrganizationForm>,
+    db: State<Db>,
+) -> Result<Redirect, Template> {
+    let form = form.into_inner();
+    match Organization::create(&form.username, &form.name, &auth_user, &db) {
+        Ok(org) => Ok(Redirect::to(&format!("/{}", org.username()))),
+        Err(e) => Err(render_form(auth_user, Some(e), Some(form))),
+    }
+}
+
+fn render_form(
+    auth_user: AuthUser,
+    error: Option<OrganizationError>,
+    values: Option<NewOrganizationForm>,
+) -> Template {
+    let context = Context {
+        flash: error.map(|e| Flash::error((), e.to_string()).into()),
+        auth_user: Some(auth_user),
+        content: Some(json!({
+            "form": values,
+        })),
+    };
+    Template::render("new/organization", &context)
+}
+
+#[derive(FromForm)]
+pub struct MemberForm {
+    username: String,
+    role: String,
+}
+
+/// Adds a member or changes the role of a member.
+#[post("/organizations/<org>/members", data = "<form>")]
+pub fn set_member(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<MemberForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "people", &db, |org| {
+        let role = match OrgRole::from_str(&form.role) {
+            Some(role) => role,
+            None => return Err(OrganizationError::RoleInvalid),
+        };
+        org.set_member(&form.username, role, &auth_user, &db)
+            .map(|_| "The member was saved.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct UsernameForm {
+    username: String,
+}
+
+/// Removes a member. Members can remove themselves to leave.
+#[post("/organizations/<org>/members/remove", data = "<form>")]
+pub fn remove_member(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<UsernameForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(org, "people", &db, |org| {
+        org.remove_member(&form.get().username, &auth_user, &db)
+            .map(|_| "The member was removed.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct OrganizationSettingsForm {
+    members_can_create_baskets: bool,
+}
+
+#[post("/organizations/<org>/settings", data = "<form>")]
+pub fn settings(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<OrganizationSettingsForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(org, "people", &db, |org| {
+        let value = form.get().members_can_create_baskets;
+        org.set_members_can_create_baskets(value, &auth_user, &db)
+            .map(|_| "The settings were saved.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct NewTeamForm {
+    name: String,
+    description: String,
+}
+
+#[post("/organizations/<org>/teams", data = "<form>")]
+pub fn create_team(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<NewTeamForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "teams", &db, |org| {
+        org.create_team(&form.name, &form.description, &auth_user, &db)
+            .map(|_| "The team was created.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct TeamForm {
+    team: String,
+}
+
+#[post("/organizations/<org>/teams/delete", data = "<form>")]
+pub fn delete_team(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<TeamForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    modify(org, "teams", &db, |org| {
+        org.delete_team(&form.get().team, &auth_user, &db)
+            .map(|_| "The team was deleted.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct TeamMemberForm {
+    team: String,
+    username: String,
+}
+
+#[post("/organizations/<org>/teams/members", data = "<form>")]
+pub fn add_team_member(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<TeamMemberForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "teams", &db, |org| {
+        org.add_team_member(&form.team, &form.username, &auth_user, &db)
+            .map(|_| "The member was added to the team.")
+    })
+}
+
+#[post("/organizations/<org>/teams/members/remove", data = "<form>")]
+pub fn remove_team_member(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<TeamMemberForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "teams", &db, |org| {
+        org.remove_team_member(&form.team, &form.username, &auth_user, &db)
+            .map(|_| "The member was removed from the team.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct TeamBasketForm {
+    team: String,
+    basket: String,
+    role: String,
+}
+
+/// Grants a team access to a basket or changes its role in the basket.
+#[post("/organizations/<org>/teams/baskets", data = "<form>")]
+pub fn grant_team(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<TeamBasketForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "teams", &db, |org| {
+        let role = match Role::from_str(&form.role) {
+            Some(role) => role,
+            None => return Err(OrganizationError::BasketRoleInvalid),
+        };
+        org.grant_team(&form.team, &form.basket, role, &auth_user, &db)
+            .map(|_| "The team's access was saved.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct TeamBasketRemoveForm {
+    team: String,
+    basket: String,
+}
+
+#[post("/organizations/<org>/teams/baskets/remove", data = "<form>")]
+pub fn revoke_team(
+    org: &str,
+    auth_user: AuthUser,
+    form: Form<TeamBasketRemoveForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let form = form.get();
+    modify(org, "teams", &db, |org| {
+        org.revoke_team(&form.team, &form.basket, &auth_user, &db)
+            .map(|_| "The team's access was removed.")
+    })
+}
+
+/// Loads the organization, applies the given modification and redirects to
+/// the given tab of the organization's page with a flash message describing
+/// the outcome.
+fn modify<F>(org: &str, tab: &str, db: &Db, f: F) -> Option<Flash<Redirect>>
+    where F: FnOnce(&mut Organization) -> Result<&'static str, OrganizationError>
+{
+    Organization::from_username(org, db).map(|mut org| {
+        let url = format!("/{}?tab={}", org.username(), tab);
+        match f(&mut org) {
+            Ok(msg) => Flash::success(Redirect::to(&url), msg),
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
@@ -0,0 +1,400 @@
+//! Routes of the records facade of "recipes" baskets.
+//!
+//! The forms are generated from the schema of the basket, see `model::record`.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FormItems, FromForm, FromFormValue, FlashMessage};
+use rocket::State;
+use std::collections::HashMap;
+
+use context::Context;
+use db::Db;
+use model::{kind, AuthUser, Basket};
+use model::content::Content;
+use model::record::{FieldError, FieldType, Record, Records, Schema};
+use super::basket::facade_content;
+use super::files::{commit_changes, load_editable, with_facade};
+
+
+/// Prefix of the names of all form inputs generated from the schema.
+const FIELD_PREFIX: &str = "f_";
+
+/// Shows all records as table.
+#[get("/<username>/<basket>/records", rank = 5)]
+pub fn list(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    with_facade(basket, kind::RECORDS.id).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let can_edit = basket.can_edit(auth_user.as_ref(), &db);
+
+        let extra = match Schema::load(&content) {
+            Ok(Some(schema)) => {
+                let columns = schema.columns();
+                let rows = Records::new(&content, &schema).all()
+                    .into_iter()
+                    .map(|record| json!({
+                        "url": record_url(&basket, &record.id),
+                        "title": record.title(&schema),
+                        "cells": columns.iter()
+                            .map(|f| f.display(record.values.get(&f.name)))
+                            .collect::<Vec<_>>(),
+                    }))
+                    .collect::<Vec<_>>();
+
+                json!({
+                    "schema": schema,
+                    "columns": columns.iter().map(|f| f.label()).collect::<Vec<_>>(),
+                    "rows": rows,
+                    "can_edit": can_edit,
+                })
+            }
+            Ok(None) => json!({ "schema_error": "This basket has no schema.toml yet." }),
+            Err(e) => json!({ "schema_error": e.to_string() }),
+        };
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::RECORDS.id, extra, &db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/records", &context)
+    })
+}
+
+/// Shows a single record.
+#[get("/<username>/<basket>/records/<id>", rank = 5)]
+pub fn show(
+    username: &str,
+    basket: &str,
+    id: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    let basket = match with_facade(basket, kind::RECORDS.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let schema = match Schema::load(&content) {
+        Ok(Some(schema)) => schema,
+        _ => return None,
+    };
+
+    Records::new(&content, &schema).get(id).map(|record| {
+        let fields = schema.fields.iter()
+            .map(|f| {
+                let value = record.values.get(&f.name);
+                json!({
+                    "label": f.label(),
+                    "type": f.ty,
+                    "value": f.format(value),
+                    "items": value.and_then(|v| v.as_array())
+                        .map(|items| items.iter().map(|i| f.format(Some(i))).collect::<Vec<_>>()),
+                    "display": f.display(value),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::RECORDS.id, json!({
+                "title": record.title(&schema),
+                "record_url": record_url(&basket, &record.id),
+                "fields": fields,
+                "head": content.head().map(|oid| oid.to_string()),
+                "can_edit": basket.can_edit(auth_user.as_ref(), &db),
+            }), &db)),
+            flash: flash.map(|f| f.into()),
+            auth_user,
+        };
+        Template::render("basket/record", &context)
+    })
+}
+
+/// Shows the form to add a new record.
+#[get("/<username>/<basket>/records/new", rank = 4)]
+pub fn new_record(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::RECORDS.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let head = content.head().map(|oid| oid.to_string()).unwrap_or_default();
+
+    match Schema::load(&content) {
+        Ok(Some(schema)) => {
+            let state = FormState { values: &HashMap::new(), parent: &head, errors: &[] };
+            Some(render_form(&basket, auth_user, &schema, None, state, &db))
+        }
+        _ => None,
+    }
+}
+
+/// Shows the form to edit a record.
+#[get("/<username>/<basket>/records/<id>/edit", rank = 5)]
+pub fn edit(
+    username: &str,
+    basket: &str,
+    id: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::RECORDS.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let head = content.head().map(|oid| oid.to_string()).unwrap_or_default();
+    let schema = match Schema::load(&content) {
+        Ok(Some(schema)) => schema,
+        _ => return None,
+    };
+
+    Records::new(&content, &schema).get(id).map(|record| {
+        let values = schema.form_values(&record);
+        let state = FormState { values: &values, parent: &head, errors: &[] };
+        render_form(&basket, auth_user, &schema, Some(&record.id), state, &db)
+    })
+}
+
+/// Data of the generated record form. The values of the schema fields are
+/// sent as `f_<field name>`.
+pub struct RecordForm {
+    values: HashMap<String, String>,
+    message: String,
+    parent: String,
+}
+
+impl<'f> FromForm<'f> for RecordForm {
+    type Error = ();
+
+    fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
+        let mut out = RecordForm {
+            values: HashMap::new(),
+            message: String::new(),
+            parent: String::new(),
+        };
+
+        for (key, value) in form_items {
+            let value = String::from_form_value(value).map_err(|_| ())?;
+            match key {
+                "message" => out.message = value,
+                "parent" => out.parent = value,
+                _ if key.starts_with(FIELD_PREFIX) => {
+                    out.values.insert(key[FIELD_PREFIX.len()..].to_string(), value);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Adds a new record.
+#[post("/<username>/<basket>/records", data = "<form>", rank = 5)]
+pub fn create(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<RecordForm>,
+    db: State<Db>,
+) -> Option<Result<Flash<Redirect>, Template>> {
+    save_handler(username, basket, None, auth_user, form.into_inner(), &db)
+}
+
+/// Saves changes of an existing record.
+#[post("/<username>/<basket>/records/<id>", data = "<form>", rank = 5)]
+pub fn save(
+    username: &str,
+    basket: &str,
+    id: &str,
+    auth_user: AuthUser,
+    form: Form<RecordForm>,
+    db: State<Db>,
+) -> Option<Result<Flash<Redirect>, Template>> {
+    save_handler(username, basket, Some(id), auth_user, form.into_inner(), &db)
+}
+
+fn save_handler(
+    username: &str,
+    basket: &str,
+    id: Option<&str>,
+    auth_user: AuthUser,
+    form: RecordForm,
+    db: &Db,
+) -> Option<Result<Flash<Redirect>, Template>> {
+    let basket = load_editable(basket, username, &auth_user, db);
+    let basket = match with_facade(basket, kind::RECORDS.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let schema = match Schema::load(&content) {
+        Ok(Some(schema)) => schema,
+        _ => return None,
+    };
+    let records = Records::new(&content, &schema);
+    if let Some(id) = id {
+        if !records.exists(id) {
+            return None;
+        }
+    }
+
+    let values = match schema.validate(&form.values) {
+        Ok(values) => values,
+        Err(errors) => {
+            let state = FormState { values: &form.values, parent: &form.parent, errors: &errors };
+            return Some(Err(render_form(&basket, auth_user, &schema, id, state, db)));
+        }
+    };
+
+    let mut record = Record { id: id.unwrap_or_default().to_string(), values };
+    if id.is_none() {
+        let title = record.title(&schema);
+        record.id = records.new_id(&title);
+    }
+
+    let name = schema.name.to_lowercase();
+    let message = match (form.message.trim(), id) {
+        ("", Some(_)) => format!("Update {} '{}'", name, record.title(&schema)),
+        ("", None) => format!("Add {} '{}'", name, record.title(&schema)),
+        (message, _) => message.to_string(),
+    };
+
+    let change = record.to_change(&schema, id.is_some());
+    let url = record_url(&basket, &record.id);
+    let out = match commit_changes(&basket, &auth_user, &[change], &message, &form.parent, db) {
+        Ok(()) => Ok(Flash::success(Redirect::to(&url), format!("The {} was saved.", schema.name))),
+        Err(e) => {
+            let errors = [FieldError { field: String::new(), msg: e }];
+            let state = FormState { values: &form.values, parent: &form.parent, errors: &errors };
+            Err(render_form(&basket, auth_user, &schema, id, state, db))
+        }
+    };
+    Some(out)
+}
+
+#[derive(FromForm)]
+pub struct DeleteRecordForm {
+    parent: String,
+}
+
+/// Deletes a record.
+#[post("/<username>/<basket>/records/<id>/delete", data = "<form>", rank = 5)]
+pub fn delete(
+    username: &str,
+    basket: &str,
+    id: &str,
+    auth_user: AuthUser,
+    form: Form<DeleteRecordForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::RECORDS.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let schema = match Schema::load(&content) {
+        Ok(Some(schema)) => schema,
+        _ => return None,
+    };
+
+    Records::new(&content, &schema).get(id).map(|record| {
+        let message = format!("Remove {} '{}'", schema.name.to_lowercase(), record.title(&schema));
+        let change = record.delete_change(&schema);
+
+        match commit_changes(&basket, &auth_user, &[change], &message, &form.get().parent, &db) {
+            Ok(()) => {
+                let url = format!("{}/records", basket.url());
+                Flash::success(Redirect::to(&url), format!("The {} was removed.", schema.name))
+            }
+            Err(e) => Flash::error(Redirect::to(&record_url(&basket, id)), e),
+        }
+    })
+}
+
+fn record_url(basket: &Basket, id: &str) -> String {
+    format!("{}/records/{}", basket.url(), id)
+}
+
+/// What is currently entered in the record form.
+struct FormState<'a> {
+    /// Raw values by field name.
+    values: &'a HashMap<String, String>,
+    parent: &'a str,
+    errors: &'a [FieldError],
+}
+
+/// Renders the form generated from the schema. `id` is `None` for new records.
+fn render_form(
+    basket: &Basket,
+    auth_user: AuthUser,
+    schema: &Schema,
+    id: Option<&str>,
+    state: FormState,
+    db: &Db,
+) -> Template {
+    let FormState { values, parent, errors } = state;
+    let fields = schema.fields.iter()
+        .map(|f| {
+            let value = values.get(&f.name).cloned().unwrap_or_default();
+            json!({
+                "input_name": format!("{}{}", FIELD_PREFIX, f.name),
+                "label": f.label(),
+                "type": f.ty,
+                "required": f.required,
+                "options": f.options,
+                "checked": f.ty == FieldType::Boolean && (value == "true" || value == "on"),
+                "value": value,
+                "error": errors.iter().find(|e| e.field == f.name).map(|e| e.msg.clone()),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let action = match id {
+        Some(id) => record_url(basket, id),
+        None => format!("{}/records", basket.url()),
+    };
+
+    let general_errors = errors.iter()
+        .filter(|e| schema.field(&e.field).is_none())
+        .map(|e| e.msg.clone())
+        .collect::<Vec<_>>();
+    let flash = if errors.is_empty() {
+        None
+    } else if general_errors.is_empty() {
+        Some(Flash::error((), "Some fields are invalid!").into())
+    } else {
+        Some(Flash::error((), general_errors.join(" ")).into())
+    };
+
+    let context = Context {
+        content: Some(facade_content(basket, kind::RECORDS.id, json!({
+            "schema_name": schema.name,
+            "is_new": id.is_none(),
+            "action": action,
+            "fields": fields,
+            "parent": parent,
+        }), db)),
+        flash,
+        auth_user: Some(auth_user),
+    };
+    Template::render("basket/record_form", &context)
+}
@@ -0,0 +1,41 @@
+use rocket::State;
+use rocket_contrib::Template;
+
+use context::Context;
+use db::Db;
+use model::AuthUser;
+use model::search;
+
+
+#[derive(FromForm)]
+pub struct SearchQuery {
+    q: String,
+}
+
+#[get("/search")]
+pub fn empty(auth_user: Option<AuthUser>) -> Template {
+    render(String::new(), auth_user, None)
+}
+
+/// Searches baskets, users and the content of baskets.
+#[get("/search?<query>")]
+pub fn results(query: SearchQuery, auth_user: Option<AuthUser>, db: State<Db>) -> Template {
+    let results = search::search(&query.q, auth_user.as_ref(), &db);
+    render(query.q, auth_user, Some(results))
+}
+
+fn render(
+    query: String,
+    auth_user: Option<AuthUser>,
+    results: Option<search::SearchResults>,
+) -> Template {
+    let context = Context {
+        content: Some(json!({
+            "query": query,
+            "results": results,
+        })),
+        auth_user,
+        .. Context::default()
+    };
+    Template::render("search", &context)
+}
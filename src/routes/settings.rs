@@ -0,0 +1,612 @@
+//! Routes of the settings facade, which every basket has.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FormItems, FromForm, FromFormValue, FlashMessage};
+use rocket::State;
+use serde_json::Value;
+
+use context::Context;
+use db::Db;
+use model::{kind, AuthUser, Basket, BasketVisibility, PubUser};
+use model::basket::{restore_window, DeletedBasket};
+use model::collaborator::{self, CollaboratorError, Role};
+use model::event::EventKind;
+use model::feed;
+use model::organization;
+use model::permissions::role_in;
+use model::transfer;
+use model::watch::{self, WatchLevel};
+use model::webhook::Webhook;
+use super::basket::facade_content;
+
+
+/// Shows the settings of a basket. Only administrators can change anything;
+/// other collaborators can leave the basket here.
+#[get("/<username>/<basket>/settings", rank = 5)]
+pub fn index(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    Basket::load(basket, username, auth_user.as_ref(), &db)
+        .map(|basket| render(&basket, auth_user, flash, &db))
+}
+
+/// Renders the settings page of the given basket.
+pub fn render(
+    basket: &Basket,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: &Db,
+) -> Template {
+    let can_manage = basket.can_manage(auth_user.as_ref(), db);
+    let role = role_in(auth_user.as_ref().map(|u| &**u), basket.owner_user(), basket, db);
+    let is_collaborator = auth_user.as_ref()
+        .and_then(|u| collaborator::role_of(basket, u, db))
+        .is_some();
+    let watch_level = auth_user.as_ref()
+        .and_then(|u| watch::level_of(basket, u, db))
+        .map(|level| level.as_str());
+    // Feeds of baskets which aren't public need the token of the reader.
+    let feed_url = match auth_user {
+        _ if basket.visibility() == BasketVisibility::Public => {
+            Some(format!("{}.atom", basket.url()))
+        }
+        Some(ref u) => {
+            feed::token_of(u, db).map(|token| format!("{}.atom?token={}", basket.url(), token))
+        }
+        None => None,
+    };
+    let (collaborators, teams, webhooks) = if can_manage {
+        (
+            collaborator::list(basket, db),
+            organization::team_grants(basket, db),
+            Webhook::list(basket, db),
+        )
+    } else {
+        (vec![], vec![], vec![])
+    };
+
+    let context = Context {
+        content: Some(facade_content(basket, kind::SETTINGS.id, json!({
+            "can_manage": can_manage,
+            "can_archive": basket.can_archive(auth_user.as_ref(), db),
+            "is_template": basket.is_template(),
+            "can_transfer": basket.can_transfer(auth_user.as_ref(), db),
+            "pending_owner": transfer::pending_owner(basket, db),
+            "role": role,
+            "is_collaborator": is_collaborator,
+            "collaborators": collaborators,
+            "teams": teams,
+            "is_organization": organization::is_organization(basket.owner_user(), db),
+            "watch_level": watch_level,
+            "feed_url": feed_url,
+            "feed_is_private": basket.visibility() != BasketVisibility::Public,
+            "webhooks": webhooks,
+            "event_kinds": event_kinds(),
+        }), db)),
+        flash: flash.map(|f| f.into()),
+        auth_user,
+    };
+    Template::render("basket/settings", &context)
+}
+
+#[derive(FromForm)]
+pub struct InviteForm {
+    username: String,
+    role: String,
+}
+
+/// Invites a user as collaborator or changes the role of a collaborator.
+#[post("/<username>/<basket>/settings/collaborators", data = "<form>", rank = 5)]
+pub fn invite(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<InviteForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        let form = form.get();
+
+        let result = if basket.is_archived() {
+            Err(CollaboratorError::Archived)
+        } else if !basket.can_manage(Some(&auth_user), &db) {
+            Err(CollaboratorError::NoPermission)
+        } else {
+            Role::from_str(&form.role)
+                .ok_or(CollaboratorError::RoleInvalid)
+                .and_then(|role| collaborator::invite(&basket, form.username.trim(), role, &db))
+        };
+
+        match result {
+            Ok(()) => {
+                let msg = format!("'{}' was invited. The role applies once the invitation \
+                    is accepted.", form.username.trim());
+                Flash::success(Redirect::to(&url), msg)
+            }
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+/// Removes a collaborator or cancels an invitation. Collaborators can also
+/// remove themselves to leave a basket.
+#[post("/<username>/<basket>/settings/collaborators/<collaborator>/remove", rank = 5)]
+pub fn remove(
+    username: &str,
+    basket: &str,
+    collaborator: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    let basket = match Basket::load(basket, username, Some(&auth_user), &db) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let user = match PubUser::from_username(collaborator, &db) {
+        Some(user) => user,
+        None => return None,
+    };
+
+    let url = settings_url(&basket);
+    let is_self = user.id() == auth_user.id();
+    if !is_self {
+        let error = if basket.is_archived() {
+            Some(CollaboratorError::Archived)
+        } else if !basket.can_manage(Some(&auth_user), &db) {
+            Some(CollaboratorError::NoPermission)
+        } else {
+            None
+        };
+        if let Some(e) = error {
+            return Some(Flash::error(Redirect::to(&url), e.to_string()));
+        }
+    }
+
+    if !collaborator::remove(&basket, &user, &db) {
+        return None;
+    }
+
+    let out = if is_self {
+        Flash::success(Redirect::to("/"), format!("You left '{}'.", basket.url()))
+    } else {
+        let msg = format!("'{}' was removed from the collaborators.", user.username());
+        Flash::success(Redirect::to(&url), msg)
+    };
+    Some(out)
+}
+
+#[derive(FromForm)]
+pub struct ArchiveForm {
+    archived: bool,
+}
+
+/// Archives or unarchives a basket.
+#[post("/<username>/<basket>/settings/archive", data = "<form>", rank = 5)]
+pub fn archive(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<ArchiveForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|mut basket| {
+        let url = settings_url(&basket);
+        let archived = form.get().archived;
+        match basket.set_archived(archived, &auth_user, &db) {
+            Ok(()) if archived => {
+                Flash::success(Redirect::to(&url), "The basket was archived. It is read-only now.")
+            }
+            Ok(()) => Flash::success(Redirect::to(&url), "The basket was unarchived."),
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+#[derive(FromForm)]
+pub struct TemplateForm {
+    is_template: bool,
+}
+
+/// Marks a basket as template or removes the mark.
+#[post("/<username>/<basket>/settings/template", data = "<form>", rank = 5)]
+pub fn template(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<TemplateForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|mut basket| {
+        let url = settings_url(&basket);
+        let is_template = form.get().is_template;
+        match basket.set_template(is_template, &auth_user, &db) {
+            Ok(()) if is_template => {
+                Flash::success(Redirect::to(&url), "The basket is offered as template now.")
+            }
+            Ok(()) => Flash::success(Redirect::to(&url), "The basket is no template anymore."),
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+#[derive(FromForm)]
+pub struct WatchForm {
+    /// One of the watch levels or empty for the default.
+    level: String,
+}
+
+/// Sets how closely the current user follows the basket.
+#[post("/<username>/<basket>/settings/watch", data = "<form>", rank = 5)]
+pub fn watch(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<WatchForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        let level = &form.get().level;
+        match WatchLevel::from_str(level) {
+            None if !level.is_empty() => {
+                Flash::error(Redirect::to(&url), "Invalid form data!")
+            }
+            level => {
+                watch::set_level(&basket, &auth_user, level, &db);
+                Flash::success(Redirect::to(&url), "Your notification settings were saved.")
+            }
+        }
+    })
+}
+
+/// The event kinds with their titles, to choose from in forms.
+fn event_kinds() -> Vec<Value> {
+    EventKind::all()
+        .iter()
+        .map(|kind| json!({ "kind": kind.as_str(), "title": kind.title() }))
+        .collect()
+}
+
+/// Loads a webhook of the basket if the user may manage the basket.
+fn load_webhook(
+    username: &str,
+    basket: &str,
+    id: i64,
+    auth_user: &AuthUser,
+    db: &Db,
+) -> Option<(Basket, Webhook)> {
+    Basket::load(basket, username, Some(auth_user), db).and_then(|basket| {
+        if !basket.can_manage(Some(auth_user), db) {
+            return None;
+        }
+        Webhook::load(id, &basket, db).map(|webhook| (basket, webhook))
+    })
+}
+
+/// A new webhook. The event kinds are given as any number of `kind` fields.
+pub struct WebhookForm {
+    url: String,
+    secret: String,
+    kinds: Vec<EventKind>,
+}
+
+impl<'f> FromForm<'f> for WebhookForm {
+    type Error = ();
+    fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
+        let mut form = WebhookForm {
+            url: String::new(),
+            secret: String::new(),
+            kinds: vec![],
+        };
+        for (key, value) in form_items {
+            match key {
+                "url" => form.url = String::from_form_value(value).map_err(|_| ())?,
+                "secret" => form.secret = String::from_form_value(value).map_err(|_| ())?,
+                "kind" => form.kinds.push(EventKind::from_str(value).ok_or(())?),
+                _ => return Err(()),
+            }
+        }
+        Ok(form)
+    }
+}
+
+/// Adds a webhook to the basket.
+#[post("/<username>/<basket>/settings/webhooks", data = "<form>", rank = 5)]
+pub fn add_webhook(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<WebhookForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        if !basket.can_manage(Some(&auth_user), &db) {
+            let msg = "You don't have the permission to manage the webhooks of this basket!";
+            return Flash::error(Redirect::to(&url), msg);
+        }
+
+        let form = form.get();
+        match Webhook::create(&basket, &form.url, &form.secret, &form.kinds, &db) {
+            Ok(webhook) => {
+                let url = format!("{}/webhooks/{}", url, webhook.id());
+                Flash::success(Redirect::to(&url), "The webhook was added.")
+            }
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+/// Shows a webhook with its secret and its recent deliveries.
+#[get("/<username>/<basket>/settings/webhooks/<id>", rank = 5)]
+pub fn webhook(
+    username: &str,
+    basket: &str,
+    id: i64,
+    auth_user: AuthUser,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    load_webhook(username, basket, id, &auth_user, &db).map(|(basket, webhook)| {
+        let context = Context {
+            content: Some(facade_content(&basket, kind::SETTINGS.id, json!({
+                "webhook": webhook,
+                "secret": webhook.secret(),
+                "deliveries": webhook.deliveries(&db),
+                "event_kinds": event_kinds(),
+            }), &db)),
+            flash: flash.map(|f| f.into()),
+            auth_user: Some(auth_user),
+        };
+        Template::render("basket/webhook", &context)
+    })
+}
+
+/// Queues a test delivery of a webhook.
+#[post("/<username>/<basket>/settings/webhooks/<id>/ping", rank = 5)]
+pub fn ping_webhook(
+    username: &str,
+    basket: &str,
+    id: i64,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    load_webhook(username, basket, id, &auth_user, &db).map(|(basket, webhook)| {
+        webhook.ping(&basket, &db);
+        let url = format!("{}/webhooks/{}", settings_url(&basket), webhook.id());
+        Flash::success(Redirect::to(&url), "A test delivery will be sent in a few seconds.")
+    })
+}
+
+/// Sends the body of an earlier delivery again.
+#[post("/<username>/<basket>/settings/webhooks/<id>/redeliver/<delivery>", rank = 5)]
+pub fn redeliver(
+    username: &str,
+    basket: &str,
+    id: i64,
+    delivery: i64,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    load_webhook(username, basket, id, &auth_user, &db).map(|(basket, webhook)| {
+        let url = format!("{}/webhooks/{}", settings_url(&basket), webhook.id());
+        if webhook.redeliver(delivery, &db) {
+            Flash::success(Redirect::to(&url), "The delivery will be sent again in a few seconds.")
+        } else {
+            Flash::error(Redirect::to(&url), "The delivery doesn't exist.")
+        }
+    })
+}
+
+/// Removes a webhook together with its delivery log.
+#[post("/<username>/<basket>/settings/webhooks/<id>/delete", rank = 5)]
+pub fn delete_webhook(
+    username: &str,
+    basket: &str,
+    id: i64,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    load_webhook(username, basket, id, &auth_user, &db).map(|(basket, webhook)| {
+        webhook.delete(&db);
+        Flash::success(Redirect::to(&settings_url(&basket)), "The webhook was removed.")
+    })
+}
+
+#[derive(FromForm)]
+pub struct TransferForm {
+    new_owner: String,
+}
+
+/// Nominates a new owner for the basket. The basket is transferred once the
+/// new owner accepts.
+#[post("/<username>/<basket>/settings/transfer", data = "<form>", rank = 5)]
+pub fn transfer(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<TransferForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        match transfer::nominate(&basket, &form.get().new_owner, &auth_user, &db) {
+            Ok(to) => {
+                let msg = format!(
+                    "The basket will be transferred once '{}' accepts the transfer.",
+                    to.username(),
+                );
+                Flash::success(Redirect::to(&url), msg)
+            }
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+#[post("/<username>/<basket>/settings/transfer/cancel", rank = 5)]
+pub fn cancel_transfer(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        match transfer::cancel(&basket, &auth_user, &db) {
+            Ok(()) => Flash::success(Redirect::to(&url), "The transfer was cancelled."),
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+#[derive(FromForm)]
+pub struct DeleteForm {
+    /// Has to repeat the name of the basket.
+    confirm: String,
+}
+
+/// Deletes a basket. It can be restored from the list of recently deleted
+/// baskets until it is purged.
+#[post("/<username>/<basket>/settings/delete", data = "<form>", rank = 5)]
+pub fn delete(
+    username: &str,
+    basket: &str,
+    auth_user: AuthUser,
+    form: Form<DeleteForm>,
+    db: State<Db>,
+) -> Option<Flash<Redirect>> {
+    Basket::load(basket, username, Some(&auth_user), &db).map(|basket| {
+        let url = settings_url(&basket);
+        if form.get().confirm.trim() != basket.name() {
+            let msg = "Please type the name of the basket to confirm the deletion.";
+            return Flash::error(Redirect::to(&url), msg);
+        }
+
+        let owner_url = format!("/{}", basket.owner());
+        let name = basket.url();
+        match basket.delete(&auth_user, &db) {
+            Ok(()) => {
+                let msg = format!(
+                    "'{}' was deleted. It can be restored within {} days from the list of \
+                        recently deleted baskets.",
+                    name,
+                    restore_window().num_days(),
+                );
+                Flash::success(Redirect::to(&owner_url), msg)
+            }
+            Err(e) => Flash::error(Redirect::to(&url), e.to_string()),
+        }
+    })
+}
+
+/// Lists the deleted baskets the current user can restore.
+#[get("/recently-deleted")]
+pub fn deleted(auth_user: AuthUser, flash: Option<FlashMessage>, db: State<Db>) -> Template {
+    let context = Context {
+        content: Some(json!({
+            "baskets": DeletedBasket::list(&auth_user, &db),
+        })),
+        flash: flash.map(|f| f.into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("recently_deleted", &context)
+}
+
+#[derive(FromForm)]
+pub struct RestoreForm {
+    owner: String,
+    basket: String,
+}
+
+/// Restores a deleted basket.
+#[post("/recently-deleted", data = "<form>")]
+pub fn restore(
+    auth_user: AuthUser,
+    form: Form<RestoreForm>,
+    db: State<Db>,
+) -> Flash<Redirect> {
+    let form = form.get();
+    match Basket::restore(&form.owner, &form.basket, &auth_user, &db) {
+        Ok(basket) => {
+            let msg = format!("'{}' was restored.", basket.url());
+            Flash::success(Redirect::to(&basket.url()), msg)
+        }
+        Err(e) => Flash::error(Redirect::to("/recently-deleted"), e.to_string()),
+    }
+}
+
+/// Lists the pending invitations of the current user.
+#[get("/invitations")]
+pub fn invitations(auth_user: AuthUser, flash: Option<FlashMessage>, db: State<Db>) -> Template {
+    let context = Context {
+        content: Some(json!({
+            "invitations": collaborator::pending_invitations(&auth_user, &db),
+            "transfers": transfer::incoming(&auth_user, &db),
+        })),
+        flash: flash.map(|f| f.into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("invitations", &context)
+}
+
+#[derive(FromForm)]
+pub struct InvitationForm {
+    owner: String,
+    basket: String,
+    accept: bool,
+}
+
+/// Accepts or declines an invitation.
+#[post("/invitations", data = "<form>")]
+pub fn respond(
+    auth_user: AuthUser,
+    form: Form<InvitationForm>,
+    db: State<Db>,
+) -> Flash<Redirect> {
+    let form = form.get();
+    let responded = collaborator::respond(
+        &form.owner,
+        &form.basket,
+        &auth_user,
+        form.accept,
+        &db,
+    );
+
+    if !responded {
+        Flash::error(Redirect::to("/invitations"), "The invitation doesn't exist anymore.")
+    } else if form.accept {
+        let url = format!("/{}/{}", form.owner, form.basket);
+        Flash::success(Redirect::to(&url), "You are now a collaborator of this basket.")
+    } else {
+        Flash::success(Redirect::to("/invitations"), "The invitation was declined.")
+    }
+}
+
+/// Accepts or declines the transfer of a basket.
+#[post("/transfers", data = "<form>")]
+pub fn respond_transfer(
+    auth_user: AuthUser,
+    form: Form<InvitationForm>,
+    db: State<Db>,
+) -> Flash<Redirect> {
+    let form = form.get();
+    let result = transfer::respond(&form.owner, &form.basket, &auth_user, form.accept, &db);
+    match result {
+        Ok(Some(basket)) => {
+            let msg = format!("The basket was transferred to '{}'.", basket.owner());
+            Flash::success(Redirect::to(&basket.url()), msg)
+        }
+        Ok(None) => Flash::success(Redirect::to("/invitations"), "The transfer was declined."),
+        Err(e) => Flash::error(Redirect::to("/invitations"), e.to_string()),
+    }
+}
+
+fn settings_url(basket: &Basket) -> String {
+    format!("{}/settings", basket.url())
+}
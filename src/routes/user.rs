@@ -3,7 +3,10 @@ use rocket::State;
 use rocket::request::{FormItems, FromForm};
 use serde_json;
 
-use model::{AuthUser, PubUser};
+use model::{AuthUser, BasketKind, PubUser};
+use model::listing::BasketListing;
+use model::organization::{self, Organization, OrgRole};
+use model::star;
 use context::Context;
 use db::Db;
 
@@ -31,13 +34,18 @@ fn handler(
 ) -> Option<Template> {
 
     PubUser::from_username(username, &db).map(|user| {
-        let (template, key, value) = match tab {
-            UserpageTab::Overview
-                => overview_tab(&user, auth_user.as_ref(), &db),
-            UserpageTab::Baskets
-                => basket_tab(&user, auth_user.as_ref(), &db),
-            UserpageTab::Stars
+        let org = Organization::from_user(user.clone(), &db);
+        let (template, key, value) = match (tab, org.as_ref()) {
+            (UserpageTab::Baskets(listing), _)
+                => basket_tab(&user, auth_user.as_ref(), listing, &db),
+            (UserpageTab::Stars, _)
                 => stars_tab(&user, auth_user.as_ref(), &db),
+            (UserpageTab::People, Some(org))
+                => people_tab(org, auth_user.as_ref(), &db),
+            (UserpageTab::Teams, Some(org))
+                => teams_tab(org, auth_user.as_ref(), &db),
+            _
+                => overview_tab(&user, auth_user.as_ref(), &db),
         };
 
         let user_url = format!("/{}", user.username());
@@ -52,6 +60,7 @@ fn handler(
                 "user": user,
                 "user_url": user_url,
                 "title_name": title_name,
+                "is_organization": org.is_some(),
                 key: value,
             })),
             .. Context::default()
@@ -62,62 +71,142 @@ fn handler(
 }
 
 fn overview_tab(
-    _user: &PubUser,
+    user: &PubUser,
     _auth_user: Option<&AuthUser>,
-    _db: &Db,
+    db: &Db,
 ) -> (&'static str, &'static str, serde_json::Value) {
     (
         "user/overview",
         "overview",
-        json!({}),
+        json!({
+            "organizations": organization::memberships(user, db),
+        }),
     )
 }
 
 fn basket_tab(
     user: &PubUser,
     auth_user: Option<&AuthUser>,
+    listing: BasketListing,
     db: &Db,
 ) -> (&'static str, &'static str, serde_json::Value) {
-    let baskets = user.baskets(auth_user, db);
+    let page = user.baskets(auth_user, &listing, db);
+
+    // Shared baskets aren't paginated, they are only shown on the first page.
+    let mut shared = if listing.is_first_page() {
+        user.shared_baskets(auth_user, db)
+    } else {
+        vec![]
+    };
+    shared.retain(|b| {
+        listing.kind.as_ref().map_or(true, |k| b.kind() == k)
+            && listing.visibility.map_or(true, |v| b.visibility() == v)
+    });
+    if listing.archived_last {
+        // The sort is stable, so the baskets stay sorted by name otherwise.
+        shared.sort_by_key(|b| b.is_archived());
+    }
+
+    let next_url = page.next.map(|cursor| {
+        format!("/{}?tab=baskets&{}&after={}", user.username(), listing.query_string(), cursor)
+    });
 
     (
         "user/baskets",
         "baskets",
-        json!(baskets),
+        json!({
+            "own": page.baskets,
+            "shared": shared,
+            "next_url": next_url,
+            "sort": listing.sort.as_str(),
+            "archived_last": listing.archived_last,
+            "kind": listing.kind,
+            "visibility": listing.visibility.map(|v| v.as_str()),
+            "kinds": BasketKind::all(),
+        }),
     )
 }
 
 fn stars_tab(
-    _user: &PubUser,
-    _auth_user: Option<&AuthUser>,
-    _db: &Db,
+    user: &PubUser,
+    auth_user: Option<&AuthUser>,
+    db: &Db,
 ) -> (&'static str, &'static str, serde_json::Value) {
     (
         "user/stars",
         "stars",
-        json!({}),
+        json!({
+            "baskets": star::starred_by(user, auth_user, db),
+        }),
+    )
+}
+
+fn people_tab(
+    org: &Organization,
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> (&'static str, &'static str, serde_json::Value) {
+    let role = auth_user.and_then(|u| org.role_of(u, db));
+
+    (
+        "user/people",
+        "people",
+        json!({
+            "members": org.members(db),
+            "members_can_create_baskets": org.members_can_create_baskets(),
+            "is_owner": role == Some(OrgRole::Owner),
+            "is_member": role.is_some(),
+        }),
+    )
+}
+
+fn teams_tab(
+    org: &Organization,
+    auth_user: Option<&AuthUser>,
+    db: &Db,
+) -> (&'static str, &'static str, serde_json::Value) {
+    let role = auth_user.and_then(|u| org.role_of(u, db));
+
+    (
+        "user/teams",
+        "teams",
+        json!({
+            "teams": org.teams(db),
+            "is_owner": role == Some(OrgRole::Owner),
+        }),
     )
 }
 
 pub enum UserpageTab {
     Overview,
-    Baskets,
+    Baskets(BasketListing),
     Stars,
+    /// Members of an organization.
+    People,
+    /// Teams of an organization.
+    Teams,
 }
 
 impl<'f> FromForm<'f> for UserpageTab {
     type Error = ();
     fn from_form_items(form_items: &mut FormItems<'f>) -> Result<Self, Self::Error> {
-        let out = form_items
-            .find(|&(key, _)| key == "tab")
-            .and_then(|(_, value)| {
-                match value {
-                    "baskets" => Some(UserpageTab::Baskets),
-                    "stars" => Some(UserpageTab::Stars),
-                    _ => None,
-                }
-            })
-            .unwrap_or(UserpageTab::Overview);
+        let mut tab = None;
+        let mut listing = BasketListing::default();
+        for (key, value) in form_items {
+            if key == "tab" {
+                tab = Some(value);
+            } else {
+                listing.apply_param(key, value);
+            }
+        }
+
+        let out = match tab {
+            Some("baskets") => UserpageTab::Baskets(listing),
+            Some("stars") => UserpageTab::Stars,
+            Some("people") => UserpageTab::People,
+            Some("teams") => UserpageTab::Teams,
+            _ => UserpageTab::Overview,
+        };
         Ok(out)
     }
 }
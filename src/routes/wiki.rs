@@ -0,0 +1,250 @@
+//! Routes of the wiki facade of "notes" baskets.
+
+use rocket_contrib::Template;
+use rocket::response::{Flash, Redirect};
+use rocket::request::{Form, FlashMessage};
+use rocket::State;
+
+use context::Context;
+use db::Db;
+use model::{kind, AuthUser, Basket};
+use model::content::{Change, Content};
+use model::wiki::{self, Wiki};
+use super::basket::facade_content;
+use super::files::{commit_changes, load_editable, with_facade};
+
+
+/// Number of commits shown on the history page of a wiki page.
+const PAGE_HISTORY_LEN: usize = 50;
+
+/// Shows the home page of the wiki.
+#[get("/<username>/<basket>/wiki", rank = 5)]
+pub fn index(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    show_page(username, basket, wiki::HOME_PAGE.into(), auth_user, flash, &db)
+}
+
+/// Shows a single wiki page. If the page doesn't exist yet and the user may
+/// edit the basket, the editor to create the page is shown instead. That way,
+/// following a link to a missing page creates it.
+#[get("/<username>/<basket>/wiki/<page>", rank = 5)]
+pub fn page(
+    username: &str,
+    basket: &str,
+    page: String,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: State<Db>,
+) -> Option<Template> {
+    show_page(username, basket, page, auth_user, flash, &db)
+}
+
+/// Shows the editor of an existing page.
+#[get("/<username>/<basket>/wiki/<page>/edit", rank = 5)]
+pub fn edit(
+    username: &str,
+    basket: &str,
+    page: String,
+    auth_user: AuthUser,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::WIKI.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let wiki = Wiki::new(&content, &basket.url());
+
+    wiki.source(&page).map(|source| {
+        let form = PageForm {
+            content: source,
+            message: format!("Update {}", page),
+            parent: content.head().map(|oid| oid.to_string()).unwrap_or_default(),
+        };
+        render_editor(&basket, auth_user, &page, false, form, None, &db)
+    })
+}
+
+/// Lists all commits changing the given page.
+#[get("/<username>/<basket>/wiki/<page>/history", rank = 5)]
+pub fn history(
+    username: &str,
+    basket: &str,
+    page: String,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    with_facade(basket, kind::WIKI.id).and_then(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let wiki = Wiki::new(&content, &basket.url());
+        if !wiki.exists(&page) {
+            return None;
+        }
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::WIKI.id, json!({
+                "page": page,
+                "page_url": wiki::page_url(&basket.url(), &page),
+                "history": wiki.history(&page, PAGE_HISTORY_LEN),
+            }), &db)),
+            auth_user,
+            .. Context::default()
+        };
+        Some(Template::render("basket/wiki_history", &context))
+    })
+}
+
+/// Lists all pages of the wiki.
+#[get("/<username>/<basket>/pages", rank = 5)]
+pub fn pages(
+    username: &str,
+    basket: &str,
+    auth_user: Option<AuthUser>,
+    db: State<Db>,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), &db);
+    with_facade(basket, kind::WIKI.id).map(|basket| {
+        let content = Content::open(&basket).expect("failed to open basket repository");
+        let pages = Wiki::new(&content, &basket.url()).page_links();
+
+        let context = Context {
+            content: Some(facade_content(&basket, kind::PAGES.id, json!({
+                "pages": pages,
+            }), &db)),
+            auth_user,
+            .. Context::default()
+        };
+        Template::render("basket/wiki_pages", &context)
+    })
+}
+
+/// Data sent by the page editor.
+#[derive(Clone, Serialize, FromForm)]
+pub struct PageForm {
+    pub content: String,
+    pub message: String,
+    /// The head commit the user started editing from. Empty if the basket
+    /// didn't contain any commits.
+    pub parent: String,
+}
+
+/// Saves a page, creating it if it doesn't exist yet.
+#[post("/<username>/<basket>/wiki/<page>", data = "<form>", rank = 5)]
+pub fn save(
+    username: &str,
+    basket: &str,
+    page: String,
+    auth_user: AuthUser,
+    form: Form<PageForm>,
+    db: State<Db>,
+) -> Option<Result<Flash<Redirect>, Template>> {
+    let basket = load_editable(basket, username, &auth_user, &db);
+    let basket = match with_facade(basket, kind::WIKI.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    if !wiki::is_valid_page_name(&page) {
+        return None;
+    }
+
+    let form = form.into_inner();
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let is_new = !Wiki::new(&content, &basket.url()).exists(&page);
+
+    let path = wiki::page_path(&page);
+    let change = if is_new {
+        Change::Create { path, content: form.content.clone() }
+    } else {
+        Change::Update { path, content: form.content.clone() }
+    };
+
+    let result = commit_changes(&basket, &auth_user, &[change], &form.message, &form.parent, &db);
+    Some(match result {
+        Ok(()) => {
+            let url = wiki::page_url(&basket.url(), &page);
+            Ok(Flash::success(Redirect::to(&url), "The page was saved."))
+        }
+        Err(e) => Err(render_editor(&basket, auth_user, &page, is_new, form, Some(e), &db)),
+    })
+}
+
+fn show_page(
+    username: &str,
+    basket: &str,
+    page: String,
+    auth_user: Option<AuthUser>,
+    flash: Option<FlashMessage>,
+    db: &Db,
+) -> Option<Template> {
+    let basket = Basket::load(basket, username, auth_user.as_ref(), db);
+    let basket = match with_facade(basket, kind::WIKI.id) {
+        Some(basket) => basket,
+        None => return None,
+    };
+    let content = Content::open(&basket).expect("failed to open basket repository");
+    let wiki = Wiki::new(&content, &basket.url());
+
+    match wiki.page(&page) {
+        Some(rendered) => {
+            let can_edit = basket.can_edit(auth_user.as_ref(), &db);
+            let context = Context {
+                content: Some(facade_content(&basket, kind::WIKI.id, json!({
+                    "page": rendered,
+                    "page_url": wiki::page_url(&basket.url(), &page),
+                    "can_edit": can_edit,
+                }), db)),
+                flash: flash.map(|f| f.into()),
+                auth_user,
+            };
+            Some(Template::render("basket/wiki_page", &context))
+        }
+
+        // The page doesn't exist: show the editor to create it, if the user
+        // is allowed to.
+        None => {
+            let auth_user = match auth_user {
+                Some(u) => u,
+                None => return None,
+            };
+            if !wiki::is_valid_page_name(&page) || !basket.can_edit(Some(&auth_user), &db) {
+                return None;
+            }
+
+            let form = PageForm {
+                content: format!("# {}\n\n", page),
+                message: format!("Create {}", page),
+                parent: content.head().map(|oid| oid.to_string()).unwrap_or_default(),
+            };
+            Some(render_editor(&basket, auth_user, &page, true, form, None, db))
+        }
+    }
+}
+
+fn render_editor(
+    basket: &Basket,
+    auth_user: AuthUser,
+    page: &str,
+    is_new: bool,
+    form: PageForm,
+    error: Option<String>,
+    db: &Db,
+) -> Template {
+    let context = Context {
+        content: Some(facade_content(basket, kind::WIKI.id, json!({
+            "page": page,
+            "page_url": wiki::page_url(&basket.url(), page),
+            "is_new": is_new,
+            "form": form,
+        }), db)),
+        flash: error.map(|e| Flash::error((), e).into()),
+        auth_user: Some(auth_user),
+    };
+    Template::render("basket/wiki_edit", &context)
+}